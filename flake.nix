@@ -7,8 +7,15 @@
 
     devShell = pkgs: with pkgs; mkShell {
 
+      # rodio links ALSA and rfd GTK 3, both found through pkg-config
+      nativeBuildInputs = [
+        pkg-config
+      ];
+
       buildInputs = [
         dev.rust-nightly
+        alsa-lib
+        gtk3
       ];
 
       LD_LIBRARY_PATH = lib.makeLibraryPath [
@@ -0,0 +1,7 @@
+-- Super Mario Bros., a thin layer over the built-in smb module
+local smb = require "smb"
+
+return setmetatable({
+  name = smb.version,
+  supported = true,
+}, { __index = smb })
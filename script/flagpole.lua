@@ -0,0 +1,51 @@
+-- Super Mario Bros. flagpole practice with practice.loop
+--
+-- Play up to the last staircase of a level, quicksave with F5 and run this
+-- script. Each of the 20 attempts starts from the quicksave; grab the pole
+-- above the top block of the staircase to score it. Backspace starts over
+-- without counting the attempt, the counters are kept in
+-- output/<rom>/practice/.
+
+local smb = require "smb"
+
+-- the player's float state while sliding down the flagpole
+local SLIDING = 3
+-- grabbing the pole above this height counts, in pixels from the top
+local TOP = 96
+-- give up on an attempt after this many frames
+local FRAMES = 300
+
+-- the keyboard controls the controller while the script runs
+local CONTROLS = {
+  Up = "Up", Down = "Down", Left = "Left", Right = "Right",
+  Z = "A", X = "B", Return = "Start", RShift = "Select",
+}
+
+local started
+
+local totals = practice.loop {
+  state = "quicksave.state",
+  attempts = 20,
+  on_attempt = function(attempt)
+    started = frame()
+    toast("attempt " .. attempt)
+  end,
+  on_frame = function()
+    local buttons = {}
+    for key, button in pairs(CONTROLS) do
+      buttons[button] = key_down(key)
+    end
+    set_buttons(buttons)
+  end,
+  success = function()
+    return memory.read(0x001D) == SLIDING and smb.y() < TOP
+  end,
+  fail = function()
+    local state = memory.read(0x000E)
+    local dying = state == 0x06 or state == 0x0B
+    local low = memory.read(0x001D) == SLIDING and smb.y() >= TOP
+    return dying or low or frame() - started >= FRAMES
+  end,
+}
+
+print(string.format("%d of %d attempts scored", totals.successes, totals.attempts))
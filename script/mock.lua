@@ -8,25 +8,20 @@ function jump(height)
   hold("A", height)
 end
 
--- player state
--- 0 = ground
-PLAYER_STATE = 0x1D
-GROUNDED = 0
+local smb = require "smb"
 
 FRAME = 60
 
-function jumps(n, height)
-  for i = 0, n do
-    while read(PLAYER_STATE) ~= GROUNDED do
-      wait(1)
-    end
-    hold("A", height)
+function ground()
+  while not smb.on_ground() do
+    wait(1)
   end
 end
 
-function ground()
-  while read(PLAYER_STATE) ~= GROUNDED do
-    wait(1)
+function jumps(n, height)
+  for i = 0, n do
+    ground()
+    hold("A", height)
   end
 end
 
@@ -0,0 +1,66 @@
+use std::{
+    sync::mpsc::{sync_channel, SyncSender, TrySendError},
+    thread,
+    time::Duration,
+};
+
+use rodio::{source::SineWave, OutputStream, Sink, Source};
+use tracing::warn;
+
+// beeps waiting to be played, further ones are dropped
+const MAX_QUEUED: usize = 8;
+
+// Plays beeps on a separate audio thread so scripts never wait for them.
+//
+// The audio device is only opened on the first beep.
+#[derive(Default)]
+pub struct Beeper {
+    queue: Option<SyncSender<(f32, u64)>>,
+}
+
+impl Beeper {
+    pub fn beep(&mut self, frequency: f32, millis: u64) {
+        let queue = self.queue.get_or_insert_with(spawn);
+        match queue.try_send((frequency, millis)) {
+            Ok(()) => {}
+            Err(TrySendError::Full(_)) => warn!("too many beeps queued, dropping one"),
+            Err(TrySendError::Disconnected(_)) => {}
+        }
+    }
+}
+
+fn spawn() -> SyncSender<(f32, u64)> {
+    let (sender, receiver) = sync_channel::<(f32, u64)>(MAX_QUEUED);
+    thread::spawn(move || {
+        let (_stream, handle) = match OutputStream::try_default() {
+            Ok(output) => output,
+            Err(e) => {
+                warn!("no audio output for beeps: {}", e);
+                return;
+            }
+        };
+        for (frequency, millis) in receiver {
+            let Ok(sink) = Sink::try_new(&handle) else {
+                continue;
+            };
+            let tone = SineWave::new(frequency)
+                .take_duration(Duration::from_millis(millis))
+                .amplify(0.2);
+            sink.append(tone);
+            sink.sleep_until_end();
+        }
+    });
+    sender
+}
+
+// a desktop notification, see [notify] in the usage
+pub fn notify(title: &str, body: &str) {
+    let result = notify_rust::Notification::new()
+        .appname("marlua")
+        .summary(title)
+        .body(body)
+        .show();
+    if let Err(e) = result {
+        warn!("could not show notification: {}", e);
+    }
+}
@@ -0,0 +1,188 @@
+use rlua::{prelude::LuaError, Context, Function, Table, Value};
+
+// The version of the Lua API, which follows marlua's own.
+pub const VERSION: &str = env!("CARGO_PKG_VERSION");
+
+// What scripts can probe for with marlua.has. Every API addition adds its
+// feature here in the same change.
+pub const FEATURES: &[&str] = &[
+    "savestates",
+    "checkpoints",
+    "instances",
+    "bisect",
+    "memory",
+    "mouse",
+    "keyboard",
+    "audio",
+    "notify",
+    "screenshots",
+    "frame_data",
+    "layers",
+    "blend",
+    "golden_images",
+    "diff_states",
+    "input_history",
+    "macros",
+    "permissions",
+    "profile",
+    "speed",
+    "frame_advance",
+    "seek_frame",
+    "rerecords",
+    "button_tables",
+    "scheduled",
+    "on_idle",
+    "quit",
+    "restart",
+    "button_aliases",
+    "user_states",
+    "toast",
+    "markers",
+    "log",
+    "images",
+    "tile_hashes",
+    "watchdog",
+    "bind_key",
+    "pause",
+    "practice",
+    "config",
+    "events",
+    "capture_rect",
+    "record_ram",
+];
+
+// the `marlua` table: version, require_api(min) and has(feature)
+pub fn install(ctx: Context) -> Result<(), LuaError> {
+    let marlua = ctx.create_table()?;
+    marlua.set("version", VERSION)?;
+    marlua.set(
+        "require_api",
+        ctx.create_function(|_, (min,): (String,)| {
+            let wanted = parse(&min).ok_or_else(|| {
+                LuaError::RuntimeError(format!("invalid version {}, expected x.y.z", min))
+            })?;
+            if parse(VERSION).unwrap() < wanted {
+                return Err(LuaError::RuntimeError(format!(
+                    "this script needs marlua {} or newer, but this is marlua {}",
+                    min, VERSION
+                )));
+            }
+            Ok(())
+        })?,
+    )?;
+    marlua.set(
+        "has",
+        ctx.create_function(|_, (feature,): (String,)| Ok(FEATURES.contains(&feature.as_str())))?,
+    )?;
+    ctx.globals().set("marlua", marlua)
+}
+
+// A proxy for `table` that raises an error on assignment, with nested
+// tables wrapped the same way. pairs and # still see the fields.
+pub fn read_only<'lua>(ctx: Context<'lua>, table: Table<'lua>) -> Result<Table<'lua>, LuaError> {
+    let nested = table
+        .clone()
+        .pairs::<Value, Table>()
+        .filter_map(Result::ok)
+        .collect::<Vec<_>>();
+    for (key, inner) in nested {
+        table.raw_set(key, read_only(ctx, inner)?)?;
+    }
+
+    let (pairs, len): (Function, Function) = ctx
+        .load(
+            "local next, data = next, ... \
+             return function() return next, data, nil end, function() return #data end",
+        )
+        .into_function()?
+        .call(table.clone())?;
+    let meta = ctx.create_table()?;
+    meta.set("__index", table)?;
+    meta.set(
+        "__newindex",
+        ctx.create_function(|_, (_, key): (Value, Value)| {
+            let key = match key {
+                Value::String(key) => key.to_str()?.to_owned(),
+                Value::Integer(i) => i.to_string(),
+                _ => "a field".to_owned(),
+            };
+            Err::<(), _>(LuaError::RuntimeError(format!(
+                "cannot set {}, the table is read-only",
+                key
+            )))
+        })?,
+    )?;
+    meta.set("__pairs", pairs)?;
+    meta.set("__len", len)?;
+    meta.set("__metatable", false)?;
+    let proxy = ctx.create_table()?;
+    proxy.set_metatable(Some(meta));
+    Ok(proxy)
+}
+
+// major, minor and patch; missing parts count as 0
+fn parse(version: &str) -> Option<(u32, u32, u32)> {
+    let mut parts = version.trim().splitn(3, '.').map(str::parse);
+    let mut next = || parts.next().unwrap_or(Ok(0)).ok();
+    Some((next()?, next()?, next()?))
+}
+
+#[cfg(test)]
+mod tests {
+    use rlua::Lua;
+
+    use super::*;
+
+    #[test]
+    fn read_only_table() {
+        Lua::new().context(|ctx| {
+            let table: Table = ctx
+                .load(r#"{ "a", "b", name = "x", nested = { 1, 2, 3, deeper = { y = true } } }"#)
+                .eval()
+                .unwrap();
+            ctx.globals()
+                .set("t", read_only(ctx, table).unwrap())
+                .unwrap();
+
+            for (lua, expected) in [
+                ("t.name = 'y'", "cannot set name, the table is read-only"),
+                ("t[1] = 'c'", "cannot set 1, the table is read-only"),
+                ("t.new = 1", "cannot set new"),
+                ("t.nested[4] = 4", "cannot set 4"),
+                ("t.nested.deeper.y = false", "cannot set y"),
+                ("t[true] = 1", "cannot set a field"),
+                ("setmetatable(t, nil)", "protected metatable"),
+            ] {
+                let error = ctx.load(lua).exec().unwrap_err().to_string();
+                assert!(error.contains(expected), "{}: {}", lua, error);
+            }
+
+            let seen: String = ctx
+                .load(
+                    r#"
+                    assert(t.name == "x" and t[2] == "b" and t.nested.deeper.y)
+                    assert(#t == 2 and #t.nested == 3)
+                    assert(getmetatable(t) == false)
+                    local keys = {}
+                    for key, value in pairs(t) do
+                        keys[#keys + 1] = tostring(key)
+                        if key == "nested" then
+                            assert(not pcall(function() value[1] = 0 end))
+                        end
+                    end
+                    for key in pairs(t.nested) do
+                        keys[#keys + 1] = "nested." .. tostring(key)
+                    end
+                    table.sort(keys)
+                    return table.concat(keys, " ")
+                    "#,
+                )
+                .eval()
+                .unwrap();
+            assert_eq!(
+                seen,
+                "1 2 name nested nested.1 nested.2 nested.3 nested.deeper"
+            );
+        });
+    }
+}
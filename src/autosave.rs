@@ -0,0 +1,93 @@
+use std::{
+    fs,
+    path::{Path, PathBuf},
+};
+
+use tracing::{info, warn};
+
+use crate::{
+    machine::Machine,
+    savestate::{Savestate, StateError},
+};
+
+// Writes a checkpoint every `interval` emulated frames into a rotating set
+// of files named autosave-NNNNNN.state, keeping only the newest `keep`.
+pub struct Autosave {
+    dir: PathBuf,
+    interval: u32,
+    keep: usize,
+    elapsed: u32,
+    next: u32,
+}
+
+impl Autosave {
+    pub fn new(dir: PathBuf, seconds: u32, keep: usize) -> Self {
+        // continue numbering after checkpoints of earlier runs
+        let next = checkpoints(&dir).last().map_or(0, |(n, _)| n + 1);
+        Self {
+            dir,
+            interval: seconds.max(1) * 60,
+            keep: keep.max(1),
+            elapsed: 0,
+            next,
+        }
+    }
+
+    // call once per emulated frame
+    pub fn tick(&mut self, machine: &Machine) {
+        self.elapsed += 1;
+        if self.elapsed < self.interval {
+            return;
+        }
+        self.elapsed = 0;
+
+        let path = self.dir.join(format!("autosave-{:06}.state", self.next));
+        self.next += 1;
+        if let Err(e) = Savestate::capture(machine).write(&path) {
+            warn!("could not write checkpoint {}: {}", path.display(), e);
+            return;
+        }
+        info!("wrote checkpoint {}", path.display());
+
+        // remove checkpoints beyond the retention count
+        let checkpoints = checkpoints(&self.dir);
+        let excess = checkpoints.len().saturating_sub(self.keep);
+        for (_, old) in &checkpoints[..excess] {
+            let _ = fs::remove_file(old);
+        }
+    }
+}
+
+// resolves the --resume argument to a savestate
+pub fn resume(dir: &Path, arg: &str) -> Result<Savestate, StateError> {
+    let path = match arg {
+        "latest" => match checkpoints(dir).pop() {
+            Some((_, path)) => path,
+            None => return Err(StateError::NoCheckpoint),
+        },
+        path => PathBuf::from(path),
+    };
+    Savestate::read(path)
+}
+
+// checkpoints on disk, oldest first
+fn checkpoints(dir: &Path) -> Vec<(u32, PathBuf)> {
+    let mut checkpoints: Vec<_> = fs::read_dir(dir)
+        .into_iter()
+        .flatten()
+        .flatten()
+        .filter_map(|entry| {
+            let path = entry.path();
+            let n = path
+                .file_name()?
+                .to_str()?
+                .strip_prefix("autosave-")?
+                .strip_suffix(".state")?
+                .parse()
+                .ok()?;
+            Some((n, path))
+        })
+        .collect();
+    checkpoints.sort();
+    checkpoints
+}
@@ -0,0 +1,228 @@
+use std::collections::HashMap;
+
+use winit::event::VirtualKeyCode;
+
+use crate::{
+    buttons::Button,
+    config::{Config, ConfigValue},
+};
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Hotkey {
+    Menu,
+    Help,
+    SaveState,
+    LoadState,
+    Break,
+    Fullscreen,
+    Screenshot,
+    Tiles,
+    Sprites,
+    Memory,
+    RecordMacro,
+    SlowMotion,
+    Pause,
+    FrameAdvance,
+    // toggles a button for the next frame while paused
+    Stage(Button),
+    SeekBack,
+    SeekForward,
+    Restart,
+    FrameTimes,
+    // moves a debug panel to a window of its own and back
+    Detach,
+    // loads the slot, or saves it with Shift
+    Slot(u8),
+}
+
+// config name and default key of every hotkey
+const HOTKEYS: &[(&str, Hotkey, VirtualKeyCode)] = &[
+    ("menu", Hotkey::Menu, VirtualKeyCode::Escape),
+    ("help", Hotkey::Help, VirtualKeyCode::F1),
+    ("save_state", Hotkey::SaveState, VirtualKeyCode::F5),
+    ("load_state", Hotkey::LoadState, VirtualKeyCode::F7),
+    ("break", Hotkey::Break, VirtualKeyCode::F9),
+    ("fullscreen", Hotkey::Fullscreen, VirtualKeyCode::F11),
+    ("screenshot", Hotkey::Screenshot, VirtualKeyCode::F12),
+    ("tiles", Hotkey::Tiles, VirtualKeyCode::F2),
+    ("sprites", Hotkey::Sprites, VirtualKeyCode::F3),
+    ("memory", Hotkey::Memory, VirtualKeyCode::F4),
+    ("record_macro", Hotkey::RecordMacro, VirtualKeyCode::F8),
+    ("slow_motion", Hotkey::SlowMotion, VirtualKeyCode::F6),
+    ("pause", Hotkey::Pause, VirtualKeyCode::Pause),
+    (
+        "frame_advance",
+        Hotkey::FrameAdvance,
+        VirtualKeyCode::Period,
+    ),
+    ("stage_a", Hotkey::Stage(Button::A), VirtualKeyCode::Z),
+    ("stage_b", Hotkey::Stage(Button::B), VirtualKeyCode::X),
+    (
+        "stage_select",
+        Hotkey::Stage(Button::Select),
+        VirtualKeyCode::RShift,
+    ),
+    (
+        "stage_start",
+        Hotkey::Stage(Button::Start),
+        VirtualKeyCode::Return,
+    ),
+    ("stage_up", Hotkey::Stage(Button::Up), VirtualKeyCode::Up),
+    (
+        "stage_down",
+        Hotkey::Stage(Button::Down),
+        VirtualKeyCode::Down,
+    ),
+    (
+        "stage_left",
+        Hotkey::Stage(Button::Left),
+        VirtualKeyCode::Left,
+    ),
+    (
+        "stage_right",
+        Hotkey::Stage(Button::Right),
+        VirtualKeyCode::Right,
+    ),
+    ("restart", Hotkey::Restart, VirtualKeyCode::F10),
+    ("frame_times", Hotkey::FrameTimes, VirtualKeyCode::Grave),
+    ("detach", Hotkey::Detach, VirtualKeyCode::Insert),
+    ("slot_1", Hotkey::Slot(1), VirtualKeyCode::Key1),
+    ("slot_2", Hotkey::Slot(2), VirtualKeyCode::Key2),
+    ("slot_3", Hotkey::Slot(3), VirtualKeyCode::Key3),
+    ("slot_4", Hotkey::Slot(4), VirtualKeyCode::Key4),
+    ("slot_5", Hotkey::Slot(5), VirtualKeyCode::Key5),
+    ("slot_6", Hotkey::Slot(6), VirtualKeyCode::Key6),
+    ("slot_7", Hotkey::Slot(7), VirtualKeyCode::Key7),
+    ("slot_8", Hotkey::Slot(8), VirtualKeyCode::Key8),
+    ("slot_9", Hotkey::Slot(9), VirtualKeyCode::Key9),
+    ("seek_back", Hotkey::SeekBack, VirtualKeyCode::PageUp),
+    (
+        "seek_forward",
+        Hotkey::SeekForward,
+        VirtualKeyCode::PageDown,
+    ),
+];
+
+macro_rules! keys {
+    ($($key:ident),* $(,)?) => {
+        &[$((stringify!($key), VirtualKeyCode::$key)),*]
+    };
+}
+
+// key names as winit spells them
+#[rustfmt::skip]
+const KEYS: &[(&str, VirtualKeyCode)] = keys![
+    A, B, C, D, E, F, G, H, I, J, K, L, M, N, O, P, Q, R, S, T, U, V, W, X, Y, Z, Key0, Key1, Key2,
+    Key3, Key4, Key5, Key6, Key7, Key8, Key9, F1, F2, F3, F4, F5, F6, F7, F8, F9, F10, F11, F12,
+    F13, F14, F15, F16, F17, F18, F19, F20, F21, F22, F23, F24, Escape, Space, Return, Tab, Back,
+    Insert, Delete, Home, End, PageUp, PageDown, Left, Right, Up, Down, Pause, Scroll, Snapshot,
+    Numpad0, Numpad1, Numpad2, Numpad3, Numpad4, Numpad5, Numpad6, Numpad7, Numpad8, Numpad9,
+    NumpadAdd, NumpadSubtract, NumpadMultiply, NumpadDivide, NumpadDecimal, NumpadEnter, Grave,
+    Minus, Equals, LBracket, RBracket, Backslash, Semicolon, Apostrophe, Comma, Period, Slash,
+    LShift, RShift, LControl, RControl, LAlt, RAlt,
+];
+
+pub fn key_name(key: VirtualKeyCode) -> &'static str {
+    KEYS.iter().find(|(_, k)| *k == key).map_or("?", |(n, _)| n)
+}
+
+pub fn parse_key(name: &str) -> Result<VirtualKeyCode, String> {
+    match KEYS.iter().find(|(n, _)| n.eq_ignore_ascii_case(name)) {
+        Some((_, key)) => Ok(*key),
+        None => {
+            let valid: Vec<_> = KEYS.iter().map(|(n, _)| *n).collect();
+            Err(format!(
+                "unknown key {}, expected one of {}",
+                name,
+                valid.join(", ")
+            ))
+        }
+    }
+}
+
+// Which key triggers which hotkey, from the [bindings] config section.
+#[derive(Clone)]
+pub struct Bindings {
+    keys: Vec<(Hotkey, VirtualKeyCode)>,
+    hide: bool,
+}
+
+impl Bindings {
+    pub fn new(config: &Config) -> Result<Self, String> {
+        let mut keys = Vec::new();
+        for &(name, hotkey, default) in HOTKEYS {
+            let key = match config.get(&format!("bindings.{}", name)) {
+                None => default,
+                Some(ConfigValue::String(key)) => {
+                    parse_key(key).map_err(|e| format!("bindings.{}: {}", name, e))?
+                }
+                Some(_) => return Err(format!("bindings.{} must be a key name", name)),
+            };
+            keys.push((hotkey, key));
+        }
+
+        for (key, _) in config.entries() {
+            if let Some(name) = key.strip_prefix("bindings.") {
+                if !HOTKEYS.iter().any(|(n, _, _)| *n == name) {
+                    let valid: Vec<_> = HOTKEYS.iter().map(|(n, _, _)| *n).collect();
+                    return Err(format!(
+                        "unknown binding {}, expected one of {}",
+                        name,
+                        valid.join(", ")
+                    ));
+                }
+            }
+        }
+
+        let mut seen = HashMap::new();
+        for &(hotkey, key) in &keys {
+            if let Some(other) = seen.insert(key, hotkey) {
+                return Err(format!(
+                    "{} is bound to both {} and {}",
+                    key_name(key),
+                    name(other),
+                    name(hotkey)
+                ));
+            }
+        }
+        let hide = config.boolean("input.hide_hotkeys")?.unwrap_or(false);
+        Ok(Self { keys, hide })
+    }
+
+    pub fn get(&self, key: VirtualKeyCode) -> Option<Hotkey> {
+        self.keys.iter().find(|(_, k)| *k == key).map(|(h, _)| *h)
+    }
+
+    // the config name of the hotkey on `key`
+    pub fn hotkey_name(&self, key: VirtualKeyCode) -> Option<&'static str> {
+        self.get(key).map(name)
+    }
+
+    // whether scripts should not see `key`, because a hotkey uses it and
+    // input.hide_hotkeys is set
+    pub fn hidden(&self, key: VirtualKeyCode) -> bool {
+        self.hide && self.get(key).is_some()
+    }
+
+    // the action, key and whether the key is the default or from the
+    // config of every hotkey, for the help overlay
+    pub fn table(&self) -> Vec<(&'static str, &'static str, &'static str)> {
+        let table = self.keys.iter().map(|&(hotkey, key)| {
+            let default = HOTKEYS.iter().find(|(_, h, _)| *h == hotkey).unwrap().2;
+            let source = if key == default { "built-in" } else { "config" };
+            (name(hotkey), key_name(key), source)
+        });
+        table.collect()
+    }
+
+    pub fn print(&self) {
+        println!("[bindings]");
+        for &(hotkey, key) in &self.keys {
+            println!("{} = \"{}\"", name(hotkey), key_name(key));
+        }
+    }
+}
+
+fn name(hotkey: Hotkey) -> &'static str {
+    HOTKEYS.iter().find(|(_, h, _)| *h == hotkey).unwrap().0
+}
@@ -0,0 +1,91 @@
+use fastnes::ppu::Color;
+
+// the most frames that can be averaged
+pub const MAX: usize = 3;
+
+const BLACK: Color = Color {
+    r: 0,
+    g: 0,
+    b: 0,
+    a: 255,
+};
+
+// Averages the last few frames to hide the 30Hz sprite flicker of many NES
+// games, for the window and screenshots; scripts analysing the frame still
+// see the raw one.
+//
+// The buffers are allocated when blending is first turned on and reused
+// for every frame after that.
+pub struct Blend {
+    n: usize,
+    history: Vec<[Color; 61440]>,
+    next: usize,
+    // how many frames in history belong to the current run of frames
+    filled: usize,
+    out: Vec<Color>,
+    revision: Option<u64>,
+}
+
+impl Blend {
+    pub fn new(n: usize) -> Self {
+        let mut blend = Self {
+            n: 0,
+            history: Vec::new(),
+            next: 0,
+            filled: 0,
+            out: Vec::new(),
+            revision: None,
+        };
+        blend.set(n);
+        blend
+    }
+
+    // 0 and 1 turn blending off
+    pub fn set(&mut self, n: usize) {
+        self.n = n.min(MAX);
+        self.filled = 0;
+        if self.enabled() && self.history.is_empty() {
+            self.history = vec![[BLACK; 61440]; MAX];
+            self.out = vec![BLACK; 61440];
+        }
+    }
+
+    pub fn enabled(&self) -> bool {
+        self.n > 1
+    }
+
+    // add the frame just emulated; `revision` is the machine's, so frames
+    // from before restoring a state are not blended with the new ones
+    pub fn push(&mut self, frame: [Color; 61440], revision: u64) {
+        if self.revision.map_or(true, |r| r + 1 != revision) {
+            self.filled = 0;
+        }
+        self.revision = Some(revision);
+
+        self.history[self.next] = frame;
+        self.next = (self.next + 1) % MAX;
+        self.filled = (self.filled + 1).min(self.n);
+
+        let n = self.filled as u32;
+        for (i, out) in self.out.iter_mut().enumerate() {
+            let (mut r, mut g, mut b) = (0, 0, 0);
+            for k in 1..=self.filled {
+                let c = self.history[(self.next + MAX - k) % MAX][i];
+                r += c.r as u32;
+                g += c.g as u32;
+                b += c.b as u32;
+            }
+            *out = Color {
+                r: (r / n) as u8,
+                g: (g / n) as u8,
+                b: (b / n) as u8,
+                a: 255,
+            };
+        }
+    }
+
+    // the blended frame, only meaningful while enabled
+    pub fn frame(&self) -> &[Color] {
+        &self.out
+    }
+}
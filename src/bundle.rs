@@ -0,0 +1,394 @@
+use std::{
+    fs,
+    io::{Read, Write},
+    path::{Component, Path, PathBuf},
+};
+
+use flate2::{read::DeflateDecoder, write::DeflateEncoder, Compression};
+use tracing::{info, warn};
+
+use crate::{
+    config::{Config, ConfigValue},
+    output, pinning,
+};
+
+const VERSION: i64 = 1;
+// the run directory's copy of what goes in the bundle besides the journal
+const SNAPSHOT: &str = "bundle";
+const MANIFEST: &str = "bundle.toml";
+const CONFIG: &str = "marlua.toml";
+const WARMUP: &str = "warmup.inputs";
+const JOURNAL: &str = "inputs.journal";
+// written at the end of runs with a journal
+pub const AUTOMATIC: &str = "run.marlua";
+
+// What a run needs to be reproduced, written to its run directory as it
+// starts: the scripts as they were then, the config, the seed of
+// math.random, the script arguments and the ROM's hash, never the ROM
+// itself. `marlua bundle` packs it with the input journal into one zip file,
+// automatically so at the end of runs that record one.
+pub struct Snapshot<'a> {
+    pub rom_hash: u32,
+    pub seed: i64,
+    pub scripts: Vec<&'a str>,
+    pub args: &'a [String],
+    pub config: Option<&'a str>,
+    pub warmup: &'a [u8],
+    // see pinning::script_hash, checked when the bundle is unpacked
+    pub script_hash: &'a str,
+}
+
+impl Snapshot<'_> {
+    pub fn write(&self, run_dir: &Path) -> Result<(), String> {
+        let dir = run_dir.join(SNAPSHOT);
+        let error = |e: std::io::Error| format!("{}: {}", dir.display(), e);
+        fs::create_dir_all(dir.join("scripts")).map_err(error)?;
+
+        let mut names = Vec::new();
+        for (i, script) in self.scripts.iter().enumerate() {
+            let file = Path::new(script).file_name().unwrap_or_default();
+            let name = format!("{}-{}", i + 1, file.to_string_lossy());
+            fs::copy(script, dir.join("scripts").join(&name)).map_err(error)?;
+            names.push(format!("{:?}", name));
+        }
+        if let Some(config) = self.config {
+            fs::copy(config, dir.join(CONFIG)).map_err(error)?;
+        }
+        fs::write(dir.join(WARMUP), self.warmup).map_err(error)?;
+
+        let args: Vec<_> = self.args.iter().map(|arg| format!("{:?}", arg)).collect();
+        let manifest = format!(
+            "version = {}\nrom_hash = \"{:08X}\"\nseed = {}\nscripts = [{}]\nargs = [{}]\n\
+             script_hash = \"sha256:{}\"\n",
+            VERSION,
+            self.rom_hash,
+            self.seed,
+            names.join(", "),
+            args.join(", "),
+            self.script_hash
+        );
+        fs::write(dir.join(MANIFEST), manifest).map_err(error)
+    }
+}
+
+// `marlua bundle RUN_DIR OUT`
+pub fn pack(run_dir: &Path, out: &Path) -> Result<(), String> {
+    let dir = run_dir.join(SNAPSHOT);
+    if !dir.join(MANIFEST).exists() {
+        return Err(format!(
+            "{} has no bundle snapshot, only runs of scripts can be bundled",
+            run_dir.display()
+        ));
+    }
+    let read = |path: &Path| fs::read(path).map_err(|e| format!("{}: {}", path.display(), e));
+    let mut entries = Vec::new();
+    for name in [MANIFEST, CONFIG, WARMUP] {
+        let path = dir.join(name);
+        if path.exists() {
+            entries.push((name.to_owned(), read(&path)?));
+        }
+    }
+    let scripts = fs::read_dir(dir.join("scripts")).map_err(|e| e.to_string())?;
+    for script in scripts {
+        let script = script.map_err(|e| e.to_string())?;
+        let name = format!("scripts/{}", script.file_name().to_string_lossy());
+        entries.push((name, read(&script.path())?));
+    }
+    let journal = run_dir.join(JOURNAL);
+    if journal.exists() {
+        entries.push((JOURNAL.to_owned(), read(&journal)?));
+    }
+    zip::write(out, &entries).map_err(|e| format!("{}: {}", out.display(), e))
+}
+
+// Packs the run directory into run.marlua when the driver is dropped, after
+// the journal it holds in the field before this one has been flushed.
+pub struct AutoBundle {
+    pub run_dir: PathBuf,
+}
+
+impl Drop for AutoBundle {
+    fn drop(&mut self) {
+        let out = self.run_dir.join(AUTOMATIC);
+        match pack(&self.run_dir, &out) {
+            Ok(()) => info!("bundled the run to {}", out.display()),
+            Err(e) => warn!("could not bundle the run: {}", e),
+        }
+    }
+}
+
+// a bundle unpacked to output/bundles/<name>/ for `--bundle`
+pub struct Unpacked {
+    pub scripts: Vec<String>,
+    pub args: Vec<String>,
+    pub seed: i64,
+    pub config: Option<String>,
+    pub journal: Option<String>,
+}
+
+pub fn unpack(bundle: &str, rom: &str, warmup: &[u8]) -> Result<Unpacked, String> {
+    let error = |msg: String| format!("{}: {}", bundle, msg);
+    let entries = zip::read(Path::new(bundle)).map_err(error)?;
+    let entry = |name: &str| {
+        entries
+            .iter()
+            .find(|(n, _)| n == name)
+            .map(|(_, data)| data.as_slice())
+    };
+
+    let manifest = entry(MANIFEST).ok_or_else(|| error("not a marlua bundle".to_owned()))?;
+    let manifest = Config::parse(&String::from_utf8_lossy(manifest)).map_err(error)?;
+    match manifest.get("version") {
+        Some(ConfigValue::Integer(VERSION)) => {}
+        _ => return Err(error("unknown bundle version".to_owned())),
+    }
+
+    // the same check as for journals, a bundle is useless with another ROM
+    let hash = manifest.string("rom_hash")?.unwrap_or_default();
+    let data = fs::read(rom).map_err(|e| format!("{}: {}", rom, e))?;
+    let actual = format!("{:08X}", crc32fast::hash(&data));
+    if hash != actual {
+        return Err(error(format!(
+            "recorded with ROM {}, not {} ({})",
+            hash, actual, rom
+        )));
+    }
+    if entry(WARMUP) != Some(warmup) {
+        return Err(error(
+            "recorded with another warm-up, it needs the same version of marlua".to_owned(),
+        ));
+    }
+
+    let name = Path::new(bundle).file_stem().unwrap_or_default();
+    let dir = Path::new(output::BASE)
+        .join("bundles")
+        .join(output::sanitize_name(&name.to_string_lossy()));
+    fs::create_dir_all(dir.join("scripts")).map_err(|e| e.to_string())?;
+    let write = |name: &str, data: &[u8]| -> Result<String, String> {
+        let path = dir.join(name);
+        fs::write(&path, data).map_err(|e| format!("{}: {}", path.display(), e))?;
+        Ok(path.to_string_lossy().into_owned())
+    };
+
+    let mut scripts = Vec::new();
+    let mut sources = Vec::new();
+    for script in manifest.strings("scripts")?.unwrap_or_default() {
+        let name = format!("scripts/{}", script_name(&script).map_err(error)?);
+        let data = entry(&name).ok_or_else(|| error(format!("{} is missing", name)))?;
+        scripts.push(write(&name, data)?);
+        sources.push(data);
+    }
+    if scripts.is_empty() {
+        return Err(error("no scripts".to_owned()));
+    }
+    // bundles from before script hashes have none to check
+    if let Some(expected) = manifest.string("script_hash")? {
+        let actual = format!("sha256:{}", pinning::script_hash(sources));
+        if expected != actual {
+            return Err(error(format!(
+                "its scripts were changed after it was recorded: expected {}, got {}",
+                expected, actual
+            )));
+        }
+    }
+    let seed = match manifest.get("seed") {
+        Some(ConfigValue::Integer(seed)) => *seed,
+        _ => return Err(error("no seed".to_owned())),
+    };
+    let config = entry(CONFIG).map(|data| write(CONFIG, data)).transpose()?;
+    let journal = entry(JOURNAL)
+        .map(|data| write(JOURNAL, data))
+        .transpose()?;
+    info!("unpacked {} to {}", bundle, dir.display());
+    Ok(Unpacked {
+        scripts,
+        args: manifest.strings("args")?.unwrap_or_default(),
+        seed,
+        config,
+        journal,
+    })
+}
+
+// a script's name from the manifest, which may only name a file directly in
+// scripts/ so that unpacking never writes outside the bundle's directory
+fn script_name(script: &str) -> Result<&str, String> {
+    let mut components = Path::new(script).components();
+    match (components.next(), components.next()) {
+        (Some(Component::Normal(_)), None) => Ok(script),
+        _ => Err(format!("script name {} must be a plain file name", script)),
+    }
+}
+
+// Just enough of the zip format for bundles: deflated files without
+// directories, comments or zip64.
+mod zip {
+    use super::*;
+
+    const LOCAL: u32 = 0x04034b50;
+    const CENTRAL: u32 = 0x02014b50;
+    const END: u32 = 0x06054b50;
+    const DEFLATE: u16 = 8;
+    const STORED: u16 = 0;
+    // names are UTF-8
+    const FLAGS: u16 = 0x0800;
+    // 1980-01-01, the earliest a zip can say
+    const DATE: u16 = 0x21;
+
+    // to a temporary file first, so a crash never leaves half a bundle
+    pub fn write(path: &Path, entries: &[(String, Vec<u8>)]) -> std::io::Result<()> {
+        let mut out = Vec::new();
+        let mut central = Vec::new();
+        for (name, data) in entries {
+            let mut encoder = DeflateEncoder::new(Vec::new(), Compression::default());
+            encoder.write_all(data)?;
+            let compressed = encoder.finish()?;
+            let crc = crc32fast::hash(data);
+            let offset = out.len() as u32;
+
+            let header = |sizes: &mut Vec<u8>| {
+                for value in [FLAGS, DEFLATE, 0, DATE] {
+                    sizes.extend_from_slice(&value.to_le_bytes());
+                }
+                sizes.extend_from_slice(&crc.to_le_bytes());
+                sizes.extend_from_slice(&(compressed.len() as u32).to_le_bytes());
+                sizes.extend_from_slice(&(data.len() as u32).to_le_bytes());
+                sizes.extend_from_slice(&(name.len() as u16).to_le_bytes());
+                sizes.extend_from_slice(&0u16.to_le_bytes());
+            };
+            out.extend_from_slice(&LOCAL.to_le_bytes());
+            out.extend_from_slice(&20u16.to_le_bytes());
+            header(&mut out);
+            out.extend_from_slice(name.as_bytes());
+            out.extend_from_slice(&compressed);
+
+            central.extend_from_slice(&CENTRAL.to_le_bytes());
+            central.extend_from_slice(&20u16.to_le_bytes());
+            central.extend_from_slice(&20u16.to_le_bytes());
+            header(&mut central);
+            // comment length, disk, internal and external attributes
+            central.extend_from_slice(&[0; 10]);
+            central.extend_from_slice(&offset.to_le_bytes());
+            central.extend_from_slice(name.as_bytes());
+        }
+        let offset = out.len() as u32;
+        out.extend_from_slice(&central);
+        out.extend_from_slice(&END.to_le_bytes());
+        out.extend_from_slice(&[0; 4]);
+        out.extend_from_slice(&(entries.len() as u16).to_le_bytes());
+        out.extend_from_slice(&(entries.len() as u16).to_le_bytes());
+        out.extend_from_slice(&(central.len() as u32).to_le_bytes());
+        out.extend_from_slice(&offset.to_le_bytes());
+        out.extend_from_slice(&0u16.to_le_bytes());
+
+        let partial = path.with_extension("partial");
+        fs::write(&partial, out)?;
+        fs::rename(partial, path)
+    }
+
+    pub fn read(path: &Path) -> Result<Vec<(String, Vec<u8>)>, String> {
+        let bytes = fs::read(path).map_err(|e| e.to_string())?;
+        let u16_at = |i: usize| {
+            bytes
+                .get(i..i + 2)
+                .map(|b| u16::from_le_bytes([b[0], b[1]]))
+        };
+        let u32_at = |i: usize| {
+            bytes
+                .get(i..i + 4)
+                .map(|b| u32::from_le_bytes([b[0], b[1], b[2], b[3]]))
+        };
+        let corrupt = || "not a zip file or a corrupt one".to_owned();
+
+        let end = (0..bytes.len().saturating_sub(21))
+            .rev()
+            .find(|&i| u32_at(i) == Some(END))
+            .ok_or_else(corrupt)?;
+        let count = u16_at(end + 10).ok_or_else(corrupt)? as usize;
+        let mut at = u32_at(end + 16).ok_or_else(corrupt)? as usize;
+
+        let mut entries = Vec::new();
+        for _ in 0..count {
+            if u32_at(at) != Some(CENTRAL) {
+                return Err(corrupt());
+            }
+            let field = |offset: usize| u16_at(at + offset).ok_or_else(corrupt);
+            let method = field(10)?;
+            let crc = u32_at(at + 16).ok_or_else(corrupt)?;
+            let compressed = u32_at(at + 20).ok_or_else(corrupt)? as usize;
+            let size = u32_at(at + 24).ok_or_else(corrupt)? as usize;
+            let (name_len, extra_len, comment_len) = (field(28)?, field(30)?, field(32)?);
+            let local = u32_at(at + 42).ok_or_else(corrupt)? as usize;
+            let name = bytes
+                .get(at + 46..at + 46 + name_len as usize)
+                .ok_or_else(corrupt)?;
+            let name = String::from_utf8_lossy(name).into_owned();
+            at += 46 + (name_len + extra_len + comment_len) as usize;
+
+            // the local header has its own name and extra field lengths
+            if u32_at(local) != Some(LOCAL) {
+                return Err(corrupt());
+            }
+            let skip = u16_at(local + 26).ok_or_else(corrupt)? as usize
+                + u16_at(local + 28).ok_or_else(corrupt)? as usize;
+            let start = local + 30 + skip;
+            let raw = bytes.get(start..start + compressed).ok_or_else(corrupt)?;
+            let data = match method {
+                STORED => raw.to_vec(),
+                DEFLATE => {
+                    // the header is not trusted with an allocation, and one
+                    // byte more than it says is enough to tell it is wrong
+                    let mut data = Vec::new();
+                    DeflateDecoder::new(raw)
+                        .take(size as u64 + 1)
+                        .read_to_end(&mut data)
+                        .map_err(|e| format!("{}: {}", name, e))?;
+                    data
+                }
+                other => return Err(format!("{}: unsupported compression {}", name, other)),
+            };
+            if data.len() != size || crc32fast::hash(&data) != crc {
+                return Err(format!("{} is corrupt", name));
+            }
+            entries.push((name, data));
+        }
+        Ok(entries)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing;
+
+    #[test]
+    fn script_names() {
+        assert_eq!(script_name("main.lua"), Ok("main.lua"));
+        for name in [
+            "",
+            ".",
+            "..",
+            "../main.lua",
+            "lib/main.lua",
+            "/main.lua",
+            "./main.lua",
+        ] {
+            assert!(script_name(name).is_err(), "{}", name);
+        }
+    }
+
+    #[test]
+    fn zip_sizes() {
+        let path = testing::temp_dir("bundle").join("sizes.zip");
+        let entries = vec![("a".to_owned(), vec![7; 1000])];
+        zip::write(&path, &entries).unwrap();
+        assert_eq!(zip::read(&path).unwrap(), entries);
+
+        // the central directory claims fewer bytes than the entry inflates to
+        let mut bytes = fs::read(&path).unwrap();
+        let end = bytes.len() - 22;
+        let central = u32::from_le_bytes(bytes[end + 16..end + 20].try_into().unwrap()) as usize;
+        bytes[central + 24..central + 28].copy_from_slice(&10u32.to_le_bytes());
+        fs::write(&path, bytes).unwrap();
+        assert_eq!(zip::read(&path).unwrap_err(), "a is corrupt");
+    }
+}
@@ -0,0 +1,380 @@
+use std::collections::BTreeMap;
+
+use rlua::{prelude::LuaError, MultiValue, Value};
+
+// The controller's buttons, in the order of their bits in the byte the machine
+// reads each frame.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Button {
+    A,
+    B,
+    Select,
+    Start,
+    Up,
+    Down,
+    Left,
+    Right,
+}
+
+const ALL: [Button; 8] = [
+    Button::A,
+    Button::B,
+    Button::Select,
+    Button::Start,
+    Button::Up,
+    Button::Down,
+    Button::Left,
+    Button::Right,
+];
+
+// Every spelling scripts can use for a button, compared without case. This
+// is the only list, everything that takes button names goes through parse;
+// games and scripts can add their own names on top, see Aliases.
+const ALIASES: &[(&str, Button)] = &[
+    ("a", Button::A),
+    ("jump", Button::A),
+    ("b", Button::B),
+    ("run", Button::B),
+    ("select", Button::Select),
+    ("start", Button::Start),
+    ("u", Button::Up),
+    ("up", Button::Up),
+    ("d", Button::Down),
+    ("down", Button::Down),
+    ("l", Button::Left),
+    ("left", Button::Left),
+    ("r", Button::Right),
+    ("right", Button::Right),
+];
+
+impl Button {
+    pub fn bit(self) -> u8 {
+        1 << self as u8
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Button::A => "A",
+            Button::B => "B",
+            Button::Select => "Select",
+            Button::Start => "Start",
+            Button::Up => "Up",
+            Button::Down => "Down",
+            Button::Left => "Left",
+            Button::Right => "Right",
+        }
+    }
+
+    // the direction a controller cannot press at the same time
+    pub fn opposite(self) -> Option<Button> {
+        match self {
+            Button::Up => Some(Button::Down),
+            Button::Down => Some(Button::Up),
+            Button::Left => Some(Button::Right),
+            Button::Right => Some(Button::Left),
+            _ => None,
+        }
+    }
+}
+
+// Names for buttons in a game's terms, such as shoot for B, from the
+// [buttons] config section, map_buttons in game modules and in scripts.
+#[derive(Clone, Default)]
+pub struct Aliases {
+    // lowercase name -> what it was defined as and the buttons that means
+    custom: BTreeMap<String, (String, Vec<Button>)>,
+}
+
+impl Aliases {
+    // `buttons` uses the built-in names, e.g. "B" or "A+B"; defining a name
+    // again replaces it
+    pub fn define(&mut self, name: &str, buttons: &str) -> Result<(), String> {
+        let name = name.trim();
+        if name.is_empty() || name.contains('+') {
+            return Err(format!("invalid button alias {:?}", name));
+        }
+        if ALIASES
+            .iter()
+            .any(|(alias, _)| alias.eq_ignore_ascii_case(name))
+        {
+            return Err(format!("{} is already a built-in button name", name));
+        }
+        let parsed = parse(buttons, &Aliases::default())
+            .map_err(|e| format!("button alias {}: {}", name, e))?;
+        self.custom
+            .insert(name.to_lowercase(), (buttons.to_owned(), parsed));
+        Ok(())
+    }
+
+    // every alias and what it stands for, sorted by name
+    pub fn list(&self) -> impl Iterator<Item = (&str, &str)> {
+        self.custom
+            .iter()
+            .map(|(name, (buttons, _))| (name.as_str(), buttons.as_str()))
+    }
+}
+
+// one name or several joined with '+', e.g. "A+RIGHT"
+pub fn parse(names: &str, aliases: &Aliases) -> Result<Vec<Button>, String> {
+    let mut buttons = Vec::new();
+    for name in names.split('+').map(str::trim) {
+        if let Some((_, button)) = ALIASES.iter().find(|(a, _)| a.eq_ignore_ascii_case(name)) {
+            buttons.push(*button);
+        } else if let Some((_, custom)) = aliases.custom.get(&name.to_lowercase()) {
+            buttons.extend(custom);
+        } else {
+            let valid: Vec<_> = ALIASES
+                .iter()
+                .map(|(alias, _)| *alias)
+                .chain(aliases.custom.keys().map(String::as_str))
+                .collect();
+            return Err(format!(
+                "unknown button {:?}, expected one of {}",
+                name,
+                valid.join(", ")
+            ));
+        }
+    }
+    Ok(buttons)
+}
+
+// button names given to toggle, press, release and set_input: names, or one
+// table with names in its array part and `name = true` entries
+pub fn parse_args(values: MultiValue, aliases: &Aliases) -> Result<Vec<Button>, LuaError> {
+    let mut buttons = Vec::new();
+    for (button, pressed, at) in args(values, aliases)? {
+        if !pressed {
+            return Err(LuaError::RuntimeError(format!(
+                "{}: only set_buttons releases buttons set to false",
+                at
+            )));
+        }
+        buttons.push(button);
+    }
+    Ok(buttons)
+}
+
+// every button with whether it is pressed and where it was given, for errors;
+// a button may only be given once in a table, as pairs has no order that
+// could decide between two entries for it
+pub fn args(args: MultiValue, aliases: &Aliases) -> Result<Vec<(Button, bool, String)>, LuaError> {
+    let named = |name: &str, at: &str| {
+        parse(name, aliases).map_err(|e| LuaError::RuntimeError(format!("{}: {}", at, e)))
+    };
+    let mut buttons = Vec::new();
+    let args = args.into_vec();
+    if let [Value::Table(table)] = args.as_slice() {
+        for pair in table.clone().pairs::<Value, Value>() {
+            let (key, value) = pair?;
+            match (key, value) {
+                (Value::Integer(i), Value::String(name)) => {
+                    let at = format!("buttons[{}]", i);
+                    for button in named(name.to_str()?, &at)? {
+                        buttons.push((button, true, at.clone()));
+                    }
+                }
+                (Value::Integer(i), _) => {
+                    return Err(LuaError::RuntimeError(format!(
+                        "buttons[{}]: expected a button name",
+                        i
+                    )))
+                }
+                (Value::String(name), value) => {
+                    let at = format!("buttons.{}", name.to_str()?);
+                    let Value::Boolean(pressed) = value else {
+                        return Err(LuaError::RuntimeError(format!(
+                            "{}: expected true or false",
+                            at
+                        )));
+                    };
+                    for button in named(name.to_str()?, &at)? {
+                        buttons.push((button, pressed, at.clone()));
+                    }
+                }
+                _ => {
+                    return Err(LuaError::RuntimeError(
+                        "buttons: keys must be indices or button names".to_owned(),
+                    ))
+                }
+            }
+        }
+        for (i, (button, _, at)) in buttons.iter().enumerate() {
+            if let Some((_, _, first)) = buttons[..i].iter().find(|(b, _, _)| b == button) {
+                return Err(LuaError::RuntimeError(format!(
+                    "{}: {} is already given by {}",
+                    at,
+                    button.name(),
+                    first
+                )));
+            }
+        }
+        return Ok(buttons);
+    }
+
+    for (i, arg) in args.into_iter().enumerate() {
+        let at = format!("argument {}", i + 1);
+        let Value::String(name) = arg else {
+            return Err(LuaError::RuntimeError(format!(
+                "{}: expected a button name, or a single table of them",
+                at
+            )));
+        };
+        for button in named(name.to_str()?, &at)? {
+            buttons.push((button, true, at.clone()));
+        }
+    }
+    Ok(buttons)
+}
+
+// the buttons pressed in a controller byte
+pub fn pressed(input: u8) -> impl Iterator<Item = Button> {
+    ALL.into_iter()
+        .filter(move |button| input & button.bit() != 0)
+}
+
+#[cfg(test)]
+mod tests {
+    use rlua::Lua;
+
+    use super::*;
+
+    // "jump" -> "JuMp"
+    fn mixed_case(name: &str) -> String {
+        name.chars()
+            .enumerate()
+            .map(|(i, c)| match i % 2 {
+                0 => c.to_ascii_uppercase(),
+                _ => c.to_ascii_lowercase(),
+            })
+            .collect()
+    }
+
+    #[test]
+    fn every_alias() {
+        let aliases = Aliases::default();
+        for &(alias, button) in ALIASES {
+            for name in [alias.to_owned(), alias.to_uppercase(), mixed_case(alias)] {
+                assert_eq!(parse(&name, &aliases), Ok(vec![button]), "{}", name);
+            }
+        }
+    }
+
+    #[test]
+    fn every_button_has_its_name() {
+        for button in ALL {
+            assert_eq!(parse(button.name(), &Aliases::default()), Ok(vec![button]));
+        }
+    }
+
+    #[test]
+    fn combinations() {
+        let aliases = Aliases::default();
+        assert_eq!(
+            parse("A+RIGHT", &aliases),
+            Ok(vec![Button::A, Button::Right])
+        );
+        assert_eq!(parse(" a + b ", &aliases), Ok(vec![Button::A, Button::B]));
+        assert_eq!(
+            parse("jump+Run+l", &aliases),
+            Ok(vec![Button::A, Button::B, Button::Left])
+        );
+    }
+
+    #[test]
+    fn unknown() {
+        let mut aliases = Aliases::default();
+        aliases.define("shoot", "B").unwrap();
+        aliases.define("spin", "A+Right").unwrap();
+
+        let error = parse("A+fire", &aliases).unwrap_err();
+        assert!(error.starts_with("unknown button \"fire\""), "{}", error);
+        for (alias, _) in ALIASES {
+            assert!(error.contains(alias), "{} missing from {}", alias, error);
+        }
+        assert!(
+            error.contains("shoot") && error.contains("spin"),
+            "{}",
+            error
+        );
+
+        assert!(parse("", &aliases).is_err());
+        assert!(parse("A+", &aliases).is_err());
+    }
+
+    #[test]
+    fn custom() {
+        let mut aliases = Aliases::default();
+        aliases.define("Spin", "A+Right").unwrap();
+        assert_eq!(
+            parse("SPIN+b", &aliases),
+            Ok(vec![Button::A, Button::Right, Button::B])
+        );
+        assert!(aliases.define("Jump", "B").is_err());
+        assert!(aliases.define("a+b", "B").is_err());
+        assert!(aliases.define("fire", "shoot").is_err());
+    }
+
+    // the arguments in `lua`, a Lua expression list, as press() gets them
+    fn lua_args(lua: &str) -> Result<Vec<(Button, bool, String)>, String> {
+        Lua::new().context(|ctx| {
+            let values = ctx.load(lua).eval::<MultiValue>().unwrap();
+            args(values, &Aliases::default()).map_err(|e| e.to_string())
+        })
+    }
+
+    #[test]
+    fn lua_arguments() {
+        assert_eq!(
+            lua_args(r#""A", "right""#),
+            Ok(vec![
+                (Button::A, true, "argument 1".to_owned()),
+                (Button::Right, true, "argument 2".to_owned()),
+            ])
+        );
+
+        let mut table = lua_args(r#"{ "A", B = true, Start = false }"#).unwrap();
+        table.sort_by_key(|(button, _, _)| button.bit());
+        assert_eq!(
+            table,
+            [
+                (Button::A, true, "buttons[1]".to_owned()),
+                (Button::B, true, "buttons.B".to_owned()),
+                (Button::Start, false, "buttons.Start".to_owned()),
+            ]
+        );
+
+        for (lua, expected) in [
+            ("{ {} }", "buttons[1]: expected a button name"),
+            (r#"{ { "A" } }"#, "buttons[1]: expected a button name"),
+            ("{ A = 1 }", "buttons.A: expected true or false"),
+            (r#"{ [1.5] = "A" }"#, "keys must be indices or button names"),
+            ("{ A = true, a = false }", "A is already given by buttons."),
+            (r#"{ "A+B", B = false }"#, "B is already given by"),
+            (r#"{ "jump", "A" }"#, "A is already given by buttons["),
+            ("false", "argument 1: expected a button name"),
+            (r#""A", 1"#, "argument 2: expected a button name"),
+        ] {
+            let error = lua_args(lua).unwrap_err();
+            assert!(error.contains(expected), "{}: {}", lua, error);
+        }
+    }
+
+    #[test]
+    fn only_set_buttons_releases() {
+        let press = |lua: &str| {
+            Lua::new().context(|ctx| {
+                let values = ctx.load(lua).eval::<MultiValue>().unwrap();
+                parse_args(values, &Aliases::default()).map_err(|e| e.to_string())
+            })
+        };
+        assert_eq!(press(r#"{ "B", Up = true }"#).map(|b| b.len()), Ok(2));
+        let error = press("{ A = false }").unwrap_err();
+        assert!(
+            error.contains("buttons.A: only set_buttons releases buttons set to false"),
+            "{}",
+            error
+        );
+        let error = press("false").unwrap_err();
+        assert!(error.contains("argument 1"), "{}", error);
+    }
+}
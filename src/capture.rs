@@ -0,0 +1,126 @@
+use rlua::prelude::LuaError;
+
+use crate::{layers::Layers, machine::Machine, picture::Picture};
+
+pub const WIDTH: usize = 256;
+pub const HEIGHT: usize = 240;
+
+// A rectangle inside the NES frame, checked against its bounds.
+#[derive(Clone, Copy)]
+pub struct Region {
+    pub x: usize,
+    pub y: usize,
+    pub w: usize,
+    pub h: usize,
+}
+
+impl Region {
+    pub fn new(x: usize, y: usize, w: usize, h: usize) -> Result<Self, LuaError> {
+        if x + w > WIDTH || y + h > HEIGHT {
+            return Err(LuaError::RuntimeError(format!(
+                "region {}x{} at ({}, {}) exceeds the {}x{} frame",
+                w, h, x, y, WIDTH, HEIGHT
+            )));
+        }
+        Ok(Self { x, y, w, h })
+    }
+
+    pub fn rows(self) -> impl Iterator<Item = std::ops::Range<usize>> {
+        (self.y..self.y + self.h).map(move |y| {
+            let start = (y * WIDTH + self.x) * 4;
+            start..start + self.w * 4
+        })
+    }
+}
+
+// Frame pixels as RGBA bytes for scripts, drawn on demand at most once
+// per emulated frame and set of layers. All buffers are allocated once and
+// reused.
+pub struct Capture {
+    rgba: Vec<u8>,
+    region: Vec<u8>,
+    revision: Option<(u64, Layers)>,
+}
+
+impl Capture {
+    pub fn new() -> Self {
+        Self {
+            rgba: vec![0; WIDTH * HEIGHT * 4],
+            region: Vec::with_capacity(WIDTH * HEIGHT * 4),
+            revision: None,
+        }
+    }
+
+    // the whole frame, 256x240 pixels of 4 bytes each in RGBA order,
+    // row by row starting at the top left
+    pub fn frame(&mut self, machine: &mut Machine) -> &[u8] {
+        self.layers(machine, Layers::All)
+    }
+
+    // the frame with only some layers drawn, independent of the window's
+    pub fn layers(&mut self, machine: &mut Machine, layers: Layers) -> &[u8] {
+        let key = (machine.revision(), layers);
+        if self.revision != Some(key) {
+            let pixels = machine.nes_mut().draw_frame(layers.draw_options());
+            for (dst, c) in self.rgba.chunks_exact_mut(4).zip(pixels.iter()) {
+                dst.copy_from_slice(&[c.r, c.g, c.b, c.a]);
+            }
+            self.revision = Some(key);
+        }
+        &self.rgba
+    }
+
+    pub fn region(&mut self, machine: &mut Machine, region: Region) -> &[u8] {
+        self.frame(machine);
+        self.region.clear();
+        for row in region.rows() {
+            self.region.extend_from_slice(&self.rgba[row]);
+        }
+        &self.region
+    }
+
+    // FNV-1a over the RGBA bytes of a region
+    pub fn hash(&mut self, machine: &mut Machine, region: Region) -> u64 {
+        self.frame(machine);
+        let mut hash: u64 = 0xcbf29ce484222325;
+        for row in region.rows() {
+            for &byte in &self.rgba[row] {
+                hash ^= byte as u64;
+                hash = hash.wrapping_mul(0x100000001b3);
+            }
+        }
+        hash
+    }
+
+    // fraction of pixels of `picture`, placed at (x, y), whose color channels
+    // all lie within `tolerance` of the frame; alpha is ignored
+    pub fn compare(
+        &mut self,
+        machine: &mut Machine,
+        picture: &Picture,
+        x: usize,
+        y: usize,
+        tolerance: u8,
+    ) -> Result<f64, LuaError> {
+        let region = Region::new(x, y, picture.width, picture.height)?;
+        let total = picture.width * picture.height;
+        if total == 0 {
+            return Ok(1.0);
+        }
+        self.frame(machine);
+
+        let mut matching = 0;
+        for (row, expected) in region
+            .rows()
+            .zip(picture.rgba.chunks_exact(picture.width * 4))
+        {
+            for (a, b) in self.rgba[row].chunks_exact(4).zip(expected.chunks_exact(4)) {
+                if (0..3).all(|c| a[c].abs_diff(b[c]) <= tolerance) {
+                    matching += 1;
+                }
+            }
+        }
+
+        Ok(matching as f64 / total as f64)
+    }
+}
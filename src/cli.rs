@@ -0,0 +1,921 @@
+use std::{env, path::Path, process, time::Duration};
+
+use crate::{
+    bindings::Bindings,
+    blend, bundle,
+    buttons::Aliases,
+    config::{self, Config, ConfigValue},
+    hashes,
+    layout::{Layout, Preset},
+    letterbox::CaptureLayout,
+    panels::Panel,
+    pinning,
+    placement::Placement,
+    priority::Priority,
+    ramdump::{self, Entry},
+};
+
+pub const USAGE: &str = "\
+usage: marlua [run] [ROM [SCRIPT...]] [options] [-- ARGS...]
+       marlua test ROM SCRIPT... [--bless] [options] [-- ARGS...]
+       marlua play ROM JOURNAL [options]
+       marlua diff STATE STATE
+       marlua bundle RUN_DIR OUT
+       marlua smoke DIR [--frames N] [--threads N] [--json PATH]
+       marlua verify ROM JOURNAL [--hashes PATH] [--output DIR]
+       marlua ram-dump FILE [--csv]
+       marlua run|play --bundle BUNDLE --rom ROM [options]
+
+commands:
+  run                    play SCRIPT on ROM in a window (default)
+  test                   run SCRIPT headless as fast as possible, checking its
+                         expect_frame and expect_memory calls; exits with 1
+                         if any of them failed
+  play                   replay an input journal in the window, e.g. to see
+                         how a crashed session went; a bar at the top shows
+                         how far it is, Left and Right seek a second back
+                         and forth, Home starts over and Space pauses.
+                         Afterwards the game keeps running without input
+  diff                   print the CPU RAM bytes that differ between two
+                         savestates, zero page addresses marked
+  bundle                 pack a run directory (e.g. output/smb/latest) into
+                         one file to share: its scripts as they were when it
+                         started, the config, the seed, the script arguments,
+                         the input journal and the ROM's hash, not the ROM.
+                         Runs with a journal that end with their script or
+                         quit() write one as run.marlua themselves
+  smoke                  run every .nes file in DIR without a window or
+                         input for --frames frames (default 600) on
+                         --threads threads (default one per core) and list
+                         whether each loaded, drew anything but a single
+                         color, its last frame's hash and any panic; --json
+                         also writes that to PATH. Exits with 1 if any ROM
+                         failed
+  verify                 replay JOURNAL without a window and compare every
+                         frame with the hashes recorded with
+                         --record-hashes, by default JOURNAL's .hashes file;
+                         at the first frame that differs, write it and the
+                         one before as PNGs, print the RAM that changed
+                         between them and the inputs around it, and exit
+                         with 1
+  ram-dump               describe a recording of --record-ram or
+                         record_ram(): the ROM, the frames and the
+                         addresses; with --csv print all of it as CSV
+                         instead, a column per address ($0086:2 for a
+                         2-byte value) and a line per frame
+
+Without a ROM, run opens a picker of recently used ROM and script pairs
+with an entry for browsing. SCRIPT defaults to script/mock.lua.
+
+Several scripts run one after the other in the same Lua state, so earlier
+ones can define functions and checkpoints for later ones. An error in any of
+them stops the rest and names the script; the test report counts each
+script's expectations separately. The restart hotkey runs all of them again.
+
+Everything after -- is passed to the script in the global `args` table:
+key=value pairs become fields, other words array entries, and values that
+are digits with an optional sign, point and exponent are converted to
+numbers; nan, inf and the like stay strings. Without arguments `args` is an
+empty table.
+
+  marlua run smb.nes practice.lua -- level=4-2 attempts=50 verbose
+
+Files are written to output/<rom-name>/: savestates and checkpoints to its
+states/ directory, everything else to a new output/<rom-name>/<timestamp>/
+directory per run, linked as output/<rom-name>/latest. Each run records
+every input to inputs.journal in its directory for `marlua play`, along with
+the markers set with marker(name); markers() lists them, and they are shown
+in the summary at the end of the run and when playing the journal.
+events.ndjson next to it gets a line for every input change, marker,
+printed or logged line and loaded state as it happens, with its frame, e.g.
+{\"frame\":120,\"kind\":\"marker\",\"name\":\"boss\"}; events(since_frame) returns
+the same to the script while it runs.
+
+Ctrl-C or SIGTERM stops tests and headless runs after the current frame,
+writing the journal and the summary like a limit does, and exits with 130;
+a second one exits at once. With a window they are like closing it.
+
+Scripts always have clock() (wall seconds since start), emu_time() (emulated
+seconds) and date([fmt]) (local time, strftime-style) in place of the os
+library, which is not available unless allowed with --lua-allow.
+
+Scripts that read or write files at paths of their choosing (savestate
+files, match_image, expect_frame, record_ram), take screenshots or use
+libraries from --lua-allow have to declare it at the top:
+
+  -- marlua: permissions = files, screenshots, stdlib
+
+Undeclared functions raise an error naming the permission they need. The
+groups are files, screenshots, network and stdlib.
+
+When the ROM is listed in games/manifest.toml, the matching module from
+games/ is loaded into the global `game` before the script runs; otherwise
+`game` is nil.
+
+Options can also be set in marlua.toml (or the file given with --config);
+command line options take precedence. Supported keys:
+
+  [lua]
+  allow = [\"io\", \"os\"]       same as --lua-allow
+  scripts = [\"a.lua\", \"b.lua\"]
+                               the scripts to run when only a ROM is given
+  strict_inputs = true         a press released again before any frame saw
+                               it (a missing wait between press and
+                               release) is a script error rather than a
+                               warning (default false)
+  max_images = 64              how many images from capture() a script can
+                               keep at a time (default 64)
+
+  [window]
+  pause_on_focus_loss = true   pause while the window is unfocused or
+                               minimized (default false); scripts can
+                               call allow_background(true) to keep running
+  monitor = 1                  the monitor to open the window on, by index
+                               from 0 or by name
+  position = [100, 50]         where on it, from its top left corner
+  fullscreen = true            open in fullscreen (default false)
+  remember_window = true       save the monitor, position and fullscreen to
+                               output/window.toml on exit and open there
+                               next time; a monitor that is gone is
+                               replaced by the current one
+  idle_fps = 1                 once the script is done and the window has
+                               been unfocused for a few seconds, emulate
+                               at this rate until it is focused again, 0
+                               to pause; not while recording a macro,
+                               dumping inputs, recording RAM or hashes,
+                               or with on_idle (default full speed)
+  title_format = \"{rom} {fps_emu}\"
+                               the window title, updated once a second;
+                               placeholders are rom, script, frame, fps_emu,
+                               speed, status (why it is paused), vsync,
+                               missed (frames that took longer than their
+                               time slot), dropped (presses no frame saw,
+                               see lua.strict_inputs) and latency (the
+                               median time from the emulator drawing a
+                               frame to the window presenting it)
+  detach = [\"memory\"]          debug panels that open in a window of their
+                               own: tiles, sprites, memory and frame_times;
+                               the detach hotkey (Insert) moves the one in
+                               the game window there and back
+
+  [overlay]
+  preset = \"stream\"            see --overlay-preset
+  [overlay.toasts]             also overlay.slot, overlay.subtitle (while
+                               playing a journal with subtitles),
+                               overlay.progress (the playback bar) and
+                               overlay.frame_times (the frame_times panel,
+                               which only takes enabled, anchor and offset)
+  enabled = false              don't show it (default true)
+  anchor = \"top_right\"         top_left, top, top_right, bottom_left,
+                               bottom or bottom_right; overlays at the same
+                               corner or in the middle of the same edge
+                               stack in the order progress, subtitle, slot,
+                               toasts, frame_times, and the ones in a corner
+                               stack beyond those in the middle and the
+                               playback bar
+  offset = [4, 6]              pixels from the anchor's edges
+  scale = 1.5                  the size of the text and boxes (default 1)
+  color = \"#ffffff\"            the text, or the bar of the playback bar
+  background = \"#000000b4\"     the boxes, or the playback bar's track
+  The [overlay] sections are read again when the config file changes while
+  the window is open; the rest of it needs a restart.
+
+  [emulator]
+  priority = \"high\"            normal (default), high or realtime for the
+                               thread that runs the game and scripts;
+                               realtime usually needs root
+  core = 2                     keep that thread on one CPU core (Linux)
+
+  [states]
+  persist_slots = true         also write the slots of the number key
+                               hotkeys (Shift+1 to 9 saves, 1 to 9 loads)
+                               to output/<rom>/states, so they outlive the
+                               session (default false, in memory only)
+  warm_cache = true            keep the state after the warm-up in
+                               output/cache/warmup and restore it in later
+                               runs, like --warm-cache (default false)
+
+  [bindings]
+  menu = \"Escape\"              hotkeys, see --print-bindings or press F1
+                               for all of them and their keys; scripts
+                               can take a hotkey's key with
+                               bind_key(key, fn, {force = true})
+
+  [watchdog]
+  frozen_seconds = 10          warn when the picture has not changed for
+                               this long of emulated time after the script
+                               is done (default off)
+  black_level = 16             the brightest a color channel can be for
+                               is_black_screen() (default 16)
+
+  [notify]
+  desktop = true               let notify(title, body) show desktop
+                               notifications (default false, only logged)
+
+  [journal]
+  enabled = false              don't write inputs.journal (default true)
+
+  [buttons]
+  shoot = \"B\"                  extra button names for scripts, like
+                               map_buttons{shoot = \"B\"}
+
+  [input]
+  hide_hotkeys = true          don't report keys bound to hotkeys to
+                               key_down and on_key (default false)
+
+options:
+  --config PATH          read configuration from PATH instead of marlua.toml
+  --bless                (test) overwrite golden images with the current frames
+  --output DIR           write this run's files to DIR instead
+  --lua-path DIR         also look for modules in DIR; require otherwise only
+                         loads .lua files from the script's directory
+  --games DIR            load game modules from DIR instead of games/
+  --lua-allow LIBS       comma-separated standard libraries to expose to the
+                         script besides the defaults: io, os, debug
+  --autosave SECONDS     write a checkpoint every SECONDS of emulated time
+  --autosave-keep N      number of checkpoints to keep (default 5)
+  --warm-cache           keep the state after the warm-up and restore it in
+                         later runs of the same ROM; the warm-up runs again
+                         when it changed or the state does not restore
+  --no-warm-cache        run the warm-up, over states.warm_cache
+  --resume latest|PATH   restore a checkpoint instead of running the warm-up;
+                         the script runs with `resuming` set up to its first
+                         wait, which stops it; on_resume(frame) is called if
+                         it defined one, then the game runs on
+  --print-bindings       print the effective hotkeys and exit
+  --trust-all            give the script every permission, see below
+  --log-level LEVEL      off, error, warn, info (default), debug or trace;
+                         events from the emulator end with frame=N, and
+                         what scripts print or log(level, ...) is marked
+                         lua:. Colored on a terminal unless NO_COLOR is set
+  -q, --quiet            only log warnings and errors, --log-level warn
+  -v, -vv                also log debug, or debug and trace messages
+  --log-file PATH        write the log to PATH instead of stderr
+  --renderer NAME        gl (default), software or wgpu; software draws on
+                         the CPU and is used anyway when OpenGL cannot be
+                         set up, but shows only the game without menus or
+                         notices. wgpu needs marlua built with
+                         `--features wgpu` and falls back to gl
+  --overlay-preset NAME  minimal (toasts, subtitles and the panels toggled
+                         with a hotkey only), debug (every overlay, the
+                         default) or stream (every overlay at the bottom,
+                         leaving the top third clear for a webcam); the
+                         [overlay] sections apply over it
+  --capture-layout SPEC  draw the game at a fixed whole scale and place in
+                         the window for capturing it, e.g. in OBS, and fill
+                         the rest with a color to key out, like
+                         scale=3,anchor=topleft,background=00FF00 (the
+                         defaults are scale 1, topleft and 00FF00). The
+                         scale is 1 to 16, the anchor topleft, top,
+                         topright, left, center, right, bottomleft, bottom
+                         or bottomright; the window can be resized and the
+                         game keeps its size and corner. Where it ends up is logged and
+                         returned by capture_rect() as {x, y, width,
+                         height} in window pixels, nil without this
+  --vsync MODE           on, off or adaptive (late frames are shown right
+                         away instead of waiting for the next refresh); the
+                         graphics driver decides otherwise. Only changes
+                         how the window presents, the game always runs at
+                         60 frames per second
+  --headless             (run, play) no window; the game runs as fast as
+                         possible and marlua exits once the script or the
+                         journal ends
+  --blend N              average the last N frames (2 or 3) in the window,
+                         screenshots and snapshots to hide sprite flicker;
+                         scripts still analyse the raw frame. blend(n) in
+                         scripts changes it, blend(0) turns it off
+  --snapshot-every N     write snapshot-<frame>.png to the run directory
+                         every N frames; like screenshot() these contain
+                         only the game, not overlays
+  --subtitles PATH       (play) commentary to show over the replay, blocks
+                         of `START --> END` frames and lines of text like
+                         .srt; by default JOURNAL's .srt file is used if
+                         there is one, e.g. inputs.srt for inputs.journal
+  --no-subtitles         (play) don't show the sidecar file and the
+                         journal's markers as subtitles
+  --seed N               seed math.random with N instead of a random one;
+                         either way the seed is logged and bundled
+  --bundle PATH          run the bundle's scripts with its config (instead
+                         of marlua.toml, other options still apply), seed
+                         and arguments, or with play replay its journal;
+                         the ROM given with --rom must be the one it was
+                         recorded with
+  --rom PATH             the ROM for --bundle
+  --verify-script sha256:HASH
+                         (run, test) refuse to run unless the script hash
+                         is HASH: the SHA-256 of the script's text, or
+                         with several scripts of their hashes one per line.
+                         Every run logs its script hash and puts it in the
+                         journal, the bundle and the summary, which also
+                         lists the hash of every module it required
+  --four-score           connect the Four Score adapter for ports 3 and 4;
+                         not available yet, marlua connects one standard
+                         controller
+  --inputs PATH          take the controller from a CSV file while it lasts,
+                         over the script's presses: rows of frame (counted
+                         like frame()) and controller, a byte or button
+                         names joined with +; frames between rows hold the
+                         previous input, or none after `# gaps = neutral`
+  --dump-inputs PATH     write every input to PATH in the same format
+  --dump-wav PATH        write the game's audio to PATH as WAV, aligned with
+                         the frames; not available yet, marlua takes no
+                         audio from fastnes
+  --record-hashes        (run, play) write a hash of the RAM after every
+                         frame to inputs.hashes in the run directory, for
+                         `marlua verify` to find where a replay desyncs
+  --record-ram LIST OUT  (run, play) write the addresses listed in LIST to
+                         OUT after every frame, for `marlua ram-dump`. LIST
+                         has one per line: an address (0x075A, $075A or
+                         decimal), an address with the width of its value
+                         in bytes (0x0086:2, 1, 2 or 4, little-endian) or a
+                         range of bytes (0x0700-0x07FF), and # comments; at
+                         most 4096 bytes per frame. Scripts can record
+                         parts of a run with record_ram(addrs, path), a
+                         table of the same, and record_ram(nil) to stop
+  --max-frames N         stop the run after emulating N frames
+  --max-wall-time TIME   stop the run after TIME, in seconds or with an s, m
+                         or h suffix (e.g. 10m); a run stopped by either
+                         limit exits with 2, a failing one with 1
+  -h, --help             show this message
+";
+
+pub const DEFAULT_SCRIPT: &str = "script/mock.lua";
+
+// why --dump-wav and record_audio fail, see Machine::frame_audio
+pub const NO_AUDIO: &str = "marlua takes no audio from fastnes";
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum Mode {
+    Run,
+    Test,
+}
+
+// how the window is drawn, each falls back to the next when unavailable
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum Renderer {
+    // only with the wgpu feature
+    Wgpu,
+    Gl,
+    Software,
+}
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum Vsync {
+    On,
+    Off,
+    Adaptive,
+}
+
+impl Vsync {
+    pub fn name(self) -> &'static str {
+        match self {
+            Vsync::On => "on",
+            Vsync::Off => "off",
+            Vsync::Adaptive => "adaptive",
+        }
+    }
+}
+
+#[derive(Clone)]
+pub struct Options {
+    pub mode: Mode,
+    pub rom: String,
+    pub script: String,
+    // scripts run after `script` in the same Lua state
+    pub chain: Vec<String>,
+    // no ROM was given, ask for one in the window
+    pub pick: bool,
+    pub bless: bool,
+    pub output: Option<String>,
+    pub lua_path: Option<String>,
+    pub lua_allow: Option<Vec<String>>,
+    pub games: String,
+    pub pause_on_focus_loss: bool,
+    pub title_format: Option<String>,
+    pub idle_fps: Option<f64>,
+    pub desktop_notifications: bool,
+    pub journal: bool,
+    pub persist_slots: bool,
+    // --warm-cache or states.warm_cache, see warmcache.rs
+    pub warm_cache: bool,
+    pub strict_inputs: bool,
+    pub log_level: String,
+    pub log_file: Option<String>,
+    pub renderer: Renderer,
+    pub vsync: Option<Vsync>,
+    pub placement: Placement,
+    // window.detach
+    pub detach: Vec<Panel>,
+    // --overlay-preset, over overlay.preset
+    pub overlay_preset: Option<Preset>,
+    // what the config file and the preset make of the overlays
+    pub layout: Layout,
+    // see letterbox.rs
+    pub capture_layout: Option<CaptureLayout>,
+    pub priority: Option<Priority>,
+    pub core: Option<usize>,
+    // run or play without a window, as fast as possible
+    pub headless: bool,
+    pub snapshot_every: Option<u64>,
+    // frames averaged against flicker, 0 for none
+    pub blend: usize,
+    pub max_frames: Option<usize>,
+    pub max_wall_time: Option<Duration>,
+    // a journal to replay instead of running a script
+    pub play: Option<String>,
+    // see subtitles.rs
+    pub subtitles: Option<String>,
+    pub no_subtitles: bool,
+    // see inputfile.rs
+    pub inputs: Option<String>,
+    // see Machine::connect_four_score
+    pub four_score: bool,
+    pub dump_inputs: Option<String>,
+    // two savestates to compare instead of running anything
+    pub diff: Option<(String, String)>,
+    // marlua bundle, the run directory and the output file
+    pub pack: Option<(String, String)>,
+    // marlua smoke, the directory and its options
+    pub smoke: Option<String>,
+    pub smoke_frames: usize,
+    pub smoke_threads: Option<usize>,
+    pub smoke_json: Option<String>,
+    // marlua verify, the ROM, the journal and its hash track
+    pub verify: Option<(String, String, String)>,
+    // see hashes.rs
+    pub record_hashes: bool,
+    // --record-ram, the addresses and the file, see ramdump.rs
+    pub record_ram: Option<(Vec<Entry>, String)>,
+    // marlua ram-dump, the file and whether to print it as CSV
+    pub ram_dump: Option<(String, bool)>,
+    // --verify-script, the hex digest, see pinning.rs
+    pub verify_script: Option<String>,
+    // for math.random, see bundle.rs
+    pub seed: Option<i64>,
+    // the config file that was read, if any
+    pub config: Option<String>,
+    pub bindings: Bindings,
+    // lua.max_images, see image.rs
+    pub max_images: usize,
+    // see watchdog.rs
+    pub frozen_seconds: Option<f64>,
+    pub black_level: u8,
+    // from the [buttons] section
+    pub buttons: Aliases,
+    pub autosave: Option<u32>,
+    pub autosave_keep: usize,
+    pub resume: Option<String>,
+    pub args: Vec<String>,
+    // skip the script's permission header
+    pub trust_all: bool,
+}
+
+impl Options {
+    // the script and the ones chained after it, in order
+    pub fn scripts(&self) -> Vec<&str> {
+        std::iter::once(&self.script)
+            .chain(&self.chain)
+            .map(String::as_str)
+            .collect()
+    }
+
+    pub fn from_env() -> Self {
+        match Self::parse(env::args().skip(1)) {
+            Ok(options) => options,
+            Err(e) => {
+                eprintln!("{}\n\n{}", e, USAGE);
+                process::exit(1);
+            }
+        }
+    }
+
+    pub fn parse(mut args: impl Iterator<Item = String>) -> Result<Self, String> {
+        let mut print_bindings = false;
+        let mut play = false;
+        let mut diff = false;
+        let mut pack = false;
+        let mut smoke = false;
+        let mut verify = false;
+        let mut ram_dump = false;
+        let mut csv = false;
+        let mut track = None;
+        let mut bundle = None;
+        let mut warm_cache = None;
+        let mut rom = None;
+        let mut options = Self {
+            mode: Mode::Run,
+            rom: String::new(),
+            script: DEFAULT_SCRIPT.to_owned(),
+            chain: Vec::new(),
+            pick: false,
+            bless: false,
+            output: None,
+            lua_path: None,
+            lua_allow: None,
+            games: "games".to_owned(),
+            pause_on_focus_loss: false,
+            title_format: None,
+            idle_fps: None,
+            desktop_notifications: false,
+            journal: true,
+            persist_slots: false,
+            warm_cache: false,
+            strict_inputs: false,
+            log_level: "info".to_owned(),
+            renderer: Renderer::Gl,
+            vsync: None,
+            placement: Placement::default(),
+            detach: Vec::new(),
+            overlay_preset: None,
+            layout: Layout::default(),
+            capture_layout: None,
+            priority: None,
+            core: None,
+            headless: false,
+            snapshot_every: None,
+            blend: 0,
+            max_frames: None,
+            max_wall_time: None,
+            log_file: None,
+            play: None,
+            subtitles: None,
+            no_subtitles: false,
+            inputs: None,
+            four_score: false,
+            dump_inputs: None,
+            diff: None,
+            pack: None,
+            smoke: None,
+            smoke_frames: 600,
+            smoke_threads: None,
+            smoke_json: None,
+            verify: None,
+            record_hashes: false,
+            record_ram: None,
+            ram_dump: None,
+            verify_script: None,
+            seed: None,
+            config: None,
+            bindings: Bindings::new(&Config::default())?,
+            buttons: Aliases::default(),
+            max_images: crate::image::MAX,
+            frozen_seconds: None,
+            black_level: crate::watchdog::BLACK_LEVEL,
+            autosave: None,
+            autosave_keep: 5,
+            resume: None,
+            args: Vec::new(),
+            trust_all: false,
+        };
+        let mut positional = Vec::new();
+        let mut config = None;
+
+        while let Some(arg) = args.next() {
+            let mut value = || {
+                args.next()
+                    .ok_or_else(|| format!("missing value for {}", arg))
+            };
+            match arg.as_str() {
+                "--bless" => options.bless = true,
+                "--subtitles" => options.subtitles = Some(value()?),
+                "--seed" => options.seed = Some(number(&arg, value()?)?),
+                "--bundle" => bundle = Some(value()?),
+                "--rom" => rom = Some(value()?),
+                "--frames" => options.smoke_frames = number(&arg, value()?)?,
+                "--threads" => options.smoke_threads = Some(number(&arg, value()?)?),
+                "--json" => options.smoke_json = Some(value()?),
+                "--hashes" => track = Some(value()?),
+                "--record-hashes" => options.record_hashes = true,
+                "--record-ram" => {
+                    let list = ramdump::load_list(&value()?)?;
+                    options.record_ram = Some((list, value()?));
+                }
+                "--csv" => csv = true,
+                "--verify-script" => options.verify_script = Some(pinning::parse(&value()?)?),
+                "--no-subtitles" => options.no_subtitles = true,
+                "--warm-cache" => warm_cache = Some(true),
+                "--no-warm-cache" => warm_cache = Some(false),
+                "--output" => options.output = Some(value()?),
+                "--lua-path" => options.lua_path = Some(value()?),
+                "--lua-allow" => {
+                    options.lua_allow = Some(value()?.split(',').map(str::to_owned).collect())
+                }
+                "--games" => options.games = value()?,
+                "--config" => config = Some(value()?),
+                "--autosave" => options.autosave = Some(number(&arg, value()?)?),
+                "--autosave-keep" => options.autosave_keep = number(&arg, value()?)?,
+                "--resume" => options.resume = Some(value()?),
+                "--print-bindings" => print_bindings = true,
+                "--trust-all" => options.trust_all = true,
+                "--log-level" => options.log_level = value()?,
+                "-q" | "--quiet" => options.log_level = "warn".to_owned(),
+                "-v" => options.log_level = "debug".to_owned(),
+                "-vv" => options.log_level = "trace".to_owned(),
+                "--renderer" => {
+                    options.renderer = match value()?.as_str() {
+                        "gl" => Renderer::Gl,
+                        "software" => Renderer::Software,
+                        "wgpu" if cfg!(feature = "wgpu") => Renderer::Wgpu,
+                        "wgpu" => {
+                            return Err("marlua was built without the wgpu feature".to_owned())
+                        }
+                        other => {
+                            return Err(format!(
+                                "unknown renderer {}, expected gl, software or wgpu",
+                                other
+                            ))
+                        }
+                    }
+                }
+                "--capture-layout" => {
+                    options.capture_layout = Some(CaptureLayout::parse(&value()?)?)
+                }
+                "--overlay-preset" => options.overlay_preset = Some(Preset::parse(&value()?)?),
+                "--vsync" => {
+                    options.vsync = Some(match value()?.as_str() {
+                        "on" => Vsync::On,
+                        "off" => Vsync::Off,
+                        "adaptive" => Vsync::Adaptive,
+                        other => {
+                            return Err(format!(
+                                "unknown vsync mode {}, expected on, off or adaptive",
+                                other
+                            ))
+                        }
+                    })
+                }
+                "--log-file" => options.log_file = Some(value()?),
+                "--inputs" => options.inputs = Some(value()?),
+                "--four-score" => options.four_score = true,
+                "--dump-inputs" => options.dump_inputs = Some(value()?),
+                "--dump-wav" => return Err(format!("{} is not available: {}", arg, NO_AUDIO)),
+                "--headless" => options.headless = true,
+                "--blend" => match number(&arg, value()?)? {
+                    n if n > blend::MAX => {
+                        return Err(format!("--blend expects at most {}", blend::MAX))
+                    }
+                    n => options.blend = n,
+                },
+                "--max-frames" => match number(&arg, value()?)? {
+                    0 => return Err("--max-frames expects at least 1".to_owned()),
+                    n => options.max_frames = Some(n),
+                },
+                "--max-wall-time" => options.max_wall_time = Some(duration(&arg, value()?)?),
+                "--snapshot-every" => match number(&arg, value()?)? {
+                    0 => return Err("--snapshot-every expects at least 1".to_owned()),
+                    n => options.snapshot_every = Some(n),
+                },
+                "--" => {
+                    options.args = args.by_ref().collect();
+                    break;
+                }
+                "-h" | "--help" => {
+                    print!("{}", USAGE);
+                    process::exit(0);
+                }
+                _ if arg.starts_with('-') => return Err(format!("unknown option {}", arg)),
+                _ => positional.push(arg),
+            }
+        }
+
+        let mut positional = positional.into_iter().peekable();
+        match positional.peek().map(String::as_str) {
+            Some("run") => {
+                positional.next();
+            }
+            Some("test") => {
+                positional.next();
+                options.mode = Mode::Test;
+            }
+            Some("play") => {
+                positional.next();
+                play = true;
+            }
+            Some("diff") => {
+                positional.next();
+                diff = true;
+            }
+            Some("bundle") => {
+                positional.next();
+                pack = true;
+            }
+            Some("smoke") => {
+                positional.next();
+                smoke = true;
+            }
+            Some("verify") => {
+                positional.next();
+                verify = true;
+            }
+            Some("ram-dump") => {
+                positional.next();
+                ram_dump = true;
+            }
+            _ => {}
+        }
+
+        let mut positional: Vec<_> = positional.collect();
+        if diff {
+            let [a, b]: [String; 2] = positional
+                .try_into()
+                .map_err(|_| "diff expects two STATE files".to_owned())?;
+            options.diff = Some((a, b));
+            return Ok(options);
+        }
+        if smoke {
+            let [dir]: [String; 1] = positional
+                .try_into()
+                .map_err(|_| "smoke expects a DIR of ROMs".to_owned())?;
+            options.smoke = Some(dir);
+            return Ok(options);
+        }
+        if verify {
+            let [rom, journal]: [String; 2] = positional
+                .try_into()
+                .map_err(|_| "verify expects a ROM and a JOURNAL".to_owned())?;
+            let track =
+                track.unwrap_or_else(|| hashes::sidecar(&journal).to_string_lossy().into_owned());
+            options.verify = Some((rom, journal, track));
+            return Ok(options);
+        }
+        if ram_dump {
+            let [file]: [String; 1] = positional
+                .try_into()
+                .map_err(|_| "ram-dump expects a FILE".to_owned())?;
+            options.ram_dump = Some((file, csv));
+            return Ok(options);
+        }
+        if csv {
+            return Err("--csv is only for ram-dump".to_owned());
+        }
+        if track.is_some() {
+            return Err("--hashes is only for verify".to_owned());
+        }
+        if pack {
+            let [run, out]: [String; 2] = positional
+                .try_into()
+                .map_err(|_| "bundle expects a RUN_DIR and an OUT file".to_owned())?;
+            options.pack = Some((run, out));
+            return Ok(options);
+        }
+        if let Some(path) = &bundle {
+            if !positional.is_empty() {
+                return Err(
+                    "--bundle takes the ROM from --rom and the rest from the bundle".to_owned(),
+                );
+            }
+            let rom = rom.ok_or("--bundle needs the ROM it was recorded with in --rom")?;
+            let unpacked = bundle::unpack(path, &rom, crate::WARMUP)?;
+            positional.push(rom);
+            if play {
+                let journal = unpacked
+                    .journal
+                    .ok_or("the bundle has no input journal to play")?;
+                positional.push(journal);
+            } else {
+                positional.extend(unpacked.scripts);
+            }
+            if options.args.is_empty() {
+                options.args = unpacked.args;
+            }
+            options.seed = options.seed.or(Some(unpacked.seed));
+            config = config.or(unpacked.config);
+        } else if rom.is_some() {
+            return Err(
+                "--rom is only for --bundle, give the ROM as the first argument".to_owned(),
+            );
+        }
+        if play {
+            if positional.len() != 2 {
+                return Err("play expects a ROM and a JOURNAL".to_owned());
+            }
+            options.play = positional.pop();
+        }
+        if options.mode == Mode::Test && positional.len() < 2 {
+            return Err("test expects a ROM and a SCRIPT".to_owned());
+        }
+        options.pick = positional.is_empty();
+        if options.pick && options.headless {
+            return Err("--headless needs a ROM".to_owned());
+        }
+        let scripts_given = positional.len() > 1;
+        let mut positional = positional.into_iter();
+        if let Some(rom) = positional.next() {
+            options.rom = rom;
+        }
+        if let Some(script) = positional.next() {
+            options.script = script;
+        }
+        options.chain = positional.collect();
+
+        // fill in what the command line left unset
+        if config.is_none() && Path::new(config::DEFAULT).exists() {
+            config = Some(config::DEFAULT.to_owned());
+        }
+        options.config = config.clone();
+        let config = match config {
+            Some(path) => Config::load(&path).map_err(|e| e.to_string())?,
+            None => Config::default(),
+        };
+        if options.lua_allow.is_none() {
+            options.lua_allow = config.strings("lua.allow")?;
+        }
+        if let Some(scripts) = config.strings("lua.scripts")? {
+            if !scripts_given && !options.pick && options.play.is_none() {
+                let mut scripts = scripts.into_iter();
+                let Some(first) = scripts.next() else {
+                    return Err("lua.scripts must name at least one script".to_owned());
+                };
+                options.script = first;
+                options.chain = scripts.collect();
+            }
+        }
+        options.pause_on_focus_loss = config
+            .boolean("window.pause_on_focus_loss")?
+            .unwrap_or(false);
+        options.placement = Placement::from_config(&config, "window.")?.restore();
+        options.title_format = config.string("window.title_format")?;
+        for name in config.strings("window.detach")?.unwrap_or_default() {
+            let panel = Panel::parse(&name).map_err(|e| format!("window.detach: {}", e))?;
+            if !options.detach.contains(&panel) {
+                options.detach.push(panel);
+            }
+        }
+        options.layout = Layout::from_config(options.overlay_preset, &config)?;
+        if let Some(priority) = config.string("emulator.priority")? {
+            options.priority = Priority::parse(&priority)?;
+        }
+        options.core = match config.get("emulator.core") {
+            None => None,
+            Some(ConfigValue::Integer(core)) if *core >= 0 => Some(*core as usize),
+            Some(_) => return Err("emulator.core must be a core number from 0".to_owned()),
+        };
+        options.idle_fps = config.number("window.idle_fps")?;
+        if options
+            .idle_fps
+            .map_or(false, |fps| !(0.0..=crate::FPS).contains(&fps))
+        {
+            return Err(format!("window.idle_fps must be from 0 to {}", crate::FPS));
+        }
+        options.journal = config.boolean("journal.enabled")?.unwrap_or(true);
+        options.persist_slots = config.boolean("states.persist_slots")?.unwrap_or(false);
+        options.warm_cache = match warm_cache {
+            Some(warm_cache) => warm_cache,
+            None => config.boolean("states.warm_cache")?.unwrap_or(false),
+        };
+        options.strict_inputs = config.boolean("lua.strict_inputs")?.unwrap_or(false);
+        options.max_images = match config.get("lua.max_images") {
+            None => crate::image::MAX,
+            Some(ConfigValue::Integer(max)) if *max > 0 => *max as usize,
+            Some(_) => return Err("lua.max_images must be at least 1".to_owned()),
+        };
+        options.frozen_seconds = config.number("watchdog.frozen_seconds")?;
+        if options.frozen_seconds.map_or(false, |s| s <= 0.0) {
+            return Err("watchdog.frozen_seconds must be above 0".to_owned());
+        }
+        options.black_level = match config.get("watchdog.black_level") {
+            None => crate::watchdog::BLACK_LEVEL,
+            Some(ConfigValue::Integer(level)) if (0..=255).contains(level) => *level as u8,
+            Some(_) => return Err("watchdog.black_level must be from 0 to 255".to_owned()),
+        };
+        options.desktop_notifications = config.boolean("notify.desktop")?.unwrap_or(false);
+        options.bindings = Bindings::new(&config)?;
+        for (key, value) in config.entries() {
+            if let Some(name) = key.strip_prefix("buttons.") {
+                let ConfigValue::String(buttons) = value else {
+                    return Err(format!("{} must be a button name like \"B\"", key));
+                };
+                options.buttons.define(name, buttons)?;
+            }
+        }
+        if print_bindings {
+            options.bindings.print();
+            process::exit(0);
+        }
+
+        Ok(options)
+    }
+}
+
+fn number<T: std::str::FromStr>(arg: &str, value: String) -> Result<T, String> {
+    value
+        .parse()
+        .map_err(|_| format!("{} expects a number, got {}", arg, value))
+}
+
+// seconds, optionally with an s, m or h suffix
+fn duration(arg: &str, value: String) -> Result<Duration, String> {
+    let (number, unit) = match value.char_indices().last() {
+        Some((i, 's')) => (&value[..i], 1),
+        Some((i, 'm')) => (&value[..i], 60),
+        Some((i, 'h')) => (&value[..i], 3600),
+        _ => (value.as_str(), 1),
+    };
+    match number.parse::<u64>() {
+        Ok(n) if n > 0 => Ok(Duration::from_secs(n * unit)),
+        _ => Err(format!(
+            "{} expects a duration like 90, 90s, 10m or 1h, got {}",
+            arg, value
+        )),
+    }
+}
@@ -0,0 +1,162 @@
+use std::{collections::HashMap, fs, io, path::Path};
+
+// The default configuration file, read from the working directory.
+pub const DEFAULT: &str = "marlua.toml";
+
+#[derive(Clone, Debug)]
+pub enum ConfigValue {
+    String(String),
+    Integer(i64),
+    Float(f64),
+    Boolean(bool),
+    Array(Vec<ConfigValue>),
+}
+
+// A small subset of TOML: `[section]` headers, `key = value` lines with
+// strings, numbers, booleans and single-line arrays of those, and `#`
+// comments. Keys are flattened to "section.key".
+#[derive(Default)]
+pub struct Config {
+    values: HashMap<String, ConfigValue>,
+}
+
+impl Config {
+    pub fn load(path: impl AsRef<Path>) -> io::Result<Self> {
+        let path = path.as_ref();
+        Self::parse(&fs::read_to_string(path)?).map_err(|e| {
+            io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("{}: {}", path.display(), e),
+            )
+        })
+    }
+
+    pub fn parse(source: &str) -> Result<Self, String> {
+        let mut values = HashMap::new();
+        let mut section = String::new();
+
+        for (n, line) in source.lines().enumerate() {
+            let line = strip_comment(line).trim();
+            let error = |msg: &str| format!("line {}: {}", n + 1, msg);
+
+            if line.is_empty() {
+                continue;
+            }
+            if let Some(header) = line.strip_prefix('[') {
+                let header = header
+                    .strip_suffix(']')
+                    .ok_or_else(|| error("unclosed section header"))?;
+                section = format!("{}.", header.trim());
+                continue;
+            }
+
+            let (key, value) = line
+                .split_once('=')
+                .ok_or_else(|| error("expected key = value"))?;
+            let key = key.trim().trim_matches('"');
+            if key.is_empty() {
+                return Err(error("missing key"));
+            }
+            let value = parse_value(value.trim()).map_err(|e| error(&e))?;
+            values.insert(format!("{}{}", section, key), value);
+        }
+
+        Ok(Self { values })
+    }
+
+    pub fn get(&self, key: &str) -> Option<&ConfigValue> {
+        self.values.get(key)
+    }
+
+    pub fn entries(&self) -> impl Iterator<Item = (&str, &ConfigValue)> {
+        self.values.iter().map(|(k, v)| (k.as_str(), v))
+    }
+
+    pub fn boolean(&self, key: &str) -> Result<Option<bool>, String> {
+        match self.get(key) {
+            None => Ok(None),
+            Some(ConfigValue::Boolean(b)) => Ok(Some(*b)),
+            Some(_) => Err(format!("{} must be true or false", key)),
+        }
+    }
+
+    pub fn number(&self, key: &str) -> Result<Option<f64>, String> {
+        match self.get(key) {
+            None => Ok(None),
+            Some(ConfigValue::Integer(n)) => Ok(Some(*n as f64)),
+            Some(ConfigValue::Float(n)) => Ok(Some(*n)),
+            Some(_) => Err(format!("{} must be a number", key)),
+        }
+    }
+
+    pub fn string(&self, key: &str) -> Result<Option<String>, String> {
+        match self.get(key) {
+            None => Ok(None),
+            Some(ConfigValue::String(s)) => Ok(Some(s.clone())),
+            Some(_) => Err(format!("{} must be a string", key)),
+        }
+    }
+
+    pub fn strings(&self, key: &str) -> Result<Option<Vec<String>>, String> {
+        let error = || format!("{} must be an array of strings", key);
+        match self.get(key) {
+            None => Ok(None),
+            Some(ConfigValue::Array(values)) => values
+                .iter()
+                .map(|v| match v {
+                    ConfigValue::String(s) => Ok(s.clone()),
+                    _ => Err(error()),
+                })
+                .collect::<Result<_, _>>()
+                .map(Some),
+            Some(_) => Err(error()),
+        }
+    }
+}
+
+fn strip_comment(line: &str) -> &str {
+    let mut quoted = false;
+    for (i, c) in line.char_indices() {
+        match c {
+            '"' => quoted = !quoted,
+            '#' if !quoted => return &line[..i],
+            _ => {}
+        }
+    }
+    line
+}
+
+fn parse_value(value: &str) -> Result<ConfigValue, String> {
+    if let Some(s) = value.strip_prefix('"') {
+        let s = s.strip_suffix('"').ok_or("unclosed string")?;
+        return Ok(ConfigValue::String(
+            s.replace("\\\"", "\"").replace("\\\\", "\\"),
+        ));
+    }
+    if let Some(items) = value.strip_prefix('[') {
+        let items = items.strip_suffix(']').ok_or("unclosed array")?.trim();
+        if items.is_empty() {
+            return Ok(ConfigValue::Array(Vec::new()));
+        }
+        return items
+            .split(',')
+            .map(str::trim)
+            .filter(|item| !item.is_empty())
+            .map(parse_value)
+            .collect::<Result<_, _>>()
+            .map(ConfigValue::Array);
+    }
+    match value {
+        "true" => return Ok(ConfigValue::Boolean(true)),
+        "false" => return Ok(ConfigValue::Boolean(false)),
+        _ => {}
+    }
+    let number = value.replace('_', "");
+    if let Ok(i) = number.parse() {
+        return Ok(ConfigValue::Integer(i));
+    }
+    if let Ok(f) = number.parse() {
+        return Ok(ConfigValue::Float(f));
+    }
+    Err(format!("invalid value {}", value))
+}
@@ -0,0 +1,264 @@
+use std::{
+    cell::Cell,
+    io::{self, BufRead, Write},
+};
+
+use rlua::{prelude::LuaError, Context, Function, MultiValue, Table, ToLuaMulti, Value};
+
+// finds the innermost Lua function on the stack (skipping the Rust functions
+// that called into the debugger) and returns its position and locals
+const LOCALS: &str = r#"
+local getinfo, getlocal = ...
+return function()
+    local level = 2
+    while true do
+        local info = getinfo(level, "S")
+        if info == nil then
+            return nil, {}
+        elseif info.what ~= "C" then
+            break
+        end
+        level = level + 1
+    end
+
+    local info = getinfo(level, "Sl")
+    local locals = {}
+    local i = 1
+    while true do
+        local name, value = getlocal(level, i)
+        if name == nil then
+            break
+        elseif name:sub(1, 1) ~= "(" then
+            locals[#locals + 1] = { name = name, value = value }
+        end
+        i = i + 1
+    end
+    return info.short_src .. ":" .. info.currentline, locals
+end
+"#;
+
+const TRACEBACK: &str = "marlua.traceback";
+
+// Keep what the debugger needs from the debug library in the registry, and
+// remove the library itself unless scripts are allowed to use it.
+pub fn install(ctx: Context, expose: bool) -> Result<(), LuaError> {
+    let globals = ctx.globals();
+    let debug: Table = globals.get("debug")?;
+    let getinfo: Function = debug.get("getinfo")?;
+    let getlocal: Function = debug.get("getlocal")?;
+
+    let locals: Function = ctx
+        .load(LOCALS)
+        .set_name("=locals")?
+        .call((getinfo, getlocal))?;
+    ctx.set_named_registry_value("marlua.locals", locals)?;
+    ctx.set_named_registry_value(TRACEBACK, debug.get::<_, Function>("traceback")?)?;
+
+    if !expose {
+        globals.set("debug", Value::Nil)?;
+    }
+    Ok(())
+}
+
+// Call a script function with a traceback added to errors raised in Lua.
+//
+// Errors from Rust functions (another file was opened, a limit was reached)
+// are passed through unchanged so they can still be told apart.
+pub fn call_traced<'lua>(
+    ctx: Context<'lua>,
+    function: Function<'lua>,
+    args: impl ToLuaMulti<'lua>,
+) -> Result<(), LuaError> {
+    let traceback: Function = ctx.named_registry_value(TRACEBACK)?;
+    let xpcall: Function = ctx.globals().get("xpcall")?;
+    let mut call = vec![Value::Function(function), Value::Function(traceback)];
+    call.extend(args.to_lua_multi(ctx)?);
+
+    let mut results = xpcall
+        .call::<_, MultiValue>(MultiValue::from_vec(call))?
+        .into_iter();
+    match (results.next(), results.next()) {
+        (Some(Value::Boolean(true)), _) => Ok(()),
+        (_, Some(Value::Error(e))) => Err(e),
+        (_, Some(Value::String(message))) => {
+            Err(LuaError::RuntimeError(message.to_str()?.to_owned()))
+        }
+        (_, other) => Err(LuaError::RuntimeError(format!("error object: {:?}", other))),
+    }
+}
+
+// a script error as reported, with the frame it happened in
+pub fn at_frame(frame: usize, error: LuaError) -> LuaError {
+    LuaError::RuntimeError(format!("at frame {}: {}", frame, error))
+}
+
+// An interactive prompt on stdin, entered before a frame step.
+//
+// Lines are evaluated with the script's globals; `step()` advances one frame,
+// `locals()` prints (and returns) the locals of the script function that was
+// interrupted and `continue()` resumes the script. End of input also resumes.
+pub fn prompt<'lua>(
+    ctx: Context<'lua>,
+    frame: impl Fn() -> usize,
+    step: impl Fn(),
+) -> Result<(), LuaError> {
+    let (location, locals) = ctx
+        .named_registry_value::<_, Function>("marlua.locals")?
+        .call::<_, (Option<String>, Vec<Table>)>(())?;
+    let location = location.unwrap_or_else(|| "?".to_owned());
+    eprintln!(
+        "break at frame {} in {}, type continue() to resume",
+        frame(),
+        location
+    );
+
+    let resume = Cell::new(false);
+    ctx.scope(|scope| {
+        let globals = ctx.globals();
+        let meta = ctx.create_table()?;
+        meta.set("__index", globals.clone())?;
+        meta.set("__newindex", globals.clone())?;
+
+        let env = ctx.create_table()?;
+        env.set_metatable(Some(meta));
+        env.set(
+            "step",
+            scope.create_function(|_, ()| {
+                step();
+                eprintln!("frame {}", frame());
+                Ok(())
+            })?,
+        )?;
+        env.set(
+            "continue",
+            scope.create_function(|_, ()| {
+                resume.set(true);
+                Ok(())
+            })?,
+        )?;
+        env.set(
+            "locals",
+            scope.create_function(|ctx, ()| {
+                let tostring: Function = ctx.globals().get("tostring")?;
+                let table = ctx.create_table()?;
+                for local in &locals {
+                    let name: String = local.get("name")?;
+                    let value: Value = local.get("value")?;
+                    eprintln!("{} = {}", name, tostring.call::<_, String>(value.clone())?);
+                    table.set(name, value)?;
+                }
+                Ok(table)
+            })?,
+        )?;
+
+        let stdin = io::stdin();
+        let mut lines = stdin.lock().lines();
+        while !resume.get() {
+            eprint!("debug:{}> ", frame());
+            let _ = io::stderr().flush();
+            let line = match lines.next() {
+                Some(line) => line.map_err(LuaError::external)?,
+                None => break,
+            };
+            if line.trim().is_empty() {
+                continue;
+            }
+
+            // try the line as an expression first, like the standalone lua
+            let result = match ctx
+                .load(&format!("return {}", line))
+                .set_name("=debug")?
+                .set_environment(env.clone())?
+                .into_function()
+            {
+                Ok(f) => f.call::<_, MultiValue>(()),
+                Err(_) => ctx
+                    .load(&line)
+                    .set_name("=debug")?
+                    .set_environment(env.clone())?
+                    .call::<_, MultiValue>(()),
+            };
+            match result {
+                Ok(values) => {
+                    let tostring: Function = globals.get("tostring")?;
+                    let values = values
+                        .into_iter()
+                        .map(|v| tostring.call::<_, String>(v))
+                        .collect::<Result<Vec<_>, _>>()?;
+                    if !values.is_empty() {
+                        eprintln!("{}", values.join("\t"));
+                    }
+                }
+                Err(e) => eprintln!("{}", e),
+            }
+        }
+        Ok(())
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sandbox;
+
+    const SCRIPT: &str = "\
+local function inner()
+    error('boom')
+end
+local function middle()
+    inner()
+end
+middle()
+";
+
+    fn assert_chain(message: &str) {
+        // where it was raised, then every frame of the chain
+        for line in ["nested.lua:2: boom", "nested.lua:2: in", "nested.lua:5: in"] {
+            assert!(message.contains(line), "{} missing from {}", line, message);
+        }
+    }
+
+    #[test]
+    fn traceback() {
+        let lua = sandbox::new_lua(sandbox::stdlib(&[]).unwrap());
+        lua.context(|ctx| {
+            let chunk = ctx
+                .load(SCRIPT)
+                .set_name("@nested.lua")
+                .unwrap()
+                .into_function()
+                .unwrap();
+            let error = call_traced(ctx, chunk, ()).unwrap_err();
+            let message = at_frame(42, error).to_string();
+            assert!(message.starts_with("at frame 42: "), "{}", message);
+            assert_chain(&message);
+            assert!(
+                message.contains("nested.lua:7: in main chunk"),
+                "{}",
+                message
+            );
+        });
+    }
+
+    // callbacks called from Rust, like on_key or at() from wait, keep their
+    // own traceback through the Rust function
+    #[test]
+    fn callback_traceback() {
+        let lua = sandbox::new_lua(sandbox::stdlib(&[]).unwrap());
+        lua.context(|ctx| {
+            let fire = ctx
+                .create_function(|ctx, callback: Function| call_traced(ctx, callback, ()))
+                .unwrap();
+            ctx.globals().set("fire", fire).unwrap();
+            let source = SCRIPT.replace("middle()\n", "fire(middle)\n");
+            let chunk = ctx
+                .load(&source)
+                .set_name("@nested.lua")
+                .unwrap()
+                .into_function()
+                .unwrap();
+            let error = call_traced(ctx, chunk, ()).unwrap_err();
+            assert_chain(&at_frame(7, error).to_string());
+        });
+    }
+}
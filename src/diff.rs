@@ -0,0 +1,67 @@
+use crate::savestate::Savestate;
+
+// A CPU RAM byte that differs between two states.
+pub struct Change {
+    pub addr: u16,
+    pub old: u8,
+    pub new: u8,
+}
+
+impl Change {
+    // the zero page is where games keep most of their variables
+    pub fn zero_page(&self) -> bool {
+        self.addr < 0x100
+    }
+}
+
+pub fn ram(a: &[u8], b: &[u8]) -> Vec<Change> {
+    a.iter()
+        .zip(b)
+        .enumerate()
+        .filter(|(_, (old, new))| old != new)
+        .map(|(addr, (&old, &new))| Change {
+            addr: addr as u16,
+            old,
+            new,
+        })
+        .collect()
+}
+
+pub fn states(a: &Savestate, b: &Savestate) -> Result<Vec<Change>, String> {
+    if a.rom_hash != b.rom_hash {
+        return Err(format!(
+            "the states were made with different ROMs ({:08x} and {:08x})",
+            a.rom_hash, b.rom_hash
+        ));
+    }
+    match (&a.ram, &b.ram) {
+        (Some(old), Some(new)) => Ok(ram(old, new)),
+        _ => Err("savestates from before version 2 do not contain RAM".to_owned()),
+    }
+}
+
+// `marlua diff`, for savestate files
+pub fn print(a: &str, b: &str) -> Result<(), String> {
+    let read = |path| Savestate::read(path).map_err(|e| format!("{}: {}", path, e));
+    let (old, new) = (read(a)?, read(b)?);
+    let changes = states(&old, &new)?;
+
+    println!(
+        "{} bytes differ between frame {} and frame {}",
+        changes.len(),
+        old.frame(),
+        new.frame()
+    );
+    for change in changes {
+        let note = if change.zero_page() {
+            "  zero page"
+        } else {
+            ""
+        };
+        println!(
+            "${:04X}  {:02X} -> {:02X}{}",
+            change.addr, change.old, change.new, note
+        );
+    }
+    Ok(())
+}
@@ -0,0 +1,166 @@
+use std::{
+    collections::VecDeque,
+    fs::File,
+    io::{BufWriter, Write},
+    path::Path,
+    time::{Duration, Instant},
+};
+
+use tracing::warn;
+
+use crate::buttons;
+
+// events(since_frame) only sees this many, the file has all of them
+const CAPACITY: usize = 65536;
+const FLUSH_INTERVAL: Duration = Duration::from_secs(1);
+
+pub enum Kind {
+    // the controller byte, whenever it differs from the frame before
+    Input(u8),
+    Marker(String),
+    // print() is level info
+    Log { level: String, line: String },
+    // a savestate, slot or checkpoint restored, by name
+    StateLoaded(String),
+}
+
+impl Kind {
+    fn name(&self) -> &'static str {
+        match self {
+            Kind::Input(_) => "input",
+            Kind::Marker(_) => "marker",
+            Kind::Log { .. } => "log",
+            Kind::StateLoaded(_) => "state_loaded",
+        }
+    }
+}
+
+pub struct Event {
+    pub frame: usize,
+    pub kind: Kind,
+}
+
+impl Event {
+    // the kind's fields besides frame and kind, as (name, JSON value)
+    fn fields(&self) -> Vec<(&'static str, String)> {
+        match &self.kind {
+            Kind::Input(input) => {
+                let pressed: Vec<_> = buttons::pressed(*input)
+                    .map(|button| string(button.name()))
+                    .collect();
+                vec![("buttons", format!("[{}]", pressed.join(",")))]
+            }
+            Kind::Marker(name) => vec![("name", string(name))],
+            Kind::Log { level, line } => vec![("level", string(level)), ("line", string(line))],
+            Kind::StateLoaded(name) => vec![("name", string(name))],
+        }
+    }
+
+    // one NDJSON line, e.g. {"frame":120,"kind":"marker","name":"boss"}
+    fn json(&self) -> String {
+        let mut line = format!(
+            "{{\"frame\":{},\"kind\":{}",
+            self.frame,
+            string(self.kind.name())
+        );
+        for (name, value) in self.fields() {
+            line.push_str(&format!(",\"{}\":{}", name, value));
+        }
+        line.push('}');
+        line
+    }
+}
+
+fn string(s: &str) -> String {
+    let mut quoted = String::from("\"");
+    for c in s.chars() {
+        match c {
+            '"' => quoted.push_str("\\\""),
+            '\\' => quoted.push_str("\\\\"),
+            c if (c as u32) < 0x20 => quoted.push_str(&format!("\\u{:04x}", c as u32)),
+            c => quoted.push(c),
+        }
+    }
+    quoted.push('"');
+    quoted
+}
+
+// Everything of note in a run, in the order it happened and tagged with the
+// frame: input changes, markers, the script's log lines and restored states.
+// Scripts read the last CAPACITY with events(since_frame). Every event is
+// also written to events.ndjson in the run directory as it happens, flushed
+// at least once a second and when the log is dropped, so closing the window
+// mid-run loses at most the last second like the input journal.
+pub struct EventLog {
+    events: VecDeque<Event>,
+    // the input of the last frame, for Kind::Input
+    input: u8,
+    writer: Option<BufWriter<File>>,
+    flushed: Instant,
+}
+
+impl EventLog {
+    // without a file when `export` is None
+    pub fn new(export: Option<&Path>) -> Self {
+        let writer = export.and_then(|path| match File::create(path) {
+            Ok(file) => Some(BufWriter::new(file)),
+            Err(e) => {
+                warn!("could not create {}: {}", path.display(), e);
+                None
+            }
+        });
+        Self {
+            events: VecDeque::new(),
+            input: 0,
+            writer,
+            flushed: Instant::now(),
+        }
+    }
+
+    pub fn push(&mut self, frame: usize, kind: Kind) {
+        let event = Event { frame, kind };
+        if let Some(writer) = &mut self.writer {
+            if let Err(e) = writeln!(writer, "{}", event.json()) {
+                warn!("stopped writing the event log: {}", e);
+                self.writer = None;
+            }
+        }
+        if self.events.len() == CAPACITY {
+            self.events.pop_front();
+        }
+        self.events.push_back(event);
+    }
+
+    // called for every frame, only changes are kept
+    pub fn input(&mut self, frame: usize, input: u8) {
+        if input != self.input {
+            self.input = input;
+            self.push(frame, Kind::Input(input));
+        }
+        if self.flushed.elapsed() >= FLUSH_INTERVAL {
+            self.flush();
+        }
+    }
+
+    // the events from `frame` on, oldest first
+    pub fn since(&self, frame: usize) -> impl Iterator<Item = &Event> {
+        // frames only go back after a state is loaded, so this is a scan
+        self.events.iter().filter(move |event| event.frame >= frame)
+    }
+
+    fn flush(&mut self) {
+        self.flushed = Instant::now();
+        if let Some(writer) = &mut self.writer {
+            if let Err(e) = writer.flush() {
+                warn!("stopped writing the event log: {}", e);
+                self.writer = None;
+            }
+        }
+    }
+}
+
+impl Drop for EventLog {
+    fn drop(&mut self) {
+        self.flush();
+    }
+}
@@ -0,0 +1,66 @@
+use std::{
+    any::Any,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Mutex,
+    },
+};
+
+use crate::limits;
+
+// Why the emulator thread stopped, kept for the window to show.
+//
+// The window does not rely on this being set: it notices a finished
+// emulator thread by itself and only uses the message when there is one.
+#[derive(Default)]
+pub struct Failure {
+    message: Mutex<Option<String>>,
+    // stopped by --max-frames or --max-wall-time rather than an error
+    limit: AtomicBool,
+    // not a failure at all, the script called quit()
+    quit: AtomicBool,
+}
+
+impl Failure {
+    pub fn set(&self, message: String) {
+        *self.message.lock().unwrap_or_else(|e| e.into_inner()) = Some(message);
+    }
+
+    pub fn set_limit(&self, message: String) {
+        self.limit.store(true, Ordering::Relaxed);
+        self.set(message);
+    }
+
+    pub fn set_quit(&self) {
+        self.quit.store(true, Ordering::Relaxed);
+    }
+
+    pub fn quit(&self) -> bool {
+        self.quit.load(Ordering::Relaxed)
+    }
+
+    pub fn exit_code(&self) -> i32 {
+        match self.limit.load(Ordering::Relaxed) {
+            true => limits::exit_code(),
+            false => 1,
+        }
+    }
+
+    pub fn get(&self) -> Option<String> {
+        self.message
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .clone()
+    }
+}
+
+// the message of a panic caught with catch_unwind
+pub fn panic_message(payload: &(dyn Any + Send)) -> String {
+    match payload.downcast_ref::<&str>() {
+        Some(s) => s.to_string(),
+        None => match payload.downcast_ref::<String>() {
+            Some(s) => s.clone(),
+            None => "unknown panic".to_owned(),
+        },
+    }
+}
@@ -0,0 +1,159 @@
+use std::time::Duration;
+
+use femtovg::{renderer::OpenGl, Canvas, Color, FontId, Paint, Path};
+
+use crate::FPS;
+
+// frames in the graph, one pixel each
+pub const LEN: usize = 240;
+pub const HEIGHT: f32 = 80.0;
+// the top of the graph, the budget line is halfway up
+const SCALE_MS: f32 = 2000.0 / FPS as f32;
+
+pub const EMULATION: [u8; 3] = [64, 200, 64];
+pub const LUA: [u8; 3] = [64, 128, 255];
+pub const BUDGET: [u8; 3] = [255, 48, 48];
+
+#[derive(Clone, Copy, Default)]
+struct Times {
+    // in milliseconds
+    emulation: f32,
+    lua: f32,
+    slack: f32,
+}
+
+// The last LEN frames of the emulator thread: the time it spent emulating,
+// in the script (callbacks included) before it and sleeping after it. It is
+// a fixed ring so recording a frame is a copy, the graph toggled with the
+// frame_times hotkey draws from a copy of it.
+#[derive(Clone, Copy)]
+pub struct FrameTimes {
+    times: [Times; LEN],
+    // where the next frame goes, the oldest one once it is full
+    next: usize,
+    len: usize,
+    // the window's median publication to present time, see Stats::latency
+    pub latency: Option<Duration>,
+}
+
+impl Default for FrameTimes {
+    fn default() -> Self {
+        Self {
+            times: [Times::default(); LEN],
+            next: 0,
+            len: 0,
+            latency: None,
+        }
+    }
+}
+
+impl FrameTimes {
+    pub fn record(&mut self, emulation: Duration, lua: Duration, slack: Duration) {
+        let ms = |d: Duration| d.as_secs_f32() * 1000.0;
+        self.times[self.next] = Times {
+            emulation: ms(emulation),
+            lua: ms(lua),
+            slack: ms(slack),
+        };
+        self.next = (self.next + 1) % LEN;
+        self.len = (self.len + 1).min(LEN);
+    }
+
+    // oldest first
+    fn iter(&self) -> impl Iterator<Item = &Times> {
+        let start = (self.next + LEN - self.len) % LEN;
+        (0..self.len).map(move |i| &self.times[(start + i) % LEN])
+    }
+
+    // the graph with its labels, for the layout, see Element::FrameTimes
+    pub fn size(&self) -> (f32, f32) {
+        (LEN as f32, HEIGHT + self.footer())
+    }
+
+    // the graph's x, y, width and height with its top left corner `at`
+    pub fn rect((x, y): (f32, f32)) -> (f32, f32, f32, f32) {
+        (x, y, LEN as f32, HEIGHT)
+    }
+
+    // one bar per frame: x, then the heights of the emulation and the Lua
+    // part stacked on it, capped at the top of the graph
+    pub fn bars(
+        &self,
+        (x, _, _, h): (f32, f32, f32, f32),
+    ) -> impl Iterator<Item = (f32, f32, f32)> + '_ {
+        let offset = LEN - self.len;
+        self.iter().enumerate().map(move |(i, t)| {
+            let emulation = (t.emulation / SCALE_MS).min(1.0) * h;
+            let lua = (t.lua / SCALE_MS).min(1.0 - emulation / h) * h;
+            (x + (offset + i) as f32, emulation, lua)
+        })
+    }
+
+    // the y of the 60 fps line
+    pub fn budget((_, y, _, h): (f32, f32, f32, f32)) -> f32 {
+        y + h / 2.0
+    }
+
+    // averages over the graph, drawn below it
+    pub fn label(&self) -> String {
+        let n = self.len.max(1) as f32;
+        let (emulation, lua, slack) = self.iter().fold((0.0, 0.0, 0.0), |(e, l, s), t| {
+            (e + t.emulation, l + t.lua, s + t.slack)
+        });
+        format!(
+            "emu {:.1} lua {:.1} slack {:.1} ms",
+            emulation / n,
+            lua / n,
+            slack / n
+        )
+    }
+
+    // the line below the label, once the window has presented a frame
+    pub fn latency_label(&self) -> Option<String> {
+        let latency = self.latency?;
+        Some(format!(
+            "to screen {:.1} ms",
+            latency.as_secs_f32() * 1000.0
+        ))
+    }
+
+    // how far the background reaches below the graph
+    pub fn footer(&self) -> f32 {
+        match self.latency {
+            Some(_) => 40.0,
+            None => 22.0,
+        }
+    }
+
+    // at the top left corner the overlay placed it, see Overlay::frame_times
+    pub fn draw(&self, canvas: &mut Canvas<OpenGl>, font: FontId, at: (f32, f32)) {
+        let rect = Self::rect(at);
+        let (x, y, w, h) = rect;
+        let color = |[r, g, b]: [u8; 3]| Color::rgb(r, g, b);
+
+        let mut background = Path::new();
+        background.rect(x, y, w, h + self.footer());
+        canvas.fill_path(&mut background, &Paint::color(Color::rgba(0, 0, 0, 180)));
+
+        let mut emulation = Path::new();
+        let mut lua = Path::new();
+        for (bar, e, l) in self.bars(rect) {
+            emulation.rect(bar, y + h - e, 1.0, e);
+            lua.rect(bar, y + h - e - l, 1.0, l);
+        }
+        canvas.fill_path(&mut emulation, &Paint::color(color(EMULATION)));
+        canvas.fill_path(&mut lua, &Paint::color(color(LUA)));
+
+        let mut budget = Path::new();
+        budget.rect(x, Self::budget(rect), w, 1.0);
+        canvas.fill_path(&mut budget, &Paint::color(color(BUDGET)));
+
+        let mut paint = Paint::color(Color::white());
+        paint.set_font(&[font]);
+        paint.set_font_size(14.0);
+        let _ = canvas.fill_text(x + 4.0, y + h + 16.0, &self.label(), &paint);
+        if let Some(latency) = self.latency_label() {
+            let _ = canvas.fill_text(x + 4.0, y + h + 34.0, &latency, &paint);
+        }
+    }
+}
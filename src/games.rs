@@ -0,0 +1,84 @@
+use std::{fs, path::Path};
+
+use rlua::{prelude::LuaError, Context, Table, Value};
+use tracing::{info, warn};
+
+use crate::config::{Config, ConfigValue};
+
+const MANIFEST: &str = "manifest.toml";
+
+// Loads the game module for a ROM into the `game` global.
+//
+// `dir`/manifest.toml maps ROM CRC32s (without the iNES header, in hex) to
+// module files in `dir`:
+//
+//   3337EC46 = "smb.lua"
+//
+// A module returns a table with at least `name`; `supported` defaults to
+// true and may be set to false by modules that recognise a ROM they cannot
+// fully handle. `game` stays nil for unknown ROMs or when loading fails, in
+// which case the error is printed but the script still runs.
+pub fn install(ctx: Context, dir: &Path, hash: u32) -> Result<(), LuaError> {
+    let game = match find(dir, hash) {
+        Ok(Some(module)) => load(ctx, &dir.join(&module)).map_or_else(
+            |e| {
+                warn!("could not load game module {}: {}", module, e);
+                Value::Nil
+            },
+            Value::Table,
+        ),
+        Ok(None) => Value::Nil,
+        Err(e) => {
+            warn!("{}", e);
+            Value::Nil
+        }
+    };
+    ctx.globals().set("game", game)
+}
+
+fn find(dir: &Path, hash: u32) -> Result<Option<String>, String> {
+    let path = dir.join(MANIFEST);
+    if !path.exists() {
+        return Ok(None);
+    }
+    let manifest = Config::load(&path).map_err(|e| e.to_string())?;
+
+    for (key, value) in manifest.entries() {
+        let key_hash = key.trim_start_matches("0x");
+        let key_hash = u32::from_str_radix(key_hash, 16)
+            .map_err(|_| format!("{}: {} is not a CRC32 in hex", path.display(), key))?;
+        if key_hash != hash {
+            continue;
+        }
+        return match value {
+            ConfigValue::String(module) => Ok(Some(module.clone())),
+            _ => Err(format!(
+                "{}: the module for {} must be a file name",
+                path.display(),
+                key
+            )),
+        };
+    }
+    Ok(None)
+}
+
+fn load<'lua>(ctx: Context<'lua>, path: &Path) -> Result<Table<'lua>, LuaError> {
+    let source = fs::read(path).map_err(LuaError::external)?;
+    let game: Table = ctx
+        .load(&source)
+        .set_name(&format!("@{}", path.display()))?
+        .call(())?;
+
+    match game.get::<_, Value>("name")? {
+        Value::String(name) => info!("loaded game module for {}", name.to_str()?),
+        _ => {
+            return Err(LuaError::RuntimeError(
+                "the module must set game.name".to_owned(),
+            ))
+        }
+    }
+    if let Value::Nil = game.get::<_, Value>("supported")? {
+        game.set("supported", true)?;
+    }
+    Ok(game)
+}
@@ -0,0 +1,205 @@
+use std::{error::Error, num::NonZeroU32};
+
+use fastnes::ppu::Color;
+use femtovg::{
+    imgref::Img, renderer::OpenGl, rgb::RGBA8, Canvas, Color as Rgba, FontId, ImageFlags, Paint,
+    Path,
+};
+use glutin::{
+    config::ConfigTemplateBuilder,
+    context::{ContextApi, ContextAttributesBuilder, PossiblyCurrentContext},
+    display::GetGlDisplay,
+    prelude::{
+        GlDisplay, NotCurrentGlContextSurfaceAccessor, PossiblyCurrentContextGlSurfaceAccessor,
+    },
+    surface::{GlSurface, Surface, SurfaceAttributesBuilder, SwapInterval, WindowSurface},
+};
+use glutin_winit::{DisplayBuilder, GlWindow};
+use raw_window_handle::HasRawWindowHandle;
+use tracing::warn;
+use winit::{
+    event_loop::EventLoopWindowTarget,
+    window::{Window, WindowBuilder},
+};
+
+use crate::{
+    cli::Vsync,
+    letterbox::Scene,
+    overlay,
+    render::{Overlays, Present},
+};
+
+// The default renderer, femtovg on an OpenGL context.
+pub struct Gl {
+    surface: Surface<WindowSurface>,
+    context: PossiblyCurrentContext,
+    canvas: Canvas<OpenGl>,
+    font: FontId,
+    vsync: Option<Vsync>,
+}
+
+impl Gl {
+    pub fn new(
+        el: &EventLoopWindowTarget<()>,
+        builder: WindowBuilder,
+        width: u32,
+        height: u32,
+        vsync: Option<Vsync>,
+    ) -> Result<(Window, Self), Box<dyn Error>> {
+        let (window, config) = DisplayBuilder::new()
+            .with_window_builder(Some(builder))
+            .build(el, ConfigTemplateBuilder::new(), |mut it| {
+                it.next().unwrap()
+            })?;
+
+        // create surface
+        let window = window.ok_or("no window for the OpenGL config")?;
+        let attrs = window.build_surface_attributes(SurfaceAttributesBuilder::new());
+
+        let display = config.display();
+        let surface = unsafe { display.create_window_surface(&config, &attrs)? };
+
+        // create context
+        let context = unsafe {
+            display.create_context(
+                &config,
+                &ContextAttributesBuilder::new()
+                    .with_context_api(ContextApi::OpenGl(None))
+                    .build(Some(window.raw_window_handle())),
+            )?
+        }
+        .make_current(&surface)?;
+
+        // glutin only knows waiting for the refresh or not
+        let mut vsync = match vsync {
+            Some(Vsync::Adaptive) => {
+                warn!("OpenGL has no adaptive vsync, using --vsync on");
+                Some(Vsync::On)
+            }
+            vsync => vsync,
+        };
+        if let Some(mode) = vsync {
+            let interval = match mode {
+                Vsync::Off => SwapInterval::DontWait,
+                _ => SwapInterval::Wait(NonZeroU32::new(1).unwrap()),
+            };
+            if let Err(e) = surface.set_swap_interval(&context, interval) {
+                warn!(
+                    "could not turn vsync {} ({}), leaving it to the driver",
+                    mode.name(),
+                    e
+                );
+                vsync = None;
+            }
+        }
+
+        // create OpenGL
+        let opengl = OpenGl::new_from_glutin_display(&display)?;
+        let mut canvas = Canvas::new(opengl)?;
+        canvas.set_size(width, height, 1.0);
+        let font = canvas.add_font_mem(overlay::FONT)?;
+
+        Ok((
+            window,
+            Self {
+                surface,
+                context,
+                canvas,
+                font,
+                vsync,
+            },
+        ))
+    }
+
+    fn draw_frame(&mut self, frame: &[Color; 61440], scene: Scene) {
+        let canvas = &mut self.canvas;
+        let [r, g, b] = scene.background;
+        let (w, h) = (canvas.width(), canvas.height());
+        canvas.clear_rect(0, 0, w, h, Rgba::rgb(r, g, b));
+
+        // create image
+        let img = Img::new(as_rgba(frame), 256, 240);
+        let image = canvas.create_image(img, ImageFlags::NEAREST).unwrap();
+
+        // draw image
+        let (x, y, width, height) = scene.image;
+        let fill_paint = Paint::image(image, x, y, width, height, 0.0, 1.0);
+        let mut path = Path::new();
+        path.rect(x, y, width, height);
+        canvas.fill_path(&mut path, &fill_paint);
+
+        // destroy image
+        // need to flush the canvas before being able to delete the image
+        canvas.flush();
+        canvas.delete_image(image);
+    }
+}
+
+impl Present for Gl {
+    fn present(
+        &mut self,
+        window: &Window,
+        frame: &[Color; 61440],
+        scene: Scene,
+        overlays: Overlays,
+    ) -> Result<(), String> {
+        // debug panels can have windows and contexts of their own
+        self.context
+            .make_current(&self.surface)
+            .map_err(|e| e.to_string())?;
+        // only --capture-layout makes the window resizable
+        let size = window.inner_size();
+        let (Some(width), Some(height)) =
+            (NonZeroU32::new(size.width), NonZeroU32::new(size.height))
+        else {
+            // minimized
+            return Ok(());
+        };
+        if (size.width, size.height) != (self.canvas.width(), self.canvas.height()) {
+            self.surface.resize(&self.context, width, height);
+            self.canvas.set_size(size.width, size.height, 1.0);
+        }
+        match (overlays.picker, overlays.tiles, overlays.memory) {
+            (Some(picker), _, _) => picker.draw(&mut self.canvas, self.font),
+            (None, Some(tiles), _) => tiles.draw(&mut self.canvas, self.font),
+            (None, None, Some(memory)) => memory.draw(&mut self.canvas, self.font),
+            (None, None, None) => self.draw_frame(frame, scene),
+        }
+        if let (None, None, Some(sprites)) = (overlays.picker, overlays.tiles, overlays.sprites) {
+            sprites.draw(&mut self.canvas, self.font);
+        }
+        if let Some(times) = overlays.frame_times {
+            let size = (self.canvas.width() as f32, self.canvas.height() as f32);
+            if let Some(at) = overlays.overlay.frame_times(times, size) {
+                times.draw(&mut self.canvas, self.font, at);
+            }
+        }
+        if let Some(menu) = overlays.menu {
+            menu.draw(&mut self.canvas, self.font);
+        }
+        if let Some(help) = overlays.help {
+            help.draw(&mut self.canvas, self.font);
+        }
+        overlays.overlay.draw(&mut self.canvas, self.font);
+        if let Some(error) = overlays.error {
+            error.draw(&mut self.canvas, self.font);
+        }
+        self.canvas.flush();
+        self.surface
+            .swap_buffers(&self.context)
+            .map_err(|e| e.to_string())
+    }
+
+    fn vsync(&self) -> Option<Vsync> {
+        self.vsync
+    }
+}
+
+fn as_rgba<const N: usize>(p: &[Color; N]) -> &[RGBA8] {
+    unsafe {
+        ::core::slice::from_raw_parts(
+            (p as *const [Color; N]) as *const RGBA8,
+            ::core::mem::size_of::<[Color; N]>(),
+        )
+    }
+}
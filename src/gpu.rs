@@ -0,0 +1,680 @@
+use std::collections::HashMap;
+
+use fastnes::ppu::Color;
+use fontdue::{Font, FontSettings, Metrics};
+use tracing::warn;
+use winit::window::Window;
+
+use crate::{
+    cli::Vsync,
+    frametimes::{self, FrameTimes},
+    help, hexview,
+    letterbox::Scene,
+    menu,
+    overlay::{self, Item},
+    picker,
+    render::{Overlays, Present},
+    sprites::{self, SpriteViewer},
+    startup::{self, ErrorScreen},
+    tiles::{self, TileViewer},
+};
+
+// draws a texture on the quad given in clip space by `rect`
+const SHADER: &str = "
+struct Rect {
+    origin: vec2<f32>,
+    size: vec2<f32>,
+};
+
+@group(0) @binding(0) var image: texture_2d<f32>;
+@group(0) @binding(1) var nearest: sampler;
+@group(0) @binding(2) var<uniform> rect: Rect;
+
+struct Vertex {
+    @builtin(position) position: vec4<f32>,
+    @location(0) uv: vec2<f32>,
+};
+
+@vertex
+fn vs(@builtin(vertex_index) i: u32) -> Vertex {
+    let uv = vec2<f32>(f32(i & 1u), f32(i >> 1u));
+    var out: Vertex;
+    out.position = vec4<f32>(rect.origin + uv * rect.size, 0.0, 1.0);
+    out.uv = uv;
+    return out;
+}
+
+@fragment
+fn fs(in: Vertex) -> @location(0) vec4<f32> {
+    return textureSample(image, nearest, in.uv);
+}
+";
+
+// the same size as the OpenGL overlays
+const FONT_SIZE: f32 = 14.0;
+
+// An alternative renderer on wgpu, built with the wgpu feature.
+//
+// The game and the overlays are two textures drawn as quads. The overlays
+// are drawn on the CPU into a texture the size of the window, with glyphs
+// rasterized once from the same font the OpenGL renderer uses.
+pub struct Gpu {
+    surface: wgpu::Surface,
+    device: wgpu::Device,
+    queue: wgpu::Queue,
+    config: wgpu::SurfaceConfiguration,
+    pipeline: wgpu::RenderPipeline,
+    layout: wgpu::BindGroupLayout,
+    sampler: wgpu::Sampler,
+    game: Layer,
+    // recreated whenever the window size changes
+    overlay: Option<Layer>,
+    raster: Raster,
+    vsync: Option<Vsync>,
+}
+
+impl Gpu {
+    pub fn new(window: &Window, vsync: Option<Vsync>) -> Result<Self, String> {
+        let instance = wgpu::Instance::new(wgpu::InstanceDescriptor::default());
+        let surface = unsafe { instance.create_surface(window) }.map_err(|e| e.to_string())?;
+        let adapter = pollster::block_on(instance.request_adapter(&wgpu::RequestAdapterOptions {
+            compatible_surface: Some(&surface),
+            ..Default::default()
+        }))
+        .ok_or("no graphics adapter")?;
+        let (device, queue) = pollster::block_on(adapter.request_device(
+            &wgpu::DeviceDescriptor {
+                label: None,
+                features: wgpu::Features::empty(),
+                limits:
+                    wgpu::Limits::downlevel_webgl2_defaults().using_resolution(adapter.limits()),
+            },
+            None,
+        ))
+        .map_err(|e| e.to_string())?;
+
+        let capabilities = surface.get_capabilities(&adapter);
+        let format = capabilities
+            .formats
+            .iter()
+            .copied()
+            .find(|f| f.is_srgb())
+            .or_else(|| capabilities.formats.first().copied())
+            .ok_or("the surface supports no formats")?;
+        // every surface supports Fifo, the others fall back to it
+        let mut vsync = vsync;
+        let present_mode = match vsync {
+            None | Some(Vsync::On) => wgpu::PresentMode::Fifo,
+            Some(mode) => {
+                let wanted = match mode {
+                    Vsync::Off => wgpu::PresentMode::Immediate,
+                    _ => wgpu::PresentMode::FifoRelaxed,
+                };
+                if capabilities.present_modes.contains(&wanted) {
+                    wanted
+                } else {
+                    warn!(
+                        "the surface does not support vsync {}, using on",
+                        mode.name()
+                    );
+                    vsync = Some(Vsync::On);
+                    wgpu::PresentMode::Fifo
+                }
+            }
+        };
+        let size = window.inner_size();
+        let config = wgpu::SurfaceConfiguration {
+            usage: wgpu::TextureUsages::RENDER_ATTACHMENT,
+            format,
+            width: size.width.max(1),
+            height: size.height.max(1),
+            present_mode,
+            alpha_mode: capabilities.alpha_modes[0],
+            view_formats: vec![],
+        };
+        surface.configure(&device, &config);
+
+        let layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            label: None,
+            entries: &[
+                wgpu::BindGroupLayoutEntry {
+                    binding: 0,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Texture {
+                        sample_type: wgpu::TextureSampleType::Float { filterable: true },
+                        view_dimension: wgpu::TextureViewDimension::D2,
+                        multisampled: false,
+                    },
+                    count: None,
+                },
+                wgpu::BindGroupLayoutEntry {
+                    binding: 1,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Sampler(wgpu::SamplerBindingType::Filtering),
+                    count: None,
+                },
+                wgpu::BindGroupLayoutEntry {
+                    binding: 2,
+                    visibility: wgpu::ShaderStages::VERTEX,
+                    ty: wgpu::BindingType::Buffer {
+                        ty: wgpu::BufferBindingType::Uniform,
+                        has_dynamic_offset: false,
+                        min_binding_size: None,
+                    },
+                    count: None,
+                },
+            ],
+        });
+        let shader = device.create_shader_module(wgpu::ShaderModuleDescriptor {
+            label: None,
+            source: wgpu::ShaderSource::Wgsl(SHADER.into()),
+        });
+        let pipeline_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: None,
+            bind_group_layouts: &[&layout],
+            push_constant_ranges: &[],
+        });
+        let pipeline = device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+            label: None,
+            layout: Some(&pipeline_layout),
+            vertex: wgpu::VertexState {
+                module: &shader,
+                entry_point: "vs",
+                buffers: &[],
+            },
+            fragment: Some(wgpu::FragmentState {
+                module: &shader,
+                entry_point: "fs",
+                targets: &[Some(wgpu::ColorTargetState {
+                    format,
+                    blend: Some(wgpu::BlendState::ALPHA_BLENDING),
+                    write_mask: wgpu::ColorWrites::ALL,
+                })],
+            }),
+            primitive: wgpu::PrimitiveState {
+                topology: wgpu::PrimitiveTopology::TriangleStrip,
+                ..Default::default()
+            },
+            depth_stencil: None,
+            multisample: wgpu::MultisampleState::default(),
+            multiview: None,
+        });
+        let sampler = device.create_sampler(&wgpu::SamplerDescriptor {
+            mag_filter: wgpu::FilterMode::Nearest,
+            min_filter: wgpu::FilterMode::Nearest,
+            ..Default::default()
+        });
+
+        let game = Layer::new(&device, &layout, &sampler, 256, 240);
+        let font = Font::from_bytes(overlay::FONT, FontSettings::default())?;
+        Ok(Self {
+            surface,
+            device,
+            queue,
+            config,
+            pipeline,
+            layout,
+            sampler,
+            game,
+            overlay: None,
+            raster: Raster::new(font),
+            // Fifo is vsync on
+            vsync: vsync.or(Some(Vsync::On)),
+        })
+    }
+}
+
+impl Present for Gpu {
+    fn present(
+        &mut self,
+        window: &Window,
+        frame: &[Color; 61440],
+        scene: Scene,
+        overlays: Overlays,
+    ) -> Result<(), String> {
+        let size = window.inner_size();
+        if size.width == 0 || size.height == 0 {
+            // minimized
+            return Ok(());
+        }
+        let screen = (size.width, size.height);
+        if screen != (self.config.width, self.config.height) {
+            self.config.width = size.width;
+            self.config.height = size.height;
+            self.surface.configure(&self.device, &self.config);
+        }
+
+        let pixels: Vec<u8> = frame.iter().flat_map(|c| [c.r, c.g, c.b, 255]).collect();
+        self.game.upload(&self.queue, &pixels);
+        self.game.place(&self.queue, scene.image, screen);
+
+        let overlay = match self.overlay.take() {
+            Some(layer) if (layer.width, layer.height) == screen => layer,
+            _ => Layer::new(
+                &self.device,
+                &self.layout,
+                &self.sampler,
+                screen.0,
+                screen.1,
+            ),
+        };
+        self.raster.resize(screen.0 as usize, screen.1 as usize);
+        self.raster.draw(overlays);
+        overlay.upload(&self.queue, &self.raster.pixels);
+        overlay.place(
+            &self.queue,
+            (0.0, 0.0, screen.0 as f32, screen.1 as f32),
+            screen,
+        );
+
+        let [r, g, b] = scene.background.map(|c| c as f64 / 255.0);
+        let background = wgpu::Color { r, g, b, a: 1.0 };
+        let output = self
+            .surface
+            .get_current_texture()
+            .map_err(|e| e.to_string())?;
+        let view = output.texture.create_view(&Default::default());
+        let mut encoder = self.device.create_command_encoder(&Default::default());
+        {
+            let mut pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+                label: None,
+                color_attachments: &[Some(wgpu::RenderPassColorAttachment {
+                    view: &view,
+                    resolve_target: None,
+                    ops: wgpu::Operations {
+                        load: wgpu::LoadOp::Clear(background),
+                        store: true,
+                    },
+                })],
+                depth_stencil_attachment: None,
+            });
+            pass.set_pipeline(&self.pipeline);
+            for layer in [&self.game, &overlay] {
+                pass.set_bind_group(0, &layer.bind_group, &[]);
+                pass.draw(0..4, 0..1);
+            }
+        }
+        self.queue.submit([encoder.finish()]);
+        output.present();
+
+        self.overlay = Some(overlay);
+        Ok(())
+    }
+
+    fn vsync(&self) -> Option<Vsync> {
+        self.vsync
+    }
+}
+
+// An RGBA texture and where on the window it goes.
+struct Layer {
+    texture: wgpu::Texture,
+    rect: wgpu::Buffer,
+    bind_group: wgpu::BindGroup,
+    width: u32,
+    height: u32,
+}
+
+impl Layer {
+    fn new(
+        device: &wgpu::Device,
+        layout: &wgpu::BindGroupLayout,
+        sampler: &wgpu::Sampler,
+        width: u32,
+        height: u32,
+    ) -> Self {
+        let texture = device.create_texture(&wgpu::TextureDescriptor {
+            label: None,
+            size: wgpu::Extent3d {
+                width,
+                height,
+                depth_or_array_layers: 1,
+            },
+            mip_level_count: 1,
+            sample_count: 1,
+            dimension: wgpu::TextureDimension::D2,
+            format: wgpu::TextureFormat::Rgba8UnormSrgb,
+            usage: wgpu::TextureUsages::TEXTURE_BINDING | wgpu::TextureUsages::COPY_DST,
+            view_formats: &[],
+        });
+        let view = texture.create_view(&Default::default());
+        let rect = device.create_buffer(&wgpu::BufferDescriptor {
+            label: None,
+            size: 16,
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+        let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            label: None,
+            layout,
+            entries: &[
+                wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: wgpu::BindingResource::TextureView(&view),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 1,
+                    resource: wgpu::BindingResource::Sampler(sampler),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 2,
+                    resource: rect.as_entire_binding(),
+                },
+            ],
+        });
+        Self {
+            texture,
+            rect,
+            bind_group,
+            width,
+            height,
+        }
+    }
+
+    fn upload(&self, queue: &wgpu::Queue, pixels: &[u8]) {
+        queue.write_texture(
+            wgpu::ImageCopyTexture {
+                texture: &self.texture,
+                mip_level: 0,
+                origin: wgpu::Origin3d::ZERO,
+                aspect: wgpu::TextureAspect::All,
+            },
+            pixels,
+            wgpu::ImageDataLayout {
+                offset: 0,
+                bytes_per_row: Some(4 * self.width),
+                rows_per_image: Some(self.height),
+            },
+            wgpu::Extent3d {
+                width: self.width,
+                height: self.height,
+                depth_or_array_layers: 1,
+            },
+        );
+    }
+
+    // a rectangle in window pixels
+    fn place(&self, queue: &wgpu::Queue, (x, y, w, h): (f32, f32, f32, f32), screen: (u32, u32)) {
+        let (sw, sh) = (screen.0 as f32, screen.1 as f32);
+        let clip = [
+            -1.0 + 2.0 * x / sw,
+            1.0 - 2.0 * y / sh,
+            2.0 * w / sw,
+            -2.0 * h / sh,
+        ];
+        let bytes: Vec<u8> = clip.iter().flat_map(|f: &f32| f.to_ne_bytes()).collect();
+        queue.write_buffer(&self.rect, 0, &bytes);
+    }
+}
+
+// Draws the overlays into an RGBA buffer, laid out like the OpenGL ones.
+struct Raster {
+    font: Font,
+    glyphs: HashMap<(char, u32), (Metrics, Vec<u8>)>,
+    width: usize,
+    height: usize,
+    pixels: Vec<u8>,
+}
+
+impl Raster {
+    fn new(font: Font) -> Self {
+        Self {
+            font,
+            glyphs: HashMap::new(),
+            width: 0,
+            height: 0,
+            pixels: Vec::new(),
+        }
+    }
+
+    // also clears the buffer
+    fn resize(&mut self, width: usize, height: usize) {
+        self.width = width;
+        self.height = height;
+        self.pixels.clear();
+        self.pixels.resize(width * height * 4, 0);
+    }
+
+    fn draw(&mut self, overlays: Overlays) {
+        let (width, height) = (self.width as f32, self.height as f32);
+        if let Some(picker) = overlays.picker {
+            self.rect(0.0, 0.0, width, height, [24, 24, 32, 255]);
+            let (entries, selected) = picker.entries();
+            self.list((8.0, 8.0, width - 16.0), picker::TITLE, &entries, selected);
+        }
+        if let Some(viewer) = overlays.tiles {
+            self.tiles(viewer);
+        }
+        if let Some(viewer) = overlays.memory {
+            self.rect(0.0, 0.0, width, height, [24, 24, 32, 255]);
+            self.text(8.0, 20.0, hexview::TITLE, [255; 4]);
+            for (x, y, text, style) in viewer.cells(height) {
+                let [r, g, b] = style.color();
+                self.text(x, y, &text, [r, g, b, 255]);
+            }
+        }
+        if let (None, None, Some(viewer)) = (overlays.picker, overlays.tiles, overlays.sprites) {
+            self.sprites(viewer);
+        }
+        if let Some(times) = overlays.frame_times {
+            if let Some(at) = overlays.overlay.frame_times(times, (width, height)) {
+                self.frame_times(times, at);
+            }
+        }
+        if let Some(menu) = overlays.menu {
+            self.rect(0.0, 0.0, width, height, [0, 0, 0, 160]);
+            let (entries, selected) = menu.entries();
+            let (w, h) = (menu::PANEL_WIDTH, 48.0 + entries.len() as f32 * 22.0);
+            let (x, y) = ((width - w) / 2.0, (height - h) / 2.0);
+            self.rect(x, y, w, h, [24, 24, 32, 255]);
+            self.list((x, y, w), menu::TITLE, &entries, selected);
+        }
+        // see Help::draw
+        if let Some(help) = overlays.help {
+            self.rect(0.0, 0.0, width, height, [0, 0, 0, 200]);
+            self.text(8.0, 20.0, help::TITLE, [255; 4]);
+            for (x, y, text, [r, g, b]) in help.cells(height) {
+                self.text(x, y, &text, [r, g, b, 255]);
+            }
+        }
+        if let Some(text) = overlays.overlay.failure() {
+            self.rect(0.0, 0.0, width, height, [96, 0, 0, 200]);
+            self.text(12.0, 28.0, "the emulator stopped:", [255; 4]);
+            for (i, line) in text.lines().enumerate() {
+                self.text(12.0, 56.0 + i as f32 * 18.0, line, [255; 4]);
+            }
+        }
+        // see Overlay::draw
+        for item in overlays.overlay.items(width, height) {
+            match item {
+                Item::Rect {
+                    x, y, w, h, color, ..
+                } => self.rect(x, y, w, h, color),
+                Item::Text {
+                    x,
+                    baseline,
+                    text,
+                    size,
+                    color,
+                    outline,
+                } => {
+                    if outline {
+                        let shadow = [0, 0, 0, color[3]];
+                        for (dx, dy) in overlay::OUTLINE {
+                            self.sized_text(x + dx, baseline + dy, &text, shadow, size);
+                        }
+                    }
+                    self.sized_text(x, baseline, &text, color, size);
+                }
+            }
+        }
+        // see ErrorScreen::draw
+        if let Some(error) = overlays.error {
+            self.rect(0.0, 0.0, width, height, [64, 16, 16, 255]);
+            self.text(16.0, 28.0, startup::TITLE, [255; 4]);
+            let (lines, entries, selected) = error.lines();
+            for (i, line) in lines.iter().enumerate() {
+                self.text(16.0, 48.0 + i as f32 * 18.0, line, [255; 4]);
+            }
+            let y = ErrorScreen::entries_y(lines.len());
+            self.list((8.0, y, width - 16.0), "", &entries, selected);
+        }
+    }
+
+    // see TileViewer::draw
+    fn tiles(&mut self, viewer: &TileViewer) {
+        let (width, height) = (self.width as f32, self.height as f32);
+        self.rect(0.0, 0.0, width, height, [24, 24, 32, 255]);
+        self.text(8.0, 20.0, tiles::TITLE, [255; 4]);
+
+        let (x, y, w, h) = TileViewer::rect();
+        let scale = w as usize / tiles::WIDTH;
+        let rgba = viewer.rgba();
+        for py in 0..h as usize {
+            for px in 0..w as usize {
+                let i = (py / scale * tiles::WIDTH + px / scale) * 4;
+                let [r, g, b, a] = [rgba[i], rgba[i + 1], rgba[i + 2], rgba[i + 3]];
+                self.blend(x as usize + px, y as usize + py, [r, g, b, a], 255);
+            }
+        }
+        for (i, line) in viewer.lines().iter().enumerate() {
+            self.text(8.0, y + h + 22.0 + i as f32 * 18.0, line, [255; 4]);
+        }
+    }
+
+    // see SpriteViewer::draw
+    fn sprites(&mut self, viewer: &SpriteViewer) {
+        let (width, height) = (self.width as f32, self.height as f32);
+        for ((x, y, w, h), hovered) in viewer.boxes() {
+            let color = match hovered {
+                true => [255, 255, 0, 255],
+                false => [255, 0, 255, 255],
+            };
+            self.rect(x, y, w, 1.0, color);
+            self.rect(x, y + h - 1.0, w, 1.0, color);
+            self.rect(x, y, 1.0, h, color);
+            self.rect(x + w - 1.0, y, 1.0, h, color);
+        }
+
+        let x = sprites::PANEL_X;
+        self.rect(x, 0.0, width - x, height, [24, 24, 32, 220]);
+        self.text(x + 8.0, 20.0, sprites::TITLE, [255; 4]);
+        self.text(x + 8.0, 36.0, sprites::HEADER, [255; 4]);
+        for (y, text, hovered) in viewer.rows() {
+            if hovered {
+                self.rect(
+                    x,
+                    y - 14.0,
+                    width - x,
+                    sprites::ROW_HEIGHT,
+                    [96, 96, 32, 255],
+                );
+            }
+            self.text(x + 8.0, y, &text, [255; 4]);
+        }
+    }
+
+    // see FrameTimes::draw
+    fn frame_times(&mut self, times: &FrameTimes, at: (f32, f32)) {
+        let rect = FrameTimes::rect(at);
+        let (x, y, w, h) = rect;
+        let opaque = |[r, g, b]: [u8; 3]| [r, g, b, 255];
+        self.rect(x, y, w, h + times.footer(), [0, 0, 0, 180]);
+        for (bar, e, l) in times.bars(rect) {
+            self.rect(bar, y + h - e, 1.0, e, opaque(frametimes::EMULATION));
+            self.rect(bar, y + h - e - l, 1.0, l, opaque(frametimes::LUA));
+        }
+        self.rect(
+            x,
+            FrameTimes::budget(rect),
+            w,
+            1.0,
+            opaque(frametimes::BUDGET),
+        );
+        self.text(x + 4.0, y + h + 16.0, &times.label(), [255; 4]);
+        if let Some(latency) = times.latency_label() {
+            self.text(x + 4.0, y + h + 34.0, &latency, [255; 4]);
+        }
+    }
+
+    // see overlay::list
+    fn list(
+        &mut self,
+        (x, y, width): (f32, f32, f32),
+        title: &str,
+        entries: &[String],
+        selected: usize,
+    ) {
+        self.text(x + 8.0, y + 20.0, title, [255; 4]);
+        for (i, entry) in entries.iter().enumerate() {
+            let row = y + 48.0 + i as f32 * 22.0;
+            if i == selected {
+                self.rect(x, row - 16.0, width, 22.0, [60, 60, 96, 255]);
+            }
+            self.text(x + 8.0, row, entry, [255; 4]);
+        }
+    }
+
+    fn rect(&mut self, x: f32, y: f32, w: f32, h: f32, color: [u8; 4]) {
+        let (x0, y0) = (x.max(0.0) as usize, y.max(0.0) as usize);
+        let x1 = ((x + w).max(0.0) as usize).min(self.width);
+        let y1 = ((y + h).max(0.0) as usize).min(self.height);
+        for py in y0..y1 {
+            for px in x0..x1 {
+                self.blend(px, py, color, 255);
+            }
+        }
+    }
+
+    // `baseline` is the bottom of the text like femtovg's fill_text
+    fn text(&mut self, x: f32, baseline: f32, text: &str, color: [u8; 4]) {
+        self.sized_text(x, baseline, text, color, FONT_SIZE);
+    }
+
+    fn sized_text(&mut self, x: f32, baseline: f32, text: &str, color: [u8; 4], size: f32) {
+        let mut pen = x;
+        for c in text.chars() {
+            let (metrics, bitmap) = self.glyph(c, size);
+            let left = (pen + metrics.xmin as f32) as i64;
+            let top = (baseline - metrics.height as f32 - metrics.ymin as f32) as i64;
+            for gy in 0..metrics.height {
+                for gx in 0..metrics.width {
+                    let coverage = bitmap[gy * metrics.width + gx];
+                    let (px, py) = (left + gx as i64, top + gy as i64);
+                    if coverage > 0 && px >= 0 && py >= 0 {
+                        self.blend(px as usize, py as usize, color, coverage);
+                    }
+                }
+            }
+            pen += metrics.advance_width;
+        }
+    }
+
+    // cached by the bits of the size, the layout only has a few
+    fn glyph(&mut self, c: char, size: f32) -> (Metrics, Vec<u8>) {
+        let font = &self.font;
+        self.glyphs
+            .entry((c, size.to_bits()))
+            .or_insert_with(|| font.rasterize(c, size))
+            .clone()
+    }
+
+    // `color` over the pixel at `coverage`, with straight alpha
+    fn blend(&mut self, x: usize, y: usize, color: [u8; 4], coverage: u8) {
+        if x >= self.width || y >= self.height {
+            return;
+        }
+        let i = (y * self.width + x) * 4;
+        let dst = &mut self.pixels[i..i + 4];
+        let src_a = color[3] as u32 * coverage as u32 / 255;
+        let dst_a = dst[3] as u32 * (255 - src_a) / 255;
+        let out_a = src_a + dst_a;
+        if out_a == 0 {
+            return;
+        }
+        for c in 0..3 {
+            dst[c] = ((color[c] as u32 * src_a + dst[c] as u32 * dst_a) / out_a) as u8;
+        }
+        dst[3] = out_a as u8;
+    }
+}
@@ -0,0 +1,326 @@
+use std::mem;
+
+use crate::machine::{Emulator, Machine};
+
+// Frames the seek hotkeys move by, a second.
+pub const SEEK_STEP: usize = 60;
+
+// The newest kept state is this many frames apart from the next, further
+// back they are twice as far apart each time.
+pub const STATE_EVERY: usize = 60;
+
+// About what the kept states may take up in memory.
+pub const BUDGET: usize = 16 << 20;
+
+// How many frames a kept state steps per frame while it is behind.
+const CATCH_UP: usize = 4;
+
+// The recorded inputs, including those after the current frame once the
+// machine was sought back, so seek_frame can move both ways.
+//
+// Seeking starts from the machine or the kept state that is closest before
+// the frame and steps the recorded inputs from there, from power-on if there
+// is none. fastnes cannot copy its state, so the kept states are machines of
+// their own that follow the recording: state i stays at the last multiple of
+// STATE_EVERY << i that is at least that far behind the current frame. A
+// state taken by a seek trades places with the machine, so seeking back and
+// forth again finds it there. Each state is emulated besides the machine, at
+// most CATCH_UP frames a frame, and there is one more each time the
+// recording doubles in length until they fill BUDGET; then the ones furthest
+// back are dropped.
+//
+// Stepping a frame with a different input than the recording has for it
+// drops the rest of the recording and counts a re-record, as does restoring
+// a state that is not part of it; kept states past that frame go with it.
+pub struct Greenzone {
+    inputs: Vec<u8>,
+    revision: u64,
+    rerecords: u32,
+    states: Vec<Option<Machine>>,
+    budget: usize,
+}
+
+impl Greenzone {
+    pub fn new(machine: &Machine) -> Self {
+        Self {
+            inputs: machine.inputs().to_vec(),
+            revision: machine.revision(),
+            rerecords: 0,
+            states: Vec::new(),
+            budget: BUDGET,
+        }
+    }
+
+    // continuing a recording that was re-recorded before
+    pub fn with_rerecords(mut self, rerecords: u32) -> Self {
+        self.rerecords = rerecords;
+        self
+    }
+
+    // call after every frame, checks it against the recording like the
+    // journal does
+    pub fn sync(&mut self, machine: &Machine) {
+        let inputs = machine.inputs();
+        let frame = inputs.len();
+        if machine.revision() == self.revision + 1 && frame > 0 && self.inputs.len() >= frame - 1 {
+            // the common case, one more frame
+            match self.inputs.get(frame - 1) {
+                Some(&input) if input == inputs[frame - 1] => {}
+                recorded => {
+                    if recorded.is_some() {
+                        self.rerecords += 1;
+                    }
+                    self.inputs.truncate(frame - 1);
+                    self.inputs.push(inputs[frame - 1]);
+                    self.drop_states(|state| state.frame() >= frame);
+                }
+            }
+        } else if !self.inputs.starts_with(inputs) {
+            // restored to a state from elsewhere, which starts a new branch
+            // unless the machine only went further than the recording
+            if !inputs.starts_with(&self.inputs) {
+                self.rerecords += 1;
+            }
+            self.drop_states(|state| !inputs.starts_with(state.inputs()));
+            self.inputs = inputs.to_vec();
+        }
+        self.revision = machine.revision();
+        self.keep(machine);
+    }
+
+    // moves the kept states along behind the machine
+    fn keep(&mut self, machine: &Machine) {
+        let frame = machine.frame();
+        let needed = (0..)
+            .take_while(|&i| 2 * (STATE_EVERY << i) <= frame)
+            .count();
+        let room = (self.budget / state_size(machine)).max(1);
+        let count = self.states.len().max(needed).min(room);
+        self.states.truncate(count);
+        self.states.resize_with(count, || None);
+
+        for (i, state) in self.states.iter_mut().enumerate().take(needed) {
+            let every = STATE_EVERY << i;
+            let target = (frame - every) / every * every;
+            let state = state.get_or_insert_with(|| Machine::replay(machine.rom(), &[]));
+            let behind = target.saturating_sub(state.frame()).min(CATCH_UP);
+            for _ in 0..behind {
+                state.set_input(self.inputs[state.frame()]);
+                state.next_frame();
+            }
+        }
+    }
+
+    fn drop_states(&mut self, stale: impl Fn(&Machine) -> bool) {
+        for state in &mut self.states {
+            if state.as_ref().map_or(false, &stale) {
+                *state = None;
+            }
+        }
+    }
+
+    // a journal being played from power-on, all of it ahead of the machine
+    // so seeking can go anywhere in it
+    pub fn with_recording(mut self, inputs: Vec<u8>) -> Self {
+        self.inputs = inputs;
+        self
+    }
+
+    // the number of recorded frames
+    pub fn frames(&self) -> usize {
+        self.inputs.len()
+    }
+
+    pub fn rerecords(&self) -> u32 {
+        self.rerecords
+    }
+
+    // the input recorded for `frame` (counted from 0), if any
+    pub fn input(&self, frame: usize) -> Option<u8> {
+        self.inputs.get(frame).copied()
+    }
+
+    pub fn seek(&mut self, machine: &mut Machine, frame: usize) -> Result<(), String> {
+        if frame > self.inputs.len() {
+            return Err(format!(
+                "cannot seek to frame {}, the recording ends at frame {}",
+                frame,
+                self.inputs.len()
+            ));
+        }
+        let closest = self
+            .states
+            .iter_mut()
+            .flatten()
+            .filter(|state| state.frame() <= frame)
+            .max_by_key(|state| state.frame());
+        match closest {
+            Some(state) if machine.frame() > frame || state.frame() > machine.frame() => {
+                machine.swap(state)
+            }
+            _ if machine.frame() > frame => machine.restore(Vec::new()),
+            _ => {}
+        }
+        for &input in &self.inputs[machine.frame()..frame] {
+            machine.set_input(input);
+            machine.next_frame();
+        }
+        self.revision = machine.revision();
+        Ok(())
+    }
+}
+
+// roughly what a machine takes up, the ROM is in there twice
+fn state_size(machine: &Machine) -> usize {
+    2 * machine.rom().len() + machine.frame() + mem::size_of::<Emulator>()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        journal::{self, Journal},
+        savestate::Savestate,
+        testing,
+    };
+
+    struct Run {
+        machine: Machine,
+        greenzone: Greenzone,
+        journal: Journal,
+    }
+
+    impl Run {
+        // what Driver::step does after every frame
+        fn step(&mut self, input: u8) {
+            testing::step(&mut self.machine, input);
+            self.greenzone.sync(&self.machine);
+            let rerecords = self.greenzone.rerecords();
+            self.journal.record(&self.machine, rerecords).unwrap();
+        }
+    }
+
+    #[test]
+    fn branches() {
+        let path = testing::temp_dir("greenzone").join("inputs.journal");
+        let machine = testing::machine();
+        let mut run = Run {
+            greenzone: Greenzone::new(&machine),
+            journal: Journal::create(&path, &machine).unwrap(),
+            machine,
+        };
+        for input in 1..=4 {
+            run.step(input);
+        }
+        let outside = Savestate::capture(&run.machine);
+        for input in 5..=6 {
+            run.step(input);
+        }
+        assert_eq!(run.greenzone.inputs, [1, 2, 3, 4, 5, 6]);
+        assert_eq!(run.greenzone.rerecords(), 0);
+        assert!(run.journal.rewinds(&path).is_empty());
+
+        // seeking back keeps the recording, the journal rewinds once a
+        // frame is stepped
+        run.greenzone.seek(&mut run.machine, 2).unwrap();
+        assert_eq!(run.machine.inputs(), [1, 2]);
+        assert_eq!(run.greenzone.inputs, [1, 2, 3, 4, 5, 6]);
+        assert_eq!(run.greenzone.input(2), Some(3));
+        run.step(3);
+        assert_eq!(run.greenzone.inputs, [1, 2, 3, 4, 5, 6]);
+        assert_eq!(run.greenzone.rerecords(), 0);
+        assert_eq!(run.journal.rewinds(&path), [2]);
+
+        // a different input branches off and drops the rest
+        run.step(9);
+        assert_eq!(run.greenzone.inputs, [1, 2, 3, 9]);
+        assert_eq!(run.greenzone.rerecords(), 1);
+        run.step(10);
+        run.step(11);
+        assert_eq!(run.greenzone.inputs, [1, 2, 3, 9, 10, 11]);
+        assert_eq!(run.greenzone.rerecords(), 1);
+        assert_eq!(run.journal.rewinds(&path), [2]);
+
+        // seeking forward past the branch replays the new inputs, and
+        // stepping what is recorded is no re-record
+        run.greenzone.seek(&mut run.machine, 1).unwrap();
+        run.greenzone.seek(&mut run.machine, 5).unwrap();
+        assert_eq!(run.machine.inputs(), [1, 2, 3, 9, 10]);
+        assert!(run.greenzone.seek(&mut run.machine, 7).is_err());
+        run.step(11);
+        assert_eq!(run.greenzone.inputs, [1, 2, 3, 9, 10, 11]);
+        assert_eq!(run.greenzone.rerecords(), 1);
+        assert_eq!(run.journal.rewinds(&path), [2]);
+
+        // a state from the abandoned branch starts another one
+        outside.apply(&mut run.machine).unwrap();
+        run.step(12);
+        assert_eq!(run.greenzone.inputs, [1, 2, 3, 4, 12]);
+        assert_eq!(run.greenzone.rerecords(), 2);
+        assert_eq!(run.journal.rewinds(&path), [2, 3]);
+
+        let recording = journal::read(&path, run.machine.hash()).unwrap();
+        assert_eq!(recording.inputs, [1, 2, 3, 4, 12]);
+        assert_eq!(recording.rerecords, 2);
+    }
+
+    fn frames(greenzone: &Greenzone) -> Vec<Option<usize>> {
+        let states = greenzone.states.iter();
+        states
+            .map(|state| state.as_ref().map(Machine::frame))
+            .collect()
+    }
+
+    #[test]
+    fn states() {
+        let path = testing::temp_dir("greenzone-states").join("inputs.journal");
+        let machine = testing::machine();
+        let mut run = Run {
+            greenzone: Greenzone::new(&machine),
+            journal: Journal::create(&path, &machine).unwrap(),
+            machine,
+        };
+        for frame in 0..650 {
+            run.step(frame as u8);
+        }
+        assert_eq!(frames(&run.greenzone), [Some(540), Some(480), Some(240)]);
+        let same = |machine: &Machine| {
+            let replayed = Machine::replay(&testing::rom(), machine.inputs());
+            machine.nes().read_internal(0) == replayed.nes().read_internal(0)
+        };
+
+        // a seek takes the closest state before the frame, which trades
+        // places with the machine
+        run.greenzone.seek(&mut run.machine, 500).unwrap();
+        assert_eq!(run.machine.frame(), 500);
+        assert!(same(&run.machine));
+        assert_eq!(frames(&run.greenzone), [Some(540), Some(650), Some(240)]);
+        run.greenzone.seek(&mut run.machine, 650).unwrap();
+        assert_eq!(frames(&run.greenzone), [Some(540), Some(500), Some(240)]);
+        assert!(same(&run.machine));
+        run.greenzone.seek(&mut run.machine, 250).unwrap();
+        assert_eq!(frames(&run.greenzone), [Some(540), Some(500), Some(650)]);
+        assert!(same(&run.machine));
+
+        // stepping what was recorded keeps them, a branch drops those past it
+        run.step(250);
+        assert_eq!(frames(&run.greenzone), [Some(540), Some(500), Some(650)]);
+        run.step(0);
+        assert_eq!(run.greenzone.rerecords(), 1);
+        // new ones start from power-on and catch up
+        assert_eq!(frames(&run.greenzone), [Some(4), Some(4), None]);
+        run.greenzone.seek(&mut run.machine, 100).unwrap();
+        assert_eq!(run.machine.frame(), 100);
+        assert!(same(&run.machine));
+
+        // nothing before the frame, the seek starts from power-on
+        run.greenzone.seek(&mut run.machine, 2).unwrap();
+        assert_eq!(run.machine.frame(), 2);
+        assert!(same(&run.machine));
+
+        // the budget drops the states furthest back
+        run.greenzone.budget = state_size(&run.machine);
+        run.step(run.greenzone.input(2).unwrap());
+        assert_eq!(run.greenzone.states.len(), 1);
+    }
+}
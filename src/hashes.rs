@@ -0,0 +1,162 @@
+use std::{
+    fs::{self, File},
+    io::{self, BufWriter, Write},
+    path::{Path, PathBuf},
+    time::{Duration, Instant},
+};
+
+use crate::{machine::Machine, memory};
+
+const MAGIC: &[u8; 8] = b"MARLUAHS";
+const VERSION: u16 = 1;
+
+// HASH and a little-endian u64 is the state hash of the next frame
+const HASH: u8 = 0x00;
+// REWIND and a little-endian u32 frame drop every hash after it
+const REWIND: u8 = 0x01;
+// SKIP and a little-endian u32 count: that many frames were restored
+// without being emulated one by one, and have no hash
+const SKIP: u8 = 0x02;
+
+const FLUSH_INTERVAL: Duration = Duration::from_secs(1);
+
+// the hash track of a journal, inputs.hashes for inputs.journal
+pub fn sidecar(journal: &str) -> PathBuf {
+    Path::new(journal).with_extension("hashes")
+}
+
+// FNV-1a over the CPU RAM, which is where a desync shows first and is
+// cheap to read every frame, unlike the picture
+pub fn state(machine: &Machine) -> u64 {
+    let mut hash: u64 = 0xcbf29ce484222325;
+    for byte in memory::read_ram(machine) {
+        hash ^= byte as u64;
+        hash = hash.wrapping_mul(0x100000001b3);
+    }
+    hash
+}
+
+// `--record-hashes`: the state hash of every frame next to the journal, for
+// `marlua verify` to find where a replay stops matching.
+//
+// After the header (MAGIC, VERSION and the ROM's CRC32) come the records
+// above. Restoring a state is written like in the journal, as a rewind to
+// the last frame both input logs have in common, after which the restored
+// frames are skipped.
+pub struct Hashes {
+    writer: BufWriter<File>,
+    // the inputs hashed so far, after rewinds
+    log: Vec<u8>,
+    revision: u64,
+    flushed: Instant,
+}
+
+impl Hashes {
+    pub fn create(path: &Path, machine: &Machine) -> io::Result<Self> {
+        let mut writer = BufWriter::new(File::create(path)?);
+        writer.write_all(MAGIC)?;
+        writer.write_all(&VERSION.to_le_bytes())?;
+        writer.write_all(&machine.hash().to_le_bytes())?;
+
+        let mut hashes = Self {
+            writer,
+            log: Vec::new(),
+            revision: machine.revision(),
+            flushed: Instant::now(),
+        };
+        hashes.resync(machine)?;
+        Ok(hashes)
+    }
+
+    // call after every frame
+    pub fn record(&mut self, machine: &Machine) -> io::Result<()> {
+        let inputs = machine.inputs();
+        if machine.revision() == self.revision + 1 && inputs.len() == self.log.len() + 1 {
+            self.writer.write_all(&[HASH])?;
+            self.writer.write_all(&state(machine).to_le_bytes())?;
+            self.log.push(inputs[inputs.len() - 1]);
+        } else {
+            self.resync(machine)?;
+        }
+        self.revision = machine.revision();
+
+        if self.flushed.elapsed() >= FLUSH_INTERVAL {
+            self.writer.flush()?;
+            self.flushed = Instant::now();
+        }
+        Ok(())
+    }
+
+    fn resync(&mut self, machine: &Machine) -> io::Result<()> {
+        let inputs = machine.inputs();
+        let common = self
+            .log
+            .iter()
+            .zip(inputs)
+            .take_while(|(a, b)| a == b)
+            .count();
+        if common < self.log.len() {
+            self.writer.write_all(&[REWIND])?;
+            self.writer.write_all(&(common as u32).to_le_bytes())?;
+            self.log.truncate(common);
+        }
+        if inputs.len() > common {
+            self.writer.write_all(&[SKIP])?;
+            self.writer
+                .write_all(&((inputs.len() - common) as u32).to_le_bytes())?;
+            self.log.extend_from_slice(&inputs[common..]);
+        }
+        Ok(())
+    }
+}
+
+impl Drop for Hashes {
+    fn drop(&mut self) {
+        let _ = self.writer.flush();
+    }
+}
+
+// the hash of every frame from the first, None where it was skipped; a
+// record cut off by a crash is ignored
+pub fn read(path: &Path, rom_hash: u32) -> Result<Vec<Option<u64>>, String> {
+    let bytes = fs::read(path).map_err(|e| format!("{}: {}", path.display(), e))?;
+    let error = |msg: &str| format!("{}: {}", path.display(), msg);
+
+    if bytes.len() < 14 || &bytes[..8] != MAGIC {
+        return Err(error("not a hash track"));
+    }
+    let version = u16::from_le_bytes([bytes[8], bytes[9]]);
+    if !(1..=VERSION).contains(&version) {
+        return Err(error(&format!("unknown hash track version {}", version)));
+    }
+    let hash = u32::from_le_bytes(bytes[10..14].try_into().unwrap());
+    if hash != rom_hash {
+        return Err(error(&format!(
+            "hashes were recorded with ROM {:08X}, not {:08X}",
+            hash, rom_hash
+        )));
+    }
+
+    let mut hashes = Vec::new();
+    let mut rest = &bytes[14..];
+    loop {
+        match rest {
+            [HASH, tail @ ..] if tail.len() >= 8 => {
+                hashes.push(Some(u64::from_le_bytes(tail[..8].try_into().unwrap())));
+                rest = &tail[8..];
+            }
+            [REWIND, a, b, c, d, tail @ ..] => {
+                hashes.truncate(u32::from_le_bytes([*a, *b, *c, *d]) as usize);
+                rest = tail;
+            }
+            [SKIP, a, b, c, d, tail @ ..] => {
+                let count = u32::from_le_bytes([*a, *b, *c, *d]) as usize;
+                hashes.resize(hashes.len() + count, None);
+                rest = tail;
+            }
+            [HASH | REWIND | SKIP, ..] | [] => break,
+            _ => return Err(error("corrupt hash track")),
+        }
+    }
+    Ok(hashes)
+}
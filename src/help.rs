@@ -0,0 +1,131 @@
+use femtovg::{renderer::OpenGl, Canvas, Color, FontId, Paint, Path};
+use winit::event::VirtualKeyCode;
+
+use crate::{bindings::Bindings, input::InputState};
+
+pub const TITLE: &str = "Key bindings (Escape: close)";
+
+// x of the action, key and source columns
+const COLUMNS: [f32; 3] = [16.0, 216.0, 336.0];
+const ROW_HEIGHT: f32 = 18.0;
+// the first row's baseline, below the column headers
+const FIRST_ROW: f32 = 66.0;
+// room for the version and files at the bottom
+const FOOTER: f32 = 30.0;
+
+const HEADER: [&str; 3] = ["action", "key", "source"];
+const LABEL: [u8; 3] = [140, 140, 160];
+const PLAIN: [u8; 3] = [255, 255, 255];
+const SCRIPT: [u8; 3] = [0, 255, 255];
+
+// The binding table opened with F1, over the dimmed game while emulation
+// is paused. It is rebuilt from the bindings every frame, so keys a script
+// binds or unbinds show up while it is open.
+pub struct Help {
+    // action, key and source
+    rows: Vec<[String; 3]>,
+    footer: String,
+    scroll: usize,
+    visible: usize,
+}
+
+impl Help {
+    pub fn new(footer: String) -> Self {
+        Self {
+            rows: Vec::new(),
+            footer,
+            scroll: 0,
+            visible: 1,
+        }
+    }
+
+    // the hotkeys, then the keys scripts bound with bind_key
+    pub fn update(&mut self, bindings: &Bindings, input: &InputState) {
+        let script = input.script_bindings();
+        let mut rows = Vec::new();
+        for (action, key, source) in bindings.table() {
+            let source = match script.iter().any(|(k, _)| *k == key) {
+                true => format!("{}, taken by script", source),
+                false => source.to_owned(),
+            };
+            rows.push([action.to_owned(), key.to_owned(), source]);
+        }
+        for (key, name) in script {
+            rows.push([name, key.to_owned(), "script".to_owned()]);
+        }
+        self.rows = rows;
+        self.scroll = self.scroll.min(self.last());
+    }
+
+    fn last(&self) -> usize {
+        self.rows.len().saturating_sub(self.visible)
+    }
+
+    pub fn key(&mut self, key: VirtualKeyCode) {
+        self.scroll = match key {
+            VirtualKeyCode::Up => self.scroll.saturating_sub(1),
+            VirtualKeyCode::Down => self.scroll + 1,
+            VirtualKeyCode::PageUp => self.scroll.saturating_sub(self.visible),
+            VirtualKeyCode::PageDown => self.scroll + self.visible,
+            VirtualKeyCode::Home => 0,
+            VirtualKeyCode::End => self.last(),
+            _ => return,
+        }
+        .min(self.last());
+    }
+
+    // how many rows fit in a window of this height, call before drawing
+    pub fn fit(&mut self, height: f32) {
+        self.visible = (((height - FIRST_ROW - FOOTER) / ROW_HEIGHT) as usize + 1).max(1);
+        self.scroll = self.scroll.min(self.last());
+    }
+
+    // text to draw at x and baseline y in a color, for any renderer
+    pub fn cells(&self, height: f32) -> Vec<(f32, f32, String, [u8; 3])> {
+        let mut cells = Vec::new();
+        for (x, header) in COLUMNS.iter().zip(HEADER) {
+            cells.push((*x, 44.0, header.to_owned(), LABEL));
+        }
+        let rows = self.rows.iter().enumerate();
+        for (i, row) in rows.skip(self.scroll).take(self.visible) {
+            let y = FIRST_ROW + (i - self.scroll) as f32 * ROW_HEIGHT;
+            let color = match row[2].as_str() {
+                "script" => SCRIPT,
+                _ => PLAIN,
+            };
+            for (x, text) in COLUMNS.iter().zip(row) {
+                cells.push((*x, y, text.clone(), color));
+            }
+        }
+
+        let mut footer = self.footer.clone();
+        if self.rows.len() > self.visible {
+            footer = format!(
+                "{}  (rows {}-{} of {}, Up/Down to scroll)",
+                footer,
+                self.scroll + 1,
+                (self.scroll + self.visible).min(self.rows.len()),
+                self.rows.len()
+            );
+        }
+        cells.push((8.0, height - 12.0, footer, LABEL));
+        cells
+    }
+
+    pub fn draw(&self, canvas: &mut Canvas<OpenGl>, font: FontId) {
+        let (width, height) = (canvas.width() as f32, canvas.height() as f32);
+        let mut dim = Path::new();
+        dim.rect(0.0, 0.0, width, height);
+        canvas.fill_path(&mut dim, &Paint::color(Color::rgba(0, 0, 0, 200)));
+
+        let mut paint = Paint::color(Color::white());
+        paint.set_font(&[font]);
+        paint.set_font_size(14.0);
+        let _ = canvas.fill_text(8.0, 20.0, TITLE, &paint);
+
+        for (x, y, text, [r, g, b]) in self.cells(height) {
+            paint.set_color(Color::rgb(r, g, b));
+            let _ = canvas.fill_text(x, y, &text, &paint);
+        }
+    }
+}
@@ -0,0 +1,177 @@
+use femtovg::{renderer::OpenGl, Canvas, Color, FontId, Paint, Path};
+use winit::event::VirtualKeyCode;
+
+use crate::views::{Ram, Views};
+
+pub const TITLE: &str = "CPU RAM (click: watch)";
+
+// fastnes has no way to write memory, and writes would not survive
+// restoring a state since states are replayed from inputs
+pub const FREEZE: &str = "freezing bytes is not available: fastnes cannot write memory";
+
+const COLUMNS: usize = 16;
+// where the first byte column starts and how far apart columns are
+const LEFT: f32 = 64.0;
+const COLUMN_WIDTH: f32 = 24.0;
+const ROW_HEIGHT: f32 = 18.0;
+// the first row's baseline
+const FIRST_ROW: f32 = 58.0;
+// room for the watch list at the bottom
+const FOOTER: f32 = 30.0;
+// rows of text extend this far above the baseline
+const ASCENT: f32 = 14.0;
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum Style {
+    Plain,
+    Label,
+    Changed,
+    Watched,
+}
+
+impl Style {
+    pub fn color(self) -> [u8; 3] {
+        match self {
+            Style::Plain => [255, 255, 255],
+            Style::Label => [140, 140, 160],
+            Style::Changed => [255, 255, 0],
+            Style::Watched => [0, 255, 255],
+        }
+    }
+}
+
+// The hex viewer, shown instead of the game. Bytes that changed since the
+// last update are highlighted, clicking a byte adds it to the watch list.
+pub struct HexViewer {
+    ram: Ram,
+    watches: Vec<u16>,
+    scroll: usize,
+    rows: usize,
+}
+
+impl HexViewer {
+    pub fn new() -> Self {
+        Self {
+            ram: Ram::default(),
+            watches: Vec::new(),
+            scroll: 0,
+            rows: 1,
+        }
+    }
+
+    // pick up what the emulator published
+    pub fn update(&mut self, views: &Views) {
+        if let Some(ram) = views.take_ram() {
+            self.ram = ram;
+        }
+    }
+
+    fn total_rows(&self) -> usize {
+        (self.ram.bytes.len() + COLUMNS - 1) / COLUMNS
+    }
+
+    pub fn key(&mut self, key: VirtualKeyCode) {
+        let last = self.total_rows().saturating_sub(self.rows);
+        self.scroll = match key {
+            VirtualKeyCode::Up => self.scroll.saturating_sub(1),
+            VirtualKeyCode::Down => self.scroll + 1,
+            VirtualKeyCode::PageUp => self.scroll.saturating_sub(self.rows),
+            VirtualKeyCode::PageDown => self.scroll + self.rows,
+            VirtualKeyCode::Home => 0,
+            VirtualKeyCode::End => last,
+            _ => return,
+        }
+        .min(last);
+    }
+
+    // how many rows fit in a window of this height, call before drawing
+    pub fn fit(&mut self, height: f32) {
+        self.rows = (((height - FIRST_ROW - FOOTER) / ROW_HEIGHT) as usize + 1).max(1);
+        let last = self.total_rows().saturating_sub(self.rows);
+        self.scroll = self.scroll.min(last);
+    }
+
+    // the address of the byte at a window position
+    pub fn byte_at(&self, (x, y): (f32, f32)) -> Option<u16> {
+        if x < LEFT || y < FIRST_ROW - ASCENT {
+            return None;
+        }
+        let column = ((x - LEFT) / COLUMN_WIDTH) as usize;
+        let row = ((y - FIRST_ROW + ASCENT) / ROW_HEIGHT) as usize;
+        if column >= COLUMNS || row >= self.rows {
+            return None;
+        }
+        let addr = (self.scroll + row) * COLUMNS + column;
+        (addr < self.ram.bytes.len()).then_some(addr as u16)
+    }
+
+    // adds the byte to the watch list, or removes it if it already was
+    pub fn toggle_watch(&mut self, addr: u16) {
+        match self.watches.iter().position(|&a| a == addr) {
+            Some(i) => {
+                self.watches.remove(i);
+            }
+            None => self.watches.push(addr),
+        }
+    }
+
+    // everything to draw as (x, baseline, text, style), for any renderer
+    pub fn cells(&self, height: f32) -> Vec<(f32, f32, String, Style)> {
+        let mut cells = Vec::new();
+        for column in 0..COLUMNS {
+            let x = LEFT + column as f32 * COLUMN_WIDTH;
+            cells.push((x, 40.0, format!("{:02X}", column), Style::Label));
+        }
+
+        let rows = self.ram.bytes.chunks(COLUMNS).enumerate();
+        for (row, bytes) in rows.skip(self.scroll).take(self.rows) {
+            let y = FIRST_ROW + (row - self.scroll) as f32 * ROW_HEIGHT;
+            let start = row * COLUMNS;
+            cells.push((8.0, y, format!("${:04X}", start), Style::Label));
+            for (column, byte) in bytes.iter().enumerate() {
+                let addr = start + column;
+                let style = if self.watches.contains(&(addr as u16)) {
+                    Style::Watched
+                } else if self.ram.changed[addr] {
+                    Style::Changed
+                } else {
+                    Style::Plain
+                };
+                let x = LEFT + column as f32 * COLUMN_WIDTH;
+                cells.push((x, y, format!("{:02X}", byte), style));
+            }
+        }
+
+        cells.push((8.0, height - 12.0, self.footer(), Style::Watched));
+        cells
+    }
+
+    fn footer(&self) -> String {
+        if self.watches.is_empty() {
+            return "no watched bytes".to_owned();
+        }
+        let watches = self.watches.iter().map(|&addr| {
+            let value = self.ram.bytes.get(addr as usize).copied().unwrap_or(0);
+            format!("${:04X}={:02X}", addr, value)
+        });
+        watches.collect::<Vec<_>>().join(" ")
+    }
+
+    pub fn draw(&self, canvas: &mut Canvas<OpenGl>, font: FontId) {
+        let (width, height) = (canvas.width() as f32, canvas.height() as f32);
+        let mut background = Path::new();
+        background.rect(0.0, 0.0, width, height);
+        canvas.fill_path(&mut background, &Paint::color(Color::rgb(24, 24, 32)));
+
+        let mut paint = Paint::color(Color::white());
+        paint.set_font(&[font]);
+        paint.set_font_size(14.0);
+        let _ = canvas.fill_text(8.0, 20.0, TITLE, &paint);
+
+        for (x, y, text, style) in self.cells(height) {
+            let [r, g, b] = style.color();
+            paint.set_color(Color::rgb(r, g, b));
+            let _ = canvas.fill_text(x, y, &text, &paint);
+        }
+    }
+}
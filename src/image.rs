@@ -0,0 +1,199 @@
+use std::{
+    path::PathBuf,
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc,
+    },
+};
+
+use rlua::{prelude::LuaError, UserData, UserDataMethods};
+
+use crate::{
+    output::{self, OutputDir},
+    picture,
+};
+
+// what lua.max_images defaults to, about 15 MB of full frames
+pub const MAX: usize = 64;
+
+// The images captured by a script, limited to `max` alive at a time so a
+// script that keeps every frame runs into an error instead of out of
+// memory. An image counts until Lua collects it.
+#[derive(Clone)]
+pub struct Images {
+    alive: Arc<AtomicUsize>,
+    max: usize,
+    run_dir: PathBuf,
+    // the screenshots permission, which :save needs like screenshot()
+    can_save: bool,
+}
+
+impl Images {
+    pub fn new(max: usize, output: &OutputDir) -> Self {
+        Self {
+            alive: Arc::new(AtomicUsize::new(0)),
+            max,
+            run_dir: output.run_dir().to_owned(),
+            can_save: false,
+        }
+    }
+
+    pub fn allow_saving(&mut self, allowed: bool) {
+        self.can_save = allowed;
+    }
+
+    pub fn is_full(&self) -> bool {
+        self.alive.load(Ordering::Relaxed) >= self.max
+    }
+
+    // a copy of `rgba`, row by row like Capture::frame
+    pub fn create(&self, width: usize, height: usize, rgba: Vec<u8>) -> Result<Image, LuaError> {
+        if self.is_full() {
+            return Err(too_many(self.max));
+        }
+        self.alive.fetch_add(1, Ordering::Relaxed);
+        Ok(Image {
+            width,
+            height,
+            rgba,
+            owner: self.clone(),
+        })
+    }
+}
+
+fn too_many(max: usize) -> LuaError {
+    LuaError::RuntimeError(format!(
+        "cannot keep more than {} images (lua.max_images), drop references to old ones \
+         or call collectgarbage()",
+        max
+    ))
+}
+
+// A snapshot of the frame or part of it, from capture(). The pixel loops
+// run here rather than in Lua, and methods return plain values except
+// :region, which is another image.
+pub struct Image {
+    width: usize,
+    height: usize,
+    rgba: Vec<u8>,
+    owner: Images,
+}
+
+impl Image {
+    fn pixel(&self, x: usize, y: usize) -> Option<&[u8]> {
+        if x >= self.width || y >= self.height {
+            return None;
+        }
+        let start = (y * self.width + x) * 4;
+        Some(&self.rgba[start..start + 4])
+    }
+
+    fn region(&self, x: usize, y: usize, w: usize, h: usize) -> Result<Image, LuaError> {
+        // huge sizes from Lua would wrap around rather than exceed the image
+        let exceeds =
+            |start: usize, len: usize, max| start.checked_add(len).map_or(true, |e| e > max);
+        if exceeds(x, w, self.width) || exceeds(y, h, self.height) {
+            return Err(LuaError::RuntimeError(format!(
+                "region {}x{} at ({}, {}) exceeds the {}x{} image",
+                w, h, x, y, self.width, self.height
+            )));
+        }
+        let mut rgba = Vec::with_capacity(w * h * 4);
+        for row in y..y + h {
+            let start = (row * self.width + x) * 4;
+            rgba.extend_from_slice(&self.rgba[start..start + w * 4]);
+        }
+        self.owner.create(w, h, rgba)
+    }
+
+    // FNV-1a over the RGBA bytes, the same as hash_region for that region
+    fn hash(&self) -> u64 {
+        let mut hash: u64 = 0xcbf29ce484222325;
+        for &byte in &self.rgba {
+            hash ^= byte as u64;
+            hash = hash.wrapping_mul(0x100000001b3);
+        }
+        hash
+    }
+
+    // a PNG in the run directory like screenshot()
+    fn save(&self, name: &str) -> Result<String, LuaError> {
+        if !self.owner.can_save {
+            return Err(LuaError::RuntimeError(
+                "image:save needs the screenshots permission, add \
+                 `-- marlua: permissions = screenshots` to the top of the script"
+                    .to_owned(),
+            ));
+        }
+        let path = output::artifact(&self.owner.run_dir, name)?;
+        picture::write_png(&path, self.width, self.height, &self.rgba)
+            .map_err(|e| LuaError::RuntimeError(format!("{}: {}", path.display(), e)))?;
+        Ok(path.display().to_string())
+    }
+}
+
+impl Drop for Image {
+    fn drop(&mut self) {
+        self.owner.alive.fetch_sub(1, Ordering::Relaxed);
+    }
+}
+
+impl UserData for Image {
+    fn add_methods<'lua, M: UserDataMethods<'lua, Self>>(methods: &mut M) {
+        methods.add_method("size", |_, this, ()| Ok((this.width, this.height)));
+        // red, green and blue from 0 to 255
+        methods.add_method("pixel", |_, this, (x, y): (usize, usize)| {
+            let pixel = this.pixel(x, y).ok_or_else(|| {
+                LuaError::RuntimeError(format!(
+                    "pixel ({}, {}) is outside the {}x{} image",
+                    x, y, this.width, this.height
+                ))
+            })?;
+            Ok((pixel[0], pixel[1], pixel[2]))
+        });
+        methods.add_method(
+            "region",
+            |_, this, (x, y, w, h): (usize, usize, usize, usize)| this.region(x, y, w, h),
+        );
+        methods.add_method("hash", |_, this, ()| Ok(this.hash() as i64));
+        methods.add_method("save", |_, this, (name,): (String,)| this.save(&name));
+        // the number of pixels whose color differs, alpha is ignored
+        methods.add_method("diff", |_, this, (other,): (rlua::AnyUserData,)| {
+            let other = other.borrow::<Image>()?;
+            if (other.width, other.height) != (this.width, this.height) {
+                return Err(LuaError::RuntimeError(format!(
+                    "cannot diff a {}x{} image with a {}x{} one",
+                    this.width, this.height, other.width, other.height
+                )));
+            }
+            let changed = this
+                .rgba
+                .chunks_exact(4)
+                .zip(other.rgba.chunks_exact(4))
+                .filter(|(a, b)| a[..3] != b[..3])
+                .count();
+            Ok(changed)
+        });
+        // the positions, as {x, y} tables row by row, of the pixels within
+        // `tolerance` of an 0xRRGGBB color in every channel
+        methods.add_method(
+            "find",
+            |ctx, this, (color, tolerance): (u32, Option<u8>)| {
+                let tolerance = tolerance.unwrap_or(0);
+                let wanted = [(color >> 16) as u8, (color >> 8) as u8, color as u8];
+                let found = ctx.create_table()?;
+                let mut n = 0;
+                for (i, pixel) in this.rgba.chunks_exact(4).enumerate() {
+                    if (0..3).all(|c| pixel[c].abs_diff(wanted[c]) <= tolerance) {
+                        let position = ctx.create_table()?;
+                        position.set("x", i % this.width)?;
+                        position.set("y", i / this.width)?;
+                        n += 1;
+                        found.set(n, position)?;
+                    }
+                }
+                Ok(found)
+            },
+        );
+    }
+}
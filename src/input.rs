@@ -0,0 +1,156 @@
+use std::{
+    collections::{BTreeMap, HashSet},
+    sync::Mutex,
+};
+
+use winit::event::{MouseButton, VirtualKeyCode};
+
+use crate::bindings;
+
+pub const LEFT: u8 = 1 << 0;
+pub const RIGHT: u8 = 1 << 1;
+pub const MIDDLE: u8 = 1 << 2;
+
+// clicks and key events kept while no script collects them
+const MAX_EVENTS: usize = 64;
+
+// a key bound with bind_key
+pub struct ScriptKey {
+    // what the help overlay calls it
+    pub name: String,
+    // it takes the key over from a hotkey
+    pub forced: bool,
+}
+
+pub struct Click {
+    pub x: u32,
+    pub y: u32,
+    pub button: &'static str,
+}
+
+#[derive(Default)]
+struct State {
+    // in NES pixels, None outside the image
+    cursor: Option<(u32, u32)>,
+    buttons: u8,
+    clicks: Vec<Click>,
+    keys: HashSet<VirtualKeyCode>,
+    // key name and whether it was pressed or released
+    key_events: Vec<(&'static str, bool)>,
+    // by key name, the callbacks are in the script's registry
+    bound: BTreeMap<&'static str, ScriptKey>,
+}
+
+// Mouse and keyboard state maintained by the window for scripts.
+#[derive(Default)]
+pub struct InputState {
+    state: Mutex<State>,
+}
+
+impl InputState {
+    // `rect` is where the image is drawn in the window: x, y, width, height
+    pub fn move_cursor(&self, position: Option<(f64, f64)>, rect: (f32, f32, f32, f32)) {
+        let (x, y, width, height) = rect;
+        let cursor = position.and_then(|(px, py)| {
+            let nx = (px - x as f64) * 256.0 / width as f64;
+            let ny = (py - y as f64) * 240.0 / height as f64;
+            ((0.0..256.0).contains(&nx) && (0.0..240.0).contains(&ny))
+                .then_some((nx as u32, ny as u32))
+        });
+        self.state.lock().unwrap().cursor = cursor;
+    }
+
+    pub fn button(&self, button: MouseButton, pressed: bool) {
+        let (bit, name) = match button {
+            MouseButton::Left => (LEFT, "left"),
+            MouseButton::Right => (RIGHT, "right"),
+            MouseButton::Middle => (MIDDLE, "middle"),
+            MouseButton::Other(_) => return,
+        };
+
+        let mut state = self.state.lock().unwrap();
+        if !pressed {
+            state.buttons &= !bit;
+            return;
+        }
+        state.buttons |= bit;
+        if let Some((x, y)) = state.cursor {
+            if state.clicks.len() == MAX_EVENTS {
+                state.clicks.remove(0);
+            }
+            state.clicks.push(Click { x, y, button: name });
+        }
+    }
+
+    // cursor position and pressed buttons
+    pub fn mouse(&self) -> Option<(u32, u32, u8)> {
+        let state = self.state.lock().unwrap();
+        state.cursor.map(|(x, y)| (x, y, state.buttons))
+    }
+
+    // clicks on the image since the last call
+    pub fn take_clicks(&self) -> Vec<Click> {
+        std::mem::take(&mut self.state.lock().unwrap().clicks)
+    }
+
+    pub fn key(&self, key: VirtualKeyCode, pressed: bool) {
+        let mut state = self.state.lock().unwrap();
+        // ignore key repeat
+        let changed = if pressed {
+            state.keys.insert(key)
+        } else {
+            state.keys.remove(&key)
+        };
+        if changed {
+            if state.key_events.len() == MAX_EVENTS {
+                state.key_events.remove(0);
+            }
+            state.key_events.push((bindings::key_name(key), pressed));
+        }
+    }
+
+    pub fn key_down(&self, key: VirtualKeyCode) -> bool {
+        self.state.lock().unwrap().keys.contains(&key)
+    }
+
+    // key presses and releases since the last call
+    pub fn take_key_events(&self) -> Vec<(&'static str, bool)> {
+        std::mem::take(&mut self.state.lock().unwrap().key_events)
+    }
+
+    pub fn bind(&self, key: VirtualKeyCode, binding: ScriptKey) {
+        let name = bindings::key_name(key);
+        self.state.lock().unwrap().bound.insert(name, binding);
+    }
+
+    // whether `key` was bound
+    pub fn unbind(&self, key: VirtualKeyCode) -> bool {
+        let name = bindings::key_name(key);
+        self.state.lock().unwrap().bound.remove(name).is_some()
+    }
+
+    // when a script starts over
+    pub fn unbind_all(&self) {
+        self.state.lock().unwrap().bound.clear();
+    }
+
+    // the window leaves `key` to the script instead of its hotkey
+    pub fn taken_over(&self, key: VirtualKeyCode) -> bool {
+        let name = bindings::key_name(key);
+        let state = self.state.lock().unwrap();
+        state
+            .bound
+            .get(name)
+            .map_or(false, |binding| binding.forced)
+    }
+
+    // key name and binding name of every script binding
+    pub fn script_bindings(&self) -> Vec<(&'static str, String)> {
+        let state = self.state.lock().unwrap();
+        state
+            .bound
+            .iter()
+            .map(|(key, binding)| (*key, binding.name.clone()))
+            .collect()
+    }
+}
@@ -0,0 +1,208 @@
+use std::{
+    fs::{self, File},
+    io::{self, BufWriter, Write},
+    time::{Duration, Instant},
+};
+
+use crate::{
+    buttons::{self, Aliases},
+    machine::Machine,
+};
+
+const HEADER: &str = "frame,controller1";
+const FLUSH_INTERVAL: Duration = Duration::from_secs(1);
+
+// Controller input from `--inputs`, for handing marlua inputs from other
+// tools:
+//
+//   # gaps = hold
+//   frame,controller1
+//   300,A+RIGHT
+//   301,129
+//
+// Frames count like frame(), the first frame is 1. A controller is a byte
+// or button names joined with '+', empty for none. Frames between rows hold
+// the previous row's input, or none with `# gaps = neutral`. A second
+// controller column may only hold 0, fastnes emulates one controller.
+pub struct InputFile {
+    // the frame of the first row
+    first: usize,
+    inputs: Vec<u8>,
+}
+
+impl InputFile {
+    // the whole file is checked before anything is emulated
+    pub fn read(path: &str, aliases: &Aliases) -> Result<Self, String> {
+        let text = fs::read_to_string(path).map_err(|e| format!("{}: {}", path, e))?;
+        let mut hold = true;
+        let mut rows: Vec<(usize, u8)> = Vec::new();
+        for (i, line) in text.lines().enumerate() {
+            let error = |msg: String| format!("{}:{}: {}", path, i + 1, msg);
+            let line = line.trim();
+            if let Some(comment) = line.strip_prefix('#') {
+                if let Some((key, value)) = comment.split_once('=') {
+                    if key.trim() == "gaps" {
+                        hold = match value.trim() {
+                            "hold" => true,
+                            "neutral" => false,
+                            other => {
+                                return Err(error(format!(
+                                    "unknown gaps {}, expected hold or neutral",
+                                    other
+                                )))
+                            }
+                        };
+                    }
+                }
+                continue;
+            }
+            if line.is_empty() || (rows.is_empty() && line.starts_with("frame")) {
+                continue;
+            }
+
+            let mut columns = line.split(',').map(str::trim);
+            let frame = columns.next().unwrap_or_default();
+            let frame = match frame.parse::<usize>() {
+                Ok(frame) if frame > 0 => frame,
+                _ => {
+                    return Err(error(format!(
+                        "expected a frame from 1 on, got {:?}",
+                        frame
+                    )))
+                }
+            };
+            if let Some(&(last, _)) = rows.last() {
+                if frame <= last {
+                    return Err(error(format!(
+                        "frame {} does not come after {}",
+                        frame, last
+                    )));
+                }
+            }
+            let input = controller(columns.next().unwrap_or_default(), aliases).map_err(error)?;
+            if let Some(second) = columns.next() {
+                if controller(second, aliases).map_err(error)? != 0 {
+                    return Err(error("only the first controller is emulated".to_owned()));
+                }
+            }
+            if columns.next().is_some() {
+                return Err(error(format!("expected {}[,controller2]", HEADER)));
+            }
+            rows.push((frame, input));
+        }
+
+        let Some(&(first, _)) = rows.first() else {
+            return Err(format!("{}: no inputs", path));
+        };
+        let mut inputs = Vec::new();
+        for (frame, input) in rows {
+            let gap = match inputs.last() {
+                Some(&previous) if hold => previous,
+                _ => 0,
+            };
+            inputs.resize(frame - first, gap);
+            inputs.push(input);
+        }
+        Ok(Self { first, inputs })
+    }
+
+    // rows for frames the warm-up already emulated have to agree with it
+    pub fn check(&self, machine: &Machine) -> Result<(), String> {
+        let done = machine.inputs();
+        for (i, &input) in self.inputs.iter().enumerate() {
+            let frame = self.first + i;
+            match done.get(frame - 1) {
+                Some(&emulated) if emulated != input => {
+                    return Err(format!(
+                        "frame {} was already emulated with input {}, not {}",
+                        frame, emulated, input
+                    ))
+                }
+                Some(_) => {}
+                None => break,
+            }
+        }
+        Ok(())
+    }
+
+    // the input for a frame, if the file has one
+    pub fn input(&self, frame: usize) -> Option<u8> {
+        frame
+            .checked_sub(self.first)
+            .and_then(|i| self.inputs.get(i))
+            .copied()
+    }
+}
+
+fn controller(value: &str, aliases: &Aliases) -> Result<u8, String> {
+    if value.is_empty() {
+        return Ok(0);
+    }
+    if let Ok(byte) = value.parse::<u8>() {
+        return Ok(byte);
+    }
+    let buttons = buttons::parse(value, aliases)?;
+    Ok(buttons
+        .into_iter()
+        .fold(0, |input, button| input | button.bit()))
+}
+
+// `--dump-inputs`: every emulated input in the same format, written as the
+// emulator runs like the journal; restoring a state rewrites it
+pub struct Dump {
+    path: String,
+    writer: BufWriter<File>,
+    frames: usize,
+    revision: u64,
+    flushed: Instant,
+}
+
+impl Dump {
+    pub fn create(path: &str, machine: &Machine) -> io::Result<Self> {
+        let mut dump = Self {
+            path: path.to_owned(),
+            writer: BufWriter::new(File::create(path)?),
+            frames: 0,
+            revision: machine.revision(),
+            flushed: Instant::now(),
+        };
+        dump.rewrite(machine)?;
+        Ok(dump)
+    }
+
+    // call after every frame
+    pub fn record(&mut self, machine: &Machine) -> io::Result<()> {
+        let inputs = machine.inputs();
+        if machine.revision() == self.revision + 1 && inputs.len() == self.frames + 1 {
+            self.frames += 1;
+            writeln!(self.writer, "{},{}", self.frames, inputs[self.frames - 1])?;
+        } else {
+            self.rewrite(machine)?;
+        }
+        self.revision = machine.revision();
+
+        if self.flushed.elapsed() >= FLUSH_INTERVAL {
+            self.writer.flush()?;
+            self.flushed = Instant::now();
+        }
+        Ok(())
+    }
+
+    fn rewrite(&mut self, machine: &Machine) -> io::Result<()> {
+        // before truncating, or the old buffer lands in the new file
+        self.writer.flush()?;
+        self.writer = BufWriter::new(File::create(&self.path)?);
+        writeln!(self.writer, "{}", HEADER)?;
+        for (i, input) in machine.inputs().iter().enumerate() {
+            writeln!(self.writer, "{},{}", i + 1, input)?;
+        }
+        self.frames = machine.frame();
+        Ok(())
+    }
+}
+
+impl Drop for Dump {
+    fn drop(&mut self) {
+        let _ = self.writer.flush();
+    }
+}
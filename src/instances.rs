@@ -0,0 +1,146 @@
+use std::thread;
+
+use rlua::prelude::LuaError;
+
+use crate::machine::Machine;
+
+// caps against scripts accidentally spawning or running too much
+pub const MAX_INSTANCES: usize = 64;
+pub const MAX_FRAMES: usize = 60 * 60 * 10;
+
+// Headless machines for brute-force searches, stepped on worker threads.
+// They never publish frames.
+#[derive(Default)]
+pub struct Instances {
+    machines: Vec<Machine>,
+}
+
+// what a run returns, the given RAM values or a hash of the CPU RAM
+pub enum Outcome {
+    Ram(Vec<u8>),
+    Hash(u64),
+}
+
+impl Instances {
+    pub fn len(&self) -> usize {
+        self.machines.len()
+    }
+
+    // replace all instances with `n` copies of the given inputs
+    pub fn spawn(&mut self, source: &Machine, inputs: &[u8], n: usize) -> Result<(), LuaError> {
+        if n > MAX_INSTANCES {
+            return Err(LuaError::RuntimeError(format!(
+                "cannot spawn {} instances, the maximum is {}",
+                n, MAX_INSTANCES
+            )));
+        }
+
+        self.machines.clear();
+        let rom = source.rom();
+        self.machines = parallel(vec![(); n], |()| Machine::replay(rom, inputs));
+        Ok(())
+    }
+
+    pub fn machine(&self, i: usize) -> Result<&Machine, LuaError> {
+        self.machines.get(i).ok_or_else(|| {
+            LuaError::RuntimeError(format!(
+                "no instance {}, there are {}",
+                i + 1,
+                self.machines.len()
+            ))
+        })
+    }
+
+    // feed `jobs[i]` to instance `i`, all instances in parallel
+    pub fn run(
+        &mut self,
+        jobs: Vec<(usize, Vec<u8>)>,
+        addrs: &[u16],
+    ) -> Result<Vec<Outcome>, LuaError> {
+        for (i, inputs) in &jobs {
+            self.machine(*i)?;
+            if jobs.iter().filter(|(j, _)| j == i).count() > 1 {
+                return Err(LuaError::RuntimeError(format!(
+                    "instance {} is given more than one job",
+                    i + 1
+                )));
+            }
+            if inputs.len() > MAX_FRAMES {
+                return Err(LuaError::RuntimeError(format!(
+                    "cannot run {} frames, the maximum is {}",
+                    inputs.len(),
+                    MAX_FRAMES
+                )));
+            }
+        }
+
+        // hand every job exclusive access to its machine
+        let mut machines: Vec<Option<&mut Machine>> = self.machines.iter_mut().map(Some).collect();
+        let work: Vec<_> = jobs
+            .into_iter()
+            .map(|(i, inputs)| (machines[i].take().unwrap(), inputs))
+            .collect();
+
+        Ok(parallel(work, |(machine, inputs)| {
+            for &input in &inputs {
+                machine.set_input(input);
+                machine.next_frame();
+            }
+            if addrs.is_empty() {
+                Outcome::Hash(ram_hash(machine))
+            } else {
+                Outcome::Ram(
+                    addrs
+                        .iter()
+                        .map(|&addr| machine.nes().read_internal(addr))
+                        .collect(),
+                )
+            }
+        }))
+    }
+}
+
+// FNV-1a over the 2KB of CPU RAM
+pub fn ram_hash(machine: &Machine) -> u64 {
+    let mut hash: u64 = 0xcbf29ce484222325;
+    for addr in 0..0x800 {
+        hash ^= machine.nes().read_internal(addr) as u64;
+        hash = hash.wrapping_mul(0x100000001b3);
+    }
+    hash
+}
+
+// maps items on up to one thread per core, keeping the order
+pub fn parallel<I: Send, T: Send>(items: Vec<I>, f: impl Fn(I) -> T + Sync) -> Vec<T> {
+    let threads = thread::available_parallelism()
+        .map_or(1, |n| n.get())
+        .min(items.len())
+        .max(1);
+
+    let mut buckets: Vec<Vec<(usize, I)>> = (0..threads).map(|_| Vec::new()).collect();
+    for (i, item) in items.into_iter().enumerate() {
+        buckets[i % threads].push((i, item));
+    }
+
+    let f = &f;
+    let mut results: Vec<(usize, T)> = thread::scope(|scope| {
+        let workers: Vec<_> = buckets
+            .into_iter()
+            .map(|bucket| {
+                scope.spawn(move || {
+                    bucket
+                        .into_iter()
+                        .map(|(i, item)| (i, f(item)))
+                        .collect::<Vec<_>>()
+                })
+            })
+            .collect();
+        workers
+            .into_iter()
+            .flat_map(|worker| worker.join().unwrap())
+            .collect()
+    });
+
+    results.sort_by_key(|(i, _)| *i);
+    results.into_iter().map(|(_, result)| result).collect()
+}
@@ -0,0 +1,46 @@
+use std::sync::atomic::{AtomicBool, Ordering};
+
+// what marlua exits with when interrupted, like shells report SIGINT
+pub const EXIT_CODE: i32 = 130;
+
+// seconds the run gets to finish its frame and write its files
+#[cfg(unix)]
+const GRACE: u32 = 1;
+
+static REQUESTED: AtomicBool = AtomicBool::new(false);
+
+// Ctrl-C and SIGTERM end the run like a limit does (see Limits::poll), so
+// the journal, hashes, dumps and report are finished the same way. Tests and
+// headless runs stop at their next frame; with a window, the window also
+// asks the emulator thread to stop and waits for it like when it is closed
+// (see stop_emulator), which wakes an emulator that waits for commands. A
+// second signal, or the run taking longer than GRACE to stop, exits right
+// away. Only on Unix, elsewhere the signal still ends the process at once.
+pub fn install() {
+    #[cfg(unix)]
+    unsafe {
+        let handler = on_signal as extern "C" fn(libc::c_int) as libc::sighandler_t;
+        libc::signal(libc::SIGINT, handler);
+        libc::signal(libc::SIGTERM, handler);
+        libc::signal(libc::SIGALRM, handler);
+    }
+}
+
+pub fn requested() -> bool {
+    REQUESTED.load(Ordering::Relaxed)
+}
+
+// what the signal handler does to the run, without the signal
+#[cfg(test)]
+pub fn set_requested(requested: bool) {
+    REQUESTED.store(requested, Ordering::SeqCst);
+}
+
+// only async-signal-safe calls in here
+#[cfg(unix)]
+extern "C" fn on_signal(signal: libc::c_int) {
+    if signal == libc::SIGALRM || REQUESTED.swap(true, Ordering::SeqCst) {
+        unsafe { libc::_exit(EXIT_CODE) };
+    }
+    unsafe { libc::alarm(GRACE) };
+}
@@ -0,0 +1,335 @@
+use std::{
+    fs::{self, File},
+    io::{self, BufWriter, Write},
+    path::Path,
+    time::{Duration, Instant},
+};
+
+use crate::machine::Machine;
+
+const MAGIC: &[u8; 8] = b"MARLUAJR";
+// version 2 added RERECORDS, 3 MARKER, 4 SCRIPT
+const VERSION: u16 = 4;
+
+// introduces a record other than a plain input byte
+const ESCAPE: u8 = 0xFF;
+// ESCAPE LITERAL is an input of 0xFF
+const LITERAL: u8 = 0x00;
+// ESCAPE REWIND and a little-endian u32 frame drop every input after it
+const REWIND: u8 = 0x01;
+// ESCAPE RERECORDS and a little-endian u32 is the re-record count from then
+// on, written whenever it changes
+const RERECORDS: u8 = 0x02;
+// ESCAPE MARKER, a little-endian u32 frame and u16 length and that many
+// bytes of UTF-8 name is a marker() from the script
+const MARKER: u8 = 0x03;
+// ESCAPE SCRIPT and 64 bytes of hex is the SHA-256 script hash of the run,
+// see pinning::script_hash; written after the header by runs of scripts
+const SCRIPT: u8 = 0x04;
+const SCRIPT_LEN: usize = 64;
+
+// frames between checks whether the journal is due for a flush
+const CHECK_EVERY: usize = 64;
+const FLUSH_INTERVAL: Duration = Duration::from_secs(1);
+
+// An append-only log of every input, written as the emulator runs so that a
+// crashed session can still be replayed with `marlua play`.
+//
+// After the header (MAGIC, VERSION and the ROM's CRC32) every frame is a
+// single byte. Restoring a state mid-run is written as a rewind to the last
+// frame both input logs have in common followed by the new inputs, and
+// branching off the greenzone's recording as a new re-record count, which
+// together with the header is the recording's metadata. The
+// file is flushed at least once a second and when the journal is dropped,
+// which includes unwinding from a panic.
+pub struct Journal {
+    writer: BufWriter<File>,
+    // the inputs written so far, after rewinds
+    log: Vec<u8>,
+    revision: u64,
+    rerecords: u32,
+    flushed: Instant,
+}
+
+impl Journal {
+    pub fn create(path: &Path, machine: &Machine) -> io::Result<Self> {
+        if let Some(dir) = path.parent() {
+            fs::create_dir_all(dir)?;
+        }
+        let mut writer = BufWriter::new(File::create(path)?);
+        writer.write_all(MAGIC)?;
+        writer.write_all(&VERSION.to_le_bytes())?;
+        writer.write_all(&machine.hash().to_le_bytes())?;
+
+        let mut journal = Self {
+            writer,
+            log: Vec::new(),
+            revision: machine.revision(),
+            rerecords: 0,
+            flushed: Instant::now(),
+        };
+        journal.resync(machine)?;
+        Ok(journal)
+    }
+
+    // call after every frame, with the greenzone's re-record count
+    pub fn record(&mut self, machine: &Machine, rerecords: u32) -> io::Result<()> {
+        let inputs = machine.inputs();
+        if machine.revision() == self.revision + 1 && inputs.len() == self.log.len() + 1 {
+            // the common case, one more frame
+            let input = inputs[inputs.len() - 1];
+            self.write_input(input)?;
+            self.log.push(input);
+        } else {
+            self.resync(machine)?;
+        }
+        self.revision = machine.revision();
+        if rerecords != self.rerecords {
+            self.writer.write_all(&[ESCAPE, RERECORDS])?;
+            self.writer.write_all(&rerecords.to_le_bytes())?;
+            self.rerecords = rerecords;
+        }
+
+        if self.log.len() % CHECK_EVERY == 0 && self.flushed.elapsed() >= FLUSH_INTERVAL {
+            self.writer.flush()?;
+            self.flushed = Instant::now();
+        }
+        Ok(())
+    }
+
+    // see SCRIPT
+    pub fn script(&mut self, hash: &str) -> io::Result<()> {
+        self.writer.write_all(&[ESCAPE, SCRIPT])?;
+        self.writer.write_all(hash.as_bytes())
+    }
+
+    // names shorter than u16::MAX bytes, see the marker global
+    pub fn marker(&mut self, frame: usize, name: &str) -> io::Result<()> {
+        self.writer.write_all(&[ESCAPE, MARKER])?;
+        self.writer.write_all(&(frame as u32).to_le_bytes())?;
+        self.writer.write_all(&(name.len() as u16).to_le_bytes())?;
+        self.writer.write_all(name.as_bytes())
+    }
+
+    fn resync(&mut self, machine: &Machine) -> io::Result<()> {
+        let inputs = machine.inputs();
+        let common = self
+            .log
+            .iter()
+            .zip(inputs)
+            .take_while(|(a, b)| a == b)
+            .count();
+        if common < self.log.len() {
+            self.writer.write_all(&[ESCAPE, REWIND])?;
+            self.writer.write_all(&(common as u32).to_le_bytes())?;
+            self.log.truncate(common);
+        }
+        for &input in &inputs[common..] {
+            self.write_input(input)?;
+        }
+        self.log.extend_from_slice(&inputs[common..]);
+        Ok(())
+    }
+
+    fn write_input(&mut self, input: u8) -> io::Result<()> {
+        match input {
+            ESCAPE => self.writer.write_all(&[ESCAPE, LITERAL]),
+            _ => self.writer.write_all(&[input]),
+        }
+    }
+}
+
+impl Drop for Journal {
+    fn drop(&mut self) {
+        let _ = self.writer.flush();
+    }
+}
+
+// the frames rewound to so far, in order, for tests of what writes them
+#[cfg(test)]
+impl Journal {
+    pub fn rewinds(&mut self, path: &Path) -> Vec<usize> {
+        self.writer.flush().unwrap();
+        let bytes = fs::read(path).unwrap();
+        let mut rewinds = Vec::new();
+        let mut rest = &bytes[14..];
+        while let Some((&byte, tail)) = rest.split_first() {
+            rest = match (byte, tail) {
+                (ESCAPE, [LITERAL, tail @ ..]) => tail,
+                (ESCAPE, [REWIND, a, b, c, d, tail @ ..]) => {
+                    rewinds.push(u32::from_le_bytes([*a, *b, *c, *d]) as usize);
+                    tail
+                }
+                (ESCAPE, [RERECORDS, tail @ ..]) => &tail[4..],
+                (ESCAPE, [MARKER, tail @ ..]) => {
+                    let len = u16::from_le_bytes([tail[4], tail[5]]) as usize;
+                    &tail[6 + len..]
+                }
+                (ESCAPE, [SCRIPT, tail @ ..]) => &tail[SCRIPT_LEN..],
+                _ => tail,
+            };
+        }
+        rewinds
+    }
+}
+
+// what a journal holds once its rewinds are applied
+pub struct Recording {
+    pub inputs: Vec<u8>,
+    pub rerecords: u32,
+    // frame and name, in the order they were set; a rewind drops the ones
+    // after it
+    pub markers: Vec<(usize, String)>,
+    // the script hash, for journals of scripts since version 4
+    pub script: Option<String>,
+}
+
+// the inputs in a journal, up to where it ends; a record cut off by a crash
+// is ignored
+pub fn read(path: &Path, rom_hash: u32) -> Result<Recording, String> {
+    let bytes = fs::read(path).map_err(|e| format!("{}: {}", path.display(), e))?;
+    let error = |msg: &str| format!("{}: {}", path.display(), msg);
+
+    if bytes.len() < 14 || &bytes[..8] != MAGIC {
+        return Err(error("not an input journal"));
+    }
+    let version = u16::from_le_bytes([bytes[8], bytes[9]]);
+    if !(1..=VERSION).contains(&version) {
+        return Err(error(&format!("unknown journal version {}", version)));
+    }
+    let hash = u32::from_le_bytes(bytes[10..14].try_into().unwrap());
+    if hash != rom_hash {
+        return Err(error(&format!(
+            "journal was recorded with ROM {:08X}, not {:08X}",
+            hash, rom_hash
+        )));
+    }
+
+    let mut inputs = Vec::new();
+    let mut rerecords = 0;
+    let mut markers = Vec::new();
+    let mut script = None;
+    let mut rest = &bytes[14..];
+    while let Some((&byte, tail)) = rest.split_first() {
+        rest = tail;
+        if byte != ESCAPE {
+            inputs.push(byte);
+            continue;
+        }
+        match rest {
+            [LITERAL, tail @ ..] => {
+                inputs.push(ESCAPE);
+                rest = tail;
+            }
+            [REWIND, a, b, c, d, tail @ ..] => {
+                let frame = u32::from_le_bytes([*a, *b, *c, *d]) as usize;
+                inputs.truncate(frame);
+                markers.retain(|&(marked, _)| marked <= frame);
+                rest = tail;
+            }
+            [RERECORDS, a, b, c, d, tail @ ..] => {
+                rerecords = u32::from_le_bytes([*a, *b, *c, *d]);
+                rest = tail;
+            }
+            [MARKER, a, b, c, d, e, f, tail @ ..] => {
+                let len = u16::from_le_bytes([*e, *f]) as usize;
+                let Some(name) = tail.get(..len) else {
+                    break;
+                };
+                let name = String::from_utf8_lossy(name).into_owned();
+                markers.push((u32::from_le_bytes([*a, *b, *c, *d]) as usize, name));
+                rest = &tail[len..];
+            }
+            [SCRIPT, tail @ ..] => {
+                let Some(hash) = tail.get(..SCRIPT_LEN) else {
+                    break;
+                };
+                script = Some(String::from_utf8_lossy(hash).into_owned());
+                rest = &tail[SCRIPT_LEN..];
+            }
+            [REWIND | RERECORDS | MARKER, ..] | [] => break,
+            _ => return Err(error("corrupt journal")),
+        }
+    }
+    Ok(Recording {
+        inputs,
+        rerecords,
+        markers,
+        script,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::BTreeMap;
+
+    use super::*;
+    use crate::{savestate::Savestate, testing};
+
+    fn step(machine: &mut Machine, journal: &mut Journal, input: u8) {
+        testing::step(machine, input);
+        journal.record(machine, 0).unwrap();
+    }
+
+    // what checkpoint(), restore() and drop() do to the machine
+    #[test]
+    fn branches() {
+        let path = testing::temp_dir("journal").join("inputs.journal");
+        let mut machine = testing::machine();
+        let mut journal = Journal::create(&path, &machine).unwrap();
+        for input in [1, 2, 3] {
+            step(&mut machine, &mut journal, input);
+        }
+        let mut checkpoints = BTreeMap::new();
+        checkpoints.insert("fork", Savestate::capture(&machine));
+
+        // the first branch is abandoned
+        step(&mut machine, &mut journal, 4);
+        step(&mut machine, &mut journal, 5);
+        checkpoints["fork"].clone().apply(&mut machine).unwrap();
+
+        // so is the second, which is checkpointed itself and dropped
+        step(&mut machine, &mut journal, 6);
+        checkpoints.insert("second", Savestate::capture(&machine));
+        step(&mut machine, &mut journal, 7);
+        checkpoints["fork"].clone().apply(&mut machine).unwrap();
+        assert!(checkpoints.remove("second").is_some());
+
+        step(&mut machine, &mut journal, ESCAPE);
+        step(&mut machine, &mut journal, 8);
+        drop(journal);
+
+        let recording = read(&path, machine.hash()).unwrap();
+        assert_eq!(recording.inputs, [1, 2, 3, ESCAPE, 8]);
+        assert_eq!(recording.inputs, machine.inputs());
+        assert_eq!(recording.rerecords, 0);
+
+        // both branches end in a rewind to the checkpoint
+        let bytes = fs::read(&path).unwrap();
+        let rewind = [ESCAPE, REWIND, 3, 0, 0, 0];
+        let rewinds = bytes.windows(rewind.len()).filter(|w| *w == rewind);
+        assert_eq!(rewinds.count(), 2);
+        assert!(bytes.ends_with(&[ESCAPE, LITERAL, 8]));
+    }
+
+    #[test]
+    fn cut_off() {
+        let path = testing::temp_dir("journal-cut-off").join("inputs.journal");
+        let mut machine = testing::machine();
+        let mut journal = Journal::create(&path, &machine).unwrap();
+        for input in [1, 2, 3] {
+            step(&mut machine, &mut journal, input);
+        }
+        journal.marker(2, "here").unwrap();
+        drop(journal);
+
+        // a marker cut off halfway is ignored
+        let bytes = fs::read(&path).unwrap();
+        fs::write(&path, &bytes[..bytes.len() - 2]).unwrap();
+        let recording = read(&path, machine.hash()).unwrap();
+        assert_eq!(recording.inputs, [1, 2, 3]);
+        assert!(recording.markers.is_empty());
+
+        assert!(read(&path, machine.hash() ^ 1).is_err());
+    }
+}
@@ -0,0 +1,32 @@
+use fastnes::ppu::DrawOptions;
+use rlua::prelude::LuaError;
+
+// Which parts of the picture the PPU draws, for the window and for captures.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Layers {
+    All,
+    Background,
+    Sprites,
+}
+
+impl Layers {
+    pub fn parse(name: &str) -> Result<Self, LuaError> {
+        match name {
+            "all" => Ok(Self::All),
+            "background" => Ok(Self::Background),
+            "sprites" => Ok(Self::Sprites),
+            _ => Err(LuaError::RuntimeError(format!(
+                "unknown layers {}, expected all, background or sprites",
+                name
+            ))),
+        }
+    }
+
+    pub fn draw_options(self) -> DrawOptions {
+        match self {
+            Self::All => DrawOptions::All,
+            Self::Background => DrawOptions::Background,
+            Self::Sprites => DrawOptions::Sprites,
+        }
+    }
+}
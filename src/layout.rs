@@ -0,0 +1,385 @@
+use std::{
+    fs,
+    path::{Path, PathBuf},
+    time::{Duration, Instant, SystemTime},
+};
+
+use crate::config::{Config, ConfigValue};
+
+// how often the config file is checked for changes
+const POLL_EVERY: Duration = Duration::from_secs(1);
+// between overlays stacked at the same edge
+const GAP: f32 = 8.0;
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Anchor {
+    TopLeft,
+    Top,
+    TopRight,
+    BottomLeft,
+    Bottom,
+    BottomRight,
+}
+
+const ANCHORS: &[(&str, Anchor)] = &[
+    ("top_left", Anchor::TopLeft),
+    ("top", Anchor::Top),
+    ("top_right", Anchor::TopRight),
+    ("bottom_left", Anchor::BottomLeft),
+    ("bottom", Anchor::Bottom),
+    ("bottom_right", Anchor::BottomRight),
+];
+
+impl Anchor {
+    pub fn top(self) -> bool {
+        matches!(self, Anchor::TopLeft | Anchor::Top | Anchor::TopRight)
+    }
+
+    // -1 for the left, 0 for the middle and 1 for the right
+    pub fn side(self) -> i8 {
+        match self {
+            Anchor::TopLeft | Anchor::BottomLeft => -1,
+            Anchor::Top | Anchor::Bottom => 0,
+            Anchor::TopRight | Anchor::BottomRight => 1,
+        }
+    }
+}
+
+// The overlays drawn over the game, in the order they stack: the first
+// one at an edge is closest to it.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Element {
+    // the playback bar of marlua play and its label
+    Progress,
+    Subtitle,
+    // the savestate slot last used
+    Slot,
+    Toasts,
+    // the graph of the frame_times panel, which only has a position
+    FrameTimes,
+}
+
+pub const ELEMENTS: [Element; 5] = [
+    Element::Progress,
+    Element::Subtitle,
+    Element::Slot,
+    Element::Toasts,
+    Element::FrameTimes,
+];
+
+impl Element {
+    // the section in the config, [overlay.<name>]
+    pub fn name(self) -> &'static str {
+        match self {
+            Element::Progress => "progress",
+            Element::Subtitle => "subtitle",
+            Element::Slot => "slot",
+            Element::Toasts => "toasts",
+            Element::FrameTimes => "frame_times",
+        }
+    }
+
+    // the playback bar spans the whole edge, what is anchored there stacks
+    // beyond it
+    fn full_width(self) -> bool {
+        self == Element::Progress
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Preset {
+    Minimal,
+    Debug,
+    Stream,
+}
+
+impl Preset {
+    pub fn parse(name: &str) -> Result<Self, String> {
+        match name {
+            "minimal" => Ok(Preset::Minimal),
+            "debug" => Ok(Preset::Debug),
+            "stream" => Ok(Preset::Stream),
+            other => Err(format!(
+                "unknown overlay preset {}, expected minimal, debug or stream",
+                other
+            )),
+        }
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct Style {
+    pub enabled: bool,
+    pub anchor: Anchor,
+    // from the anchor's edges, inwards
+    pub offset: (f32, f32),
+    pub scale: f32,
+    // the text, and the bar of the playback bar
+    pub color: [u8; 4],
+    pub background: [u8; 4],
+}
+
+impl Style {
+    fn new(anchor: Anchor, offset: (f32, f32)) -> Self {
+        Self {
+            enabled: true,
+            anchor,
+            offset,
+            scale: 1.0,
+            color: [255; 4],
+            background: [0, 0, 0, 180],
+        }
+    }
+}
+
+// Where each overlay goes, how large and in which colors: a preset with the
+// [overlay.<name>] sections of the config over it. Overlays anchored to
+// the same corner stack in ELEMENTS order, as do the ones in the middle of
+// an edge; those in a corner also stack beyond the middle ones and the
+// playback bar at their edge, which are as wide as the window.
+#[derive(Clone, PartialEq, Debug)]
+pub struct Layout {
+    styles: [Style; 5],
+}
+
+impl Default for Layout {
+    fn default() -> Self {
+        Self::preset(Preset::Debug)
+    }
+}
+
+impl Layout {
+    pub fn preset(preset: Preset) -> Self {
+        let corner = (4.0, 6.0);
+        let mut styles = [
+            Style::new(Anchor::TopLeft, (4.0, 0.0)),
+            Style::new(Anchor::Bottom, (0.0, 6.0)),
+            Style::new(Anchor::BottomRight, corner),
+            Style::new(Anchor::BottomLeft, corner),
+            Style::new(Anchor::TopRight, (8.0, 8.0)),
+        ];
+        styles[0].color = [255, 48, 48, 255];
+        match preset {
+            Preset::Debug => {}
+            Preset::Minimal => {
+                styles[0].enabled = false;
+                styles[2].enabled = false;
+            }
+            // a webcam goes over the top third
+            Preset::Stream => {
+                styles[0].anchor = Anchor::BottomLeft;
+                styles[4].anchor = Anchor::BottomRight;
+            }
+        }
+        Self { styles }
+    }
+
+    // `preset` unless overlay.preset is given, then the element sections
+    pub fn from_config(preset: Option<Preset>, config: &Config) -> Result<Self, String> {
+        let preset = match (preset, config.string("overlay.preset")?) {
+            (Some(preset), _) => preset,
+            (None, Some(name)) => Preset::parse(&name)?,
+            (None, None) => Preset::Debug,
+        };
+        let mut layout = Self::preset(preset);
+        for (key, value) in config.entries() {
+            let Some(rest) = key.strip_prefix("overlay.") else {
+                continue;
+            };
+            if rest == "preset" {
+                continue;
+            }
+            let (name, field) = rest.split_once('.').unwrap_or((rest, ""));
+            let Some(i) = ELEMENTS.iter().position(|e| e.name() == name) else {
+                let names: Vec<_> = ELEMENTS.iter().map(|e| e.name()).collect();
+                return Err(format!(
+                    "{}: unknown overlay {}, expected one of {}",
+                    key,
+                    name,
+                    names.join(", ")
+                ));
+            };
+            set(&mut layout.styles[i], key, field, value)?;
+        }
+        Ok(layout)
+    }
+
+    pub fn style(&self, element: Element) -> &Style {
+        &self.styles[element as usize]
+    }
+
+    // The top left corner of each overlay of `sizes` in a window of `width`
+    // by `height`, skipping disabled ones and those without a size (nothing
+    // to show).
+    pub fn place(
+        &self,
+        sizes: [Option<(f32, f32)>; 5],
+        (width, height): (f32, f32),
+    ) -> [Option<(f32, f32)>; 5] {
+        let mut placed = [None; 5];
+        // how far from its edge each one ends, for those after it
+        let mut extents = [0.0; 5];
+        for (i, &element) in ELEMENTS.iter().enumerate() {
+            let style = self.style(element);
+            let Some((w, h)) = sizes[i].filter(|_| style.enabled) else {
+                continue;
+            };
+            let anchor = style.anchor;
+            let below = ELEMENTS[..i]
+                .iter()
+                .enumerate()
+                .filter(|&(j, &other)| {
+                    let theirs = self.style(other).anchor;
+                    placed[j].is_some()
+                        && theirs.top() == anchor.top()
+                        && (theirs == anchor || theirs.side() == 0 || other.full_width())
+                })
+                .map(|(j, _)| extents[j] + GAP)
+                .fold(0.0, f32::max);
+            // the offset is from the edge, stacked ones keep the gap
+            let distance = below.max(style.offset.1);
+            let y = match anchor.top() {
+                true => distance,
+                false => height - distance - h,
+            };
+            let x = match anchor.side() {
+                -1 => style.offset.0,
+                0 => (width - w) / 2.0 + style.offset.0,
+                _ => width - style.offset.0 - w,
+            };
+            placed[i] = Some((x, y));
+            extents[i] = distance + h;
+        }
+        placed
+    }
+}
+
+fn set(style: &mut Style, key: &str, field: &str, value: &ConfigValue) -> Result<(), String> {
+    match (field, value) {
+        ("enabled", ConfigValue::Boolean(enabled)) => style.enabled = *enabled,
+        ("anchor", ConfigValue::String(name)) => {
+            style.anchor = match ANCHORS.iter().find(|(n, _)| n == name) {
+                Some((_, anchor)) => *anchor,
+                None => {
+                    let names: Vec<_> = ANCHORS.iter().map(|(n, _)| *n).collect();
+                    return Err(format!("{} must be one of {}", key, names.join(", ")));
+                }
+            }
+        }
+        ("offset", ConfigValue::Array(values)) => match values.as_slice() {
+            [x, y] => style.offset = (pixels(key, x)?, pixels(key, y)?),
+            _ => return Err(format!("{} must be [x, y] in pixels", key)),
+        },
+        ("scale", ConfigValue::Integer(n)) if *n > 0 => style.scale = *n as f32,
+        ("scale", ConfigValue::Float(n)) if *n > 0.0 => style.scale = *n as f32,
+        ("color", ConfigValue::String(hex)) => style.color = color(key, hex)?,
+        ("background", ConfigValue::String(hex)) => style.background = color(key, hex)?,
+        ("enabled" | "anchor" | "offset" | "scale" | "color" | "background", _) => {
+            return Err(format!("{} has the wrong type, see --help", key))
+        }
+        _ => {
+            return Err(format!(
+                "unknown key {}, expected enabled, anchor, offset, scale, color or background",
+                key
+            ))
+        }
+    }
+    Ok(())
+}
+
+fn pixels(key: &str, value: &ConfigValue) -> Result<f32, String> {
+    match value {
+        ConfigValue::Integer(n) => Ok(*n as f32),
+        ConfigValue::Float(n) => Ok(*n as f32),
+        _ => Err(format!("{} must be [x, y] in pixels", key)),
+    }
+}
+
+// "#rrggbb" or "#rrggbbaa"
+fn color(key: &str, hex: &str) -> Result<[u8; 4], String> {
+    let error = || format!("{} must be a color like \"#ff3030\" or \"#000000b4\"", key);
+    let digits = hex.strip_prefix('#').ok_or_else(error)?;
+    if !matches!(digits.len(), 6 | 8) || !digits.is_ascii() {
+        return Err(error());
+    }
+    let mut rgba = [255; 4];
+    for (i, channel) in rgba.iter_mut().enumerate().take(digits.len() / 2) {
+        *channel = u8::from_str_radix(&digits[i * 2..i * 2 + 2], 16).map_err(|_| error())?;
+    }
+    Ok(rgba)
+}
+
+// Reads the layout again when the config file changes, checking once a
+// second. Only the [overlay] sections apply without a restart.
+pub struct Watcher {
+    path: Option<PathBuf>,
+    preset: Option<Preset>,
+    modified: Option<SystemTime>,
+    checked: Instant,
+}
+
+impl Watcher {
+    // `path` is the config file that was read, if any, and `preset`
+    // --overlay-preset
+    pub fn new(path: Option<PathBuf>, preset: Option<Preset>) -> Self {
+        let modified = path.as_ref().and_then(|path| modified(path));
+        Self {
+            path,
+            preset,
+            modified,
+            checked: Instant::now(),
+        }
+    }
+
+    // the new layout once the file changed, or why it could not be read
+    pub fn poll(&mut self) -> Option<Result<Layout, String>> {
+        if self.checked.elapsed() < POLL_EVERY {
+            return None;
+        }
+        self.checked = Instant::now();
+        let path = self.path.as_ref()?;
+        let modified = modified(path);
+        if modified == self.modified {
+            return None;
+        }
+        self.modified = modified;
+        let config = Config::load(path).map_err(|e| e.to_string());
+        Some(config.and_then(|config| Layout::from_config(self.preset, &config)))
+    }
+}
+
+fn modified(path: &Path) -> Option<SystemTime> {
+    fs::metadata(path).and_then(|m| m.modified()).ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn place() {
+        let window = (768.0, 720.0);
+        let mut sizes = [
+            Some((768.0, 30.0)),
+            Some((400.0, 36.0)),
+            Some((60.0, 22.0)),
+            Some((200.0, 48.0)),
+            Some((240.0, 120.0)),
+        ];
+
+        // a webcam goes over the top third
+        let placed = Layout::preset(Preset::Stream).place(sizes, window);
+        for (i, at) in placed.iter().enumerate() {
+            let (_, y) = at.unwrap();
+            assert!(y >= window.1 / 3.0, "{:?} at {}", ELEMENTS[i], y);
+        }
+
+        // the frame time graph stacks below the playback bar, otherwise it
+        // is in the top right corner
+        let placed = Layout::preset(Preset::Debug).place(sizes, window);
+        assert_eq!(placed[4], Some((520.0, 38.0)));
+        sizes[0] = None;
+        let placed = Layout::preset(Preset::Debug).place(sizes, window);
+        assert_eq!(placed[4], Some((520.0, 8.0)));
+    }
+}
@@ -0,0 +1,186 @@
+use crate::IMAGE_RECT;
+
+// what the window opens at unless the game needs more room
+const WINDOW: (u32, u32) = (640, 360);
+
+// 4096 by 3840 pixels, more than any capture needs
+const MAX_SCALE: u32 = 16;
+
+// Where the game goes in the window and what fills the rest of it.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct Scene {
+    // x, y, width, height
+    pub image: (f32, f32, f32, f32),
+    pub background: [u8; 3],
+}
+
+impl Default for Scene {
+    fn default() -> Self {
+        Self {
+            image: IMAGE_RECT,
+            background: [0, 0, 0],
+        }
+    }
+}
+
+// --capture-layout: the game at a whole scale at a fixed spot of the
+// window, e.g. for OBS, with the rest of the window one color to key out.
+// The anchor keeps the game at its corner or edge when the window is
+// resized.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct CaptureLayout {
+    pub scale: u32,
+    // -1, 0 or 1 for the left, middle or right and the top, middle or
+    // bottom
+    anchor: (i8, i8),
+    pub background: [u8; 3],
+}
+
+const ANCHORS: &[(&str, (i8, i8))] = &[
+    ("topleft", (-1, -1)),
+    ("top", (0, -1)),
+    ("topright", (1, -1)),
+    ("left", (-1, 0)),
+    ("center", (0, 0)),
+    ("right", (1, 0)),
+    ("bottomleft", (-1, 1)),
+    ("bottom", (0, 1)),
+    ("bottomright", (1, 1)),
+];
+
+impl CaptureLayout {
+    // comma-separated key=value pairs, e.g. "scale=3,anchor=topleft,
+    // background=00FF00"; by default the game is at scale 1 in the top left
+    // corner on green
+    pub fn parse(spec: &str) -> Result<Self, String> {
+        let mut layout = Self {
+            scale: 1,
+            anchor: (-1, -1),
+            background: [0, 255, 0],
+        };
+        for pair in spec.split(',').map(str::trim).filter(|p| !p.is_empty()) {
+            let (key, value) = pair
+                .split_once('=')
+                .ok_or_else(|| format!("--capture-layout expects key=value, got {}", pair))?;
+            match key.trim() {
+                "scale" => {
+                    layout.scale = match value.trim().parse() {
+                        Ok(scale) if (1..=MAX_SCALE).contains(&scale) => scale,
+                        _ => {
+                            return Err(format!(
+                                "--capture-layout scale must be a whole number from 1 to {}, \
+                                 got {}",
+                                MAX_SCALE, value
+                            ))
+                        }
+                    }
+                }
+                "anchor" => {
+                    let name = value.trim().to_ascii_lowercase().replace(['_', '-'], "");
+                    layout.anchor = match ANCHORS.iter().find(|(n, _)| *n == name) {
+                        Some((_, anchor)) => *anchor,
+                        None => {
+                            let names: Vec<_> = ANCHORS.iter().map(|(n, _)| *n).collect();
+                            return Err(format!(
+                                "unknown --capture-layout anchor {}, expected one of {}",
+                                value,
+                                names.join(", ")
+                            ));
+                        }
+                    }
+                }
+                "background" => layout.background = color(value.trim())?,
+                other => {
+                    return Err(format!(
+                        "unknown --capture-layout key {}, expected scale, anchor or background",
+                        other
+                    ))
+                }
+            }
+        }
+        Ok(layout)
+    }
+
+    // the window's size when it opens, large enough for the game
+    pub fn window_size(&self) -> (u32, u32) {
+        (
+            WINDOW.0.max(256 * self.scale),
+            WINDOW.1.max(240 * self.scale),
+        )
+    }
+
+    // the game in a window of `width` by `height`, in whole pixels
+    pub fn rect(&self, (width, height): (u32, u32)) -> (u32, u32, u32, u32) {
+        let (w, h) = (256 * self.scale, 240 * self.scale);
+        let place = |side: i8, room: u32, size: u32| match side {
+            -1 => 0,
+            0 => room.saturating_sub(size) / 2,
+            _ => room.saturating_sub(size),
+        };
+        (
+            place(self.anchor.0, width, w),
+            place(self.anchor.1, height, h),
+            w,
+            h,
+        )
+    }
+
+    pub fn scene(&self, window: (u32, u32)) -> Scene {
+        let (x, y, w, h) = self.rect(window);
+        Scene {
+            image: (x as f32, y as f32, w as f32, h as f32),
+            background: self.background,
+        }
+    }
+
+    // e.g. "00ff00"
+    pub fn hex(&self) -> String {
+        let [r, g, b] = self.background;
+        format!("{:02x}{:02x}{:02x}", r, g, b)
+    }
+}
+
+// "RRGGBB", optionally with a #
+fn color(hex: &str) -> Result<[u8; 3], String> {
+    let error = || {
+        format!(
+            "--capture-layout background must be a color like 00FF00, got {}",
+            hex
+        )
+    };
+    let digits = hex.strip_prefix('#').unwrap_or(hex);
+    if digits.len() != 6 || !digits.is_ascii() {
+        return Err(error());
+    }
+    let channel = |i: usize| u8::from_str_radix(&digits[i * 2..i * 2 + 2], 16).map_err(|_| error());
+    Ok([channel(0)?, channel(1)?, channel(2)?])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse() {
+        let layout =
+            CaptureLayout::parse("scale=3, anchor=Bottom_Right,background=#0000ff").unwrap();
+        assert_eq!(layout.scale, 3);
+        assert_eq!(layout.background, [0, 0, 255]);
+        assert_eq!(layout.window_size(), (768, 720));
+        assert_eq!(layout.rect((1000, 800)), (232, 80, 768, 720));
+        assert_eq!(
+            CaptureLayout::parse("").unwrap().rect((640, 360)),
+            (0, 0, 256, 240)
+        );
+
+        let largest = CaptureLayout::parse("scale=16").unwrap();
+        assert_eq!(largest.window_size(), (4096, 3840));
+        for scale in ["0", "17", "4294967295", "4294967296", "-1", "2.5", "x"] {
+            let error = CaptureLayout::parse(&format!("scale={}", scale)).unwrap_err();
+            assert!(error.contains("from 1 to 16"), "{}: {}", scale, error);
+        }
+        assert!(CaptureLayout::parse("scale").is_err());
+        assert!(CaptureLayout::parse("size=2").is_err());
+        assert!(CaptureLayout::parse("anchor=middle").is_err());
+    }
+}
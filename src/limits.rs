@@ -0,0 +1,110 @@
+use std::{
+    error::Error,
+    fmt::{self, Display},
+    time::{Duration, Instant},
+};
+
+use rlua::prelude::LuaError;
+
+use crate::interrupt;
+
+// what marlua exits with when a limit stopped the run, scripts failing
+// exit with 1
+pub const EXIT_CODE: i32 = 2;
+
+// raised through the script when a limit is reached
+#[derive(Debug)]
+pub struct Exceeded(pub String);
+
+impl Display for Exceeded {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+impl Error for Exceeded {}
+
+pub fn is_exceeded(error: &LuaError) -> bool {
+    matches!(error, LuaError::ExternalError(e) if e.is::<Exceeded>())
+}
+
+// for a run that ended with Exceeded, which an interrupt raises too
+pub fn exit_code() -> i32 {
+    if interrupt::requested() {
+        interrupt::EXIT_CODE
+    } else {
+        EXIT_CODE
+    }
+}
+
+// --max-frames and --max-wall-time, checked by the driver on every frame it
+// steps so they hold whatever is driving the emulator. Ctrl-C and SIGTERM
+// stop the run the same way, see interrupt.rs, and so does closing the
+// window, see stop.
+//
+// Once a limit is reached every further wait raises Exceeded, so a script
+// that catches it with pcall is still stopped at its next frame.
+pub struct Limits {
+    max_frames: Option<usize>,
+    max_wall_time: Option<Duration>,
+    start: Instant,
+    frames: usize,
+    exceeded: Option<String>,
+}
+
+impl Limits {
+    pub fn new(max_frames: Option<usize>, max_wall_time: Option<Duration>) -> Self {
+        Self {
+            max_frames,
+            max_wall_time,
+            start: Instant::now(),
+            frames: 0,
+            exceeded: None,
+        }
+    }
+
+    // after every stepped frame
+    pub fn tick(&mut self) {
+        self.frames += 1;
+        self.poll();
+    }
+
+    // while no frames are stepped, e.g. during pause()
+    pub fn poll(&mut self) {
+        if self.exceeded.is_some() {
+            return;
+        }
+        if interrupt::requested() {
+            self.exceeded = Some("interrupted".to_owned());
+        } else if let Some(max) = self.max_frames.filter(|&max| self.frames >= max) {
+            self.exceeded = Some(format!("stopped after {} frames (--max-frames)", max));
+        } else if let Some(max) = self
+            .max_wall_time
+            .filter(|&max| self.start.elapsed() >= max)
+        {
+            self.exceeded = Some(format!(
+                "stopped after {} seconds (--max-wall-time)",
+                max.as_secs()
+            ));
+        }
+    }
+
+    // ends the run now, unless something else already did
+    pub fn stop(&mut self, reason: &str) {
+        self.poll();
+        if self.exceeded.is_none() {
+            self.exceeded = Some(reason.to_owned());
+        }
+    }
+
+    pub fn exceeded(&self) -> Option<&str> {
+        self.exceeded.as_deref()
+    }
+
+    pub fn check(&self) -> Result<(), LuaError> {
+        match &self.exceeded {
+            Some(reason) => Err(LuaError::external(Exceeded(reason.clone()))),
+            None => Ok(()),
+        }
+    }
+}
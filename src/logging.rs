@@ -0,0 +1,106 @@
+use std::{
+    env, fmt,
+    fs::File,
+    io::{self, IsTerminal},
+    str::FromStr,
+    sync::Mutex,
+};
+
+use tracing::{Event, Level, Subscriber};
+use tracing_subscriber::{
+    filter::LevelFilter,
+    fmt::{
+        format::Writer, writer::BoxMakeWriter, FmtContext, FormatEvent, FormatFields,
+        FormattedFields,
+    },
+    registry::LookupSpan,
+};
+
+// the target of what scripts print and log, see sandbox.rs
+pub const LUA: &str = "lua";
+
+// Sends tracing events at `level` and above to stderr, or to `file`.
+//
+// Every line is the level, the message and then the fields of the spans it
+// happened in, such as `frame=1234` from the driver, so logs can be grepped
+// by frame. Output from scripts is marked with `lua:`. On a terminal levels
+// are colored and the span fields dimmed, unless NO_COLOR is set.
+pub fn init(level: &str, file: Option<&str>) -> Result<(), String> {
+    let level = LevelFilter::from_str(level).map_err(|_| {
+        format!(
+            "unknown log level {}, expected off, error, warn, info, debug or trace",
+            level
+        )
+    })?;
+
+    let color = file.is_none()
+        && io::stderr().is_terminal()
+        && env::var_os("NO_COLOR").map_or(true, |v| v.is_empty());
+    let builder = tracing_subscriber::fmt()
+        .with_max_level(level)
+        .with_ansi(color)
+        .event_format(Format { color });
+    match file {
+        Some(path) => {
+            let file = File::create(path).map_err(|e| format!("{}: {}", path, e))?;
+            builder
+                .with_writer(BoxMakeWriter::new(Mutex::new(file)))
+                .init();
+        }
+        None => builder.with_writer(io::stderr).init(),
+    }
+    Ok(())
+}
+
+struct Format {
+    color: bool,
+}
+
+impl Format {
+    fn paint(&self, code: &str, text: &str) -> String {
+        match self.color {
+            true => format!("\x1b[{}m{}\x1b[0m", code, text),
+            false => text.to_owned(),
+        }
+    }
+}
+
+impl<S, N> FormatEvent<S, N> for Format
+where
+    S: Subscriber + for<'a> LookupSpan<'a>,
+    N: for<'a> FormatFields<'a> + 'static,
+{
+    fn format_event(
+        &self,
+        ctx: &FmtContext<'_, S, N>,
+        mut writer: Writer<'_>,
+        event: &Event<'_>,
+    ) -> fmt::Result {
+        let level = *event.metadata().level();
+        let code = match level {
+            Level::ERROR => "31",
+            Level::WARN => "33",
+            Level::INFO => "32",
+            Level::DEBUG => "34",
+            Level::TRACE => "35",
+        };
+        write!(writer, "{} ", self.paint(code, &format!("{:>5}", level)))?;
+        if event.metadata().target() == LUA {
+            write!(writer, "{} ", self.paint("36", "lua:"))?;
+        }
+        ctx.field_format().format_fields(writer.by_ref(), event)?;
+
+        if let Some(scope) = ctx.event_scope() {
+            for span in scope.from_root() {
+                let extensions = span.extensions();
+                let Some(fields) = extensions.get::<FormattedFields<N>>() else {
+                    continue;
+                };
+                if !fields.is_empty() {
+                    write!(writer, " {}", self.paint("2", fields))?;
+                }
+            }
+        }
+        writeln!(writer)
+    }
+}
@@ -0,0 +1,78 @@
+-- Super Mario Bros. helpers built on the memory API
+--
+-- RAM addresses follow the Data Crystal RAM map:
+-- https://datacrystal.romhacking.net/wiki/Super_Mario_Bros.:RAM_map
+
+local smb = {}
+
+-- CRC32 of the PRG and CHR data (without the iNES header), as listed in
+-- No-Intro; other revisions go here once their RAM layout is verified
+local VERSIONS = {
+  [0x3337EC46] = "Super Mario Bros. (World)",
+}
+
+smb.version = VERSIONS[rom_hash()]
+if smb.version == nil then
+  error(string.format(
+    "the smb module needs a Super Mario Bros. ROM, this one has CRC32 %08X",
+    rom_hash()
+  ), 2)
+end
+
+local ENEMY_SLOTS = 5
+
+-- position in pixels from the start of the level
+function smb.x()
+  return memory.read(0x006D) * 256 + memory.read(0x0086)
+end
+
+-- position in pixels from the top of the screen, larger is lower
+function smb.y()
+  return memory.read(0x00B5) * 256 + memory.read(0x00CE) - 256
+end
+
+-- horizontal speed, negative when moving left
+function smb.speed()
+  return memory.read_s8(0x0057)
+end
+
+-- 1-based, as shown on screen
+function smb.world()
+  return memory.read(0x075F) + 1
+end
+
+function smb.level()
+  return memory.read(0x075C) + 1
+end
+
+-- "small", "big" or "fire"
+function smb.powerup()
+  return ({ "small", "big", "fire" })[memory.read(0x0756) + 1]
+end
+
+-- the timer is stored as one decimal digit per byte
+function smb.timer()
+  return memory.read(0x07F8) * 100 + memory.read(0x07F9) * 10 + memory.read(0x07FA)
+end
+
+function smb.on_ground()
+  return memory.read(0x001D) == 0
+end
+
+-- active enemies as { slot, type, x, y } tables
+function smb.enemies()
+  local enemies = {}
+  for slot = 0, ENEMY_SLOTS - 1 do
+    if memory.read(0x000F + slot) ~= 0 then
+      enemies[#enemies + 1] = {
+        slot = slot + 1,
+        type = memory.read(0x0016 + slot),
+        x = memory.read(0x006E + slot) * 256 + memory.read(0x0087 + slot),
+        y = memory.read(0x00B6 + slot) * 256 + memory.read(0x00CF + slot) - 256,
+      }
+    end
+  end
+  return enemies
+end
+
+return smb
@@ -0,0 +1,250 @@
+use std::sync::{
+    atomic::{AtomicU8, Ordering},
+    Arc,
+};
+
+use fastnes::{cart::NROM, input::Controllers, nes::NES, ppu::FastPPU};
+use tracing::info;
+
+pub type Emulator = NES<NROM, FastPPU>;
+
+const INES_HEADER: usize = 16;
+
+// What wait_scanlines and wait_cycles step by.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum SubFrame {
+    Scanlines,
+    Cycles,
+}
+
+// fastnes cannot stop in the middle of a frame.
+#[derive(PartialEq, Eq, Debug)]
+pub struct Unsupported;
+
+// The emulator together with everything needed to rebuild it.
+//
+// fastnes has no way to serialize its internal state, but it is fully
+// deterministic: a machine is therefore identified by its ROM and the
+// controller byte of every frame since power-on. Restoring a state means
+// powering on again and replaying those inputs.
+pub struct Machine {
+    rom: Vec<u8>,
+    hash: u32,
+    data_hash: u32,
+    status: Arc<AtomicU8>,
+    nes: Emulator,
+    inputs: Vec<u8>,
+    revision: u64,
+}
+
+impl Machine {
+    pub fn new(rom: Vec<u8>, status: Arc<AtomicU8>) -> Self {
+        let machine = Self::powered_on(rom, status);
+        info!(
+            "loaded ROM {:08X} ({} bytes)",
+            machine.hash,
+            machine.rom.len()
+        );
+        machine
+    }
+
+    // a machine with its own controller, powered on and replayed to the
+    // given inputs
+    pub fn replay(rom: &[u8], inputs: &[u8]) -> Self {
+        let mut machine = Self::powered_on(rom.to_vec(), Arc::new(AtomicU8::new(0)));
+        machine.restore(inputs.to_vec());
+        machine
+    }
+
+    fn powered_on(rom: Vec<u8>, status: Arc<AtomicU8>) -> Self {
+        let hash = crc32fast::hash(&rom);
+        let data_hash = crc32fast::hash(rom.get(INES_HEADER..).unwrap_or_default());
+        let nes = power_on(&rom, &status);
+        Self {
+            rom,
+            hash,
+            data_hash,
+            status,
+            nes,
+            inputs: Vec::new(),
+            revision: 0,
+        }
+    }
+
+    pub fn set_input(&self, input: u8) {
+        self.status.store(input, Ordering::Relaxed);
+    }
+
+    pub fn next_frame(&mut self) {
+        self.inputs.push(self.status.load(Ordering::Relaxed));
+        self.nes.next_frame();
+        self.revision += 1;
+    }
+
+    // Steps `n` scanlines or CPU cycles, which can end in the middle of a
+    // frame. A frame is only recorded once it is complete: inputs, revision
+    // and with them the journal and greenzone see nothing of it before, its
+    // controller byte is the one set when it started, so input set in between
+    // waits for the next frame, and a state captured mid-frame is the last
+    // complete one. fastnes only steps whole frames, so for now this is
+    // always Unsupported and leaves the machine as it is.
+    pub fn step_sub_frame(&mut self, _unit: SubFrame, _n: u32) -> Result<(), Unsupported> {
+        Err(Unsupported)
+    }
+
+    // The samples the APU generated during the last frame, for --dump-wav and
+    // record_audio. A dump writes all of them, also when running faster than
+    // real time, so that a frame's audio ends exactly where the frame does
+    // once it is muxed with the video. marlua takes no audio from fastnes,
+    // so for now this is always Unsupported.
+    pub fn frame_audio(&self) -> Result<&[f32], Unsupported> {
+        Err(Unsupported)
+    }
+
+    // Controller bytes for the successive latches within the next frame, the
+    // last one repeating once they run out; the frame after starts over with
+    // the staged byte. A frame stepped with more than one would need all of
+    // them in the journal, which stores one byte a frame. The controller
+    // reads the byte marlua sets once a frame, so for now this is always
+    // Unsupported.
+    pub fn queue_latches(&mut self, _inputs: &[u8]) -> Result<(), Unsupported> {
+        Err(Unsupported)
+    }
+
+    // Puts a Four Score adapter between the console and the controllers for
+    // --four-score, so games for up to four players see ports 3 and 4. The
+    // buttons, the journal and the input display would then take a port
+    // from 1 to 4 where they now have the one controller. marlua connects a
+    // single standard controller to port 1, so for now this is always
+    // Unsupported.
+    pub fn connect_four_score(&mut self) -> Result<(), Unsupported> {
+        Err(Unsupported)
+    }
+
+    // Aims the Zapper in port 2 at a pixel, or keeps the aim, and pulls the
+    // trigger for the next frame. Its light sense would read the brightness
+    // around the aim from the frame being drawn, the window's mouse would
+    // aim it under a crosshair, and the journal would store the aim and the
+    // trigger of every frame. marlua connects nothing to port 2, so for now
+    // this is always Unsupported.
+    pub fn set_zapper(
+        &mut self,
+        _aim: Option<(u8, u8)>,
+        _trigger: bool,
+    ) -> Result<(), Unsupported> {
+        Err(Unsupported)
+    }
+
+    // PPUCTRL and PPUMASK as last written before the frame ended, and whether
+    // the sprite-zero hit flag was set, for ppu.status(). The scanline of the
+    // hit would need the PPU to report when it sets the flag. marlua can
+    // neither read back the registers nor the status flags, so for now this
+    // is always Unsupported.
+    pub fn ppu_registers(&self) -> Result<(u8, u8, bool), Unsupported> {
+        Err(Unsupported)
+    }
+
+    // The scroll the PPU rendered a visible scanline of the last frame with,
+    // in pixels into the nametables, for scroll_at(). Games that split the
+    // screen change it between scanlines, so it would be kept for each of them
+    // as the frame is drawn, or rebuilt from the $2005 and $2006 writes.
+    // marlua sees neither, so for now this is always Unsupported.
+    pub fn scroll_at(&self, _scanline: u8) -> Result<(u16, u16), Unsupported> {
+        Err(Unsupported)
+    }
+
+    pub fn restore(&mut self, inputs: Vec<u8>) {
+        self.nes = power_on(&self.rom, &self.status);
+        for &input in &inputs {
+            self.status.store(input, Ordering::Relaxed);
+            self.nes.next_frame();
+        }
+        self.inputs = inputs;
+        self.revision += 1;
+    }
+
+    // trades emulator states with another machine of the same ROM, without
+    // replaying either. Both revisions move past the two before, so what
+    // follows either machine by its revision sees the change
+    pub fn swap(&mut self, other: &mut Machine) {
+        debug_assert_eq!(self.hash, other.hash);
+        std::mem::swap(&mut self.status, &mut other.status);
+        std::mem::swap(&mut self.nes, &mut other.nes);
+        std::mem::swap(&mut self.inputs, &mut other.inputs);
+        let revision = self.revision.max(other.revision) + 1;
+        self.revision = revision;
+        other.revision = revision;
+    }
+
+    // number of frames emulated since power-on
+    pub fn frame(&self) -> usize {
+        self.inputs.len()
+    }
+
+    // changes whenever the emulator state does
+    pub fn revision(&self) -> u64 {
+        self.revision
+    }
+
+    pub fn rom(&self) -> &[u8] {
+        &self.rom
+    }
+
+    // CRC32 of the loaded iNES file
+    pub fn hash(&self) -> u32 {
+        self.hash
+    }
+
+    // CRC32 without the iNES header, which is how ROM databases identify
+    // games regardless of header variations
+    pub fn data_hash(&self) -> u32 {
+        self.data_hash
+    }
+
+    pub fn inputs(&self) -> &[u8] {
+        &self.inputs
+    }
+
+    pub fn nes(&self) -> &Emulator {
+        &self.nes
+    }
+
+    pub fn nes_mut(&mut self) -> &mut Emulator {
+        &mut self.nes
+    }
+}
+
+fn power_on(rom: &[u8], status: &Arc<AtomicU8>) -> Emulator {
+    NES::new(
+        NROM::from_ines(rom.to_vec()),
+        Controllers::standard(status),
+        FastPPU::new(),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::testing;
+
+    #[test]
+    fn swap() {
+        let mut a = testing::machine();
+        let mut b = testing::machine();
+        for input in [1, 2, 3] {
+            testing::step(&mut a, input);
+        }
+        let revision = a.revision();
+        a.swap(&mut b);
+        assert!(a.inputs().is_empty());
+        assert_eq!(b.inputs(), [1, 2, 3]);
+        assert!(a.revision() > revision && b.revision() > revision);
+
+        // each keeps stepping its own state with its own controller
+        testing::step(&mut b, 4);
+        testing::step(&mut a, 5);
+        let replayed = super::Machine::replay(&testing::rom(), &[1, 2, 3, 4]);
+        assert_eq!(b.inputs(), replayed.inputs());
+        assert_eq!(b.nes().read_internal(0), replayed.nes().read_internal(0));
+        assert_eq!(a.inputs(), [5]);
+    }
+}
@@ -0,0 +1,107 @@
+use std::{collections::BTreeMap, fs, path::PathBuf};
+
+use rlua::prelude::LuaError;
+
+use crate::machine::Machine;
+
+// macro files are one controller byte per frame, nothing else
+const EXTENSION: &str = "macro";
+
+// Inputs recorded with the record_macro hotkey.
+//
+// Recording takes whatever reached the controller between the two presses
+// from the machine's input history, so it captures the script's inputs as
+// well as the player's. Macros are saved to output/<rom-name>/macros/ and
+// can be played in later sessions.
+pub struct Macros {
+    dir: PathBuf,
+    // the frame recording started at
+    recording: Option<usize>,
+    recorded: BTreeMap<String, Vec<u8>>,
+}
+
+impl Macros {
+    pub fn new(dir: PathBuf) -> Self {
+        Self {
+            dir,
+            recording: None,
+            recorded: BTreeMap::new(),
+        }
+    }
+
+    // start or stop recording, returns what happened for the log
+    pub fn toggle(&mut self, machine: &Machine) -> Result<String, String> {
+        let Some(start) = self.recording.take() else {
+            self.recording = Some(machine.frame());
+            return Ok(format!("recording a macro from frame {}", machine.frame()));
+        };
+
+        // restoring a state while recording can move back before the start
+        let inputs = machine.inputs();
+        let inputs = &inputs[start.min(inputs.len())..];
+        if inputs.is_empty() {
+            return Err("no frames were recorded, the macro was discarded".to_owned());
+        }
+
+        let name = self.free_name();
+        let path = self.path(&name);
+        fs::create_dir_all(&self.dir)
+            .and_then(|()| fs::write(&path, inputs))
+            .map_err(|e| format!("could not save {}: {}", path.display(), e))?;
+        self.recorded.insert(name.clone(), inputs.to_vec());
+        Ok(format!(
+            "recorded macro {} ({} frames) to {}",
+            name,
+            inputs.len(),
+            path.display()
+        ))
+    }
+
+    // macro1, macro2, ... skipping names used in this or earlier sessions
+    fn free_name(&self) -> String {
+        (1..)
+            .map(|n| format!("macro{}", n))
+            .find(|name| !self.recorded.contains_key(name) && !self.path(name).exists())
+            .unwrap()
+    }
+
+    fn path(&self, name: &str) -> PathBuf {
+        self.dir.join(name).with_extension(EXTENSION)
+    }
+
+    // a macro from this session or a saved one
+    pub fn get(&self, name: &str) -> Result<Vec<u8>, LuaError> {
+        if let Some(inputs) = self.recorded.get(name) {
+            return Ok(inputs.clone());
+        }
+        if name.contains(['/', '\\', '.']) {
+            return Err(LuaError::RuntimeError(format!(
+                "invalid macro name {}",
+                name
+            )));
+        }
+        fs::read(self.path(name))
+            .map_err(|_| LuaError::RuntimeError(format!("no macro named {}", name)))
+    }
+
+    // every macro that can be played, sorted
+    pub fn is_recording(&self) -> bool {
+        self.recording.is_some()
+    }
+
+    pub fn names(&self) -> Vec<String> {
+        let mut names: Vec<_> = self.recorded.keys().cloned().collect();
+        let saved = fs::read_dir(&self.dir).into_iter().flatten().flatten();
+        for entry in saved {
+            let path = entry.path();
+            if path.extension().map_or(false, |e| e == EXTENSION) {
+                if let Some(stem) = path.file_stem().and_then(|s| s.to_str()) {
+                    names.push(stem.to_owned());
+                }
+            }
+        }
+        names.sort();
+        names.dedup();
+        names
+    }
+}
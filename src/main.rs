@@ -1,113 +1,869 @@
+mod alert;
+mod api;
+mod autosave;
+mod bindings;
+mod blend;
+mod bundle;
+mod buttons;
+mod capture;
+mod cli;
+mod config;
+mod debugger;
+mod diff;
+mod events;
+mod failure;
+mod frametimes;
+mod games;
+mod gl;
+#[cfg(feature = "wgpu")]
+mod gpu;
+mod greenzone;
+mod hashes;
+mod help;
+mod hexview;
+mod image;
+mod input;
+mod inputfile;
+mod instances;
+mod interrupt;
+mod journal;
+mod layers;
+mod layout;
+mod letterbox;
+mod limits;
+mod logging;
+mod machine;
+mod macros;
+mod memory;
+mod menu;
+mod modules;
+mod output;
+mod overlay;
+mod panels;
+mod pause;
+mod permissions;
+mod picker;
+mod picture;
+mod pinning;
+mod placement;
+mod practice;
+mod priority;
+mod profile;
+mod ramdump;
+mod recent;
+mod render;
+mod report;
+mod sandbox;
+mod savestate;
+mod smoke;
+mod software;
+mod sprites;
+mod staged;
+mod startup;
+mod stats;
+mod subtitles;
+#[cfg(test)]
+mod testing;
+mod tilehash;
+mod tiles;
+mod time;
+mod verify;
+mod views;
+mod warmcache;
+mod watchdog;
+
 use std::{
     cell::RefCell,
+    collections::{BTreeMap, HashMap},
+    error::Error,
+    fmt,
     fs::{read, read_to_string},
+    panic::{self, AssertUnwindSafe},
+    path::PathBuf,
+    process,
     rc::Rc,
     sync::{
         atomic::{AtomicBool, AtomicU8, Ordering},
-        Arc, Mutex,
+        mpsc::{channel, Receiver, RecvTimeoutError, Sender},
+        Arc, Mutex, Once,
     },
-    thread, unreachable,
+    thread::{self, JoinHandle},
+    time::{Duration, Instant, SystemTime, UNIX_EPOCH},
+    unreachable,
 };
 
-use fastnes::{
-    cart::{Cartridge, NROM},
-    input::Controllers,
-    nes::NES,
-    ppu::{DrawOptions, FastPPU, PPU},
-};
-use femtovg::{imgref::Img, renderer::OpenGl, rgb::RGBA8, Canvas, ImageFlags, Paint, Path};
-use glutin::{
-    config::ConfigTemplateBuilder,
-    context::{ContextApi, ContextAttributesBuilder, PossiblyCurrentContext},
-    display::GetGlDisplay,
-    prelude::{GlDisplay, NotCurrentGlContextSurfaceAccessor},
-    surface::{GlSurface, Surface, SurfaceAttributesBuilder, WindowSurface},
-};
-use glutin_winit::{DisplayBuilder, GlWindow};
-use raw_window_handle::HasRawWindowHandle;
-use rlua::{prelude::LuaError, Context, FromLua, Function, MultiValue};
-use rlua::{Lua, StdLib};
+use alert::Beeper;
+use autosave::Autosave;
+use bindings::{Bindings, Hotkey};
+use blend::Blend;
+use bundle::AutoBundle;
+use buttons::{Aliases, Button};
+use capture::{Capture, Region};
+use cli::{Mode, Options, Renderer, Vsync};
+use events::{EventLog, Kind};
+use failure::Failure;
+use fastnes::ppu::DrawOptions;
+use gl::Gl;
+use greenzone::Greenzone;
+use hashes::Hashes;
+use help::Help;
+use image::Images;
+use input::{InputState, ScriptKey};
+use inputfile::{Dump, InputFile};
+use instances::{Instances, Outcome};
+use journal::Journal;
+use layers::Layers;
+use layout::{Layout, Watcher};
+use letterbox::{CaptureLayout, Scene};
+use limits::Limits;
+use machine::{Machine, SubFrame, Unsupported};
+use macros::Macros;
+use menu::{Action, Menu};
+use output::OutputDir;
+use overlay::{Overlay, TOAST_TIME};
+use panels::{Panel, Panels};
+use pause::{Pause, Reason};
+use permissions::Permissions;
+use picker::Picker;
+use picture::Picture;
+use pinning::ScriptHashes;
+use placement::Placement;
+use practice::Counter;
+use profile::Profiler;
+use ramdump::Recorder;
+use render::{Overlays, Present};
+use report::Report;
+use rlua::{prelude::LuaError, Context, Function, Lua, MultiValue, StdLib, ToLua, Value};
+use savestate::{Savestate, StateError};
+use software::Software;
+use spin_sleep::LoopHelper;
+use staged::{Dropped, Staged};
+use startup::{Choice, ErrorScreen};
+use stats::{Sample, Stats, Title};
+use subtitles::Subtitles;
+use time::{CpuUsage, DateTime};
+use tracing::{debug, error, info, info_span, span::EnteredSpan, warn};
+use views::{View, Views};
+use warmcache::WarmCache;
+use watchdog::Watchdog;
 use winit::{
     dpi::PhysicalSize,
-    event_loop::{ControlFlow, EventLoop},
-    window::{Window, WindowBuilder},
+    event::{ElementState, KeyboardInput, MouseButton, VirtualKeyCode},
+    event_loop::{ControlFlow, EventLoop, EventLoopProxy},
+    window::{Fullscreen, Window, WindowBuilder},
 };
 
+// where the game image is drawn in the window: x, y, width, height
+const IMAGE_RECT: (f32, f32, f32, f32) = (0.0, 0.0, 256.0, 240.0);
+
+// on NTSC, from the NMI to the pre-render scanline
+const VBLANK_SCANLINES: u32 = 20;
+
+// registry keys of the functions given to on_click and on_key
+const ON_CLICK: &str = "marlua.on_click";
+const ON_KEY: &str = "marlua.on_key";
+const ON_IDLE: &str = "marlua.on_idle";
+// frame -> sequence of at and in_frames callbacks
+const SCHEDULED: &str = "marlua.scheduled";
+// key name -> bind_key callback
+const BOUND_KEYS: &str = "marlua.bound_keys";
+
+// savestate file used by the quicksave hotkeys
+const QUICKSAVE: &str = "quicksave.state";
+
+// with states.persist_slots
+fn slot_file(slot: u8) -> String {
+    format!("slot-{}.state", slot)
+}
+
+// how long the window has to be unfocused before the trailing loop idles,
+// see window.idle_fps
+const IDLE_AFTER: Duration = Duration::from_secs(5);
+
+// how often a formatted window title is updated
+const TITLE_EVERY: Duration = Duration::from_secs(1);
+
+// how often the window presents without a new frame, for the overlays
+const REDRAW_EVERY: Duration = Duration::from_nanos(1_000_000_000 / FPS as u64);
+
+// how often pause() looks for key callbacks and interrupts
+const RESUME_POLL: Duration = Duration::from_millis(10);
+
+// how long closing the window waits for the emulator thread to finish the
+// journal and the other files of the run, see stop_emulator
+const CLOSE_GRACE: Duration = Duration::from_secs(1);
+const CLOSE_POLL: Duration = Duration::from_millis(5);
+
+// the rate the clock steps the emulator at, at normal speed
+const FPS: f64 = 60.0;
+const MAX_SPEED: f64 = 16.0;
+
+// inputs that bring SMB from power-on to level 1-1
+const WARMUP: &[u8] = &[
+    0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0,
+    0b00001000, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0,
+    0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0,
+    0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0,
+    0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0,
+    0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0,
+];
+
+// RAM after the warm-up, see src/lua/smb.lua for the map: in a game
+// (OperMode 1) at world 1, level 1, area 0. A mismatch means another game,
+// another revision or lag the inputs did not account for.
+const WARMUP_CHECK: &[(u16, u8)] = &[
+    (0x0770, 0x01),
+    (0x075F, 0x00),
+    (0x075C, 0x00),
+    (0x0760, 0x00),
+];
+
+// requests from the window to the emulator thread
+enum Command {
+    SaveState,
+    LoadState,
+    Break,
+    // a dropped ROM or script
+    Open(PathBuf),
+    // a ROM and script chosen in the picker
+    Pick(PathBuf, PathBuf),
+    Focus(bool),
+    Menu(bool),
+    // the help overlay opened or closed
+    Help(bool),
+    // run the current script again from the start
+    Restart,
+    Screenshot,
+    // start or stop recording a macro
+    RecordMacro,
+    // normal, half and quarter speed in turn
+    SlowMotion,
+    // pause for frame advance, or resume
+    Pause,
+    // one frame with the staged input, pausing first if running
+    FrameAdvance,
+    // toggle a button of the staged input while paused
+    Stage(Button),
+    // greenzone::SEEK_STEP frames forward or back while paused
+    Seek { forward: bool },
+    // the debug views the window shows, in the game window or their own
+    Views(Vec<View>),
+    // back to the start of the journal being played
+    Rewind,
+    // the number key hotkeys, 1 to 9
+    SaveSlot(u8),
+    LoadSlot(u8),
+    // the window is closing, the run ends like at a limit
+    Close,
+}
+
+// why run_lua returned
+enum Exit {
+    Finished(Report),
+    Open(PathBuf),
+    // stopped by --max-frames or --max-wall-time, with the reason
+    Limit(Report, String),
+}
+
+// raised through the script to unwind it when another file is opened
+#[derive(Debug)]
+struct Reopen;
+
+impl fmt::Display for Reopen {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "another file was opened")
+    }
+}
+
+impl Error for Reopen {}
+
+// raised through the script to unwind it after quit()
+#[derive(Debug)]
+struct Quit;
+
+impl fmt::Display for Quit {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "the script quit")
+    }
+}
+
+impl Error for Quit {}
+
+// raised through a script body at its first wait after --resume
+#[derive(Debug)]
+struct Resumed;
+
+impl fmt::Display for Resumed {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "the script resumed from a checkpoint")
+    }
+}
+
+impl Error for Resumed {}
+
 struct Screen {
     el: EventLoop<()>,
+    // dropped before the window it draws to
+    backend: Box<dyn Present>,
     window: Window,
-    surface: Surface<WindowSurface>,
-    context: PossiblyCurrentContext,
-    canvas: Canvas<OpenGl>,
+    overlay: Overlay,
+    remember: bool,
+    // window.detach, and the window size the panels are laid out for
+    detach: Vec<Panel>,
+    size: (u32, u32),
+    // reads the overlay layout again when the config changes
+    layout: Option<Watcher>,
+    // --capture-layout
+    capture: Option<CaptureLayout>,
 }
 
 impl Screen {
-    fn new(title: &str, width: u32, height: u32) -> Self {
+    fn new(
+        title: &str,
+        width: u32,
+        height: u32,
+        renderer: Renderer,
+        vsync: Option<Vsync>,
+        placement: Placement,
+        detach: Vec<Panel>,
+    ) -> Result<Self, String> {
         // create window
         let el = EventLoop::new();
-        let (window, config) = DisplayBuilder::new()
-            .with_window_builder(Some(
-                WindowBuilder::new()
-                    .with_title(title)
-                    .with_inner_size(PhysicalSize::new(width, height))
-                    .with_resizable(false),
-            ))
-            .build(&el, ConfigTemplateBuilder::new(), |mut it| {
-                it.next().unwrap()
-            })
-            .unwrap();
+        let builder = || {
+            WindowBuilder::new()
+                .with_title(title)
+                .with_inner_size(PhysicalSize::new(width, height))
+                .with_resizable(false)
+        };
 
-        // create surface
-        let window = window.unwrap();
-        let attrs = window.build_surface_attributes(SurfaceAttributesBuilder::new());
-
-        let display = config.display();
-        let surface = unsafe { display.create_window_surface(&config, &attrs).unwrap() };
-
-        // create context
-        let context = unsafe {
-            display
-                .create_context(
-                    &config,
-                    &ContextAttributesBuilder::new()
-                        .with_context_api(ContextApi::OpenGl(None))
-                        .build(Some(window.raw_window_handle())),
-                )
-                .unwrap()
-                .make_current(&surface)
-                .unwrap()
+        // wgpu falls back to OpenGL, which falls back to software
+        let mut backend: Option<(Window, Box<dyn Present>)> = None;
+        #[cfg(feature = "wgpu")]
+        if renderer == Renderer::Wgpu {
+            let window = builder().build(&el).map_err(|e| e.to_string())?;
+            match gpu::Gpu::new(&window, vsync) {
+                Ok(gpu) => backend = Some((window, Box::new(gpu))),
+                Err(e) => warn!("could not set up wgpu ({}), using OpenGL", e),
+            }
+        }
+        if backend.is_none() && renderer != Renderer::Software {
+            match Gl::new(&el, builder(), width, height, vsync) {
+                Ok((window, gl)) => backend = Some((window, Box::new(gl))),
+                Err(e) => warn!(
+                    "could not set up OpenGL ({}), using the software renderer without overlays",
+                    e
+                ),
+            }
+        }
+        let (window, backend) = match backend {
+            Some(backend) => backend,
+            None => {
+                let window = builder().build(&el).map_err(|e| e.to_string())?;
+                let software = Software::new(&window)?;
+                if let Some(vsync) = vsync {
+                    warn!(
+                        "the software renderer cannot turn vsync {}, it presents like the system does",
+                        vsync.name()
+                    );
+                }
+                (window, Box::new(software) as Box<dyn Present>)
+            }
         };
 
-        // create OpenGL
-        let opengl = OpenGl::new_from_glutin_display(&display).unwrap();
-        let mut canvas = Canvas::new(opengl).unwrap();
-        canvas.set_size(width, height, 1.0);
+        placement.apply(&window);
 
         // return
-        Self {
+        Ok(Self {
             el,
+            backend,
             window,
-            surface,
-            context,
-            canvas,
+            overlay: Overlay::default(),
+            remember: placement.remember,
+            detach,
+            size: (width, height),
+            layout: None,
+            capture: None,
+        })
+    }
+
+    // the game stays where the layout puts it however the window is resized
+    fn set_capture(&mut self, capture: Option<CaptureLayout>) {
+        self.window.set_resizable(capture.is_some());
+        self.capture = capture;
+    }
+
+    // where the game goes in the window, see letterbox.rs
+    fn scene(&self) -> Scene {
+        let size = self.window.inner_size();
+        self.capture.map_or_else(Scene::default, |capture| {
+            capture.scene((size.width, size.height))
+        })
+    }
+
+    // tell capture software and the script where the game is, whenever it
+    // moves
+    fn publish_capture(&self, frame: &Frame) {
+        let Some(capture) = self.capture else {
+            return;
+        };
+        let size = self.window.inner_size();
+        let rect = capture.rect((size.width, size.height));
+        if frame.capture_rect() != Some(rect) {
+            let (x, y, width, height) = rect;
+            info!(
+                "capture region: x={} y={} width={} height={} in a {}x{} window, background #{}",
+                x,
+                y,
+                width,
+                height,
+                size.width,
+                size.height,
+                capture.hex()
+            );
+            frame.set_capture_rect(Some(rect));
+        }
+    }
+
+    fn set_layout(&mut self, layout: Layout, watcher: Watcher) {
+        self.overlay.set_layout(layout);
+        self.layout = Some(watcher);
+    }
+
+    // a toast at the bottom of the window, or in the log without overlays
+    fn toast(&mut self, text: String, time: Duration) {
+        match self.backend.overlays() {
+            true => self.overlay.toast(text, time),
+            false => info!("{}", text),
         }
     }
-    fn run(mut self, f: impl Fn(&mut Canvas<OpenGl>) + 'static) -> ! {
-        self.el.run(move |event, _, cf| match event {
+    fn notify(&mut self, text: String) {
+        self.toast(text, TOAST_TIME);
+    }
+
+    fn run(
+        mut self,
+        commands: Sender<Command>,
+        pause: Arc<Pause>,
+        input: Arc<InputState>,
+        bindings: Bindings,
+        mut picker: Option<Picker>,
+        emulator: JoinHandle<()>,
+        failure: Arc<Failure>,
+        frame: Arc<Frame>,
+    ) -> ! {
+        if picker.is_some() && !self.backend.overlays() {
+            warn!("the startup screen needs OpenGL, drop a ROM on the window or press Enter for the most recent one");
+        }
+        let mut title = None;
+        let mut sample: Option<Sample> = None;
+        frame.stats.set_vsync(self.backend.vsync());
+        frame.set_wake(self.el.create_proxy());
+        self.publish_capture(&frame);
+        // the exit code once the emulator has failed
+        let mut crashed = None;
+        let mut menu: Option<Menu> = None;
+        let mut help: Option<Help> = None;
+        let mut panels = Panels::new(self.detach.clone(), self.size);
+        let mut cursor = None;
+        // shown while the emulator waits for the ROM or script to be fixed
+        let mut error: Option<ErrorScreen> = None;
+        // Shift turns loading a slot into saving it
+        let mut shift = false;
+        self.el.run(move |event, target, cf| match event {
             // Window events
             winit::event::Event::WindowEvent {
                 ref event,
                 window_id,
             } if window_id == self.window.id() => match event {
                 // Exit on window close
-                winit::event::WindowEvent::CloseRequested => *cf = ControlFlow::Exit,
+                winit::event::WindowEvent::CloseRequested => {
+                    stop_emulator(&commands, &emulator);
+                    // quitting from the error screen is not a clean exit
+                    *cf = exit(crashed.or(error.as_ref().map(|_| 1)))
+                }
+                winit::event::WindowEvent::Resized(size) => {
+                    debug!("window resized to {}x{}", size.width, size.height);
+                    self.publish_capture(&frame);
+                }
+
+                // Mouse state for scripts
+                winit::event::WindowEvent::CursorMoved { position, .. } => {
+                    cursor = Some((position.x as f32, position.y as f32));
+                    input.move_cursor(Some((position.x, position.y)), self.scene().image);
+                }
+                winit::event::WindowEvent::CursorLeft { .. } => {
+                    cursor = None;
+                    input.move_cursor(None, self.scene().image);
+                }
+                winit::event::WindowEvent::MouseInput { state, button, .. } => {
+                    let pressed = *state == ElementState::Pressed;
+                    // clicks on a byte go to the hex viewer
+                    let in_main = panels.in_main(Panel::Memory);
+                    let memory = panels.memory.as_mut().filter(|_| in_main);
+                    let byte = memory.as_ref().zip(cursor).and_then(|(m, c)| m.byte_at(c));
+                    match (byte, button) {
+                        (Some(addr), MouseButton::Left) if pressed => {
+                            memory.unwrap().toggle_watch(addr)
+                        }
+                        (Some(_), MouseButton::Right) if pressed => {
+                            self.notify(hexview::FREEZE.to_owned())
+                        }
+                        (Some(_), _) => {}
+                        (None, _) => input.button(*button, pressed),
+                    }
+                }
+
+                winit::event::WindowEvent::ModifiersChanged(modifiers) => {
+                    shift = modifiers.shift();
+                }
+                // Minimizing also unfocuses the window
+                winit::event::WindowEvent::Focused(focused) => {
+                    let _ = commands.send(Command::Focus(*focused));
+                }
+
+                // Hotkeys
+                winit::event::WindowEvent::KeyboardInput {
+                    input:
+                        KeyboardInput {
+                            state,
+                            virtual_keycode: Some(key),
+                            ..
+                        },
+                    ..
+                } => {
+                    let pressed = *state == ElementState::Pressed;
+                    let modal =
+                        picker.is_some() || menu.is_some() || help.is_some() || error.is_some();
+                    let taken_over = input.taken_over(*key);
+                    if !modal && (!bindings.hidden(*key) || taken_over) {
+                        input.key(*key, pressed);
+                    }
+                    if !pressed {
+                        return;
+                    }
+
+                    // the error screen takes all keys until the files are fixed
+                    if let Some(screen) = &mut error {
+                        match screen.key(*key) {
+                            Some(Choice::Open(path)) => {
+                                let _ = commands.send(Command::Open(path));
+                            }
+                            Some(Choice::Quit) => {
+                                stop_emulator(&commands, &emulator);
+                                *cf = exit(Some(1))
+                            }
+                            None => {}
+                        }
+                        return;
+                    }
+
+                    // the picker takes all keys until something was chosen
+                    if let Some(p) = &mut picker {
+                        if let Some((rom, script)) = p.key(*key) {
+                            picker = None;
+                            let _ = commands.send(Command::Pick(rom, script));
+                        }
+                        return;
+                    }
+
+                    if let Some(m) = &mut menu {
+                        let Some(action) = m.key(*key) else { return };
+                        menu = None;
+                        let _ = commands.send(Command::Menu(false));
+                        let command = match action {
+                            Action::Resume => return,
+                            Action::Restart => Command::Restart,
+                            Action::Fullscreen => {
+                                toggle_fullscreen(&self.window);
+                                return;
+                            }
+                            Action::SaveState => Command::SaveState,
+                            Action::LoadState => Command::LoadState,
+                            Action::Screenshot => Command::Screenshot,
+                            Action::Quit => {
+                                stop_emulator(&commands, &emulator);
+                                *cf = exit(crashed);
+                                return;
+                            }
+                        };
+                        let _ = commands.send(command);
+                        return;
+                    }
+
+                    // F1 again or Escape closes the help, other keys scroll
+                    if let Some(h) = &mut help {
+                        if *key == VirtualKeyCode::Escape
+                            || bindings.get(*key) == Some(Hotkey::Help)
+                        {
+                            help = None;
+                            let _ = commands.send(Command::Help(false));
+                        } else {
+                            h.key(*key);
+                        }
+                        return;
+                    }
+
+                    // the transport keys while a journal plays, ahead of
+                    // the hotkeys they share keys with
+                    if frame.playback().is_some() {
+                        let command = match key {
+                            VirtualKeyCode::Left => Some(Command::Seek { forward: false }),
+                            VirtualKeyCode::Right => Some(Command::Seek { forward: true }),
+                            VirtualKeyCode::Home => Some(Command::Rewind),
+                            VirtualKeyCode::Space => Some(Command::Pause),
+                            _ => None,
+                        };
+                        if let Some(command) = command {
+                            let _ = commands.send(command);
+                            return;
+                        }
+                    }
+
+                    // the panels in windows of their own get keys from there
+                    if panels.in_main(Panel::Tiles) {
+                        panels.tiles.as_mut().unwrap().key(*key);
+                    }
+                    if panels.in_main(Panel::Sprites) {
+                        panels.sprites.as_mut().unwrap().key(*key);
+                    }
+                    if panels.in_main(Panel::Memory) {
+                        panels.memory.as_mut().unwrap().key(*key);
+                    }
+
+                    // bind_key with force, the script handles it
+                    if taken_over {
+                        return;
+                    }
+                    let command = match bindings.get(*key) {
+                        Some(Hotkey::Menu) if !self.backend.overlays() => {
+                            warn!("the menu is not available with the software renderer");
+                            return;
+                        }
+                        Some(Hotkey::Menu) => {
+                            menu = Some(Menu::default());
+                            Command::Menu(true)
+                        }
+                        // the software renderer draws no overlays, the table
+                        // goes to the terminal instead
+                        Some(Hotkey::Help) if !self.backend.overlays() => {
+                            bindings.print();
+                            return;
+                        }
+                        Some(Hotkey::Help) => {
+                            let (rom, script) = frame.stats.names();
+                            let footer = format!("marlua {}, {} and {}", api::VERSION, rom, script);
+                            help = Some(Help::new(footer));
+                            Command::Help(true)
+                        }
+                        Some(Hotkey::SaveState) => Command::SaveState,
+                        Some(Hotkey::LoadState) => Command::LoadState,
+                        Some(Hotkey::Break) => Command::Break,
+                        Some(Hotkey::Fullscreen) => {
+                            toggle_fullscreen(&self.window);
+                            return;
+                        }
+                        Some(Hotkey::Screenshot) => Command::Screenshot,
+                        Some(Hotkey::RecordMacro) => Command::RecordMacro,
+                        Some(Hotkey::SlowMotion) => Command::SlowMotion,
+                        Some(Hotkey::Pause) => Command::Pause,
+                        Some(Hotkey::FrameAdvance) => Command::FrameAdvance,
+                        Some(Hotkey::Stage(button)) => Command::Stage(button),
+                        Some(Hotkey::Restart) => Command::Restart,
+                        Some(Hotkey::Slot(slot)) if shift => Command::SaveSlot(slot),
+                        Some(Hotkey::Slot(slot)) => Command::LoadSlot(slot),
+                        Some(Hotkey::SeekBack) => Command::Seek { forward: false },
+                        Some(Hotkey::SeekForward) => Command::Seek { forward: true },
+                        Some(Hotkey::FrameTimes) if !self.backend.overlays() => {
+                            warn!(
+                                "the frame time graph is not available with the software renderer"
+                            );
+                            return;
+                        }
+                        Some(Hotkey::FrameTimes) => {
+                            panels.toggle(Panel::FrameTimes, target);
+                            return;
+                        }
+                        Some(Hotkey::Tiles | Hotkey::Sprites | Hotkey::Memory)
+                            if !self.backend.overlays() =>
+                        {
+                            warn!("debug views are not available with the software renderer");
+                            return;
+                        }
+                        Some(hotkey @ (Hotkey::Tiles | Hotkey::Sprites | Hotkey::Memory)) => {
+                            let panel = match hotkey {
+                                Hotkey::Tiles => Panel::Tiles,
+                                Hotkey::Sprites => Panel::Sprites,
+                                _ => Panel::Memory,
+                            };
+                            panels.toggle(panel, target);
+                            Command::Views(panels.views())
+                        }
+                        Some(Hotkey::Detach) => {
+                            if let Err(e) = panels.detach(target) {
+                                self.notify(e);
+                                return;
+                            }
+                            Command::Views(panels.views())
+                        }
+                        None => return,
+                    };
+                    // the emulator thread may have exited
+                    let _ = commands.send(command);
+                }
+
+                // Open dropped ROMs and scripts
+                winit::event::WindowEvent::DroppedFile(path) => {
+                    let name = path.file_name().unwrap_or_default().to_string_lossy();
+                    let extension = path.extension().map(|e| e.to_string_lossy().to_lowercase());
+                    match extension.as_deref() {
+                        Some("nes") | Some("lua") => {
+                            // a dropped ROM also answers the picker
+                            if extension.as_deref() == Some("nes") {
+                                picker = None;
+                            }
+                            self.notify(format!("opening {}", name));
+                            let _ = commands.send(Command::Open(path.clone()));
+                        }
+                        _ => self.notify(format!(
+                            "cannot open {}, expected a .nes ROM or a .lua script",
+                            name
+                        )),
+                    }
+                }
                 _ => {}
             },
 
+            // the windows of detached debug panels
+            winit::event::Event::WindowEvent {
+                ref event,
+                window_id,
+            } => {
+                if panels.window_event(window_id, event, &bindings, target) {
+                    let _ = commands.send(Command::Views(panels.views()));
+                }
+            }
+
             // Redraw event
+            winit::event::Event::LoopDestroyed if self.remember => Placement::save(&self.window),
             winit::event::Event::MainEventsCleared => {
-                f(&mut self.canvas);
-                self.surface.swap_buffers(&self.context).unwrap();
+                // Ctrl-C or SIGTERM, like closing the window
+                if interrupt::requested() {
+                    stop_emulator(&commands, &emulator);
+                    *cf = exit(Some(interrupt::EXIT_CODE));
+                    return;
+                }
+                // the emulator thread only ever finishes by failing or when
+                // the script quit
+                if emulator.is_finished() && failure.quit() {
+                    *cf = ControlFlow::Exit;
+                    return;
+                }
+                if crashed.is_none() && emulator.is_finished() {
+                    crashed = Some(failure.exit_code());
+                    let message = failure
+                        .get()
+                        .unwrap_or_else(|| "the emulator thread exited unexpectedly".to_owned());
+                    error!("{}", message);
+                    self.overlay.fail(message);
+                    self.window.set_title("Marlua (stopped)");
+                }
+                match (frame.problem(), &error) {
+                    (Some(problem), Some(screen)) if problem == screen.message() => {}
+                    (Some(problem), _) => {
+                        if !self.backend.overlays() {
+                            warn!("drop a ROM or script on the window to fix this");
+                        }
+                        error = Some(ErrorScreen::new(problem));
+                        self.window.set_title("Marlua (cannot start)");
+                        title = Some("cannot start".to_owned());
+                    }
+                    (None, Some(_)) => error = None,
+                    (None, None) => {}
+                }
+
+                // formatted titles once a second and when the status changes
+                let status = pause.describe();
+                let due = sample
+                    .as_ref()
+                    .map_or(true, |s: &Sample| s.age() >= TITLE_EVERY);
+                match &frame.title {
+                    _ if crashed.is_some() || error.is_some() => {}
+                    Some(format) if due || status != title => {
+                        let mut next = Sample::take(&frame.stats, sample.as_ref());
+                        next.speed = pause.speed();
+                        next.status = status.clone();
+                        self.window.set_title(&format.render(&next));
+                        sample = Some(next);
+                        title = status;
+                    }
+                    None if status != title => {
+                        match &status {
+                            Some(status) => self.window.set_title(&format!("Marlua ({})", status)),
+                            None => self.window.set_title("Marlua"),
+                        }
+                        title = status;
+                    }
+                    _ => {}
+                }
+
+                let height = self.window.inner_size().height as f32;
+                let cursor = input.mouse().map(|(x, y, _)| (x, y));
+                let scene = self.scene();
+                panels.update(&frame.views, height, cursor, scene.image);
+                if let Some(help) = &mut help {
+                    help.update(&bindings, &input);
+                    help.fit(self.window.inner_size().height as f32);
+                }
+                for (text, time) in frame.take_toasts() {
+                    self.toast(text, time);
+                }
+                match self.layout.as_mut().and_then(Watcher::poll) {
+                    Some(Ok(layout)) => {
+                        self.overlay.set_layout(layout);
+                        self.notify("reloaded the overlay layout".to_owned());
+                    }
+                    Some(Err(e)) => {
+                        warn!("could not reload the overlay layout: {}", e);
+                        self.notify("the overlay layout has an error, see the log".to_owned());
+                    }
+                    None => {}
+                }
+                self.overlay.set_slot(frame.stats.slot());
+                self.overlay
+                    .set_progress(frame.playback(), pause.is_set(Reason::Hotkey));
+                let width = self.window.inner_size().width as f32;
+                self.overlay.set_subtitle(frame.subtitle(), width);
+                let times = panels.frame_times.then(|| frame.stats.frame_times());
+                let main = |panel| panels.in_main(panel);
+                let overlays = Overlays {
+                    picker: picker.as_ref(),
+                    menu: menu.as_ref(),
+                    help: help.as_ref(),
+                    tiles: panels.tiles.as_ref().filter(|_| main(Panel::Tiles)),
+                    sprites: panels.sprites.as_ref().filter(|_| main(Panel::Sprites)),
+                    memory: panels.memory.as_ref().filter(|_| main(Panel::Memory)),
+                    frame_times: times.as_ref().filter(|_| main(Panel::FrameTimes)),
+                    error: error.as_ref(),
+                    overlay: &mut self.overlay,
+                };
+                let published = frame.take_published();
+                let image = frame.frame();
+                let result = self.backend.present(&self.window, &image, scene, overlays);
+                if let Err(e) = result {
+                    error!("could not present the frame: {}", e);
+                }
+                // with vsync this includes the wait for the monitor
+                if let Some(published) = published {
+                    frame.stats.record_latency(published.elapsed());
+                }
+                panels.present(&image, times.as_ref());
+
+                // sleep until the emulator publishes the next frame, an
+                // event comes in or the overlays are due for a redraw, unless
+                // an event this iteration asked to exit
+                if !matches!(*cf, ControlFlow::ExitWithCode(_)) {
+                    *cf = ControlFlow::WaitUntil(Instant::now() + REDRAW_EVERY);
+                }
             }
 
             _ => (),
@@ -115,166 +871,2479 @@ impl Screen {
     }
 }
 
-fn as_rgba<const N: usize>(p: &[fastnes::ppu::Color; N]) -> &[RGBA8] {
-    unsafe {
-        ::core::slice::from_raw_parts(
-            (p as *const [fastnes::ppu::Color; N]) as *const RGBA8,
-            ::core::mem::size_of::<[fastnes::ppu::Color; N]>(),
-        )
+// Asks the emulator thread to end the run and gives it CLOSE_GRACE to do
+// so, which drops the journal, event log and other writers of the run so
+// they are flushed before the process exits.
+fn stop_emulator(commands: &Sender<Command>, emulator: &JoinHandle<()>) {
+    // the emulator thread may have exited
+    let _ = commands.send(Command::Close);
+    let deadline = Instant::now() + CLOSE_GRACE;
+    while !emulator.is_finished() && Instant::now() < deadline {
+        thread::sleep(CLOSE_POLL);
+    }
+}
+
+// the window exits with an error once the emulator has failed
+fn exit(crashed: Option<i32>) -> ControlFlow {
+    match crashed {
+        Some(code) => ControlFlow::ExitWithCode(code),
+        None => ControlFlow::Exit,
+    }
+}
+
+fn toggle_fullscreen(window: &Window) {
+    let fullscreen = match window.fullscreen() {
+        Some(_) => None,
+        None => Some(Fullscreen::Borderless(None)),
+    };
+    window.set_fullscreen(fullscreen);
+}
+
+// steps the emulator at a fixed rate (or as fast as possible without a
+// clock), publishing frames and handling requests from the window in between
+struct Driver {
+    clock: Option<LoopHelper>,
+    machine: Machine,
+    frame: Arc<Frame>,
+    commands: Rc<Receiver<Command>>,
+    autosave: Option<Autosave>,
+    checkpoints: BTreeMap<String, Savestate>,
+    // the number key slots, see Command::SaveSlot
+    slots: BTreeMap<u8, Savestate>,
+    persist_slots: bool,
+    // a script is driving the frames, and whether it allows loading slots
+    // under it with allow_user_states
+    scripted: bool,
+    user_states: bool,
+    capture: Capture,
+    images: Images,
+    watchdog: Watchdog,
+    pictures: HashMap<String, Picture>,
+    report: Report,
+    output: OutputDir,
+    instances: Instances,
+    // enter the debugger before the next frame a script waits for
+    breaking: bool,
+    // a file to restart with, see Command::Open
+    open: Option<PathBuf>,
+    // quit() was called
+    quit: bool,
+    // --resume runs the script bodies only up to their first wait, which
+    // sets `skipped` and stops them
+    resuming: bool,
+    skipped: bool,
+    pause: Arc<Pause>,
+    input: Arc<InputState>,
+    // for bind_key to find conflicts
+    bindings: Bindings,
+    // the buttons for the next frame, see step
+    staged: Arc<Staged>,
+    journal: Option<Journal>,
+    events: EventLog,
+    // --record-hashes
+    hashes: Option<Hashes>,
+    // --record-ram, and the segment of record_ram() if one is running
+    record_ram: Option<Recorder>,
+    script_ram: Option<Recorder>,
+    // after the journal, so that it is flushed before it is bundled
+    bundle: Option<AutoBundle>,
+    // events between two frames happen in that frame's span
+    span: Option<EnteredSpan>,
+    beeper: Beeper,
+    script: PathBuf,
+    focused: bool,
+    unfocused_since: Option<Instant>,
+    pause_on_focus_loss: bool,
+    // window.idle_fps, and whether the trailing loop is idling at it
+    idle_fps: Option<f64>,
+    idling: bool,
+    // the process's CPU usage since the focus changed or idling started,
+    // and before idling, logged when the window is focused again
+    cpu: Option<CpuUsage>,
+    busy_cpu: Option<f64>,
+    headless: bool,
+    snapshot_every: Option<u64>,
+    blend: Blend,
+    // what the window shows, captures choose their own
+    layers: Layers,
+    // the debug views the window shows
+    views: Vec<View>,
+    // the number of frames of the journal being played, see Frame::playback
+    playback: Option<usize>,
+    macros: Macros,
+    limits: Limits,
+    // multiplier of the clock's 60 frames per second
+    speed: f64,
+    // step one frame although paused
+    advance: bool,
+    // when the last step returned, and how many steps together with the
+    // script before them took longer than a frame at the current speed
+    stepped: Option<Instant>,
+    missed: usize,
+    dropped: Dropped,
+    greenzone: Greenzone,
+    aliases: Aliases,
+    // --inputs and --dump-inputs
+    input_file: Option<InputFile>,
+    dump: Option<Dump>,
+}
+
+impl Driver {
+    fn step(&mut self) {
+        let script = self.stepped.map_or(Duration::ZERO, |t| t.elapsed());
+        if let Some(clock) = &mut self.clock {
+            clock.loop_start();
+        }
+
+        while let Ok(command) = self.commands.try_recv() {
+            self.handle(command);
+        }
+        // sleep until something unpauses, taking staged input changes
+        // from the window and the frame advance hotkey in the meantime
+        self.pause.set_staged(self.staged.load());
+        while self.pause.is_paused()
+            && self.open.is_none()
+            && self.limits.exceeded().is_none()
+            && !std::mem::take(&mut self.advance)
+        {
+            match self.commands.recv() {
+                Ok(command) => self.handle(command),
+                Err(_) => break,
+            }
+        }
+        let started = Instant::now();
+
+        // the controller only changes here, once per frame; --inputs takes
+        // over while it lasts
+        let next = self.machine.frame() + 1;
+        if let Some(input) = self.input_file.as_ref().and_then(|f| f.input(next)) {
+            self.staged.reset(input);
+        }
+        let (input, dropped) = self.staged.step(&mut self.machine);
+        if dropped != 0 {
+            self.drop_input(next, dropped);
+        }
+        self.events.input(next, input);
+        self.frame.stats.set_frame(self.machine.frame());
+        self.greenzone.sync(&self.machine);
+        self.limits.tick();
+        self.publish();
+        if self.watchdog.is_tracking() {
+            let rgba = self.capture.frame(&mut self.machine);
+            self.watchdog.update(rgba, !self.scripted);
+        }
+        for &view in &self.views {
+            if self.machine.frame() % view.every() == 0 {
+                self.frame.views.publish(view, &self.machine);
+            }
+        }
+        self.span = None;
+        self.span = Some(info_span!("frame", frame = self.machine.frame()).entered());
+
+        if let Some(journal) = &mut self.journal {
+            if let Err(e) = journal.record(&self.machine, self.greenzone.rerecords()) {
+                warn!("stopped writing the input journal: {}", e);
+                self.journal = None;
+            }
+        }
+
+        if let Some(hashes) = &mut self.hashes {
+            if let Err(e) = hashes.record(&self.machine) {
+                warn!("stopped writing the hash track: {}", e);
+                self.hashes = None;
+            }
+        }
+
+        for recorder in [&mut self.record_ram, &mut self.script_ram] {
+            if let Some(ram) = recorder {
+                if let Err(e) = ram.record(&self.machine) {
+                    warn!("stopped recording RAM: {}", e);
+                    *recorder = None;
+                }
+            }
+        }
+
+        if let Some(dump) = &mut self.dump {
+            if let Err(e) = dump.record(&self.machine) {
+                warn!("stopped writing --dump-inputs: {}", e);
+                self.dump = None;
+            }
+        }
+
+        if let Some(autosave) = &mut self.autosave {
+            autosave.tick(&self.machine);
+        }
+
+        if let Some(n) = self.snapshot_every {
+            let frame = self.machine.frame();
+            if frame % n == 0 {
+                if let Err(e) = self.screenshot(&format!("snapshot-{:06}.png", frame), None) {
+                    warn!("could not save snapshot: {}", e);
+                }
+            }
+        }
+
+        // late when this frame and the script before it took longer than
+        // the clock gives them, which [emulator] priority is meant to help
+        let emulation = started.elapsed();
+        let mut slack = Duration::ZERO;
+        if let Some(clock) = &mut self.clock {
+            if (script + emulation).as_secs_f64() > 1.0 / (FPS * self.speed) {
+                self.missed += 1;
+                self.frame.stats.set_missed(self.missed);
+            }
+            let sleep = Instant::now();
+            clock.loop_sleep();
+            slack = sleep.elapsed();
+        }
+        self.frame.stats.record_times(emulation, script, slack);
+        self.stepped = Some(Instant::now());
+    }
+    // hand the frame to the window, blended if enabled
+    fn publish(&mut self) {
+        let playback = self.playback.map(|total| (self.machine.frame(), total));
+        self.frame.set_playback(playback);
+        let revision = self.machine.revision();
+        let options = self.layers.draw_options();
+        let nes = self.machine.nes_mut();
+        if self.blend.enabled() {
+            self.blend.push(nes.draw_frame(options), revision);
+            let blended = self.blend.frame();
+            self.frame.update(|frame| frame.copy_from_slice(blended));
+        } else {
+            self.frame.update(|frame| *frame = nes.draw_frame(options));
+        }
+    }
+    fn handle(&mut self, command: Command) {
+        match command {
+            Command::SaveState => {
+                let path = self.output.state(QUICKSAVE).unwrap();
+                match Savestate::capture(&self.machine).write(&path) {
+                    Ok(()) => info!("saved {}", path.display()),
+                    Err(e) => warn!("could not save {}: {}", path.display(), e),
+                }
+            }
+            Command::LoadState => {
+                let path = self.output.state(QUICKSAVE).unwrap();
+                let result = Savestate::read(&path).and_then(|s| s.apply(&mut self.machine));
+                match result {
+                    Ok(()) => {
+                        info!("loaded {}", path.display());
+                        self.loaded(QUICKSAVE);
+                    }
+                    Err(e) => warn!("could not load {}: {}", path.display(), e),
+                }
+            }
+            Command::SaveSlot(slot) => {
+                let state = Savestate::capture(&self.machine);
+                if self.persist_slots {
+                    let path = self.output.state(&slot_file(slot)).unwrap();
+                    if let Err(e) = state.write(&path) {
+                        warn!("could not save {}: {}", path.display(), e);
+                    }
+                }
+                self.slots.insert(slot, state);
+                self.frame.stats.set_slot(slot);
+                self.notify(format!("saved slot {}", slot));
+            }
+            // a state the script does not expect desyncs whatever it does next
+            Command::LoadSlot(_) if self.scripted && !self.user_states => self.notify(
+                "the script does not allow loading states, see allow_user_states".to_owned(),
+            ),
+            Command::LoadSlot(slot) => {
+                let Some(state) = self.slot(slot) else {
+                    self.notify(format!("slot {} is empty", slot));
+                    return;
+                };
+                match state.and_then(|state| state.apply(&mut self.machine)) {
+                    Ok(()) => {
+                        self.frame.stats.set_slot(slot);
+                        self.loaded(&format!("slot {}", slot));
+                        self.publish();
+                        self.notify(format!("loaded slot {}", slot));
+                    }
+                    Err(e) => self.notify(format!("could not load slot {}: {}", slot, e)),
+                }
+            }
+            Command::Break => self.breaking = true,
+            Command::Open(path) => self.open = Some(path),
+            // only sent before the emulator starts
+            Command::Pick(..) => {}
+            Command::Focus(focused) => {
+                self.focused = focused;
+                self.unfocused_since = (!focused).then(Instant::now);
+                let usage =
+                    std::mem::replace(&mut self.cpu, (!focused).then(CpuUsage::start).flatten());
+                if focused && std::mem::take(&mut self.idling) {
+                    match (
+                        self.busy_cpu.take(),
+                        usage.as_ref().and_then(CpuUsage::percent),
+                    ) {
+                        (Some(busy), Some(idle)) => info!(
+                            "focused, back to full speed; the process used {:.1}% CPU \
+                             idling, {:.1}% before",
+                            idle, busy
+                        ),
+                        _ => info!("focused, back to full speed"),
+                    }
+                }
+                self.update_focus();
+            }
+            Command::Menu(open) => self.pause.set(Reason::Menu, open),
+            Command::Help(open) => self.pause.set(Reason::Help, open),
+            Command::Restart => self.open = Some(self.script.clone()),
+            Command::Views(views) => {
+                for &view in &views {
+                    self.frame.views.publish(view, &self.machine);
+                }
+                self.views = views;
+            }
+            Command::Pause if self.pause.is_set(Reason::Script) => {
+                self.pause.set(Reason::Script, false)
+            }
+            Command::Pause => {
+                let paused = !self.pause.is_set(Reason::Hotkey);
+                self.pause.set(Reason::Hotkey, paused);
+            }
+            Command::FrameAdvance => {
+                if self.pause.is_set(Reason::Hotkey) {
+                    self.advance = true;
+                } else {
+                    self.pause.set(Reason::Hotkey, true);
+                }
+            }
+            Command::Stage(button) if self.pause.is_set(Reason::Hotkey) => {
+                let mut input = self.staged.load() ^ button.bit();
+                // like toggle, pressing a direction releases its opposite
+                if let Some(opposite) = button.opposite() {
+                    input &= !opposite.bit();
+                }
+                self.staged.reset(input);
+                self.pause.set_staged(input);
+            }
+            Command::Stage(_) => {}
+            // a journal being played can be sought at any time
+            Command::Seek { forward }
+                if self.pause.is_set(Reason::Hotkey) || self.playback.is_some() =>
+            {
+                let frame = self.machine.frame();
+                let frame = if forward {
+                    (frame + greenzone::SEEK_STEP).min(self.greenzone.frames())
+                } else {
+                    frame.saturating_sub(greenzone::SEEK_STEP)
+                };
+                match self.seek(frame) {
+                    Ok(()) => info!("sought frame {}", frame),
+                    Err(e) => warn!("{}", e),
+                }
+            }
+            Command::Seek { .. } => {}
+            Command::Rewind if self.playback.is_some() => {
+                if let Err(e) = self.seek(0) {
+                    warn!("{}", e);
+                }
+            }
+            Command::Rewind => {}
+            Command::SlowMotion => {
+                let speed = match self.speed {
+                    s if s > 0.5 => 0.5,
+                    s if s > 0.25 => 0.25,
+                    _ => 1.0,
+                };
+                self.set_speed(speed);
+            }
+            Command::RecordMacro => match self.macros.toggle(&self.machine) {
+                Ok(message) => info!("{}", message),
+                Err(e) => warn!("{}", e),
+            },
+            Command::Screenshot => match self.screenshot("screenshot.png", None) {
+                Ok(path) => info!("saved screenshot {}", path.display()),
+                Err(e) => warn!("could not save screenshot: {}", e),
+            },
+            Command::Close => self.limits.stop("the window was closed"),
+        }
+    }
+
+    // to a frame of the recording; frame advance continues with the input
+    // recorded for the next frame, if there is one
+    fn seek(&mut self, frame: usize) -> Result<(), String> {
+        self.greenzone.seek(&mut self.machine, frame)?;
+        if let Some(input) = self.greenzone.input(frame) {
+            self.staged.reset(input);
+            self.pause.set_staged(input);
+        }
+        self.publish();
+        for &view in &self.views {
+            self.frame.views.publish(view, &self.machine);
+        }
+        Ok(())
+    }
+
+    // runs without a clock are as fast as possible regardless
+    fn set_speed(&mut self, speed: f64) {
+        self.speed = speed;
+        self.pause.set_speed(speed);
+        if let Some(clock) = &mut self.clock {
+            *clock = LoopHelper::builder().build_with_target_rate(FPS * speed);
+        }
+        debug!("speed set to {}x", speed);
+    }
+
+    // the current frame as a PNG in the run directory, drawn from the
+    // emulator and not the window so it works with any renderer or none;
+    // without `layers` it is the frame as the window shows it
+    fn screenshot(&mut self, name: &str, layers: Option<Layers>) -> Result<PathBuf, String> {
+        // overlays would have to be rasterized on the CPU to be included
+        static OVERLAYS: Once = Once::new();
+        if self.headless {
+            OVERLAYS.call_once(|| warn!("headless screenshots contain only the game, no overlays"));
+        }
+
+        let path = self.output.artifact(name).map_err(|e| e.to_string())?;
+        let blended: Vec<u8>;
+        let frame = match (layers, self.blend.enabled()) {
+            (Some(layers), _) => self.capture.layers(&mut self.machine, layers),
+            (None, true) => {
+                blended = self
+                    .blend
+                    .frame()
+                    .iter()
+                    .flat_map(|c| [c.r, c.g, c.b, c.a])
+                    .collect();
+                &blended
+            }
+            (None, false) => self.capture.layers(&mut self.machine, self.layers),
+        };
+        picture::write_png(&path, capture::WIDTH, capture::HEIGHT, frame)
+            .map_err(|e| e.to_string())?;
+        Ok(path)
+    }
+
+    // nothing can tell the trailing loop's frames apart while nobody watches
+    // the window, unless they are recorded: a macro, --dump-inputs,
+    // --record-ram, record_ram() and --record-hashes keep it at full speed
+    fn is_idle(&self) -> bool {
+        let unwatched = self
+            .unfocused_since
+            .map_or(false, |since| since.elapsed() >= IDLE_AFTER);
+        let recording = self.macros.is_recording()
+            || self.dump.is_some()
+            || self.record_ram.is_some()
+            || self.script_ram.is_some()
+            || self.hashes.is_some();
+        self.idle_fps.is_some() && unwatched && !recording
+    }
+
+    // in place of the clock's sleep between the trailing loop's frames,
+    // any command, such as the window getting focus, ends it early
+    fn idle(&mut self) {
+        let Some(fps) = self.idle_fps.filter(|_| self.is_idle()) else {
+            return;
+        };
+        if !self.idling {
+            self.idling = true;
+            // the CPU usage since the focus was lost, at full speed
+            self.busy_cpu = self.cpu.as_ref().and_then(CpuUsage::percent);
+            self.cpu = CpuUsage::start();
+            if fps > 0.0 {
+                info!("window unfocused, idling at {} fps", fps);
+            } else {
+                info!("window unfocused, idling until it is focused");
+            }
+        }
+        // the wait is not a late frame
+        self.stepped = None;
+        let timeout = (fps > 0.0).then(|| Duration::from_secs_f64(1.0 / fps));
+        loop {
+            let command = match timeout {
+                Some(timeout) => self.commands.recv_timeout(timeout).ok(),
+                None => self.commands.recv().ok(),
+            };
+            let Some(command) = command else {
+                return;
+            };
+            self.handle(command);
+            if timeout.is_some() || !self.is_idle() {
+                return;
+            }
+        }
+    }
+
+    // a state was restored, for the event log
+    fn loaded(&mut self, name: &str) {
+        let frame = self.machine.frame();
+        self.events.push(frame, Kind::StateLoaded(name.to_owned()));
+    }
+
+    fn drop_input(&mut self, frame: usize, bits: u8) {
+        warn!("{}", self.dropped.add(frame, bits));
+        self.frame.stats.set_dropped(self.dropped.count());
+    }
+
+    // in memory, or on disk with persist_slots; None when empty
+    fn slot(&self, slot: u8) -> Option<Result<Savestate, StateError>> {
+        if let Some(state) = self.slots.get(&slot) {
+            return Some(Ok(state.clone()));
+        }
+        let path = self.output.state(&slot_file(slot)).ok()?;
+        (self.persist_slots && path.exists()).then(|| Savestate::read(&path))
+    }
+
+    // on screen, in the log without a window
+    fn toast(&self, text: String, time: Duration) {
+        if self.headless {
+            info!("{}", text);
+        } else {
+            self.frame.toast(text, time);
+        }
+    }
+    fn notify(&self, text: String) {
+        self.toast(text, TOAST_TIME);
+    }
+
+    fn update_focus(&self) {
+        self.pause
+            .set(Reason::FocusLost, self.pause_on_focus_loss && !self.focused);
+    }
+}
+
+// call the callbacks scripts registered for what happened during the frame
+fn frame_callbacks(ctx: Context, driver: &RefCell<Driver>) -> Result<(), LuaError> {
+    let clicks = driver.borrow().input.take_clicks();
+    if !clicks.is_empty() {
+        if let Some(on_click) = ctx.named_registry_value::<_, Option<Function>>(ON_CLICK)? {
+            for click in clicks {
+                debugger::call_traced(ctx, on_click.clone(), (click.x, click.y, click.button))?;
+            }
+        }
+    }
+
+    let keys = driver.borrow().input.take_key_events();
+    if !keys.is_empty() {
+        let on_key = ctx.named_registry_value::<_, Option<Function>>(ON_KEY)?;
+        let bound: rlua::Table = ctx.named_registry_value(BOUND_KEYS)?;
+        for (name, pressed) in keys {
+            if let Some(on_key) = &on_key {
+                debugger::call_traced(ctx, on_key.clone(), (name, pressed))?;
+            }
+            if !pressed {
+                continue;
+            }
+            if let Some(callback) = bound.get::<_, Option<Function>>(name)? {
+                debugger::call_traced(ctx, callback, ())?;
+            }
+        }
+    }
+    Ok(())
+}
+
+// the loop of pause(), which takes commands from the window a little at a
+// time so that key and click callbacks run while emulation is paused
+fn wait_for_resume(ctx: Context, driver: &RefCell<Driver>) -> Result<(), LuaError> {
+    while driver.borrow().pause.is_set(Reason::Script) {
+        let commands = driver.borrow().commands.clone();
+        match commands.recv_timeout(RESUME_POLL) {
+            Ok(command) => driver.borrow_mut().handle(command),
+            Err(RecvTimeoutError::Timeout) => {}
+            // the window is gone and the process with it
+            Err(RecvTimeoutError::Disconnected) => return Ok(()),
+        }
+        if driver.borrow().open.is_some() {
+            return Err(LuaError::external(Reopen));
+        }
+        driver.borrow_mut().limits.poll();
+        driver.borrow().limits.check()?;
+        frame_callbacks(ctx, driver)?;
+    }
+    Ok(())
+}
+
+// at and in_frames callbacks for the frame about to be stepped, run before
+// its input is committed so their presses land on it
+fn run_scheduled(ctx: Context, driver: &RefCell<Driver>) -> Result<(), LuaError> {
+    let frame = driver.borrow().machine.frame() + 1;
+    let scheduled: rlua::Table = ctx.named_registry_value(SCHEDULED)?;
+    if let Some(due) = scheduled.get::<_, Option<rlua::Table>>(frame)? {
+        scheduled.set(frame, Value::Nil)?;
+        for callback in due.sequence_values::<Function>() {
+            debugger::call_traced(ctx, callback?, ())?;
+        }
     }
+    Ok(())
 }
 
-fn run_lua<'lua>(ctx: Context<'lua>, frame: Arc<Frame>) -> Result<(), LuaError> {
-    // create clock
-    let mut clock = spin_sleep::LoopHelper::builder().build_with_target_rate(60);
+// for at and in_frames
+fn schedule<'lua>(
+    ctx: Context<'lua>,
+    driver: &RefCell<Driver>,
+    frame: usize,
+    callback: Function<'lua>,
+) -> Result<(), LuaError> {
+    let current = driver.borrow().machine.frame();
+    if frame <= current {
+        return Err(LuaError::RuntimeError(format!(
+            "frame {} has already been emulated, this is frame {}",
+            frame, current
+        )));
+    }
+    let scheduled: rlua::Table = ctx.named_registry_value(SCHEDULED)?;
+    let due = match scheduled.get::<_, Option<rlua::Table>>(frame)? {
+        Some(due) => due,
+        None => ctx.create_table()?,
+    };
+    due.set(due.len()? + 1, callback)?;
+    scheduled.set(frame, due)
+}
+
+fn outcome_to_lua(ctx: Context, outcome: Outcome) -> Result<Value, LuaError> {
+    match outcome {
+        Outcome::Ram(values) => values.to_lua(ctx),
+        Outcome::Hash(hash) => (hash as i64).to_lua(ctx),
+    }
+}
+
+// wait_scanlines and wait_cycles, see Machine::step_sub_frame
+fn wait_sub_frame(
+    machine: &mut Machine,
+    name: &str,
+    unit: SubFrame,
+    n: u32,
+) -> Result<(), LuaError> {
+    if n == 0 {
+        return Err(LuaError::RuntimeError(format!(
+            "{} expects at least 1",
+            name
+        )));
+    }
+    machine.step_sub_frame(unit, n).map_err(|Unsupported| {
+        LuaError::RuntimeError(format!(
+            "{} is not available: fastnes cannot step less than a frame, use wait",
+            name
+        ))
+    })
+}
+
+fn set_subframe_inputs(machine: &mut Machine, inputs: &[u8]) -> Result<(), LuaError> {
+    if inputs.is_empty() {
+        return Err(LuaError::RuntimeError(
+            "set_subframe_inputs expects at least one input".to_owned(),
+        ));
+    }
+    machine.queue_latches(inputs).map_err(|Unsupported| {
+        LuaError::RuntimeError(
+            "set_subframe_inputs is not available: the controller holds one input a frame, \
+             use set_input"
+                .to_owned(),
+        )
+    })
+}
+
+fn set_zapper(
+    machine: &mut Machine,
+    name: &str,
+    aim: Option<(u8, u8)>,
+    trigger: bool,
+) -> Result<(), LuaError> {
+    machine.set_zapper(aim, trigger).map_err(|Unsupported| {
+        LuaError::RuntimeError(format!(
+            "{} is not available: marlua connects no Zapper to port 2",
+            name
+        ))
+    })
+}
+
+// the PPU at the end of the last frame for ppu.status(): sprite0_hit,
+// vblank_scanlines, whether the background and sprites are rendered, and the
+// raw mask and control registers. `scanline`, where sprite zero hit, stays
+// nil, see Machine::ppu_registers
+fn ppu_status<'lua>(ctx: Context<'lua>, machine: &Machine) -> Result<rlua::Table<'lua>, LuaError> {
+    let (control, mask, sprite0_hit) = machine.ppu_registers().map_err(|Unsupported| {
+        LuaError::RuntimeError(
+            "ppu.status is not available: marlua cannot read the PPU registers back".to_owned(),
+        )
+    })?;
+    let table = ctx.create_table()?;
+    table.set("sprite0_hit", sprite0_hit)?;
+    table.set("vblank_scanlines", VBLANK_SCANLINES)?;
+    table.set("background", mask & 0x08 != 0)?;
+    table.set("sprites", mask & 0x10 != 0)?;
+    table.set("mask", mask)?;
+    table.set("control", control)?;
+    Ok(table)
+}
+
+fn scroll_at(machine: &Machine, scanline: u32) -> Result<(u16, u16), LuaError> {
+    if scanline >= 240 {
+        return Err(LuaError::RuntimeError(format!(
+            "scroll_at expects a scanline from 0 to 239, got {}",
+            scanline
+        )));
+    }
+    machine.scroll_at(scanline as u8).map_err(|Unsupported| {
+        LuaError::RuntimeError(
+            "scroll_at is not available: marlua sees neither the scroll while rendering \
+             nor the writes that set it"
+                .to_owned(),
+        )
+    })
+}
+
+fn dump_audio(machine: &Machine, name: &str) -> Result<(), LuaError> {
+    machine.frame_audio().map(|_| ()).map_err(|Unsupported| {
+        LuaError::RuntimeError(format!("{} is not available: {}", name, cli::NO_AUDIO))
+    })
+}
+
+// digits with an optional sign, point and exponent, unlike what f64 parses,
+// which includes nan, inf and infinity
+fn numeric(s: &str) -> bool {
+    let s = s.strip_prefix(['+', '-']).unwrap_or(s);
+    let (mantissa, exponent) = match s.find(['e', 'E']) {
+        Some(i) => (&s[..i], Some(&s[i + 1..])),
+        None => (s, None),
+    };
+    let digits = |s: &str| s.bytes().all(|b| b.is_ascii_digit());
+    let (whole, fraction) = mantissa.split_once('.').unwrap_or((mantissa, ""));
+    let exponent = exponent.map(|e| e.strip_prefix(['+', '-']).unwrap_or(e));
+    digits(whole)
+        && digits(fraction)
+        && whole.len() + fraction.len() > 0
+        && exponent.map_or(true, |e| !e.is_empty() && digits(e))
+}
+
+// the `args` table, see the usage text
+fn script_args<'lua>(ctx: Context<'lua>, args: &[String]) -> Result<rlua::Table<'lua>, LuaError> {
+    let value = |s: &str| -> Result<Value<'lua>, LuaError> {
+        if let Ok(i) = s.parse::<i64>() {
+            Ok(Value::Integer(i))
+        } else if let Some(n) = s.parse::<f64>().ok().filter(|_| numeric(s)) {
+            Ok(Value::Number(n))
+        } else {
+            s.to_lua(ctx)
+        }
+    };
+
+    let table = ctx.create_table()?;
+    let mut n = 0;
+    for arg in args {
+        match arg.split_once('=') {
+            Some((key, v)) if !key.is_empty() => table.set(key, value(v)?)?,
+            _ => {
+                n += 1;
+                table.set(n, value(arg)?)?;
+            }
+        }
+    }
+    Ok(table)
+}
+
+fn run_lua<'lua>(
+    ctx: Context<'lua>,
+    lua: &Lua,
+    options: Options,
+    frame: Arc<Frame>,
+    commands: Rc<Receiver<Command>>,
+    pause: Arc<Pause>,
+    input: Arc<InputState>,
+) -> Result<Exit, LuaError> {
+    // create clock, tests and headless runs are unthrottled
+    let clock = match options.mode {
+        Mode::Run if !options.headless => Some(LoopHelper::builder().build_with_target_rate(FPS)),
+        Mode::Run | Mode::Test => None,
+    };
+
+    // create emulator
+    let status = Arc::new(AtomicU8::new(0));
+    // scripts press buttons here, the driver hands them to the controller
+    // when it steps the next frame
+    let staged = Arc::new(Staged::default());
+    frame.stats.set_names(&options.rom, &options.script);
+    let input_file = match &options.inputs {
+        Some(path) => {
+            Some(InputFile::read(path, &options.buttons).map_err(LuaError::RuntimeError)?)
+        }
+        None => None,
+    };
+    let file = read(&options.rom)
+        .map_err(|e| LuaError::RuntimeError(format!("cannot read {}: {}", options.rom, e)))?;
+    let mut machine = Machine::new(file, status.clone());
+    if options.four_score {
+        machine.connect_four_score().map_err(|Unsupported| {
+            LuaError::RuntimeError(
+                "--four-score is not available: marlua connects one standard controller to \
+                 port 1"
+                    .to_owned(),
+            )
+        })?;
+    }
+
+    let output =
+        OutputDir::create(&options.rom, options.output.as_deref()).map_err(LuaError::external)?;
+    let checkpoints = output.state("autosave")?;
+
+    let resume = match &options.resume {
+        Some(arg) => Some(autosave::resume(&checkpoints, arg).map_err(LuaError::external)?),
+        None => None,
+    };
+    let resume_frame = resume.as_ref().map(Savestate::frame);
+
+    match resume {
+        // continue from a checkpoint
+        Some(state) => state.apply(&mut machine).map_err(LuaError::external)?,
+
+        // journals start at power-on
+        None if options.play.is_some() => {}
+
+        // run nes to level 1-1
+        None => {
+            let cache = options.warm_cache.then(|| {
+                let dir = std::path::Path::new(output::BASE).join("cache/warmup");
+                WarmCache::new(dir, machine.hash(), WARMUP, WARMUP_CHECK)
+            });
+            let cached = cache
+                .as_ref()
+                .map_or(false, |cache| cache.restore(&mut machine, WARMUP));
+            if !cached {
+                for &input in WARMUP {
+                    status.store(input, Ordering::Relaxed);
+                    machine.next_frame();
+                }
+            }
+            for &(addr, expected) in WARMUP_CHECK {
+                let actual = memory::read_u8(&machine, addr);
+                if actual != expected {
+                    return Err(LuaError::RuntimeError(format!(
+                        "warm-up verification failed: ${:04X} expected {:02X}, got {:02X} \
+                         (ROM {:08X}); wrong ROM revision?",
+                        addr,
+                        expected,
+                        actual,
+                        machine.data_hash()
+                    )));
+                }
+            }
+            if let Some(cache) = cache.filter(|_| !cached) {
+                cache.store(&machine);
+            }
+        }
+    }
+    frame.update(|frame| *frame = machine.nes_mut().draw_frame(DrawOptions::All));
+    if let (Some(file), Some(path)) = (&input_file, &options.inputs) {
+        file.check(&machine)
+            .map_err(|e| LuaError::RuntimeError(format!("{}: {}", path, e)))?;
+    }
+    let dump = match &options.dump_inputs {
+        Some(path) => Some(
+            Dump::create(path, &machine)
+                .map_err(|e| LuaError::RuntimeError(format!("{}: {}", path, e)))?,
+        ),
+        None => None,
+    };
+
+    // the scripts are read once, here, so what runs is what was hashed
+    let mut scripts = Vec::new();
+    if options.play.is_none() {
+        for path in options.scripts() {
+            let source = read_to_string(path)
+                .map_err(|e| LuaError::RuntimeError(format!("{}: {}", path, e)))?;
+            scripts.push((path, source));
+        }
+    }
+    let script_hashes = ScriptHashes::new(&scripts);
+    if options.play.is_none() {
+        info!("script sha256:{}", script_hashes.script());
+        if let Some(pin) = &options.verify_script {
+            script_hashes.verify(pin).map_err(LuaError::RuntimeError)?;
+        }
+    }
+
+    // the journal is a convenience, runs go on without it
+    let journal = options.journal.then(|| {
+        output
+            .artifact("inputs.journal")
+            .map_err(|e| e.to_string())
+            .and_then(|path| Journal::create(&path, &machine).map_err(|e| e.to_string()))
+            .and_then(|mut journal| match options.play {
+                Some(_) => Ok(journal),
+                None => journal
+                    .script(script_hashes.script())
+                    .map(|()| journal)
+                    .map_err(|e| e.to_string()),
+            })
+            .map_err(|e| warn!("could not create the input journal: {}", e))
+            .ok()
+    });
+    let journal = journal.flatten();
+    let events = output
+        .artifact("events.ndjson")
+        .map_err(|e| warn!("the event log will not be written: {}", e))
+        .ok();
+    let record_ram = match &options.record_ram {
+        Some((entries, path)) => {
+            let recorder = Recorder::create(path.as_ref(), &machine, entries.clone())
+                .map_err(|e| LuaError::RuntimeError(format!("{}: {}", path, e)))?;
+            info!(
+                "recording {} bytes of RAM every frame to {}",
+                recorder.bytes(),
+                path
+            );
+            Some(recorder)
+        }
+        None => None,
+    };
+    let hashes = if options.record_hashes {
+        let path = output.artifact("inputs.hashes")?;
+        let hashes = Hashes::create(&path, &machine)
+            .map_err(|e| LuaError::RuntimeError(format!("{}: {}", path.display(), e)))?;
+        Some(hashes)
+    } else {
+        None
+    };
+
+    // math.random starts the same in every run with the same seed, which
+    // goes in the bundle with everything else needed to reproduce the run
+    let seed = options.seed.unwrap_or_else(|| {
+        let now = SystemTime::now().duration_since(UNIX_EPOCH);
+        now.map_or(0, |d| d.as_nanos() as i64)
+    });
+    if let Some(math) = ctx.globals().get::<_, Option<rlua::Table>>("math")? {
+        math.get::<_, Function>("randomseed")?.call::<_, ()>(seed)?;
+        info!("math.random seed {}", seed);
+    }
+    let bundle = match (&options.play, resume_frame) {
+        (None, None) => {
+            let snapshot = bundle::Snapshot {
+                rom_hash: machine.hash(),
+                seed,
+                scripts: options.scripts(),
+                args: &options.args,
+                config: options.config.as_deref(),
+                warmup: WARMUP,
+                script_hash: script_hashes.script(),
+            };
+            match snapshot.write(output.run_dir()) {
+                Ok(()) => Some(AutoBundle {
+                    run_dir: output.run_dir().to_owned(),
+                }),
+                Err(e) => {
+                    warn!("could not keep the files for a bundle: {}", e);
+                    None
+                }
+            }
+        }
+        // a journal or a checkpoint instead of the scripts from power-on
+        _ => None,
+    };
+
+    // run script
+    let macros = Macros::new(output.macros());
+    let greenzone = Greenzone::new(&machine);
+    let driver = Rc::new(RefCell::new(Driver {
+        clock,
+        machine,
+        frame,
+        commands,
+        autosave: options
+            .autosave
+            .map(|seconds| Autosave::new(checkpoints, seconds, options.autosave_keep)),
+        checkpoints: BTreeMap::new(),
+        slots: BTreeMap::new(),
+        persist_slots: options.persist_slots,
+        scripted: true,
+        user_states: false,
+        capture: Capture::new(),
+        images: Images::new(options.max_images, &output),
+        watchdog: Watchdog::new(
+            options.black_level,
+            options
+                .frozen_seconds
+                .map(|seconds| (seconds * FPS) as usize),
+        ),
+        pictures: HashMap::new(),
+        report: {
+            let mut report = Report::new(options.bless);
+            if options.play.is_none() {
+                report.set_hashes(script_hashes.clone());
+            }
+            report
+        },
+        output,
+        instances: Instances::default(),
+        breaking: false,
+        open: None,
+        quit: false,
+        resuming: resume_frame.is_some(),
+        skipped: false,
+        pause,
+        input,
+        bindings: options.bindings.clone(),
+        staged: staged.clone(),
+        bundle: bundle.filter(|_| journal.is_some()),
+        journal,
+        events: EventLog::new(events.as_deref()),
+        hashes,
+        record_ram,
+        script_ram: None,
+        span: None,
+        beeper: Beeper::default(),
+        script: PathBuf::from(&options.script),
+        focused: true,
+        unfocused_since: None,
+        pause_on_focus_loss: options.pause_on_focus_loss,
+        idle_fps: options.idle_fps,
+        idling: false,
+        cpu: None,
+        busy_cpu: None,
+        headless: options.headless,
+        snapshot_every: options.snapshot_every,
+        blend: Blend::new(options.blend),
+        layers: Layers::All,
+        views: Vec::new(),
+        playback: None,
+        macros,
+        limits: Limits::new(options.max_frames, options.max_wall_time),
+        speed: 1.0,
+        advance: false,
+        stepped: None,
+        missed: 0,
+        dropped: Dropped::new(options.strict_inputs),
+        greenzone,
+        aliases: options.buttons.clone(),
+        input_file,
+        dump,
+    }));
+    let profiler = Profiler::default();
+
+    if let Some(path) = &options.play {
+        let mut driver = match Rc::try_unwrap(driver) {
+            Ok(t) => t,
+            Err(_) => unreachable!(),
+        }
+        .into_inner();
+        let recording = journal::read(std::path::Path::new(path), driver.machine.hash())
+            .map_err(LuaError::RuntimeError)?;
+        if let Some(hash) = &recording.script {
+            info!("recorded with script sha256:{}", hash);
+        }
+        // the whole journal is in the greenzone, for seeking with the
+        // transport keys
+        let total = recording.inputs.len();
+        driver.greenzone = Greenzone::new(&driver.machine)
+            .with_rerecords(recording.rerecords)
+            .with_recording(recording.inputs);
+        driver.playback = Some(total);
+
+        let subtitles = if options.no_subtitles {
+            None
+        } else {
+            let mut subtitles = Subtitles::from_markers(&recording.markers);
+            let sidecar = options
+                .subtitles
+                .as_ref()
+                .map(std::path::PathBuf::from)
+                .or_else(|| Some(subtitles::sidecar(path)).filter(|p| p.exists()));
+            if let Some(sidecar) = sidecar {
+                subtitles.extend(Subtitles::read(&sidecar).map_err(LuaError::RuntimeError)?);
+            }
+            Some(subtitles).filter(|subtitles| !subtitles.is_empty())
+        };
+
+        // replay in real time, the markers as they come up; they are
+        // subtitles unless those are turned off. Seeking back shows them
+        // again but only reports them once
+        let markers = recording.markers;
+        let mut next_marker = 0;
+        let mut reported = 0;
+        let mut subtitle = None;
+        while driver.machine.frame() < total {
+            let before = driver.machine.frame();
+            if let Some(input) = driver.greenzone.input(before) {
+                driver.staged.reset(input);
+            }
+            driver.step();
+            let frame = driver.machine.frame();
+            if frame < before {
+                next_marker = markers.partition_point(|&(marked, _)| marked <= frame);
+            }
+            while let Some((marked, name)) = markers.get(next_marker).filter(|m| m.0 <= frame) {
+                if subtitles.is_none() {
+                    driver.notify(format!("marker: {}", name));
+                }
+                if next_marker >= reported {
+                    driver.events.push(*marked, Kind::Marker(name.clone()));
+                    driver.report.marker(*marked, name.clone());
+                    reported = next_marker + 1;
+                }
+                next_marker += 1;
+            }
+            if let Some(subtitles) = &subtitles {
+                let current = subtitles.at(frame);
+                if current != subtitle {
+                    match &current {
+                        Some(text) if options.headless => info!("frame {}: {}", frame, text),
+                        _ => driver.frame.set_subtitle(current.clone()),
+                    }
+                    subtitle = current;
+                }
+            }
+            if let Some(path) = driver.open.take() {
+                return Ok(Exit::Open(path));
+            }
+            if let Some(reason) = driver.limits.exceeded() {
+                return Ok(Exit::Limit(driver.report, reason.to_owned()));
+            }
+        }
+        driver.frame.set_subtitle(None);
+        driver.playback = None;
+        driver.frame.set_playback(None);
+        info!(
+            "journal ends at frame {} ({} re-records)",
+            driver.machine.frame(),
+            recording.rerecords
+        );
+        if options.headless {
+            return Ok(Exit::Finished(driver.report));
+        }
+        driver.staged.reset(0);
+        return Ok(run_forever(driver));
+    }
+
+    let result = ctx.scope(|scope| {
+        let globals = ctx.globals();
+        globals.set(
+            "wait",
+            scope.create_function(|ctx, (time,): (u32,)| {
+                if time > 0 && driver.borrow().resuming {
+                    driver.borrow_mut().skipped = true;
+                    return Err(LuaError::external(Resumed));
+                }
+                for _ in 0..time {
+                    if std::mem::take(&mut driver.borrow_mut().breaking) {
+                        debugger::prompt(
+                            ctx,
+                            || driver.borrow().machine.frame(),
+                            || driver.borrow_mut().step(),
+                        )?;
+                    }
+                    run_scheduled(ctx, &driver)?;
+                    driver.borrow_mut().step();
+                    if driver.borrow().open.is_some() {
+                        return Err(LuaError::external(Reopen));
+                    }
+                    if let Some(message) = driver.borrow_mut().dropped.take_error() {
+                        return Err(LuaError::RuntimeError(message));
+                    }
+                    driver.borrow().limits.check()?;
+                    frame_callbacks(ctx, &driver)?;
+                }
+                Ok(())
+            })?,
+        )?;
+        // wait a number of scanlines or CPU cycles instead of frames. fastnes
+        // only steps whole frames, so until it can stop mid-frame these raise
+        // an error and marlua.has("sub_frame") is false for scripts to check
+        for (name, unit) in [
+            ("wait_scanlines", SubFrame::Scanlines),
+            ("wait_cycles", SubFrame::Cycles),
+        ] {
+            let driver = &driver;
+            globals.set(
+                name,
+                scope.create_function(move |_, (n,): (u32,)| {
+                    wait_sub_frame(&mut driver.borrow_mut().machine, name, unit, n)
+                })?,
+            )?;
+        }
+        // write the game's audio to a WAV file from now on, or stop; like
+        // --dump-wav these raise an error until there is audio, see
+        // Machine::frame_audio
+        for name in ["record_audio", "stop_audio"] {
+            let driver = &driver;
+            globals.set(
+                name,
+                scope.create_function(move |_, _: MultiValue| {
+                    dump_audio(&driver.borrow().machine, name)
+                })?,
+            )?;
+        }
+        // called with the frame number every frame after the script has
+        // returned, instead of just letting the game run
+        globals.set(
+            "on_idle",
+            scope.create_function(|ctx, (callback,): (Option<Function>,)| {
+                ctx.set_named_registry_value(ON_IDLE, callback)
+            })?,
+        )?;
+        // run the script again from power-on, read anew from disk, in a fresh
+        // Lua state and run directory like the restart hotkey.
+        //
+        // Nothing of the Lua state survives, there is no store for values
+        // across runs. The machine warms up again, also after --resume, and
+        // without --seed math.random gets a new seed. Checkpoints and slots in
+        // memory are gone; slots kept with states.persist_slots, autosaves
+        // and macros are files in the ROM's output directory and stay. The
+        // new run directory gets its own inputs.journal, events.ndjson and
+        // --record-hashes file (numbered ones in the same directory with
+        // --output), while --record-ram and --dump-inputs start their files
+        // over. A pause from the window carries over.
+        globals.set(
+            "restart",
+            scope.create_function(|_, ()| {
+                debug!("restart()");
+                if options.mode == Mode::Test || options.headless {
+                    return Err(LuaError::RuntimeError(
+                        "restart() needs the window, tests and headless runs cannot restart"
+                            .to_owned(),
+                    ));
+                }
+                let mut driver = driver.borrow_mut();
+                driver.open = Some(driver.script.clone());
+                Err(LuaError::external(Reopen))
+            })?,
+        )?;
+        // end the session: the window closes, headless runs and tests finish
+        // with their report
+        globals.set(
+            "quit",
+            scope.create_function(|_, ()| {
+                debug!("quit()");
+                driver.borrow_mut().quit = true;
+                Err::<(), _>(LuaError::external(Quit))
+            })?,
+        )?;
+
+        // run a function right before a frame is stepped, whatever the script
+        // is waiting for at the time; frames count like frame(), so presses
+        // in the function are the input of that frame
+        ctx.set_named_registry_value(SCHEDULED, ctx.create_table()?)?;
+        globals.set(
+            "at",
+            scope.create_function(|ctx, (frame, callback): (usize, Function)| {
+                debug!("at({})", frame);
+                schedule(ctx, &driver, frame, callback)
+            })?,
+        )?;
+        globals.set(
+            "in_frames",
+            scope.create_function(|ctx, (n, callback): (usize, Function)| {
+                debug!("in_frames({})", n);
+                if n == 0 {
+                    return Err(LuaError::RuntimeError(
+                        "in_frames expects at least 1 frame".to_owned(),
+                    ));
+                }
+                let frame = driver.borrow().machine.frame() + n;
+                schedule(ctx, &driver, frame, callback)
+            })?,
+        )?;
+
+        // the cursor in NES pixels and the pressed buttons (1 left, 2 right,
+        // 4 middle), nothing while the cursor is outside the image
+        globals.set(
+            "mouse",
+            scope.create_function(|_, ()| {
+                Ok(match driver.borrow().input.mouse() {
+                    Some((x, y, buttons)) => (Some(x), Some(y), Some(buttons)),
+                    None => (None, None, None),
+                })
+            })?,
+        )?;
+        globals.set(
+            "on_click",
+            scope.create_function(|ctx, (callback,): (Option<Function>,)| {
+                ctx.set_named_registry_value(ON_CLICK, callback)
+            })?,
+        )?;
+
+        // getting attention during long runs; only logged in tests
+        globals.set(
+            "beep",
+            scope.create_function(|_, (frequency, millis): (Option<f32>, Option<u64>)| {
+                let frequency = frequency.unwrap_or(880.0).clamp(20.0, 20000.0);
+                let millis = millis.unwrap_or(100).min(2000);
+                match options.mode {
+                    Mode::Run => driver.borrow_mut().beeper.beep(frequency, millis),
+                    Mode::Test => info!("beep ({} Hz, {} ms)", frequency, millis),
+                }
+                Ok(())
+            })?,
+        )?;
+        globals.set(
+            "notify",
+            scope.create_function(|_, (title, body): (String, Option<String>)| {
+                let body = body.unwrap_or_default();
+                if options.mode == Mode::Run && options.desktop_notifications {
+                    alert::notify(&title, &body);
+                } else {
+                    info!("notify: {}: {}", title, body);
+                }
+                Ok(())
+            })?,
+        )?;
+
+        // a named moment of the run: in the journal, the summary at the end
+        // and briefly on screen
+        globals.set(
+            "marker",
+            scope.create_function(|_, (name,): (String,)| {
+                if name.len() >= u16::MAX as usize {
+                    return Err(LuaError::RuntimeError("marker name too long".to_owned()));
+                }
+                let mut driver = driver.borrow_mut();
+                let frame = driver.machine.frame();
+                debug!("marker({}) at frame {}", name, frame);
+                if let Some(journal) = &mut driver.journal {
+                    if let Err(e) = journal.marker(frame, &name) {
+                        warn!("could not write the marker to the input journal: {}", e);
+                    }
+                }
+                driver.notify(format!("marker: {}", name));
+                driver.events.push(frame, Kind::Marker(name.clone()));
+                driver.report.marker(frame, name);
+                Ok(())
+            })?,
+        )?;
+        globals.set(
+            "markers",
+            scope.create_function(|ctx, ()| {
+                let table = ctx.create_table()?;
+                for (i, (frame, name)) in driver.borrow().report.markers().iter().enumerate() {
+                    let entry = ctx.create_table()?;
+                    entry.set("frame", *frame)?;
+                    entry.set("name", name.as_str())?;
+                    table.set(i + 1, entry)?;
+                }
+                Ok(table)
+            })?,
+        )?;
+
+        // the event log from a frame on, see events.rs
+        globals.set(
+            "events",
+            scope.create_function(|ctx, (since,): (Option<usize>,)| {
+                let table = ctx.create_table()?;
+                let driver = driver.borrow();
+                for (i, event) in driver.events.since(since.unwrap_or(0)).enumerate() {
+                    let entry = ctx.create_table()?;
+                    entry.set("frame", event.frame)?;
+                    match &event.kind {
+                        Kind::Input(input) => {
+                            entry.set("kind", "input")?;
+                            let pressed = ctx.create_table()?;
+                            for (j, button) in buttons::pressed(*input).enumerate() {
+                                pressed.set(j + 1, button.name())?;
+                            }
+                            entry.set("buttons", pressed)?;
+                        }
+                        Kind::Marker(name) => {
+                            entry.set("kind", "marker")?;
+                            entry.set("name", name.as_str())?;
+                        }
+                        Kind::Log { level, line } => {
+                            entry.set("kind", "log")?;
+                            entry.set("level", level.as_str())?;
+                            entry.set("line", line.as_str())?;
+                        }
+                        Kind::StateLoaded(name) => {
+                            entry.set("kind", "state_loaded")?;
+                            entry.set("name", name.as_str())?;
+                        }
+                    }
+                    table.set(i + 1, entry)?;
+                }
+                Ok(table)
+            })?,
+        )?;
+        // a segment like --record-ram from now on, until record_ram(nil)
+        // or the next call
+        globals.set(
+            "record_ram",
+            scope.create_function(|_, (addrs, path): (Option<rlua::Table>, Option<String>)| {
+                let mut driver = driver.borrow_mut();
+                // the last segment is complete before the next one starts
+                driver.script_ram = None;
+                let Some(addrs) = addrs else {
+                    return Ok(());
+                };
+                let path = path.ok_or_else(|| {
+                    LuaError::RuntimeError("record_ram needs a path to write to".to_owned())
+                })?;
+                let mut entries = Vec::new();
+                for value in addrs.sequence_values::<Value>() {
+                    let parsed = match value? {
+                        Value::Integer(addr) => ramdump::parse_entry(&addr.to_string()),
+                        Value::String(text) => ramdump::parse_entry(text.to_str()?),
+                        other => Err(format!(
+                            "expected an address or a range, got a {}",
+                            other.type_name()
+                        )),
+                    };
+                    entries.extend(parsed.map_err(LuaError::RuntimeError)?);
+                    ramdump::check_size(&entries).map_err(LuaError::RuntimeError)?;
+                }
+                ramdump::check(&entries).map_err(LuaError::RuntimeError)?;
+                let recorder = Recorder::create(path.as_ref(), &driver.machine, entries)
+                    .map_err(|e| LuaError::RuntimeError(format!("{}: {}", path, e)))?;
+                driver.script_ram = Some(recorder);
+                Ok(())
+            })?,
+        )?;
+        // where --capture-layout put the game in the window, nil without it
+        globals.set(
+            "capture_rect",
+            scope.create_function(|ctx, ()| {
+                let Some((x, y, width, height)) = driver.borrow().frame.capture_rect() else {
+                    return Ok(None);
+                };
+                let rect = ctx.create_table()?;
+                rect.set("x", x)?;
+                rect.set("y", y)?;
+                rect.set("width", width)?;
+                rect.set("height", height)?;
+                Ok(Some(rect))
+            })?,
+        )?;
+        // the sandbox's print and log, with the lines in the event log too
+        globals.set(
+            "print",
+            scope.create_function(|ctx, values: MultiValue| {
+                let line = sandbox::line(ctx, values)?;
+                sandbox::log_line("info", &line)?;
+                let driver = &mut *driver.borrow_mut();
+                let level = "info".to_owned();
+                let frame = driver.machine.frame();
+                driver.events.push(frame, Kind::Log { level, line });
+                Ok(())
+            })?,
+        )?;
+        globals.set(
+            "log",
+            scope.create_function(|ctx, (level, values): (String, MultiValue)| {
+                let line = sandbox::line(ctx, values)?;
+                sandbox::log_line(&level, &line)?;
+                let driver = &mut *driver.borrow_mut();
+                let frame = driver.machine.frame();
+                driver.events.push(frame, Kind::Log { level, line });
+                Ok(())
+            })?,
+        )?;
+
+        // a message over the game for a few seconds, logged without a window
+        globals.set(
+            "toast",
+            scope.create_function(|_, (text, seconds): (String, Option<f64>)| {
+                let time = match seconds {
+                    None => TOAST_TIME,
+                    Some(seconds) if seconds > 0.0 && seconds.is_finite() => {
+                        Duration::from_secs_f64(seconds)
+                    }
+                    Some(seconds) => {
+                        return Err(LuaError::RuntimeError(format!(
+                            "toast needs a positive number of seconds, not {}",
+                            seconds
+                        )))
+                    }
+                };
+                driver.borrow().toast(text, time);
+                Ok(())
+            })?,
+        )?;
+
+        // stop emulation, showing `message` at the bottom, until the pause
+        // hotkey is pressed or a key callback calls resume()
+        globals.set(
+            "pause",
+            scope.create_function(|ctx, (message,): (Option<String>,)| {
+                if driver.borrow().frame.inline {
+                    warn!("pause() does nothing without a window");
+                    return Ok(());
+                }
+                debug!("pause()");
+                driver.borrow().pause.set(Reason::Script, true);
+                driver.borrow().frame.set_subtitle(message);
+                let result = wait_for_resume(ctx, &driver);
+                driver.borrow().pause.set(Reason::Script, false);
+                driver.borrow().frame.set_subtitle(None);
+                result
+            })?,
+        )?;
+        globals.set(
+            "resume",
+            scope.create_function(|_, ()| {
+                driver.borrow().pause.set(Reason::Script, false);
+                Ok(())
+            })?,
+        )?;
+        // paused for any reason, such as pause() or the menu
+        globals.set(
+            "is_paused",
+            scope.create_function(|_, ()| Ok(driver.borrow().pause.is_paused()))?,
+        )?;
+
+        // keys by their binding names, see --print-bindings
+        globals.set(
+            "key_down",
+            scope.create_function(|_, (name,): (String,)| {
+                let key = bindings::parse_key(&name).map_err(LuaError::RuntimeError)?;
+                Ok(driver.borrow().input.key_down(key))
+            })?,
+        )?;
+        globals.set(
+            "on_key",
+            scope.create_function(|ctx, (callback,): (Option<Function>,)| {
+                ctx.set_named_registry_value(ON_KEY, callback)
+            })?,
+        )?;
+        // call a function when a key is pressed, at the next frame; keys of
+        // hotkeys need {force = true} and then no longer trigger the hotkey
+        ctx.set_named_registry_value(BOUND_KEYS, ctx.create_table()?)?;
+        driver.borrow().input.unbind_all();
+        globals.set(
+            "bind_key",
+            scope.create_function(
+                |ctx, (name, callback, options): (String, Function, Option<rlua::Table>)| {
+                    let key = bindings::parse_key(&name).map_err(LuaError::RuntimeError)?;
+                    let (force, label) = match &options {
+                        Some(options) => (
+                            options.get::<_, Option<bool>>("force")?.unwrap_or(false),
+                            options.get::<_, Option<String>>("name")?,
+                        ),
+                        None => (false, None),
+                    };
+                    let driver = driver.borrow();
+                    let hotkey = driver.bindings.hotkey_name(key);
+                    if let (Some(hotkey), false) = (hotkey, force) {
+                        return Err(LuaError::RuntimeError(format!(
+                            "{} is bound to the {} hotkey, pass {{force = true}} to bind_key \
+                             to take it over or rebind it in [bindings]",
+                            bindings::key_name(key),
+                            hotkey
+                        )));
+                    }
+                    debug!("bind_key({})", bindings::key_name(key));
+                    let bound: rlua::Table = ctx.named_registry_value(BOUND_KEYS)?;
+                    bound.set(bindings::key_name(key), callback)?;
+                    driver.input.bind(
+                        key,
+                        ScriptKey {
+                            name: label.unwrap_or_else(|| "script".to_owned()),
+                            forced: hotkey.is_some(),
+                        },
+                    );
+                    Ok(())
+                },
+            )?,
+        )?;
+        globals.set(
+            "unbind_key",
+            scope.create_function(|ctx, (name,): (String,)| {
+                let key = bindings::parse_key(&name).map_err(LuaError::RuntimeError)?;
+                let bound: rlua::Table = ctx.named_registry_value(BOUND_KEYS)?;
+                bound.set(bindings::key_name(key), Value::Nil)?;
+                Ok(driver.borrow().input.unbind(key))
+            })?,
+        )?;
+
+        // let the player load slots while the script runs, it has to cope
+        // with the machine jumping to another frame
+        globals.set(
+            "allow_user_states",
+            scope.create_function(|_, (allow,): (bool,)| {
+                debug!("allow_user_states({})", allow);
+                driver.borrow_mut().user_states = allow;
+                Ok(())
+            })?,
+        )?;
+
+        // keep running while the window is unfocused, see [window] in the usage
+        globals.set(
+            "allow_background",
+            scope.create_function(|_, (allow,): (bool,)| {
+                let mut driver = driver.borrow_mut();
+                driver.pause_on_focus_loss = options.pause_on_focus_loss && !allow;
+                driver.update_focus();
+                Ok(())
+            })?,
+        )?;
+
+        globals.set(
+            "breakpoint",
+            scope.create_function(|_, ()| {
+                driver.borrow_mut().breaking = true;
+                Ok(())
+            })?,
+        )?;
+
+        globals.set(
+            "read",
+            scope.create_function(|_, (addr,): (u16,)| {
+                Ok(driver.borrow().machine.nes().read_internal(addr))
+            })?,
+        )?;
+
+        // typed reads, see memory.rs for how addresses wrap
+        let memory = ctx.create_table()?;
+        memory.set(
+            "read",
+            scope.create_function(|_, (addr,): (u16,)| {
+                Ok(memory::read_u8(&driver.borrow().machine, addr))
+            })?,
+        )?;
+        memory.set(
+            "read_s8",
+            scope.create_function(|_, (addr,): (u16,)| {
+                Ok(memory::read_s8(&driver.borrow().machine, addr))
+            })?,
+        )?;
+        memory.set(
+            "read_u16",
+            scope.create_function(|_, (addr, big_endian): (u16, Option<bool>)| {
+                Ok(memory::read_u16(
+                    &driver.borrow().machine,
+                    addr,
+                    big_endian.unwrap_or(false),
+                ))
+            })?,
+        )?;
+        memory.set(
+            "read_bcd",
+            scope.create_function(|_, (addr, digits): (u16, usize)| {
+                memory::read_bcd(&driver.borrow().machine, addr, digits)
+            })?,
+        )?;
+        memory.set(
+            "read_bytes",
+            scope.create_function(|ctx, (addr, len): (u16, usize)| {
+                ctx.create_string(&memory::read_bytes(&driver.borrow().machine, addr, len)?)
+            })?,
+        )?;
+        globals.set("memory", memory)?;
+
+        let savestate = ctx.create_table()?;
+        savestate.set(
+            "save_file",
+            scope.create_function(|_, (path,): (String,)| {
+                debug!("savestate.save_file({})", path);
+                let driver = driver.borrow();
+                Savestate::capture(&driver.machine)
+                    .write(driver.output.state(&path)?)
+                    .map_err(LuaError::external)
+            })?,
+        )?;
+        savestate.set(
+            "load_file",
+            scope.create_function(|_, (path,): (String,)| {
+                debug!("savestate.load_file({})", path);
+                let driver = &mut *driver.borrow_mut();
+                Savestate::read(driver.output.state(&path)?)
+                    .and_then(|s| s.apply(&mut driver.machine))
+                    .map_err(LuaError::external)?;
+                driver.loaded(&path);
+                Ok(())
+            })?,
+        )?;
+        globals.set("savestate", savestate)?;
+
+        // clocks for scripts, which get no os library by default
+        let start = Instant::now();
+        globals.set(
+            "clock",
+            scope.create_function(move |_, ()| Ok(start.elapsed().as_secs_f64()))?,
+        )?;
+        globals.set(
+            "emu_time",
+            scope.create_function(|_, ()| {
+                Ok(driver.borrow().machine.frame() as f64 / time::FRAME_RATE)
+            })?,
+        )?;
+        globals.set(
+            "date",
+            scope.create_function(|_, (fmt,): (Option<String>,)| {
+                DateTime::local(SystemTime::now())
+                    .format(fmt.as_deref().unwrap_or("%F %T"))
+                    .map_err(LuaError::RuntimeError)
+            })?,
+        )?;
+
+        globals.set(
+            "output_dir",
+            scope.create_function(|_, ()| {
+                Ok(driver.borrow().output.run_dir().display().to_string())
+            })?,
+        )?;
+
+        globals.set(
+            "rom_hash",
+            scope.create_function(|_, ()| Ok(driver.borrow().machine.data_hash()))?,
+        )?;
+
+        globals.set(
+            "frame",
+            scope.create_function(|_, ()| Ok(driver.borrow().machine.frame()))?,
+        )?;
+
+        // the machine keeps every controller byte since power-on to restore
+        // states, so the whole history is available
+        globals.set(
+            "input_history",
+            scope.create_function(|ctx, (n,): (usize,)| {
+                let driver = driver.borrow();
+                let inputs = driver.machine.inputs();
+                let recent = &inputs[inputs.len().saturating_sub(n)..];
+                ctx.create_sequence_from(recent.iter().copied())
+            })?,
+        )?;
+        // the input of a frame, counted like frame(): the first frame is 1
+        globals.set(
+            "input_at",
+            scope.create_function(|_, (frame,): (usize,)| {
+                let driver = driver.borrow();
+                let inputs = driver.machine.inputs();
+                Ok(frame.checked_sub(1).and_then(|i| inputs.get(i).copied()))
+            })?,
+        )?;
+
+        // slow motion below 1, fast forward above; emu_time still counts
+        // emulated seconds and clock wall seconds
+        globals.set(
+            "speed",
+            scope.create_function(|_, (speed,): (f64,)| {
+                if !(speed > 0.0 && speed <= MAX_SPEED) {
+                    return Err(LuaError::RuntimeError(format!(
+                        "speed expects a multiplier above 0 and at most {}, got {}",
+                        MAX_SPEED, speed
+                    )));
+                }
+                driver.borrow_mut().set_speed(speed);
+                Ok(())
+            })?,
+        )?;
+
+        // average the last n frames in the window and screenshots, 0 to stop
+        globals.set(
+            "blend",
+            scope.create_function(|_, (n,): (usize,)| {
+                if n > blend::MAX {
+                    return Err(LuaError::RuntimeError(format!(
+                        "can blend at most {} frames",
+                        blend::MAX
+                    )));
+                }
+                driver.borrow_mut().blend.set(n);
+                Ok(())
+            })?,
+        )?;
+
+        // a PNG of the game in the run directory, returns its path
+        globals.set(
+            "screenshot",
+            scope.create_function(|_, (name, layers): (Option<String>, Option<String>)| {
+                let name = name.unwrap_or_else(|| "screenshot.png".to_owned());
+                let layers = layers.as_deref().map(Layers::parse).transpose()?;
+                debug!("screenshot({}, {:?})", name, layers);
+                let path = driver
+                    .borrow_mut()
+                    .screenshot(&name, layers)
+                    .map_err(LuaError::RuntimeError)?;
+                info!("saved screenshot {}", path.display());
+                Ok(path.display().to_string())
+            })?,
+        )?;
+
+        // raw pixels for analysis in scripts, see Capture::frame for the layout
+        globals.set(
+            "frame_data",
+            scope.create_function(|ctx, (layers,): (Option<String>,)| {
+                let layers = Layers::parse(layers.as_deref().unwrap_or("all"))?;
+                let driver = &mut *driver.borrow_mut();
+                ctx.create_string(driver.capture.layers(&mut driver.machine, layers))
+            })?,
+        )?;
+
+        // the frame as an image with methods, see image.rs
+        globals.set(
+            "capture",
+            scope.create_function(|_, (layers,): (Option<String>,)| {
+                let layers = Layers::parse(layers.as_deref().unwrap_or("all"))?;
+                // images only hold a handle on the Lua side, so the
+                // collector rarely runs on their account
+                if driver.borrow().images.is_full() {
+                    lua.gc_collect()?;
+                }
+                let driver = &mut *driver.borrow_mut();
+                let rgba = driver.capture.layers(&mut driver.machine, layers).to_vec();
+                driver.images.create(capture::WIDTH, capture::HEIGHT, rgba)
+            })?,
+        )?;
+
+        // kept up to date as frames are stepped, see watchdog.rs
+        globals.set(
+            "is_black_screen",
+            scope.create_function(|_, (level,): (Option<u8>,)| {
+                let driver = &mut *driver.borrow_mut();
+                driver
+                    .watchdog
+                    .track(driver.capture.frame(&mut driver.machine));
+                Ok(driver.watchdog.is_black(level))
+            })?,
+        )?;
+        globals.set(
+            "is_frozen",
+            scope.create_function(|_, (frames,): (usize,)| {
+                let driver = &mut *driver.borrow_mut();
+                driver
+                    .watchdog
+                    .track(driver.capture.frame(&mut driver.machine));
+                Ok(driver.watchdog.is_frozen(frames))
+            })?,
+        )?;
+
+        // screens told apart by the layout of their tiles rather than their
+        // colors, see tilehash.rs
+        globals.set(
+            "tile_hashes",
+            scope.create_function(|ctx, ()| {
+                let driver = &mut *driver.borrow_mut();
+                let grid = tilehash::grid(driver.capture.frame(&mut driver.machine));
+                tilehash::to_lua(ctx, &grid)
+            })?,
+        )?;
+        globals.set(
+            "match_tiles",
+            scope.create_function(|_, (reference, min): (Vec<Vec<i64>>, Option<f64>)| {
+                let reference = tilehash::from_lua(reference)?;
+                let driver = &mut *driver.borrow_mut();
+                let grid = tilehash::grid(driver.capture.frame(&mut driver.machine));
+                let fraction = tilehash::matching(&grid, &reference);
+                Ok((fraction >= min.unwrap_or(1.0), fraction))
+            })?,
+        )?;
+        globals.set(
+            "save_tiles",
+            scope.create_function(|_, (path,): (String,)| {
+                debug!("save_tiles({})", path);
+                let driver = &mut *driver.borrow_mut();
+                let grid = tilehash::grid(driver.capture.frame(&mut driver.machine));
+                tilehash::write(std::path::Path::new(&path), &grid).map_err(LuaError::RuntimeError)
+            })?,
+        )?;
+        globals.set(
+            "load_tiles",
+            scope.create_function(|ctx, (path,): (String,)| {
+                let grid =
+                    tilehash::read(std::path::Path::new(&path)).map_err(LuaError::RuntimeError)?;
+                tilehash::to_lua(ctx, &grid)
+            })?,
+        )?;
+
+        // what the window shows: "all", "background" or "sprites"
+        globals.set(
+            "set_layers",
+            scope.create_function(|_, (layers,): (String,)| {
+                driver.borrow_mut().layers = Layers::parse(&layers)?;
+                Ok(())
+            })?,
+        )?;
+        globals.set(
+            "frame_region",
+            scope.create_function(|ctx, (x, y, w, h): (usize, usize, usize, usize)| {
+                let region = Region::new(x, y, w, h)?;
+                let driver = &mut *driver.borrow_mut();
+                ctx.create_string(driver.capture.region(&mut driver.machine, region))
+            })?,
+        )?;
+        globals.set(
+            "hash_region",
+            scope.create_function(|_, (x, y, w, h): (usize, usize, usize, usize)| {
+                let region = Region::new(x, y, w, h)?;
+                let driver = &mut *driver.borrow_mut();
+                Ok(driver.capture.hash(&mut driver.machine, region) as i64)
+            })?,
+        )?;
+
+        // compare the frame against a reference image, loaded once per path
+        globals.set(
+            "match_image",
+            scope.create_function(
+                |_, (path, x, y, tolerance): (String, usize, usize, Option<u8>)| {
+                    let driver = &mut *driver.borrow_mut();
+                    if !driver.pictures.contains_key(&path) {
+                        let picture = Picture::load(&path).map_err(|e| {
+                            LuaError::RuntimeError(format!("could not load {}: {}", path, e))
+                        })?;
+                        driver.pictures.insert(path.clone(), picture);
+                    }
+                    driver.capture.compare(
+                        &mut driver.machine,
+                        &driver.pictures[&path],
+                        x,
+                        y,
+                        tolerance.unwrap_or(0),
+                    )
+                },
+            )?,
+        )?;
+
+        // assertions for `marlua test`
+        globals.set(
+            "expect_frame",
+            scope.create_function(|_, (path, tolerance): (String, Option<u8>)| {
+                debug!("expect_frame({})", path);
+                let driver = &mut *driver.borrow_mut();
+                let frame = driver.machine.frame();
+                let rgba = driver.capture.frame(&mut driver.machine);
+                driver.report.expect_frame(
+                    &driver.output,
+                    frame,
+                    &path,
+                    rgba,
+                    tolerance.unwrap_or(0),
+                );
+                Ok(())
+            })?,
+        )?;
+        globals.set(
+            "expect_memory",
+            scope.create_function(|_, (addr, value): (u16, u8)| {
+                debug!("expect_memory({:#06x}, {})", addr, value);
+                let driver = &mut *driver.borrow_mut();
+                let actual = driver.machine.nes().read_internal(addr);
+                let frame = driver.machine.frame();
+                driver.report.expect_memory(frame, addr, value, actual);
+                Ok(())
+            })?,
+        )?;
+
+        // headless copies of the emulator for parallel searches
+        let instances = ctx.create_table()?;
+        instances.set(
+            "spawn",
+            scope.create_function(|_, (n, name): (usize, Option<String>)| {
+                debug!("instances.spawn({}, {:?})", n, name);
+                let driver = &mut *driver.borrow_mut();
+                let inputs = match &name {
+                    Some(name) => match driver.checkpoints.get(name) {
+                        Some(state) => &state.inputs,
+                        None => {
+                            return Err(LuaError::RuntimeError(format!(
+                                "no checkpoint named {}",
+                                name
+                            )))
+                        }
+                    },
+                    None => driver.machine.inputs(),
+                };
+                driver.instances.spawn(&driver.machine, inputs, n)
+            })?,
+        )?;
+        instances.set(
+            "count",
+            scope.create_function(|_, ()| Ok(driver.borrow().instances.len()))?,
+        )?;
+        instances.set(
+            "run",
+            scope.create_function(
+                |ctx, (i, inputs, addrs): (usize, Vec<u8>, Option<Vec<u16>>)| {
+                    let mut driver = driver.borrow_mut();
+                    let mut outcomes = driver.instances.run(
+                        vec![(i.wrapping_sub(1), inputs)],
+                        &addrs.unwrap_or_default(),
+                    )?;
+                    outcome_to_lua(ctx, outcomes.remove(0))
+                },
+            )?,
+        )?;
+        instances.set(
+            "run_all",
+            scope.create_function(|ctx, (jobs, addrs): (Vec<Vec<u8>>, Option<Vec<u16>>)| {
+                let mut driver = driver.borrow_mut();
+                let outcomes = driver.instances.run(
+                    jobs.into_iter().enumerate().collect(),
+                    &addrs.unwrap_or_default(),
+                )?;
+                outcomes
+                    .into_iter()
+                    .map(|o| outcome_to_lua(ctx, o))
+                    .collect::<Result<Vec<Value>, _>>()
+            })?,
+        )?;
+        globals.set("instances", instances)?;
+
+        // first frame after a checkpoint at which `predicate` holds, for a
+        // predicate that keeps holding once it does
+        //
+        // Frames beyond the given inputs are played with no buttons pressed.
+        // The machines 1, 2, 4, ... frames past the checkpoint, up to
+        // max_frames, are replayed on worker threads like instances and
+        // probed in that order; once one holds, the frames between it and the
+        // probe before are stepped from the latter and checked one by one.
+        // The predicate sees each probe as the current machine, which is
+        // swapped back in afterwards instead of being restored. fastnes
+        // cannot copy its state, so the probes replay from power-on (see
+        // Machine), the checkpoint's frames included.
+        globals.set(
+            "bisect",
+            scope.create_function(
+                |_, (name, max_frames, inputs, predicate): (String, usize, Vec<u8>, Function)| {
+                    let (rom, start) = {
+                        let driver = driver.borrow();
+                        let start = driver.checkpoints.get(&name).ok_or_else(|| {
+                            LuaError::RuntimeError(format!("no checkpoint named {}", name))
+                        })?;
+                        if start.rom_hash != driver.machine.hash() {
+                            return Err(LuaError::external(StateError::RomMismatch {
+                                expected: driver.machine.hash(),
+                                found: start.rom_hash,
+                            }));
+                        }
+                        (driver.machine.rom().to_vec(), start.inputs.clone())
+                    };
+                    if max_frames > instances::MAX_FRAMES {
+                        return Err(LuaError::RuntimeError(format!(
+                            "cannot bisect over {} frames, the maximum is {}",
+                            max_frames,
+                            instances::MAX_FRAMES
+                        )));
+                    }
+
+                    let from = start.len();
+                    let mut played = start;
+                    played.extend((0..max_frames).map(|i| inputs.get(i).copied().unwrap_or(0)));
+                    let offsets = bisect_probes(max_frames);
+                    let probes = instances::parallel(offsets.clone(), |offset| {
+                        Machine::replay(&rom, &played[..from + offset])
+                    });
+
+                    let holds = |machine: &mut Machine| {
+                        driver.borrow_mut().machine.swap(machine);
+                        let result = predicate.call::<_, bool>(());
+                        driver.borrow_mut().machine.swap(machine);
+                        result
+                    };
+                    let mut before = (0, None);
+                    for (offset, mut probe) in offsets.into_iter().zip(probes) {
+                        if holds(&mut probe)? {
+                            if let (last, Some(mut machine)) = before {
+                                for i in last + 1..offset {
+                                    machine.set_input(played[from + i - 1]);
+                                    machine.next_frame();
+                                    if holds(&mut machine)? {
+                                        return Ok(Some(i));
+                                    }
+                                }
+                            }
+                            return Ok(Some(offset));
+                        }
+                        before = (offset, Some(probe));
+                    }
+                    Ok(None)
+                },
+            )?,
+        )?;
+
+        // sampling profiler for finding slow script code
+        let profile = ctx.create_table()?;
+        profile.set(
+            "start",
+            scope.create_function(|_, ()| {
+                profiler.start(lua);
+                Ok(())
+            })?,
+        )?;
+        profile.set(
+            "stop",
+            scope.create_function(|_, ()| {
+                profiler.stop(lua);
+                Ok(())
+            })?,
+        )?;
+        profile.set(
+            "report",
+            scope.create_function(|ctx, (n,): (Option<usize>,)| {
+                let table = ctx.create_table()?;
+                for (i, entry) in profiler.report(n.unwrap_or(10)).into_iter().enumerate() {
+                    // in the log as the script's, like print
+                    let line = format!(
+                        "{:6.2}% {:8} {}",
+                        entry.percent, entry.samples, entry.location
+                    );
+                    sandbox::log_line("info", &line)?;
+                    let row = ctx.create_table()?;
+                    row.set("location", entry.location)?;
+                    row.set("samples", entry.samples)?;
+                    row.set("percent", entry.percent)?;
+                    table.set(i + 1, row)?;
+                }
+                Ok(table)
+            })?,
+        )?;
+        globals.set("profile", profile)?;
+
+        // named checkpoints for branching searches
+        // restoring one also rewinds the input log, so it only ever
+        // contains the inputs of the branch that was chosen
+        globals.set(
+            "checkpoint",
+            scope.create_function(|_, (name,): (String,)| {
+                debug!("checkpoint({})", name);
+                let mut driver = driver.borrow_mut();
+                let state = Savestate::capture(&driver.machine);
+                driver.checkpoints.insert(name, state);
+                Ok(())
+            })?,
+        )?;
+        globals.set(
+            "restore",
+            scope.create_function(|_, (name,): (String,)| {
+                debug!("restore({})", name);
+                let mut driver = driver.borrow_mut();
+                let state = match driver.checkpoints.get(&name) {
+                    Some(state) => state.clone(),
+                    None => {
+                        return Err(LuaError::RuntimeError(format!(
+                            "no checkpoint named {}",
+                            name
+                        )))
+                    }
+                };
+                state
+                    .apply(&mut driver.machine)
+                    .map_err(LuaError::external)?;
+                driver.loaded(&name);
+                Ok(())
+            })?,
+        )?;
+        globals.set(
+            "drop",
+            scope.create_function(|_, (name,): (String,)| {
+                debug!("drop({})", name);
+                Ok(driver.borrow_mut().checkpoints.remove(&name).is_some())
+            })?,
+        )?;
+        globals.set(
+            "diff_states",
+            scope.create_function(|ctx, (a, b, filter): (String, String, Option<Function>)| {
+                debug!("diff_states({}, {})", a, b);
+                // the filter may use the driver, it runs without the borrow
+                let changes = {
+                    let driver = driver.borrow();
+                    let get = |name: &String| {
+                        driver.checkpoints.get(name).ok_or_else(|| {
+                            LuaError::RuntimeError(format!("no checkpoint named {}", name))
+                        })
+                    };
+                    diff::states(get(&a)?, get(&b)?).map_err(LuaError::RuntimeError)?
+                };
+
+                let table = ctx.create_table()?;
+                let mut n = 0;
+                for change in changes {
+                    if let Some(filter) = &filter {
+                        let keep: bool = filter.call((change.addr, change.old, change.new))?;
+                        if !keep {
+                            continue;
+                        }
+                    }
+                    let entry = ctx.create_table()?;
+                    entry.set("addr", change.addr)?;
+                    entry.set("old", change.old)?;
+                    entry.set("new", change.new)?;
+                    entry.set("zero_page", change.zero_page())?;
+                    n += 1;
+                    table.set(n, entry)?;
+                }
+                Ok(table)
+            })?,
+        )?;
+        globals.set(
+            "checkpoints",
+            scope.create_function(|ctx, ()| {
+                let driver = driver.borrow();
+                let mut list: Vec<_> = driver.checkpoints.iter().collect();
+                list.sort_by_key(|(_, state)| state.frame());
+
+                let table = ctx.create_table()?;
+                for (i, (name, state)) in list.into_iter().enumerate() {
+                    let entry = ctx.create_table()?;
+                    entry.set("name", name.as_str())?;
+                    entry.set("frame", state.frame())?;
+                    table.set(i + 1, entry)?;
+                }
+                Ok(table)
+            })?,
+        )?;
+
+        // practice a state over and over: load it, let the player try until
+        // success() or fail() returns true, count the attempt and start
+        // again; the redo key starts over without counting
+        let practice = ctx.create_table()?;
+        practice.set(
+            "loop",
+            scope.create_function(|ctx, (options,): (rlua::Table,)| {
+                let options = practice::Options::from_lua(options)?;
+                let redo =
+                    bindings::parse_key(&options.redo_key).map_err(LuaError::RuntimeError)?;
+                if let Some(hotkey) = driver.borrow().bindings.hotkey_name(redo) {
+                    return Err(LuaError::RuntimeError(format!(
+                        "redo_key {} is bound to the {} hotkey",
+                        bindings::key_name(redo),
+                        hotkey
+                    )));
+                }
+                debug!("practice.loop({})", options.state);
+                let state = {
+                    let driver = driver.borrow();
+                    match driver.checkpoints.get(&options.state) {
+                        Some(state) => state.clone(),
+                        None => Savestate::read(driver.output.state(&options.state)?)
+                            .map_err(LuaError::external)?,
+                    }
+                };
+                let mut counter = Counter::load(driver.borrow().output.practice(&options.state));
+                let wait: Function = ctx.globals().get("wait")?;
+
+                let mut attempts = 0;
+                while options.attempts.map_or(true, |max| attempts < max) {
+                    {
+                        let driver = &mut *driver.borrow_mut();
+                        state
+                            .apply(&mut driver.machine)
+                            .map_err(LuaError::external)?;
+                        driver.loaded(&options.state);
+                    }
+                    let summary = counter.summary(&options.state);
+                    driver.borrow().frame.set_subtitle(Some(summary));
+                    if let Some(on_attempt) = &options.on_attempt {
+                        on_attempt.call::<_, ()>(counter.attempts + 1)?;
+                    }
+
+                    // only a new press of the redo key counts
+                    let mut held = driver.borrow().input.key_down(redo);
+                    let success = loop {
+                        if let Some(on_frame) = &options.on_frame {
+                            on_frame.call::<_, ()>(())?;
+                        }
+                        wait.call::<_, ()>(1)?;
+                        if options.success.call::<_, bool>(())? {
+                            break Some(true);
+                        }
+                        if options.fail.call::<_, bool>(())? {
+                            break Some(false);
+                        }
+                        let down = driver.borrow().input.key_down(redo);
+                        if down && !held {
+                            break None;
+                        }
+                        held = down;
+                    };
+                    let Some(success) = success else {
+                        driver.borrow().notify("redo".to_owned());
+                        continue;
+                    };
+                    counter.record(success);
+                    attempts += 1;
+                    let result = if success { "success" } else { "fail" };
+                    let summary = counter.summary(&options.state);
+                    driver.borrow().notify(format!("{}, {}", result, summary));
+                }
+                driver.borrow().frame.set_subtitle(None);
+
+                let totals = ctx.create_table()?;
+                totals.set("attempts", counter.attempts)?;
+                totals.set("successes", counter.successes)?;
+                Ok(totals)
+            })?,
+        )?;
+        globals.set("practice", practice)?;
 
-    // create emulator
-    let status = Arc::new(AtomicU8::new(0));
-    let file = read("rom/smb.nes").unwrap();
-    let mut emulator = NES::new(
-        NROM::from_ines(file),
-        Controllers::standard(&status),
-        FastPPU::new(),
-    );
+        // toggle, release, press and hold change the staged input, which the
+        // driver commits right before the next frame it steps: a change always
+        // takes effect on the next frame, however the script and the stepping
+        // interleave
+        globals.set(
+            "toggle",
+            scope.create_function(|_, names: MultiValue| {
+                let mut input = staged.load();
+                for button in buttons::parse_args(names, &driver.borrow().aliases)? {
+                    input ^= button.bit();
+                    if let Some(opposite) = button.opposite() {
+                        input &= !opposite.bit();
+                    }
+                }
+                staged.store(input);
+                Ok(())
+            })?,
+        )?;
 
-    // run nes to level 1-1
-    for input in vec![
-        0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0,
-        0, 0, 0b00001000, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0,
-        0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0,
-        0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0,
-        0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0,
-        0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0,
-    ] {
-        status.store(input, Ordering::Relaxed);
-        emulator.next_frame();
-    }
-    frame.update(&mut emulator);
+        // jump to any frame up to the end of the recording, which keeps the
+        // frames after the current one until different inputs are stepped;
+        // seeking starts from the closest state kept before the frame, see
+        // Greenzone
+        globals.set(
+            "seek_frame",
+            scope.create_function(|_, (frame,): (usize,)| {
+                debug!("seek_frame({})", frame);
+                driver
+                    .borrow_mut()
+                    .seek(frame)
+                    .map_err(LuaError::RuntimeError)
+            })?,
+        )?;
 
-    // run script
-    let emulator = Rc::new(RefCell::new(emulator));
+        // how often the recording was branched off, see Greenzone
+        globals.set(
+            "rerecords",
+            scope.create_function(|_, ()| Ok(driver.borrow().greenzone.rerecords()))?,
+        )?;
 
-    ctx.scope(|scope| {
-        let globals = ctx.globals();
+        // exactly these buttons from the next frame on, none without
+        // arguments; with the pause and frame_advance hotkeys this is how
+        // inputs are crafted one frame at a time
         globals.set(
-            "wait",
-            scope.create_function_mut(|_, (time,): (u32,)| {
-                for _ in 0..time {
-                    clock.loop_start();
+            "set_input",
+            scope.create_function(|_, names: MultiValue| {
+                let input = buttons::parse_args(names, &driver.borrow().aliases)?
+                    .into_iter()
+                    .fold(0, |input, button| input | button.bit());
+                debug!("set_input({:08b})", input);
+                staged.store(input);
+                Ok(())
+            })?,
+        )?;
 
-                    let mut emulator = emulator.borrow_mut();
-                    emulator.next_frame();
-                    frame.update(&mut emulator);
+        // PPU introspection; an error until the registers can be read back,
+        // see Machine::ppu_registers
+        let ppu = ctx.create_table()?;
+        ppu.set(
+            "status",
+            scope.create_function(|ctx, ()| {
+                debug!("ppu.status()");
+                ppu_status(ctx, &driver.borrow().machine)
+            })?,
+        )?;
+        globals.set("ppu", ppu)?;
+        // the scroll x and y a visible scanline of the last frame was drawn
+        // with; an error until it is kept, see Machine::scroll_at
+        globals.set(
+            "scroll_at",
+            scope.create_function(|_, scanline: u32| {
+                debug!("scroll_at({})", scanline);
+                scroll_at(&driver.borrow().machine, scanline)
+            })?,
+        )?;
 
-                    clock.loop_sleep();
+        // the light gun, aimed in NES pixels; an error until there is one,
+        // see Machine::set_zapper
+        let zapper = ctx.create_table()?;
+        zapper.set(
+            "aim",
+            scope.create_function(|_, (x, y): (u32, u32)| {
+                debug!("zapper.aim({}, {})", x, y);
+                if x >= 256 || y >= 240 {
+                    return Err(LuaError::RuntimeError(format!(
+                        "zapper.aim expects x from 0 to 255 and y from 0 to 239, got {}, {}",
+                        x, y
+                    )));
                 }
-                Ok(())
+                let aim = Some((x as u8, y as u8));
+                set_zapper(&mut driver.borrow_mut().machine, "zapper.aim", aim, false)
             })?,
         )?;
+        zapper.set(
+            "trigger",
+            scope.create_function(|_, ()| {
+                debug!("zapper.trigger()");
+                set_zapper(
+                    &mut driver.borrow_mut().machine,
+                    "zapper.trigger",
+                    None,
+                    true,
+                )
+            })?,
+        )?;
+        globals.set("zapper", zapper)?;
 
+        // controller bytes for the latches within the next frame, see
+        // Machine::queue_latches; an error until the controller can change
+        // within a frame
         globals.set(
-            "read",
-            scope
-                .create_function(|_, (addr,): (u16,)| Ok(emulator.borrow().read_internal(addr)))?,
+            "set_subframe_inputs",
+            scope.create_function(|_, (inputs,): (Vec<u8>,)| {
+                debug!("set_subframe_inputs({:?})", inputs);
+                set_subframe_inputs(&mut driver.borrow_mut().machine, &inputs)
+            })?,
         )?;
 
+        // names in the game's terms for buttons, e.g. {shoot = "B"}; game
+        // modules and scripts can both add to those from the config
         globals.set(
-            "toggle",
-            scope.create_function(|ctx, buttons: MultiValue| {
-                let mut input = status.load(Ordering::Relaxed);
-
-                for button in buttons.into_iter().map(|v| String::from_lua(v, ctx)) {
-                    let button = button?;
-                    match button.to_uppercase().as_str() {
-                        "A" | "JUMP" => {
-                            input ^= 1 << 0;
-                        }
-                        "B" | "RUN" => {
-                            input ^= 1 << 1;
-                        }
-                        "U" | "UP" => {
-                            input ^= 1 << 4;
-                            input &= !(1 << 5);
-                        }
-                        "D" | "DOWN" => {
-                            input ^= 1 << 5;
-                            input &= !(1 << 4);
-                        }
-                        "L" | "LEFT" => {
-                            input ^= 1 << 6;
-                            input &= !(1 << 7);
-                        }
-                        "R" | "RIGHT" => {
-                            input ^= 1 << 7;
-                            input &= !(1 << 6);
-                        }
-                        _ => todo!("give error for {}", button),
-                    };
+            "map_buttons",
+            scope.create_function(|_, (aliases,): (rlua::Table,)| {
+                let mut driver = driver.borrow_mut();
+                for pair in aliases.pairs::<String, String>() {
+                    let (name, buttons) = pair?;
+                    debug!("map_buttons({} = {})", name, buttons);
+                    driver
+                        .aliases
+                        .define(&name, &buttons)
+                        .map_err(LuaError::RuntimeError)?;
+                }
+                Ok(())
+            })?,
+        )?;
+        globals.set(
+            "button_aliases",
+            scope.create_function(|ctx, ()| {
+                let table = ctx.create_table()?;
+                for (name, buttons) in driver.borrow().aliases.list() {
+                    table.set(name, buttons)?;
                 }
+                Ok(table)
+            })?,
+        )?;
 
-                status.store(input, Ordering::Relaxed);
+        // a table of `name = true` to press and `name = false` to release,
+        // other buttons are left as they are
+        globals.set(
+            "set_buttons",
+            scope.create_function(|_, (buttons,): (rlua::Table,)| {
+                let mut input = staged.load();
+                let args = MultiValue::from_vec(vec![Value::Table(buttons)]);
+                for (button, pressed, _) in buttons::args(args, &driver.borrow().aliases)? {
+                    if pressed {
+                        input |= button.bit();
+                    } else {
+                        input &= !button.bit();
+                    }
+                }
+                staged.store(input);
                 Ok(())
             })?,
         )?;
 
         globals.set(
             "release",
-            scope.create_function(|ctx, buttons: MultiValue| {
-                let mut input = status.load(Ordering::Relaxed);
-
-                for button in buttons.into_iter().map(|v| String::from_lua(v, ctx)) {
-                    let button = button?;
-                    match button.to_uppercase().as_str() {
-                        "A" | "JUMP" => input &= !(1 << 0),
-                        "B" | "RUN" => input &= !(1 << 1),
-                        "U" | "UP" => input &= !(1 << 4),
-                        "D" | "DOWN" => input &= !(1 << 5),
-                        "L" | "LEFT" => input &= !(1 << 6),
-                        "R" | "RIGHT" => input &= !(1 << 7),
-                        _ => todo!("give error for {}", button),
-                    };
+            scope.create_function(|_, names: MultiValue| {
+                let mut input = staged.load();
+                for button in buttons::parse_args(names, &driver.borrow().aliases)? {
+                    input &= !button.bit();
                 }
-
-                status.store(input, Ordering::Relaxed);
+                staged.store(input);
                 Ok(())
             })?,
         )?;
 
         globals.set(
             "press",
-            scope.create_function(|ctx, buttons: MultiValue| {
-                let mut input = status.load(Ordering::Relaxed);
-
-                for button in buttons.into_iter().map(|v| String::from_lua(v, ctx)) {
-                    let button = button?;
-                    match button.to_uppercase().as_str() {
-                        "A" | "JUMP" => {
-                            input |= 1 << 0;
-                        }
-                        "B" | "RUN" => {
-                            input |= 1 << 1;
-                        }
-                        "U" | "UP" => {
-                            input |= 1 << 4;
-                            input &= !(1 << 5);
-                        }
-                        "D" | "DOWN" => {
-                            input |= 1 << 5;
-                            input &= !(1 << 4);
-                        }
-                        "L" | "LEFT" => {
-                            input |= 1 << 6;
-                            input &= !(1 << 7);
-                        }
-                        "R" | "RIGHT" => {
-                            input |= 1 << 7;
-                            input &= !(1 << 6);
-                        }
-                        _ => todo!("give error for {}", button),
-                    };
+            scope.create_function(|_, names: MultiValue| {
+                let mut input = staged.load();
+                for button in buttons::parse_args(names, &driver.borrow().aliases)? {
+                    input |= button.bit();
+                    if let Some(opposite) = button.opposite() {
+                        input &= !opposite.bit();
+                    }
                 }
-
-                status.store(input, Ordering::Relaxed);
+                staged.store(input);
                 Ok(())
             })?,
         )?;
@@ -298,34 +3367,266 @@ fn run_lua<'lua>(ctx: Context<'lua>, frame: Arc<Frame>) -> Result<(), LuaError>
             })?,
         )?;
 
-        ctx.load(&read_to_string("script/mock.lua").unwrap())
-            .exec()?;
+        // macros recorded with the record_macro hotkey; while one plays it
+        // sets the controller every frame, overriding the script's presses,
+        // and the controller goes back to how it was afterwards
+        let macros = ctx.create_table()?;
+        macros.set(
+            "play",
+            scope.create_function(|ctx, (name,): (String,)| {
+                debug!("macro.play({})", name);
+                let inputs = driver.borrow().macros.get(&name)?;
+                let wait: Function = ctx.globals().get("wait")?;
+                let before = staged.load();
+                for input in inputs {
+                    staged.store(input);
+                    wait.call::<_, ()>(1)?;
+                }
+                staged.store(before);
+                Ok(())
+            })?,
+        )?;
+        macros.set(
+            "list",
+            scope.create_function(|ctx, ()| {
+                ctx.create_sequence_from(driver.borrow().macros.names())
+            })?,
+        )?;
+        globals.set("macro", macros)?;
+
+        globals.set("resuming", resume_frame.is_some())?;
+        globals.set("args", script_args(ctx, &options.args)?)?;
+
+        // modules resolve relative to the script and --lua-path
+        let script_dir = std::path::Path::new(&options.script)
+            .parent()
+            .filter(|dir| !dir.as_os_str().is_empty())
+            .map_or_else(|| ".".into(), |dir| dir.to_path_buf());
+        let mut roots = vec![script_dir];
+        roots.extend(options.lua_path.iter().map(Into::into));
+        modules::install(ctx, roots, script_hashes.clone())?;
+
+        api::install(ctx)?;
+
+        let hash = driver.borrow().machine.data_hash();
+        games::install(ctx, std::path::Path::new(&options.games), hash)?;
+
+        // the scripts share their globals, so what one declares all get
+        let mut permissions = Permissions::none();
+        for (path, source) in &scripts {
+            let declared = match options.trust_all {
+                true => Permissions::all(),
+                false => Permissions::parse(source)
+                    .map_err(|e| LuaError::RuntimeError(format!("{}: {}", path, e)))?,
+            };
+            permissions.grant(declared);
+        }
+        permissions.apply(ctx, options.lua_allow.as_deref().unwrap_or_default())?;
+        driver
+            .borrow_mut()
+            .images
+            .allow_saving(permissions.has("screenshots"));
+
+        // how the script is being run, as of now; the table is read-only
+        let config = ctx.create_table()?;
+        {
+            let driver = driver.borrow();
+            config.set("rom", options.rom.as_str())?;
+            config.set("rom_hash", hash)?;
+            config.set("script", options.script.as_str())?;
+            config.set(
+                "chain",
+                ctx.create_sequence_from(options.chain.iter().map(String::as_str))?,
+            )?;
+            config.set("output_dir", driver.output.run_dir().display().to_string())?;
+            // fastnes only emulates NTSC consoles
+            config.set("region", "NTSC")?;
+            let mode = match options.mode {
+                Mode::Run => "run",
+                Mode::Test => "test",
+            };
+            config.set("mode", mode)?;
+            config.set("headless", options.headless)?;
+            config.set("speed", driver.speed)?;
+            // paced at 60 frames a second, not as fast as possible
+            config.set("realtime", driver.clock.is_some())?;
+            config.set("journal", driver.journal.is_some())?;
+            config.set("record_hashes", driver.hashes.is_some())?;
+            config.set("record_ram", driver.record_ram.is_some())?;
+            config.set("script_hash", format!("sha256:{}", script_hashes.script()))?;
+            config.set(
+                "features",
+                ctx.create_sequence_from(api::FEATURES.iter().copied())?,
+            )?;
+            config.set(
+                "permissions",
+                ctx.create_sequence_from(permissions.granted())?,
+            )?;
+        }
+        globals.set("config", api::read_only(ctx, config)?)?;
+        let chained = !options.chain.is_empty();
+        for (path, source) in &scripts {
+            if chained {
+                driver.borrow_mut().report.begin(path);
+            }
+            let chunk = ctx
+                .load(source)
+                .set_name(&format!("@{}", path))?
+                .into_function()?;
+            let result = debugger::call_traced(ctx, chunk, ());
+            if std::mem::take(&mut driver.borrow_mut().skipped) {
+                continue;
+            }
+            if chained {
+                result.map_err(|e| LuaError::RuntimeError(format!("{}: {}", path, e)))?;
+            } else {
+                result?;
+            }
+        }
+
+        // hand control to the script after restoring a checkpoint. The
+        // bodies above only defined its globals: each stopped at its first
+        // wait, so nothing of what they do afterwards happens and on_resume
+        // takes over from there, or the game just runs on
+        driver.borrow_mut().resuming = false;
+        if let Some(frame) = resume_frame {
+            if let Some(on_resume) = globals.get::<_, Option<Function>>("on_resume")? {
+                debugger::call_traced(ctx, on_resume, frame)?;
+            }
+        }
+
+        // the idle hook keeps the scope alive for as long as the game runs
+        if let Some(on_idle) = ctx.named_registry_value::<_, Option<Function>>(ON_IDLE)? {
+            if options.mode == Mode::Test || options.headless {
+                warn!("on_idle is not called in tests and headless runs, they end with the script");
+                return Ok(());
+            }
+            if !driver.borrow().report.is_empty() {
+                driver.borrow().report.print();
+            }
+            let wait: Function = globals.get("wait")?;
+            loop {
+                wait.call::<_, ()>(1)?;
+                let frame = driver.borrow().machine.frame();
+                debugger::call_traced(ctx, on_idle.clone(), frame)?;
+            }
+        }
 
         Ok(())
-    })?;
+    });
 
     // run the rest of the emulator
-    let mut emulator = match Rc::try_unwrap(emulator) {
+    let mut driver = match Rc::try_unwrap(driver) {
         Ok(t) => t,
         Err(_) => unreachable!(),
     }
     .into_inner();
+    if let Some(path) = driver.open.take() {
+        return Ok(Exit::Open(path));
+    }
+    if driver.quit {
+        return Ok(Exit::Finished(driver.report));
+    }
+    // the script was stopped, its error is the limit
+    if let Some(reason) = driver.limits.exceeded() {
+        return Ok(Exit::Limit(driver.report, reason.to_owned()));
+    }
+    let frame = driver.machine.frame();
+    result.map_err(|e| debugger::at_frame(frame, e))?;
+
+    if options.mode == Mode::Test || options.headless {
+        return Ok(Exit::Finished(driver.report));
+    }
+    if !driver.report.is_empty() {
+        driver.report.print();
+    }
+    Ok(run_forever(driver))
+}
 
+// how far past the checkpoint bisect probes: the powers of two below
+// `max_frames`, then `max_frames` itself
+fn bisect_probes(max_frames: usize) -> Vec<usize> {
+    let mut offsets: Vec<usize> = std::iter::successors(Some(1), |&n| n.checked_mul(2))
+        .take_while(|&n| n < max_frames)
+        .collect();
+    if max_frames > 0 {
+        offsets.push(max_frames);
+    }
+    offsets
+}
+
+// keep the game running once the script is done, until another file is opened
+fn run_forever(mut driver: Driver) -> Exit {
+    driver.scripted = false;
     loop {
-        clock.loop_start();
-        emulator.next_frame();
-        frame.update(&mut emulator);
-        clock.loop_sleep();
+        driver.idle();
+        driver.step();
+        if let Some(path) = driver.open.take() {
+            return Exit::Open(path);
+        }
+        if let Some(reason) = driver.limits.exceeded() {
+            return Exit::Limit(driver.report, reason.to_owned());
+        }
     }
 }
 
 struct Frame {
     frame: Mutex<[fastnes::ppu::Color; 61440]>,
     ready: AtomicBool,
+    // tests and headless runs, nothing takes the frames
+    inline: bool,
+    views: Views,
+    stats: Stats,
+    // window.title_format
+    title: Option<Title>,
+    // why the emulator cannot start, see startup::check
+    problem: Mutex<Option<String>>,
+    // toasts from the emulator for the window to show
+    toasts: Mutex<Vec<(String, Duration)>>,
+    // while playing a journal, see subtitles.rs
+    subtitle: Mutex<Option<String>>,
+    // when the frame not presented yet was drawn
+    published: Mutex<Option<Instant>>,
+    // wakes the window to present a new frame, set once it is open
+    wake: Mutex<Option<EventLoopProxy<()>>>,
+    // the frame and number of frames while a journal plays, for the
+    // playback bar and the transport keys
+    playback: Mutex<Option<(usize, usize)>>,
+    // where --capture-layout put the game in the window, for capture_rect()
+    capture_rect: Mutex<Option<(u32, u32, u32, u32)>>,
 }
 
 impl Frame {
-    fn update<C: Cartridge, P: PPU>(self: &Arc<Self>, emulator: &mut NES<C, P>) {
+    fn new(inline: bool, title: Option<Title>) -> Self {
+        Self {
+            frame: Mutex::new(
+                [fastnes::ppu::Color {
+                    r: 0,
+                    g: 0,
+                    b: 0,
+                    a: 0,
+                }; 61440],
+            ),
+            ready: AtomicBool::new(true),
+            inline,
+            views: Views::default(),
+            stats: Stats::default(),
+            title,
+            problem: Mutex::new(None),
+            toasts: Mutex::new(Vec::new()),
+            subtitle: Mutex::new(None),
+            published: Mutex::new(None),
+            wake: Mutex::new(None),
+            playback: Mutex::new(None),
+            capture_rect: Mutex::new(None),
+        }
+    }
+
+    // `draw` is only called when the window is ready for another frame
+    fn update(self: &Arc<Self>, draw: impl FnOnce(&mut [fastnes::ppu::Color; 61440])) {
+        if self.inline {
+            return;
+        }
         if self
             .ready
             .compare_exchange(true, false, Ordering::Relaxed, Ordering::Relaxed)
@@ -335,53 +3636,797 @@ impl Frame {
             return;
         }
 
-        let mut frame = self.frame.lock().unwrap();
-        *frame = emulator.draw_frame(DrawOptions::All);
+        draw(&mut self.frame.lock().unwrap());
+        *self.published.lock().unwrap() = Some(Instant::now());
+        // the window waits for this rather than polling for it, so the frame
+        // goes out right away instead of after the present in progress
+        if let Some(wake) = &*self.wake.lock().unwrap() {
+            let _ = wake.send_event(());
+        }
+    }
+    fn toast(&self, text: String, time: Duration) {
+        self.toasts.lock().unwrap().push((text, time));
+    }
+    fn take_toasts(&self) -> Vec<(String, Duration)> {
+        std::mem::take(&mut self.toasts.lock().unwrap())
+    }
+    fn subtitle(&self) -> Option<String> {
+        self.subtitle.lock().unwrap().clone()
+    }
+    fn set_subtitle(&self, subtitle: Option<String>) {
+        *self.subtitle.lock().unwrap() = subtitle;
+    }
+    fn problem(&self) -> Option<String> {
+        self.problem.lock().unwrap().clone()
+    }
+    fn playback(&self) -> Option<(usize, usize)> {
+        *self.playback.lock().unwrap()
+    }
+    fn set_playback(&self, playback: Option<(usize, usize)>) {
+        *self.playback.lock().unwrap() = playback;
+    }
+    fn capture_rect(&self) -> Option<(u32, u32, u32, u32)> {
+        *self.capture_rect.lock().unwrap()
+    }
+    fn set_capture_rect(&self, rect: Option<(u32, u32, u32, u32)>) {
+        *self.capture_rect.lock().unwrap() = rect;
+    }
+    fn set_wake(&self, wake: EventLoopProxy<()>) {
+        *self.wake.lock().unwrap() = Some(wake);
+    }
+    // when the frame frame() returns next was drawn, if it is a new one;
+    // call it before frame(), the emulator cannot draw again until then
+    fn take_published(&self) -> Option<Instant> {
+        self.published.lock().unwrap().take()
+    }
+    fn set_problem(&self, problem: Option<String>) {
+        *self.problem.lock().unwrap() = problem;
     }
+    // The window presents whenever it is idle, repeating this frame until
+    // the emulator draws the next. With vsync every present waits for the
+    // monitor, but only on the window's thread: it holds the lock for the
+    // copy alone, so the emulator's pacing is the same at any refresh rate.
     fn frame(self: &Arc<Self>) -> [fastnes::ppu::Color; 61440] {
         self.ready.store(true, Ordering::Relaxed);
-        self.frame.lock().unwrap().clone()
+        // a panic on the emulator thread must not take the window with it
+        self.frame.lock().unwrap_or_else(|e| e.into_inner()).clone()
     }
 }
 
 fn main() -> Result<(), LuaError> {
-    let frame = Arc::new(Frame {
-        frame: Mutex::new(
-            [fastnes::ppu::Color {
-                r: 0,
-                g: 0,
-                b: 0,
-                a: 0,
-            }; 61440],
-        ),
-        ready: AtomicBool::new(true),
+    let options = Options::from_env();
+    if let Err(e) = logging::init(&options.log_level, options.log_file.as_deref()) {
+        eprintln!("{}", e);
+        process::exit(1);
+    }
+    // tests and headless runs don't need a window, they run on the main
+    // thread
+    let inline = options.mode == Mode::Test || options.headless;
+    if let Some(dir) = &options.smoke {
+        let threads = options
+            .smoke_threads
+            .unwrap_or_else(|| thread::available_parallelism().map_or(1, |n| n.get()));
+        let results = smoke::run(dir, options.smoke_frames, threads).unwrap_or_else(|e| {
+            eprintln!("{}", e);
+            process::exit(1);
+        });
+        smoke::print(&results);
+        if let Some(path) = &options.smoke_json {
+            if let Err(e) = std::fs::write(path, smoke::json(&results)) {
+                eprintln!("{}: {}", path, e);
+                process::exit(1);
+            }
+        }
+        process::exit(if smoke::passed(&results) { 0 } else { 1 });
+    }
+    if let Some((rom, journal, hashes)) = &options.verify {
+        match verify::run(rom, journal, hashes.as_ref(), options.output.as_deref()) {
+            Ok(matched) => process::exit(if matched { 0 } else { 1 }),
+            Err(e) => {
+                eprintln!("{}", e);
+                process::exit(1);
+            }
+        }
+    }
+    if let Some((run, out)) = &options.pack {
+        if let Err(e) = bundle::pack(run.as_ref(), out.as_ref()) {
+            eprintln!("{}", e);
+            process::exit(1);
+        }
+        return Ok(());
+    }
+    if let Some((path, csv)) = &options.ram_dump {
+        if let Err(e) = ramdump::print(path, *csv) {
+            eprintln!("{}", e);
+            process::exit(1);
+        }
+        return Ok(());
+    }
+    if let Some((a, b)) = &options.diff {
+        if let Err(e) = diff::print(a, b) {
+            eprintln!("{}", e);
+            process::exit(1);
+        }
+        return Ok(());
+    }
+
+    let title = options.title_format.as_deref().map(|format| {
+        let (title, unknown) = Title::parse(format);
+        if !unknown.is_empty() {
+            warn!(
+                "unknown placeholders in window.title_format: {}, expected {}",
+                unknown.join(", "),
+                stats::names().join(", ")
+            );
+        }
+        title
     });
+    let frame = Arc::new(Frame::new(inline, title));
+
+    let (commands, receiver) = channel();
+    let libs =
+        sandbox::stdlib(options.lua_allow.as_deref().unwrap_or_default()).unwrap_or_else(|e| {
+            eprintln!("{}", e);
+            process::exit(1);
+        });
+
+    interrupt::install();
+    if inline {
+        if let Err(e) = startup::check(&options.rom, &options.scripts()) {
+            error!("{}", e);
+            process::exit(1);
+        }
+        let mode = options.mode;
+        let lua = sandbox::new_lua(libs);
+        let pause = Arc::new(Pause::default());
+        let commands = Rc::new(receiver);
+        let input = Arc::new(InputState::default());
+        let exit = lua.context(|ctx| run_lua(ctx, &lua, options, frame, commands, pause, input));
+        let code = match exit.and_then(|exit| finish(exit, mode)) {
+            Ok(true) => 0,
+            Ok(false) => 1,
+            Err(e) if limits::is_exceeded(&e) => {
+                error!("{}", e);
+                limits::exit_code()
+            }
+            Err(e) => {
+                error!("script error: {}", e);
+                1
+            }
+        };
+        process::exit(code);
+    }
 
     let clone = frame.clone();
-    let _handle = thread::spawn(move || {
-        let lua = Lua::new_with(
-            StdLib::all().difference(StdLib::OS | StdLib::IO | StdLib::DEBUG | StdLib::PACKAGE),
-        );
-        lua.context(|ctx| run_lua(ctx, clone)).unwrap();
+    let picker = options.pick.then(|| Picker::new(recent::load()));
+    let pause = Arc::new(Pause::default());
+    let emulator_pause = pause.clone();
+    let input = Arc::new(InputState::default());
+    let emulator_input = input.clone();
+    let bindings = options.bindings.clone();
+    let renderer = options.renderer;
+    let vsync = options.vsync;
+    let placement = options.placement.clone();
+    let detach = options.detach.clone();
+    let layout = options.layout.clone();
+    let capture = options.capture_layout;
+    let watcher = Watcher::new(
+        options.config.as_ref().map(Into::into),
+        options.overlay_preset,
+    );
+    let failure = Arc::new(Failure::default());
+    let emulator_failure = failure.clone();
+    let (priority, core) = (options.priority, options.core);
+    let emulator = thread::spawn(move || {
+        priority::apply(priority, core);
+        let result = panic::catch_unwind(AssertUnwindSafe(|| {
+            emulate(
+                options,
+                libs,
+                clone,
+                receiver,
+                emulator_pause,
+                emulator_input,
+            )
+        }));
+        let message = match result {
+            Ok(Ok(())) => return emulator_failure.set_quit(),
+            Ok(Err(e)) if limits::is_exceeded(&e) => {
+                return emulator_failure.set_limit(e.to_string())
+            }
+            Ok(Err(e)) => format!("script error: {}", e),
+            Err(payload) => format!("panic: {}", failure::panic_message(&*payload)),
+        };
+        emulator_failure.set(message);
     });
 
     // open window
-    Screen::new("Marlua", 640, 360).run(move |canvas| {
-        let frame = frame.frame();
-
-        // create image
-        let img = Img::new(as_rgba(&frame), 256, 240);
-        let image = canvas.create_image(img, ImageFlags::NEAREST).unwrap();
-
-        // draw image
-        let fill_paint = Paint::image(image, 0.0, 0.0, 256.0, 240.0, 0.0, 1.0);
-        let mut path = Path::new();
-        path.rect(0.0, 0.0, 256.0, 240.0);
-        canvas.fill_path(&mut path, &fill_paint);
-
-        // destroy image
-        // need to flush the canvas before being able to delete the image
-        canvas.flush();
-        canvas.delete_image(image);
-    });
+    let (width, height) = capture.map_or((640, 360), |capture| capture.window_size());
+    let mut screen = Screen::new("Marlua", width, height, renderer, vsync, placement, detach)
+        .unwrap_or_else(|e| {
+            eprintln!("could not open a window: {}", e);
+            process::exit(1);
+        });
+    screen.set_layout(layout, watcher);
+    screen.set_capture(capture);
+    screen.run(
+        commands, pause, input, bindings, picker, emulator, failure, frame,
+    );
+}
+
+// runs scripts on the emulator thread until the window is closed
+fn emulate(
+    mut options: Options,
+    libs: StdLib,
+    frame: Arc<Frame>,
+    receiver: Receiver<Command>,
+    pause: Arc<Pause>,
+    input: Arc<InputState>,
+) -> Result<(), LuaError> {
+    let receiver = Rc::new(receiver);
+
+    // wait for the picker, or for a ROM dropped on it
+    while options.pick {
+        match receiver.recv() {
+            Ok(Command::Pick(rom, script)) => {
+                options.rom = rom.to_string_lossy().into_owned();
+                options.script = script.to_string_lossy().into_owned();
+                options.pick = false;
+            }
+            Ok(Command::Open(rom)) if rom.to_string_lossy().to_lowercase().ends_with(".nes") => {
+                options.rom = rom.to_string_lossy().into_owned();
+                options.pick = false;
+            }
+            Ok(Command::Close) | Err(_) => return Ok(()),
+            Ok(_) => {}
+        }
+    }
+
+    // start over with a fresh emulator and Lua state whenever a file is
+    // dropped on the window
+    loop {
+        if !check_files(&mut options, &frame, &receiver) {
+            return Ok(());
+        }
+        recent::add(&options.rom, &options.script);
+        let lua = sandbox::new_lua(libs);
+        let exit = lua.context(|ctx| {
+            run_lua(
+                ctx,
+                &lua,
+                options.clone(),
+                frame.clone(),
+                receiver.clone(),
+                pause.clone(),
+                input.clone(),
+            )
+        })?;
+        match exit {
+            Exit::Open(path) => open(&mut options, path),
+            // quit() or a limit
+            exit => return finish(exit, options.mode).map(drop),
+        }
+    }
+}
+
+// The end of a session that does not start over, the same in the window's
+// emulator thread and inline: the report is printed when there is anything
+// in it, or always for tests, and a limit is raised as limits::Exceeded.
+// Ok tells whether every expectation passed.
+fn finish(exit: Exit, mode: Mode) -> Result<bool, LuaError> {
+    let (report, limit) = match exit {
+        Exit::Finished(report) => (report, None),
+        Exit::Limit(report, reason) => (report, Some(reason)),
+        // only the window can open files
+        Exit::Open(_) => unreachable!(),
+    };
+    if mode == Mode::Test || !report.is_empty() {
+        report.print();
+    }
+    match limit {
+        Some(reason) => Err(LuaError::external(limits::Exceeded(reason))),
+        None => Ok(report.passed()),
+    }
+}
+
+// a dropped or picked ROM or script for the next start
+fn open(options: &mut Options, path: PathBuf) {
+    // --resume only applies to the first start
+    options.resume = None;
+    let path = path.to_string_lossy().into_owned();
+    if path.to_lowercase().ends_with(".nes") {
+        options.rom = path;
+    } else {
+        // a dropped script replaces all of them
+        if path != options.script {
+            options.chain.clear();
+        }
+        options.script = path;
+    }
+}
+
+// waits on the window's error screen while the ROM or a script cannot be
+// used, until they are fixed on disk, or another one is picked or dropped;
+// false once the window is closed
+fn check_files(options: &mut Options, frame: &Frame, receiver: &Receiver<Command>) -> bool {
+    loop {
+        let problem = match startup::check(&options.rom, &options.scripts()) {
+            Ok(()) => {
+                frame.set_problem(None);
+                return true;
+            }
+            Err(problem) => problem,
+        };
+        if frame.problem().as_ref() != Some(&problem) {
+            error!("{}", problem);
+            frame.set_problem(Some(problem));
+        }
+        match receiver.recv_timeout(startup::RETRY) {
+            Ok(Command::Open(path)) => open(options, path),
+            Ok(Command::Close) | Err(RecvTimeoutError::Disconnected) => return false,
+            Ok(_) | Err(RecvTimeoutError::Timeout) => {}
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs::File;
+
+    use super::*;
+
+    fn options(args: &[&str]) -> Options {
+        Options::parse(args.iter().map(|arg| arg.to_string())).unwrap()
+    }
+
+    // run_lua headless with a window that never sends anything; hold
+    // testing::session() around it
+    fn session(options: Options) -> Result<Exit, LuaError> {
+        let lua = sandbox::new_lua(sandbox::stdlib(&[]).unwrap());
+        let (_commands, receiver) = channel();
+        lua.context(|ctx| {
+            let frame = Arc::new(Frame::new(true, None));
+            let commands = Rc::new(receiver);
+            run_lua(
+                ctx,
+                &lua,
+                options,
+                frame,
+                commands,
+                Arc::default(),
+                Arc::default(),
+            )
+        })
+    }
+
+    // what restart() keeps of the options and the run directory
+    #[test]
+    fn restart() {
+        let mut options = options(&[
+            "game.nes", "a.lua", "b.lua", "--resume", "latest", "--seed", "7",
+        ]);
+        open(&mut options, PathBuf::from("a.lua"));
+        assert_eq!(options.resume, None);
+        assert_eq!(options.scripts(), ["a.lua", "b.lua"]);
+        assert_eq!(options.seed, Some(7));
+
+        // another script replaces the chain, a ROM keeps the scripts
+        open(&mut options, PathBuf::from("c.lua"));
+        assert_eq!(options.scripts(), ["c.lua"]);
+        open(&mut options, PathBuf::from("other.NES"));
+        assert_eq!(options.rom, "other.NES");
+        assert_eq!(options.scripts(), ["c.lua"]);
+
+        // with --output the run directory stays, the journal is numbered
+        let run = testing::temp_dir("restart");
+        let first = output::artifact(&run, "inputs.journal").unwrap();
+        File::create(&first).unwrap();
+        let second = output::artifact(&run, "inputs.journal").unwrap();
+        assert_eq!(second, run.join("inputs-1.journal"));
+    }
+
+    // Ctrl-C during a headless run still finishes the files of the run
+    #[test]
+    fn interrupted() {
+        let dir = testing::temp_dir("interrupted");
+        let rom = dir.join("game.nes");
+        std::fs::write(&rom, testing::rom()).unwrap();
+        let recorded = dir.join("recorded.journal");
+        let mut machine = testing::machine();
+        let mut journal = Journal::create(&recorded, &machine).unwrap();
+        for input in [1, 2, 3] {
+            testing::step(&mut machine, input);
+            journal.record(&machine, 0).unwrap();
+        }
+        drop(journal);
+
+        let run = dir.join("run");
+        let paths = [&rom, &recorded, &run].map(|path| path.display().to_string());
+        let options = options(&[
+            "play",
+            &paths[0],
+            &paths[1],
+            "--headless",
+            "--output",
+            &paths[2],
+        ]);
+        let _session = testing::session();
+        interrupt::set_requested(true);
+        let exit = session(options);
+        interrupt::set_requested(false);
+
+        match exit {
+            Ok(Exit::Limit(_, reason)) => assert_eq!(reason, "interrupted"),
+            _ => panic!("the run was not interrupted"),
+        }
+        // stopped at the first frame, the writers were flushed on the way out
+        let written = journal::read(&run.join("inputs.journal"), machine.hash()).unwrap();
+        assert_eq!(written.inputs, [1]);
+        let events = std::fs::read_to_string(run.join("events.ndjson")).unwrap();
+        assert!(events.contains("input"), "{}", events);
+    }
+
+    // the config table of `marlua test ROM SCRIPT CHAINED --headless ...`,
+    // checked by the script itself
+    #[test]
+    fn config() {
+        let dir = testing::temp_dir("config");
+        let rom = dir.join("game.nes");
+        std::fs::write(&rom, testing::rom()).unwrap();
+        // a state to resume from skips the warm-up, which is for the real game
+        let state = dir.join("start.state");
+        Savestate::capture(&testing::machine())
+            .write(&state)
+            .unwrap();
+        let chained = dir.join("chained.lua");
+        std::fs::write(&chained, "").unwrap();
+        let run = dir.join("run");
+        let [rom, state, chained, run] =
+            [&rom, &state, &chained, &run].map(|path| path.display().to_string());
+
+        let script = dir.join("config.lua");
+        let expected = format!(
+            r#"
+            local expected = {{
+                rom = {:?}, rom_hash = {}, script = {:?}, output_dir = {:?},
+                region = "NTSC", mode = "test", headless = true, speed = 1,
+                realtime = false, journal = true, record_hashes = true,
+                record_ram = false,
+            }}
+            local keys = 0
+            for key, value in pairs(config) do
+                keys = keys + 1
+                if expected[key] ~= nil then
+                    assert(value == expected[key], key .. " is " .. tostring(value))
+                end
+            end
+            assert(keys == 16, keys .. " keys")
+            assert(#config.chain == 1 and config.chain[1] == {:?})
+            assert(#config.features == {} and #config.permissions == 0)
+            assert(config.script_hash:match("^sha256:%x+$"))
+            assert(not pcall(function() config.mode = "run" end))
+            assert(not pcall(function() config.chain[1] = "other.lua" end))
+            "#,
+            rom,
+            testing::machine().data_hash(),
+            script.display().to_string(),
+            run,
+            chained,
+            api::FEATURES.len()
+        );
+        std::fs::write(&script, expected).unwrap();
+        let script = script.display().to_string();
+
+        let options = options(&[
+            "test",
+            &rom,
+            &script,
+            &chained,
+            "--headless",
+            "--resume",
+            &state,
+            "--output",
+            &run,
+            "--record-hashes",
+        ]);
+        let _session = testing::session();
+        if let Err(e) = session(options) {
+            panic!("{}", e);
+        }
+    }
+
+    #[test]
+    fn numeric() {
+        for s in ["1", "-1", "+2.5", "1e3", "1.5E-3", ".5", "5.", "-0"] {
+            assert!(super::numeric(s), "{}", s);
+        }
+        for s in [
+            "nan", "inf", "-inf", "infinity", "NaN", "", ".", "e5", "1e", "1.2.3", "0x10",
+        ] {
+            assert!(!super::numeric(s), "{}", s);
+        }
+    }
+
+    // the filter of diff_states can call what needs the driver
+    #[test]
+    fn diff_states_filter() {
+        let dir = testing::temp_dir("diff-states");
+        let rom = dir.join("game.nes");
+        std::fs::write(&rom, testing::rom()).unwrap();
+        let state = dir.join("start.state");
+        Savestate::capture(&testing::machine())
+            .write(&state)
+            .unwrap();
+        let script = dir.join("diff.lua");
+        std::fs::write(
+            &script,
+            r#"
+            function on_resume()
+                checkpoint("a")
+                wait(5)
+                checkpoint("b")
+                local seen = 0
+                local changes = diff_states("a", "b", function(addr)
+                    seen = seen + 1
+                    checkpoint("filtered")
+                    return addr == 0
+                end)
+                assert(seen > 0)
+                assert(#changes == 1 and changes[1].addr == 0)
+            end
+            "#,
+        )
+        .unwrap();
+        let paths =
+            [&rom, &script, &state, &dir.join("run")].map(|path| path.display().to_string());
+
+        let options = options(&[
+            "test",
+            &paths[0],
+            &paths[1],
+            "--headless",
+            "--resume",
+            &paths[2],
+            "--output",
+            &paths[3],
+        ]);
+        let _session = testing::session();
+        if let Err(e) = session(options) {
+            panic!("{}", e);
+        }
+    }
+
+    #[test]
+    fn bisect_probes() {
+        assert!(super::bisect_probes(0).is_empty());
+        assert_eq!(super::bisect_probes(1), [1]);
+        assert_eq!(super::bisect_probes(5), [1, 2, 4, 5]);
+        assert_eq!(super::bisect_probes(8), [1, 2, 4, 8]);
+    }
+
+    // which frames bisect shows the predicate, and that the machine is left
+    // where it was
+    #[test]
+    fn bisect() {
+        let dir = testing::temp_dir("bisect");
+        let rom = dir.join("game.nes");
+        std::fs::write(&rom, testing::rom()).unwrap();
+        let state = dir.join("start.state");
+        let mut machine = testing::machine();
+        testing::step(&mut machine, 1);
+        testing::step(&mut machine, 2);
+        Savestate::capture(&machine).write(&state).unwrap();
+        let script = dir.join("bisect.lua");
+        std::fs::write(
+            &script,
+            r#"
+            checkpoint("start")
+            local seen = {}
+            local function after(n)
+                return function()
+                    seen[#seen + 1] = frame()
+                    return frame() >= 2 + n
+                end
+            end
+            assert(bisect("start", 40, {}, after(13)) == 13)
+            assert(table.concat(seen, " ") == "3 4 6 10 18 11 12 13 14 15", table.concat(seen, " "))
+            assert(frame() == 2)
+            assert(bisect("start", 10, {}, after(13)) == nil)
+            assert(bisect("start", 10, {}, after(0)) == 1)
+            local pressed = function() return input_at(frame()) == 7 end
+            assert(bisect("start", 10, {0, 0, 0, 0, 0, 7, 7, 7, 7, 7}, pressed) == 6)
+            assert(frame() == 2)
+            "#,
+        )
+        .unwrap();
+        let paths =
+            [&rom, &script, &state, &dir.join("run")].map(|path| path.display().to_string());
+
+        let options = options(&[
+            "test",
+            &paths[0],
+            &paths[1],
+            "--headless",
+            "--resume",
+            &paths[2],
+            "--output",
+            &paths[3],
+        ]);
+        let _session = testing::session();
+        if let Err(e) = session(options) {
+            panic!("{}", e);
+        }
+    }
+
+    // with --resume the script stops at its first wait and on_resume takes
+    // over from the checkpoint
+    #[test]
+    fn resume() {
+        let dir = testing::temp_dir("resume");
+        let rom = dir.join("game.nes");
+        std::fs::write(&rom, testing::rom()).unwrap();
+        let state = dir.join("start.state");
+        let mut machine = testing::machine();
+        testing::step(&mut machine, 1);
+        testing::step(&mut machine, 2);
+        Savestate::capture(&machine).write(&state).unwrap();
+        let script = dir.join("resume.lua");
+        std::fs::write(
+            &script,
+            r#"
+            defined = resuming
+            function on_resume(frame)
+                assert(defined)
+                error("on_resume(" .. frame .. ")")
+            end
+            wait(1)
+            error("the body went on after wait")
+            "#,
+        )
+        .unwrap();
+        let paths =
+            [&rom, &script, &state, &dir.join("run")].map(|path| path.display().to_string());
+
+        let options = options(&[
+            "test",
+            &paths[0],
+            &paths[1],
+            "--headless",
+            "--resume",
+            &paths[2],
+            "--output",
+            &paths[3],
+        ]);
+        let _session = testing::session();
+        match session(options) {
+            Err(e) => assert!(e.to_string().contains("on_resume(2)"), "{}", e),
+            Ok(_) => panic!("on_resume was not called"),
+        }
+    }
+
+    #[test]
+    fn sub_frame_waits() {
+        let mut machine = testing::machine();
+        for (name, unit) in [
+            ("wait_scanlines", SubFrame::Scanlines),
+            ("wait_cycles", SubFrame::Cycles),
+        ] {
+            let error = wait_sub_frame(&mut machine, name, unit, 0).unwrap_err();
+            assert!(
+                error
+                    .to_string()
+                    .contains(&format!("{} expects at least 1", name)),
+                "{}",
+                error
+            );
+            for n in [1, u32::MAX] {
+                let error = wait_sub_frame(&mut machine, name, unit, n).unwrap_err();
+                assert!(
+                    error
+                        .to_string()
+                        .contains(&format!("{} is not available", name)),
+                    "{}",
+                    error
+                );
+            }
+        }
+        // nothing was stepped or recorded
+        assert_eq!(machine.frame(), 0);
+        assert_eq!(machine.revision(), 0);
+    }
+
+    #[test]
+    fn subframe_inputs() {
+        let mut machine = testing::machine();
+        let error = set_subframe_inputs(&mut machine, &[]).unwrap_err();
+        assert!(
+            error.to_string().contains("at least one input"),
+            "{}",
+            error
+        );
+        let error = set_subframe_inputs(&mut machine, &[1, 2]).unwrap_err();
+        assert!(error.to_string().contains("is not available"), "{}", error);
+        assert_eq!(machine.revision(), 0);
+    }
+
+    #[test]
+    fn four_score() {
+        let dir = testing::temp_dir("four-score");
+        let rom = dir.join("game.nes");
+        std::fs::write(&rom, testing::rom()).unwrap();
+        let script = dir.join("empty.lua");
+        std::fs::write(&script, "").unwrap();
+        let paths = [&rom, &script, &dir.join("run")].map(|path| path.display().to_string());
+
+        let options = options(&[
+            "test",
+            &paths[0],
+            &paths[1],
+            "--headless",
+            "--four-score",
+            "--output",
+            &paths[2],
+        ]);
+        let _session = testing::session();
+        match session(options) {
+            Err(e) => assert!(
+                e.to_string().contains("--four-score is not available"),
+                "{}",
+                e
+            ),
+            Ok(_) => panic!("--four-score was accepted"),
+        }
+    }
+
+    #[test]
+    fn zapper() {
+        let mut machine = testing::machine();
+        for (name, aim, trigger) in [
+            ("zapper.aim", Some((128, 120)), false),
+            ("zapper.trigger", None, true),
+        ] {
+            let error = set_zapper(&mut machine, name, aim, trigger).unwrap_err();
+            assert!(
+                error
+                    .to_string()
+                    .contains(&format!("{} is not available", name)),
+                "{}",
+                error
+            );
+        }
+        assert_eq!(machine.revision(), 0);
+    }
+
+    #[test]
+    fn ppu_status() {
+        let machine = testing::machine();
+        Lua::new().context(|ctx| {
+            let error = super::ppu_status(ctx, &machine).unwrap_err().to_string();
+            assert!(error.contains("ppu.status is not available"), "{}", error);
+        });
+    }
+
+    #[test]
+    fn scroll_at() {
+        let machine = testing::machine();
+        let error = super::scroll_at(&machine, 240).unwrap_err().to_string();
+        assert!(error.contains("from 0 to 239"), "{}", error);
+        let error = super::scroll_at(&machine, 0).unwrap_err().to_string();
+        assert!(error.contains("scroll_at is not available"), "{}", error);
+    }
+
+    #[test]
+    fn audio() {
+        let machine = testing::machine();
+        for name in ["record_audio", "stop_audio"] {
+            let error = dump_audio(&machine, name).unwrap_err().to_string();
+            assert!(
+                error.contains(&format!("{} is not available", name)),
+                "{}",
+                error
+            );
+        }
+        let error = Options::parse(["--dump-wav", "out.wav"].map(String::from).into_iter());
+        assert!(error.err().unwrap().contains("--dump-wav is not available"));
+    }
 }
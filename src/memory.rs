@@ -0,0 +1,168 @@
+use rlua::prelude::LuaError;
+
+use crate::machine::Machine;
+
+// Multi-byte reads from the CPU address space.
+//
+// Addresses are 16 bits; a read that runs past $FFFF wraps around to $0000,
+// just like the 6502 would.
+
+// the CPU's internal RAM, mirrored up to $2000
+pub const RAM: usize = 0x800;
+
+// What the reads below decode bytes from: the machine, or a 64 KiB image of
+// the address space.
+pub trait Bus {
+    fn read(&self, addr: u16) -> u8;
+}
+
+impl Bus for Machine {
+    fn read(&self, addr: u16) -> u8 {
+        self.nes().read_internal(addr)
+    }
+}
+
+impl Bus for [u8] {
+    fn read(&self, addr: u16) -> u8 {
+        self[addr as usize]
+    }
+}
+
+pub fn read_u8<B: Bus + ?Sized>(bus: &B, addr: u16) -> u8 {
+    bus.read(addr)
+}
+
+pub fn read_s8<B: Bus + ?Sized>(bus: &B, addr: u16) -> i8 {
+    read_u8(bus, addr) as i8
+}
+
+// little-endian unless `big_endian`
+pub fn read_u16<B: Bus + ?Sized>(bus: &B, addr: u16, big_endian: bool) -> u16 {
+    let bytes = [read_u8(bus, addr), read_u8(bus, addr.wrapping_add(1))];
+    if big_endian {
+        u16::from_be_bytes(bytes)
+    } else {
+        u16::from_le_bytes(bytes)
+    }
+}
+
+pub fn read_bytes<B: Bus + ?Sized>(bus: &B, addr: u16, len: usize) -> Result<Vec<u8>, LuaError> {
+    if len > 0x10000 {
+        return Err(LuaError::RuntimeError(format!(
+            "cannot read {} bytes, the address space is only 65536 bytes",
+            len
+        )));
+    }
+    Ok((0..len)
+        .map(|i| read_u8(bus, addr.wrapping_add(i as u16)))
+        .collect())
+}
+
+// `digits` packed BCD digits, most significant first, read from
+// (digits + 1) / 2 bytes; an odd count skips the first high nibble.
+// None if any digit is above 9.
+pub fn read_bcd<B: Bus + ?Sized>(
+    bus: &B,
+    addr: u16,
+    digits: usize,
+) -> Result<Option<u64>, LuaError> {
+    if digits == 0 || digits > 18 {
+        return Err(LuaError::RuntimeError(format!(
+            "cannot read {} BCD digits, expected 1 to 18",
+            digits
+        )));
+    }
+
+    let bytes = read_bytes(bus, addr, (digits + 1) / 2)?;
+    let nibbles = bytes.iter().flat_map(|b| [b >> 4, b & 0xF]);
+    let mut value = 0;
+    for digit in nibbles.skip(digits % 2) {
+        if digit > 9 {
+            return Ok(None);
+        }
+        value = value * 10 + digit as u64;
+    }
+    Ok(Some(value))
+}
+
+pub fn read_ram<B: Bus + ?Sized>(bus: &B) -> Vec<u8> {
+    (0..RAM as u16).map(|addr| read_u8(bus, addr)).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // the address space with `bytes` at `addr`
+    fn fixture(addr: u16, bytes: &[u8]) -> Vec<u8> {
+        let mut image = vec![0; 0x10000];
+        for (i, &byte) in bytes.iter().enumerate() {
+            image[addr.wrapping_add(i as u16) as usize] = byte;
+        }
+        image
+    }
+
+    #[test]
+    fn integers() {
+        let image = fixture(0x0700, &[0xFE, 0x34, 0x12]);
+        assert_eq!(read_u8(&image[..], 0x0700), 0xFE);
+        assert_eq!(read_s8(&image[..], 0x0700), -2);
+        assert_eq!(read_s8(&image[..], 0x0701), 0x34);
+        assert_eq!(read_u16(&image[..], 0x0701, false), 0x1234);
+        assert_eq!(read_u16(&image[..], 0x0701, true), 0x3412);
+    }
+
+    #[test]
+    fn wrap_around() {
+        let image = fixture(0xFFFF, &[0x34, 0x12, 0x56]);
+        assert_eq!(read_u16(&image[..], 0xFFFF, false), 0x1234);
+        assert_eq!(
+            read_bytes(&image[..], 0xFFFE, 4).unwrap(),
+            [0x00, 0x34, 0x12, 0x56]
+        );
+        assert_eq!(read_bcd(&image[..], 0xFFFF, 4).unwrap(), Some(3412));
+        assert_eq!(
+            read_bytes(&image[..], 0x1234, 0x10000).unwrap().len(),
+            0x10000
+        );
+        assert!(read_bytes(&image[..], 0, 0x10001).is_err());
+    }
+
+    #[test]
+    fn bcd() {
+        let image = fixture(0x07DD, &[0x01, 0x23, 0x45, 0x67, 0x89]);
+        // an even count reads whole bytes
+        assert_eq!(read_bcd(&image[..], 0x07DD, 2).unwrap(), Some(1));
+        assert_eq!(read_bcd(&image[..], 0x07DD, 6).unwrap(), Some(12345));
+        assert_eq!(read_bcd(&image[..], 0x07DD, 10).unwrap(), Some(123456789));
+        // an odd one skips the first high nibble
+        assert_eq!(read_bcd(&image[..], 0x07DE, 1).unwrap(), Some(3));
+        assert_eq!(read_bcd(&image[..], 0x07DE, 3).unwrap(), Some(345));
+        assert_eq!(read_bcd(&image[..], 0x07DD, 9).unwrap(), Some(123456789));
+
+        let image = fixture(0, &[0x99; 9]);
+        assert_eq!(
+            read_bcd(&image[..], 0, 18).unwrap(),
+            Some(999_999_999_999_999_999)
+        );
+    }
+
+    #[test]
+    fn bcd_above_9() {
+        let image = fixture(0x0100, &[0x12, 0x3A, 0xF1]);
+        assert_eq!(read_bcd(&image[..], 0x0100, 4).unwrap(), None);
+        assert_eq!(read_bcd(&image[..], 0x0101, 2).unwrap(), None);
+        // the skipped high nibble of an odd count does not count
+        assert_eq!(read_bcd(&image[..], 0x0102, 1).unwrap(), Some(1));
+        assert_eq!(read_bcd(&image[..], 0x0102, 2).unwrap(), None);
+    }
+
+    #[test]
+    fn bcd_digits() {
+        let image = fixture(0, &[]);
+        for digits in [0, 19, 64] {
+            let error = read_bcd(&image[..], 0, digits).unwrap_err();
+            assert!(error.to_string().contains("expected 1 to 18"), "{}", error);
+        }
+    }
+}
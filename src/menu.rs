@@ -0,0 +1,73 @@
+use femtovg::{renderer::OpenGl, Canvas, Color, FontId, Paint, Path};
+use winit::event::VirtualKeyCode;
+
+use crate::overlay;
+
+#[derive(Clone, Copy)]
+pub enum Action {
+    Resume,
+    Restart,
+    Fullscreen,
+    SaveState,
+    LoadState,
+    Screenshot,
+    Quit,
+}
+
+const ENTRIES: &[(&str, Action)] = &[
+    ("Resume", Action::Resume),
+    ("Restart script", Action::Restart),
+    ("Toggle fullscreen", Action::Fullscreen),
+    ("Save state", Action::SaveState),
+    ("Load state", Action::LoadState),
+    ("Screenshot", Action::Screenshot),
+    ("Quit", Action::Quit),
+];
+
+pub const TITLE: &str = "Paused";
+pub const PANEL_WIDTH: f32 = 220.0;
+
+// The pause menu opened with Escape.
+#[derive(Default)]
+pub struct Menu {
+    selected: usize,
+}
+
+impl Menu {
+    // the chosen action once Enter is pressed; Escape resumes
+    pub fn key(&mut self, key: VirtualKeyCode) -> Option<Action> {
+        match key {
+            VirtualKeyCode::Up => {
+                self.selected = (self.selected + ENTRIES.len() - 1) % ENTRIES.len()
+            }
+            VirtualKeyCode::Down => self.selected = (self.selected + 1) % ENTRIES.len(),
+            VirtualKeyCode::Return => return Some(ENTRIES[self.selected].1),
+            VirtualKeyCode::Escape => return Some(Action::Resume),
+            _ => {}
+        }
+        None
+    }
+
+    // the lines to show and which one is selected, for any renderer
+    pub fn entries(&self) -> (Vec<String>, usize) {
+        let entries = ENTRIES.iter().map(|(name, _)| name.to_string()).collect();
+        (entries, self.selected)
+    }
+
+    // over a dimmed game frame
+    pub fn draw(&self, canvas: &mut Canvas<OpenGl>, font: FontId) {
+        let (width, height) = (canvas.width() as f32, canvas.height() as f32);
+        let mut dim = Path::new();
+        dim.rect(0.0, 0.0, width, height);
+        canvas.fill_path(&mut dim, &Paint::color(Color::rgba(0, 0, 0, 160)));
+
+        let (w, h) = (PANEL_WIDTH, 48.0 + ENTRIES.len() as f32 * 22.0);
+        let (x, y) = ((width - w) / 2.0, (height - h) / 2.0);
+        let mut panel = Path::new();
+        panel.rounded_rect(x, y, w, h, 4.0);
+        canvas.fill_path(&mut panel, &Paint::color(Color::rgb(24, 24, 32)));
+
+        let (entries, selected) = self.entries();
+        overlay::list(canvas, font, (x, y, w), TITLE, &entries, selected);
+    }
+}
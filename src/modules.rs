@@ -0,0 +1,187 @@
+use std::{
+    fs,
+    path::{Path, PathBuf},
+};
+
+use rlua::{prelude::LuaError, Context, Table, Value};
+
+use crate::pinning::ScriptHashes;
+
+const LOADED: &str = "marlua.loaded";
+
+// modules shipped with marlua, used when no file of that name is found
+const BUILTIN: &[(&str, &str)] = &[("smb", include_str!("lua/smb.lua"))];
+
+// Installs `require`, which only loads Lua files from the given root
+// directories. "a.b" resolves to a/b.lua or a/b/init.lua inside a root;
+// compiled modules are not supported. Files take precedence over the
+// built-in modules, so a script can ship its own copy of one. Every module
+// loaded is added to `hashes`.
+pub fn install(ctx: Context, roots: Vec<PathBuf>, hashes: ScriptHashes) -> Result<(), LuaError> {
+    ctx.set_named_registry_value(LOADED, ctx.create_table()?)?;
+
+    let require = ctx.create_function(move |ctx, name: String| {
+        let loaded: Table = ctx.named_registry_value(LOADED)?;
+        let cached: Value = loaded.get(name.as_str())?;
+        if !matches!(cached, Value::Nil) {
+            return Ok(cached);
+        }
+
+        let (source, chunk_name) = match find(&roots, &name) {
+            Ok(path) => {
+                let source = fs::read(&path).map_err(|e| {
+                    LuaError::RuntimeError(format!("could not read {}: {}", path.display(), e))
+                })?;
+                // @ makes Lua report errors as path:line
+                (source, format!("@{}", path.display()))
+            }
+            Err(e) => match BUILTIN.iter().find(|(n, _)| *n == name) {
+                Some((_, source)) => (source.as_bytes().to_vec(), format!("=[builtin {}]", name)),
+                None => return Err(e),
+            },
+        };
+        hashes.module(&name, &source);
+        let chunk = ctx.load(&source).set_name(&chunk_name)?.into_function()?;
+
+        let value = match chunk.call::<_, Value>(name.as_str())? {
+            Value::Nil => Value::Boolean(true),
+            value => value,
+        };
+        loaded.set(name.as_str(), value.clone())?;
+        Ok(value)
+    })?;
+    ctx.globals().set("require", require)?;
+    Ok(())
+}
+
+fn find(roots: &[PathBuf], name: &str) -> Result<PathBuf, LuaError> {
+    let valid = |part: &str| {
+        !part.is_empty()
+            && part
+                .chars()
+                .all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '-')
+    };
+    if !name.split('.').all(valid) {
+        return Err(LuaError::RuntimeError(format!(
+            "invalid module name '{}', expected names like lib.smb",
+            name
+        )));
+    }
+
+    let relative: PathBuf = name.split('.').collect();
+    let mut tried = Vec::new();
+    for root in roots {
+        for candidate in [
+            root.join(&relative).with_extension("lua"),
+            root.join(&relative).join("init.lua"),
+        ] {
+            if inside(root, &candidate) {
+                return Ok(candidate);
+            }
+            tried.push(candidate);
+        }
+    }
+
+    let tried: Vec<_> = tried
+        .iter()
+        .map(|path| format!("\n\tno file '{}'", path.display()))
+        .collect();
+    Err(LuaError::RuntimeError(format!(
+        "module '{}' not found:{}",
+        name,
+        tried.concat()
+    )))
+}
+
+// whether `path` is an existing file that, with symlinks resolved, does
+// not escape `root`
+fn inside(root: &Path, path: &Path) -> bool {
+    match (fs::canonicalize(root), fs::canonicalize(path)) {
+        (Ok(root), Ok(path)) => path.is_file() && path.starts_with(root),
+        _ => false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rlua::Lua;
+
+    use super::*;
+    use crate::testing;
+
+    // what require(name) returned, as a string, or the error
+    fn require(roots: &[PathBuf], name: &str) -> Result<String, String> {
+        let lua = Lua::new();
+        lua.context(|ctx| {
+            install(ctx, roots.to_vec(), ScriptHashes::new(&[])).unwrap();
+            ctx.globals().set("name", name).unwrap();
+            ctx.load("return tostring(require(name))")
+                .eval::<String>()
+                .map_err(|e| e.to_string())
+        })
+    }
+
+    #[test]
+    fn helper() {
+        let root = testing::temp_dir("modules-helper");
+        fs::create_dir_all(root.join("lib")).unwrap();
+        fs::create_dir_all(root.join("pkg")).unwrap();
+        fs::write(root.join("lib/helper.lua"), "return 'helper'").unwrap();
+        fs::write(root.join("pkg/init.lua"), "return 'init'").unwrap();
+        fs::write(root.join("counted.lua"), "n = (n or 0) + 1 return n").unwrap();
+
+        let roots = [root.join("missing"), root.clone()];
+        assert_eq!(require(&roots, "lib.helper"), Ok("helper".to_owned()));
+        assert_eq!(require(&roots, "pkg"), Ok("init".to_owned()));
+
+        // loaded once
+        let lua = Lua::new();
+        lua.context(|ctx| {
+            install(ctx, roots.to_vec(), ScriptHashes::new(&[])).unwrap();
+            let twice = ctx.load("require('counted') return require('counted')");
+            assert_eq!(twice.eval::<i64>().unwrap(), 1);
+        });
+
+        let error = require(&roots, "lib.other").unwrap_err();
+        assert!(error.contains("module 'lib.other' not found"), "{}", error);
+    }
+
+    #[test]
+    fn traversal() {
+        let dir = testing::temp_dir("modules-traversal");
+        let root = dir.join("root");
+        fs::create_dir_all(&root).unwrap();
+        fs::write(dir.join("x.lua"), "return 'outside'").unwrap();
+
+        let roots = [root.clone()];
+        let outside = dir.join("x").display().to_string();
+        for name in ["../x", "..x", ".x", "x.", "/x", &outside, "a/../../x"] {
+            let error = require(&roots, name).unwrap_err();
+            assert!(error.contains("invalid module name"), "{}: {}", name, error);
+        }
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn symlinks() {
+        use std::os::unix::fs::symlink;
+
+        let dir = testing::temp_dir("modules-symlinks");
+        let root = dir.join("root");
+        fs::create_dir_all(root.join("lib")).unwrap();
+        fs::create_dir_all(dir.join("outside")).unwrap();
+        fs::write(dir.join("outside/secret.lua"), "return 'outside'").unwrap();
+        fs::write(root.join("lib/inner.lua"), "return 'inner'").unwrap();
+        symlink(dir.join("outside/secret.lua"), root.join("escape.lua")).unwrap();
+        symlink(dir.join("outside"), root.join("linked")).unwrap();
+        symlink(root.join("lib/inner.lua"), root.join("alias.lua")).unwrap();
+
+        let roots = [root.clone()];
+        for name in ["escape", "linked.secret"] {
+            let error = require(&roots, name).unwrap_err();
+            assert!(error.contains("not found"), "{}: {}", name, error);
+        }
+        // a link that stays inside the root is fine
+        assert_eq!(require(&roots, "alias"), Ok("inner".to_owned()));
+    }
+}
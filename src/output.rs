@@ -0,0 +1,142 @@
+use std::{
+    fs, io,
+    path::{Component, Path, PathBuf},
+    time::SystemTime,
+};
+
+use rlua::prelude::LuaError;
+use tracing::{debug, warn};
+
+use crate::time::DateTime;
+
+pub const BASE: &str = "output";
+
+// Where marlua writes files.
+//
+// Everything lives under output/<rom-name>/. Files that should outlive the
+// run (savestates, checkpoints) go in that ROM directory; everything else
+// goes in a fresh output/<rom-name>/<timestamp>/ directory per run, which
+// output/<rom-name>/latest points to.
+pub struct OutputDir {
+    rom: PathBuf,
+    run: PathBuf,
+}
+
+impl OutputDir {
+    // `run` overrides the per-run directory
+    pub fn create(rom_path: &str, run: Option<&str>) -> io::Result<Self> {
+        let name = Path::new(rom_path)
+            .file_stem()
+            .and_then(|s| s.to_str())
+            .map(sanitize_name)
+            .unwrap_or_else(|| "rom".to_owned());
+        let rom = Path::new(BASE).join(name);
+
+        let run = match run {
+            Some(run) => PathBuf::from(run),
+            None => {
+                let now = DateTime::utc(SystemTime::now());
+                collision_free(rom.join(now.format("%Y%m%d-%H%M%S").unwrap()))
+            }
+        };
+        fs::create_dir_all(rom.join("states"))?;
+        fs::create_dir_all(&run)?;
+        debug!("writing output to {}", run.display());
+
+        link_latest(&rom, &run);
+        Ok(Self { rom, run })
+    }
+
+    pub fn run_dir(&self) -> &Path {
+        &self.run
+    }
+
+    // a savestate path inside output/<rom-name>/states
+    pub fn state(&self, relative: &str) -> Result<PathBuf, LuaError> {
+        Ok(self.rom.join("states").join(checked(relative)?))
+    }
+
+    // recorded macros, kept across runs like savestates
+    pub fn macros(&self) -> PathBuf {
+        self.rom.join("macros")
+    }
+
+    // the counters of practice.loop for a state, see practice.rs
+    pub fn practice(&self, state: &str) -> PathBuf {
+        let name = format!("{}.txt", sanitize_name(state));
+        self.rom.join("practice").join(name)
+    }
+
+    // a new file in the run directory; existing files are never
+    // overwritten, instead -1, -2, ... is appended to the file name
+    pub fn artifact(&self, relative: &str) -> Result<PathBuf, LuaError> {
+        artifact(&self.run, relative)
+    }
+}
+
+// OutputDir::artifact for a run directory kept elsewhere
+pub fn artifact(run: &Path, relative: &str) -> Result<PathBuf, LuaError> {
+    let path = collision_free(run.join(checked(relative)?));
+    if let Some(dir) = path.parent() {
+        fs::create_dir_all(dir).map_err(LuaError::external)?;
+    }
+    Ok(path)
+}
+
+// only allow plain relative paths that stay inside their directory
+fn checked(relative: &str) -> Result<&Path, LuaError> {
+    let path = Path::new(relative);
+    let plain = path
+        .components()
+        .all(|c| matches!(c, Component::Normal(_) | Component::CurDir));
+    if !plain || path.as_os_str().is_empty() {
+        return Err(LuaError::RuntimeError(format!(
+            "output path {} must be relative and may not contain ..",
+            relative
+        )));
+    }
+    Ok(path)
+}
+
+pub fn sanitize_name(name: &str) -> String {
+    name.chars()
+        .map(|c| match c {
+            'a'..='z' | 'A'..='Z' | '0'..='9' | '-' | '_' | '.' => c,
+            _ => '_',
+        })
+        .collect()
+}
+
+fn collision_free(path: PathBuf) -> PathBuf {
+    if !path.exists() {
+        return path;
+    }
+
+    let stem = path.file_stem().unwrap_or_default().to_string_lossy();
+    let extension = path
+        .extension()
+        .map(|e| format!(".{}", e.to_string_lossy()))
+        .unwrap_or_default();
+    (1..)
+        .map(|n| path.with_file_name(format!("{}-{}{}", stem, n, extension)))
+        .find(|candidate| !candidate.exists())
+        .unwrap()
+}
+
+fn link_latest(rom: &Path, run: &Path) {
+    let latest = rom.join("latest");
+    let _ = fs::remove_file(&latest);
+
+    #[cfg(unix)]
+    let result = match fs::canonicalize(run) {
+        Ok(target) => std::os::unix::fs::symlink(target, &latest),
+        Err(e) => Err(e),
+    };
+    // no unprivileged symlinks elsewhere, write the path instead
+    #[cfg(not(unix))]
+    let result = fs::write(&latest, run.to_string_lossy().as_bytes());
+
+    if let Err(e) = result {
+        warn!("could not update {}: {}", latest.display(), e);
+    }
+}
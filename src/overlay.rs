@@ -0,0 +1,422 @@
+use std::{
+    collections::VecDeque,
+    time::{Duration, Instant},
+};
+
+use femtovg::{renderer::OpenGl, Canvas, Color, FontId, Paint, Path};
+
+use crate::{
+    frametimes::FrameTimes,
+    layout::{Anchor, Element, Layout, Style},
+    subtitles,
+};
+
+pub const FONT: &[u8] = include_bytes!("../assets/DejaVuSansMono.ttf");
+
+// how long a toast stays on screen unless it says otherwise
+pub const TOAST_TIME: Duration = Duration::from_secs(3);
+// the last part of it, where it fades out
+const FADE_TIME: Duration = Duration::from_millis(500);
+// more than this drops the oldest
+const MAX_TOASTS: usize = 4;
+const TOAST_HEIGHT: f32 = 26.0;
+const BANNER_HEIGHT: f32 = 22.0;
+const FONT_SIZE: f32 = 14.0;
+// DejaVu Sans Mono at 14 pixels, for wrapping subtitles and laying out
+const CHAR_WIDTH: f32 = 8.5;
+const LINE_HEIGHT: f32 = 18.0;
+// the playback bar along the edge it is anchored to
+const PROGRESS_HEIGHT: f32 = 4.0;
+
+// Text drawn by the window on top of the game.
+#[derive(Default)]
+pub struct Overlay {
+    // oldest first, see Overlay::toast
+    toasts: VecDeque<Toast>,
+    failure: Option<String>,
+    // the savestate slot last used, shown in a corner
+    slot: Option<u8>,
+    // the text and its wrapped lines, see subtitles.rs
+    subtitle: Option<(String, Vec<String>)>,
+    // while a journal plays: the frame, the number of frames and whether
+    // it is paused
+    progress: Option<(usize, usize, bool)>,
+    // where the above go, see layout.rs
+    layout: Layout,
+}
+
+// What Overlay::items lays out, for the backends to draw in order.
+pub enum Item {
+    Rect {
+        x: f32,
+        y: f32,
+        w: f32,
+        h: f32,
+        color: [u8; 4],
+        // of the corners, where the backend can
+        radius: f32,
+    },
+    // at the left end of its baseline, outlined in black for subtitles
+    Text {
+        x: f32,
+        baseline: f32,
+        text: String,
+        size: f32,
+        color: [u8; 4],
+        outline: bool,
+    },
+}
+
+impl Overlay {
+    // show a short message for `time`, next to the ones still showing
+    pub fn toast(&mut self, text: impl Into<String>, time: Duration) {
+        if self.toasts.len() == MAX_TOASTS {
+            self.toasts.pop_front();
+        }
+        self.toasts.push_back(Toast {
+            text: text.into(),
+            shown: Instant::now(),
+            time,
+        });
+    }
+
+    // show an error over the game until the window is closed
+    pub fn fail(&mut self, text: impl Into<String>) {
+        self.failure = Some(text.into());
+    }
+
+    pub fn set_layout(&mut self, layout: Layout) {
+        self.layout = layout;
+        // wrapped again at the subtitle's scale
+        self.subtitle = None;
+    }
+
+    // wrapped to a window `width` pixels wide, only again when it changes
+    pub fn set_subtitle(&mut self, text: Option<String>, width: f32) {
+        let Some(text) = text else {
+            self.subtitle = None;
+            return;
+        };
+        if self.subtitle.as_ref().map(|(shown, _)| shown) != Some(&text) {
+            let scale = self.layout.style(Element::Subtitle).scale;
+            let columns = ((width - 24.0) / (CHAR_WIDTH * scale)) as usize;
+            let lines = subtitles::wrap(&text, columns);
+            self.subtitle = Some((text, lines));
+        }
+    }
+
+    pub fn failure(&self) -> Option<&str> {
+        self.failure.as_deref()
+    }
+
+    pub fn set_slot(&mut self, slot: Option<u8>) {
+        self.slot = slot;
+    }
+
+    pub fn set_progress(&mut self, progress: Option<(usize, usize)>, paused: bool) {
+        self.progress = progress.map(|(frame, total)| (frame, total, paused));
+    }
+
+    // the playback bar's label, e.g. "0:12 / 1:30 (frame 720 of 5400)"
+    fn progress_label(&self) -> Option<String> {
+        let (frame, total, paused) = self.progress?;
+        let time = |frame: usize| {
+            let seconds = (frame as f64 / crate::FPS) as usize;
+            format!("{}:{:02}", seconds / 60, seconds % 60)
+        };
+        let mut label = format!(
+            "{} / {} (frame {} of {})",
+            time(frame),
+            time(total),
+            frame,
+            total
+        );
+        if paused {
+            label.push_str(", paused");
+        }
+        Some(label)
+    }
+
+    // the toasts that have not expired yet, newest first, with their
+    // opacity from 0 to 1
+    fn toasts(&mut self) -> Vec<(String, f32)> {
+        self.toasts
+            .retain(|toast| toast.shown.elapsed() < toast.time);
+        self.toasts
+            .iter()
+            .rev()
+            .map(|toast| {
+                let left = toast.time.saturating_sub(toast.shown.elapsed());
+                let alpha = (left.as_secs_f32() / FADE_TIME.as_secs_f32()).min(1.0);
+                (toast.text.clone(), alpha)
+            })
+            .collect()
+    }
+
+    // the size of each element that has something to show, see Layout::place
+    fn sizes(
+        &self,
+        toasts: &[(String, f32)],
+        label: Option<&str>,
+        slot_text: Option<&str>,
+        frame_times: Option<(f32, f32)>,
+    ) -> [Option<(f32, f32)>; 5] {
+        let scale = |element| self.layout.style(element).scale;
+        let lines = self.subtitle.as_ref().map_or(&[][..], |(_, lines)| lines);
+        [
+            label.map(|label| {
+                let s = scale(Element::Progress);
+                let (w, h) = banner_size(label, s);
+                (w, PROGRESS_HEIGHT * s + 4.0 * s + h)
+            }),
+            (!lines.is_empty()).then(|| {
+                let s = scale(Element::Subtitle);
+                let w = lines.iter().map(|l| text_width(l, s)).fold(0.0, f32::max);
+                (w, lines.len() as f32 * LINE_HEIGHT * s)
+            }),
+            slot_text.map(|text| banner_size(text, scale(Element::Slot))),
+            (!toasts.is_empty()).then(|| {
+                let s = scale(Element::Toasts);
+                let w = toasts
+                    .iter()
+                    .map(|(text, _)| banner_size(text, s).0)
+                    .fold(0.0, f32::max);
+                (
+                    w,
+                    BANNER_HEIGHT * s + (toasts.len() - 1) as f32 * TOAST_HEIGHT * s,
+                )
+            }),
+            frame_times,
+        ]
+    }
+
+    // The top left corner of the frame time graph in a window of `size`,
+    // stacked beyond the other overlays, or None when the layout disables
+    // it. It comes last in ELEMENTS, so the others are placed the same with
+    // or without it.
+    pub fn frame_times(&mut self, times: &FrameTimes, size: (f32, f32)) -> Option<(f32, f32)> {
+        let toasts = self.toasts();
+        let label = self.progress_label();
+        let slot_text = self.slot.map(|slot| format!("slot {}", slot));
+        let sizes = self.sizes(
+            &toasts,
+            label.as_deref(),
+            slot_text.as_deref(),
+            Some(times.size()),
+        );
+        self.layout.place(sizes, size)[4]
+    }
+
+    // the slot, the subtitle, the toasts and the playback bar laid out in a
+    // window of `width` by `height`, without the failure and the frame time
+    // graph
+    pub fn items(&mut self, width: f32, height: f32) -> Vec<Item> {
+        let style = |element| *self.layout.style(element);
+        let (progress, subtitle, slot) = (
+            style(Element::Progress),
+            style(Element::Subtitle),
+            style(Element::Slot),
+        );
+        let toast_style = style(Element::Toasts);
+
+        let toasts = self.toasts();
+        let label = self.progress_label();
+        let lines = self.subtitle.as_ref().map_or(&[][..], |(_, lines)| lines);
+        let slot_text = self.slot.map(|slot| format!("slot {}", slot));
+
+        let sizes = self.sizes(&toasts, label.as_deref(), slot_text.as_deref(), None);
+        let placed = self.layout.place(sizes, (width, height));
+
+        let mut items = Vec::new();
+        if let (Some((x, y)), Some(text)) = (placed[2], &slot_text) {
+            banner(&mut items, x, y, text, &slot, 1.0);
+        }
+        if let (Some((x, y)), Some((w, _))) = (placed[1], sizes[1]) {
+            let s = subtitle.scale;
+            for (i, line) in lines.iter().enumerate() {
+                let x = x + align(subtitle.anchor, w, text_width(line, s));
+                let baseline = y + (i + 1) as f32 * LINE_HEIGHT * s - 4.0 * s;
+                items.push(Item::Text {
+                    x,
+                    baseline,
+                    text: line.clone(),
+                    size: FONT_SIZE * s,
+                    color: subtitle.color,
+                    outline: true,
+                });
+            }
+        }
+        if let (Some((x, y)), Some((w, h))) = (placed[3], sizes[3]) {
+            let s = toast_style.scale;
+            let top = toast_style.anchor.top();
+            for (i, (text, alpha)) in toasts.iter().enumerate() {
+                // the newest is closest to the edge
+                let row = i as f32 * TOAST_HEIGHT * s;
+                let y = match top {
+                    true => y + row,
+                    false => y + h - BANNER_HEIGHT * s - row,
+                };
+                let x = x + align(toast_style.anchor, w, banner_size(text, s).0);
+                banner(&mut items, x, y, text, &toast_style, *alpha);
+            }
+        }
+        if let (Some((x, y)), Some(label), Some((_, h))) = (placed[0], &label, sizes[0]) {
+            let (frame, total, _) = self.progress.unwrap_or_default();
+            let s = progress.scale;
+            let bar = PROGRESS_HEIGHT * s;
+            let filled = width * frame.min(total) as f32 / total.max(1) as f32;
+            // the bar along the edge, the label inside it
+            let (bar_y, label_y) = match progress.anchor.top() {
+                true => (y, y + bar + 4.0 * s),
+                false => (y + h - bar, y),
+            };
+            let track = progress.background;
+            items.push(rect(0.0, bar_y, width, bar, track, 0.0));
+            items.push(rect(0.0, bar_y, filled, bar, progress.color, 0.0));
+            let label_style = Style {
+                color: [255; 4],
+                ..progress
+            };
+            banner(&mut items, x, label_y, label, &label_style, 1.0);
+        }
+        items
+    }
+
+    pub fn draw(&mut self, canvas: &mut Canvas<OpenGl>, font: FontId) {
+        if let Some(text) = &self.failure {
+            let mut path = Path::new();
+            path.rect(0.0, 0.0, canvas.width() as f32, canvas.height() as f32);
+            canvas.fill_path(&mut path, &Paint::color(Color::rgba(96, 0, 0, 200)));
+
+            let mut paint = Paint::color(Color::white());
+            paint.set_font(&[font]);
+            paint.set_font_size(14.0);
+            let _ = canvas.fill_text(12.0, 28.0, "the emulator stopped:", &paint);
+            for (i, line) in text.lines().enumerate() {
+                let _ = canvas.fill_text(12.0, 56.0 + i as f32 * 18.0, line, &paint);
+            }
+        }
+
+        let (width, height) = (canvas.width() as f32, canvas.height() as f32);
+        let rgba = |[r, g, b, a]: [u8; 4]| Color::rgba(r, g, b, a);
+        for item in self.items(width, height) {
+            match item {
+                Item::Rect {
+                    x,
+                    y,
+                    w,
+                    h,
+                    color,
+                    radius,
+                } => {
+                    let mut path = Path::new();
+                    path.rounded_rect(x, y, w, h, radius);
+                    canvas.fill_path(&mut path, &Paint::color(rgba(color)));
+                }
+                Item::Text {
+                    x,
+                    baseline,
+                    text,
+                    size,
+                    color,
+                    outline,
+                } => {
+                    let mut paint = Paint::color(rgba(color));
+                    paint.set_font(&[font]);
+                    paint.set_font_size(size);
+                    // an outline keeps it readable on any background
+                    if outline {
+                        let mut shadow = paint.clone();
+                        shadow.set_color(Color::rgba(0, 0, 0, color[3]));
+                        for (dx, dy) in OUTLINE {
+                            let _ = canvas.fill_text(x + dx, baseline + dy, &text, &shadow);
+                        }
+                    }
+                    let _ = canvas.fill_text(x, baseline, &text, &paint);
+                }
+            }
+        }
+    }
+}
+
+// the offsets the outline of outlined text is drawn at
+pub const OUTLINE: [(f32, f32); 4] = [(-1.0, 0.0), (1.0, 0.0), (0.0, -1.0), (0.0, 1.0)];
+
+struct Toast {
+    text: String,
+    shown: Instant,
+    time: Duration,
+}
+
+// the monospace font makes this a multiplication
+fn text_width(text: &str, scale: f32) -> f32 {
+    text.chars().count() as f32 * CHAR_WIDTH * scale
+}
+
+fn banner_size(text: &str, scale: f32) -> (f32, f32) {
+    (
+        text_width(text, scale) + 12.0 * scale,
+        BANNER_HEIGHT * scale,
+    )
+}
+
+// where something `w` wide goes in a column `column` wide
+fn align(anchor: Anchor, column: f32, w: f32) -> f32 {
+    match anchor.side() {
+        -1 => 0.0,
+        0 => (column - w) / 2.0,
+        _ => column - w,
+    }
+}
+
+fn rect(x: f32, y: f32, w: f32, h: f32, color: [u8; 4], radius: f32) -> Item {
+    Item::Rect {
+        x,
+        y,
+        w,
+        h,
+        color,
+        radius,
+    }
+}
+
+// text in a box at `alpha` of the style's colors
+fn banner(items: &mut Vec<Item>, x: f32, y: f32, text: &str, style: &Style, alpha: f32) {
+    let s = style.scale;
+    let fade = |[r, g, b, a]: [u8; 4]| [r, g, b, (a as f32 * alpha) as u8];
+    let (w, h) = banner_size(text, s);
+    items.push(rect(x, y, w, h, fade(style.background), 3.0 * s));
+    items.push(Item::Text {
+        x: x + 6.0 * s,
+        baseline: y + 16.0 * s,
+        text: text.to_owned(),
+        size: FONT_SIZE * s,
+        color: fade(style.color),
+        outline: false,
+    });
+}
+
+// a title with a list of entries below it, the selected one highlighted
+pub fn list(
+    canvas: &mut Canvas<OpenGl>,
+    font: FontId,
+    (x, y, width): (f32, f32, f32),
+    title: &str,
+    entries: &[String],
+    selected: usize,
+) {
+    let mut paint = Paint::color(Color::white());
+    paint.set_font(&[font]);
+    paint.set_font_size(14.0);
+
+    let _ = canvas.fill_text(x + 8.0, y + 20.0, title, &paint);
+    for (i, entry) in entries.iter().enumerate() {
+        let row = y + 48.0 + i as f32 * 22.0;
+        if i == selected {
+            let mut highlight = Path::new();
+            highlight.rect(x, row - 16.0, width, 22.0);
+            canvas.fill_path(&mut highlight, &Paint::color(Color::rgb(60, 60, 96)));
+        }
+        let _ = canvas.fill_text(x + 8.0, row, entry, &paint);
+    }
+}
@@ -0,0 +1,389 @@
+use fastnes::ppu::Color;
+use tracing::warn;
+use winit::{
+    dpi::PhysicalSize,
+    event::{ElementState, KeyboardInput, MouseButton, WindowEvent},
+    event_loop::EventLoopWindowTarget,
+    window::{Window, WindowBuilder, WindowId},
+};
+
+use crate::{
+    bindings::{Bindings, Hotkey},
+    cli::Vsync,
+    frametimes::FrameTimes,
+    gl::Gl,
+    hexview::HexViewer,
+    letterbox::Scene,
+    overlay::Overlay,
+    render::{Overlays, Present},
+    sprites::SpriteViewer,
+    tiles::TileViewer,
+    views::{View, Views},
+    IMAGE_RECT,
+};
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Panel {
+    Tiles,
+    Sprites,
+    Memory,
+    FrameTimes,
+}
+
+// window.detach names, the same as their hotkeys
+const NAMES: &[(&str, Panel)] = &[
+    ("tiles", Panel::Tiles),
+    ("sprites", Panel::Sprites),
+    ("memory", Panel::Memory),
+    ("frame_times", Panel::FrameTimes),
+];
+
+impl Panel {
+    pub fn parse(name: &str) -> Result<Self, String> {
+        match NAMES.iter().find(|(n, _)| *n == name) {
+            Some((_, panel)) => Ok(*panel),
+            None => {
+                let valid: Vec<_> = NAMES.iter().map(|(n, _)| *n).collect();
+                Err(format!(
+                    "unknown panel {}, expected one of {}",
+                    name,
+                    valid.join(", ")
+                ))
+            }
+        }
+    }
+
+    fn name(self) -> &'static str {
+        NAMES.iter().find(|(_, p)| *p == self).unwrap().0
+    }
+
+    pub fn hotkey(self) -> Hotkey {
+        match self {
+            Panel::Tiles => Hotkey::Tiles,
+            Panel::Sprites => Hotkey::Sprites,
+            Panel::Memory => Hotkey::Memory,
+            Panel::FrameTimes => Hotkey::FrameTimes,
+        }
+    }
+
+    // what the emulator has to publish for it
+    fn view(self) -> Option<View> {
+        match self {
+            Panel::Tiles => Some(View::Tiles),
+            Panel::Sprites => Some(View::Sprites),
+            Panel::Memory => Some(View::Memory),
+            Panel::FrameTimes => None,
+        }
+    }
+}
+
+// the panels the game window has room for one of at a time
+const VIEWS: [Panel; 3] = [Panel::Tiles, Panel::Sprites, Panel::Memory];
+
+// A panel in a window of its own, drawn with OpenGL whatever the game
+// window uses.
+struct ToolWindow {
+    panel: Panel,
+    // dropped before the window it draws to
+    backend: Gl,
+    window: Window,
+    overlay: Overlay,
+    cursor: Option<(f32, f32)>,
+}
+
+impl ToolWindow {
+    fn open(
+        target: &EventLoopWindowTarget<()>,
+        panel: Panel,
+        (width, height): (u32, u32),
+    ) -> Result<Self, String> {
+        let builder = WindowBuilder::new()
+            .with_title(format!("Marlua {}", panel.name()))
+            .with_inner_size(PhysicalSize::new(width, height))
+            .with_resizable(false);
+        // presenting waits for the monitor in the game window already, if it
+        // did here too every window would take a refresh of its own
+        let (window, backend) =
+            Gl::new(target, builder, width, height, Some(Vsync::Off)).map_err(|e| e.to_string())?;
+        Ok(Self {
+            panel,
+            backend,
+            window,
+            overlay: Overlay::default(),
+            cursor: None,
+        })
+    }
+
+    fn height(&self) -> f32 {
+        self.window.inner_size().height as f32
+    }
+
+    // the cursor in NES pixels, like InputState does for the game window
+    fn nes_cursor(&self) -> Option<(u32, u32)> {
+        let (x, y, width, height) = IMAGE_RECT;
+        let (px, py) = self.cursor?;
+        let nx = (px - x) * 256.0 / width;
+        let ny = (py - y) * 240.0 / height;
+        ((0.0..256.0).contains(&nx) && (0.0..240.0).contains(&ny)).then_some((nx as u32, ny as u32))
+    }
+}
+
+// The debug panels: the pattern tables, the sprite list, the hex viewer and
+// the frame time graph. Each is shown in the game window, or in a window of
+// its own once detached with window.detach or the detach hotkey. Closing
+// such a window only hides the panel, it opens there again next time.
+pub struct Panels {
+    pub tiles: Option<TileViewer>,
+    pub sprites: Option<SpriteViewer>,
+    pub memory: Option<HexViewer>,
+    pub frame_times: bool,
+    detached: Vec<Panel>,
+    windows: Vec<ToolWindow>,
+    // what the panel windows other than the sprite list draw over
+    blank: Vec<Color>,
+    // of the game window, which the panels are laid out for
+    size: (u32, u32),
+}
+
+impl Panels {
+    pub fn new(detached: Vec<Panel>, size: (u32, u32)) -> Self {
+        Self {
+            tiles: None,
+            sprites: None,
+            memory: None,
+            frame_times: false,
+            detached,
+            windows: Vec::new(),
+            blank: vec![
+                Color {
+                    r: 0,
+                    g: 0,
+                    b: 0,
+                    a: 255,
+                };
+                61440
+            ],
+            size,
+        }
+    }
+
+    fn is_open(&self, panel: Panel) -> bool {
+        match panel {
+            Panel::Tiles => self.tiles.is_some(),
+            Panel::Sprites => self.sprites.is_some(),
+            Panel::Memory => self.memory.is_some(),
+            Panel::FrameTimes => self.frame_times,
+        }
+    }
+
+    fn set_open(&mut self, panel: Panel, open: bool) {
+        match panel {
+            Panel::Tiles => self.tiles = open.then(TileViewer::new),
+            Panel::Sprites => self.sprites = open.then(SpriteViewer::new),
+            Panel::Memory => self.memory = open.then(HexViewer::new),
+            Panel::FrameTimes => self.frame_times = open,
+        }
+    }
+
+    // whether `panel` is open and drawn in the game window
+    pub fn in_main(&self, panel: Panel) -> bool {
+        self.is_open(panel) && !self.detached.contains(&panel)
+    }
+
+    // what the emulator publishes for the open panels
+    pub fn views(&self) -> Vec<View> {
+        VIEWS
+            .iter()
+            .filter(|&&panel| self.is_open(panel))
+            .filter_map(|panel| panel.view())
+            .collect()
+    }
+
+    // the panel's hotkey, which closes it when it is open
+    pub fn toggle(&mut self, panel: Panel, target: &EventLoopWindowTarget<()>) {
+        if self.is_open(panel) {
+            self.hide(panel);
+        } else {
+            self.show(panel, target);
+        }
+    }
+
+    fn show(&mut self, panel: Panel, target: &EventLoopWindowTarget<()>) {
+        if self.detached.contains(&panel) {
+            match ToolWindow::open(target, panel, self.size) {
+                Ok(window) => self.windows.push(window),
+                Err(e) => {
+                    warn!(
+                        "could not open a window for {} ({}), showing it in the game window",
+                        panel.name(),
+                        e
+                    );
+                    self.detached.retain(|&p| p != panel);
+                }
+            }
+        }
+        // the game window shows one view at a time
+        if !self.detached.contains(&panel) && VIEWS.contains(&panel) {
+            for other in VIEWS {
+                if other != panel && self.in_main(other) {
+                    self.set_open(other, false);
+                }
+            }
+        }
+        self.set_open(panel, true);
+    }
+
+    fn hide(&mut self, panel: Panel) {
+        self.set_open(panel, false);
+        self.windows.retain(|window| window.panel != panel);
+    }
+
+    // the detach hotkey in the game window, which moves its view (or else
+    // the frame time graph) to a window of its own
+    pub fn detach(&mut self, target: &EventLoopWindowTarget<()>) -> Result<(), String> {
+        let all = VIEWS.iter().chain(&[Panel::FrameTimes]);
+        let Some(&panel) = all.into_iter().find(|&&panel| self.in_main(panel)) else {
+            return Err("no panel is open in the game window to detach".to_owned());
+        };
+        self.set_open(panel, false);
+        self.detached.push(panel);
+        self.show(panel, target);
+        Ok(())
+    }
+
+    // the detach hotkey in a panel's window, which moves it back
+    fn attach(&mut self, panel: Panel, target: &EventLoopWindowTarget<()>) {
+        self.hide(panel);
+        self.detached.retain(|&p| p != panel);
+        self.show(panel, target);
+    }
+
+    // an event of a panel's window, whether the views changed; nothing
+    // happens for other windows
+    pub fn window_event(
+        &mut self,
+        id: WindowId,
+        event: &WindowEvent,
+        bindings: &Bindings,
+        target: &EventLoopWindowTarget<()>,
+    ) -> bool {
+        let Some(i) = self.windows.iter().position(|w| w.window.id() == id) else {
+            return false;
+        };
+        let panel = self.windows[i].panel;
+        match event {
+            WindowEvent::CloseRequested => {
+                self.hide(panel);
+                true
+            }
+            WindowEvent::CursorMoved { position, .. } => {
+                self.windows[i].cursor = Some((position.x as f32, position.y as f32));
+                false
+            }
+            WindowEvent::CursorLeft { .. } => {
+                self.windows[i].cursor = None;
+                false
+            }
+            WindowEvent::MouseInput {
+                state: ElementState::Pressed,
+                button: MouseButton::Left,
+                ..
+            } => {
+                let cursor = self.windows[i].cursor;
+                if let Some(memory) = &mut self.memory {
+                    if let Some(addr) = cursor.and_then(|c| memory.byte_at(c)) {
+                        memory.toggle_watch(addr);
+                    }
+                }
+                false
+            }
+            WindowEvent::KeyboardInput {
+                input:
+                    KeyboardInput {
+                        state: ElementState::Pressed,
+                        virtual_keycode: Some(key),
+                        ..
+                    },
+                ..
+            } => match bindings.get(*key) {
+                Some(Hotkey::Detach) => {
+                    self.attach(panel, target);
+                    true
+                }
+                Some(hotkey) if hotkey == panel.hotkey() => {
+                    self.hide(panel);
+                    true
+                }
+                _ => {
+                    match (panel, &mut self.tiles, &mut self.sprites, &mut self.memory) {
+                        (Panel::Tiles, Some(tiles), _, _) => tiles.key(*key),
+                        (Panel::Sprites, _, Some(sprites), _) => sprites.key(*key),
+                        (Panel::Memory, _, _, Some(memory)) => memory.key(*key),
+                        _ => {}
+                    }
+                    false
+                }
+            },
+            _ => false,
+        }
+    }
+
+    // take what the emulator published; `main_height`, `cursor` and
+    // `image` are the game window's, for the panels shown there
+    pub fn update(
+        &mut self,
+        views: &Views,
+        main_height: f32,
+        cursor: Option<(u32, u32)>,
+        image: (f32, f32, f32, f32),
+    ) {
+        let window = |panel| self.windows.iter().find(|w| w.panel == panel);
+        let sprites_at = window(Panel::Sprites).map_or((main_height, cursor, image), |w| {
+            (w.height(), w.nes_cursor(), IMAGE_RECT)
+        });
+        let memory_height = window(Panel::Memory).map_or(main_height, ToolWindow::height);
+
+        if let Some(tiles) = &mut self.tiles {
+            tiles.update(views);
+        }
+        if let Some(sprites) = &mut self.sprites {
+            sprites.update(views, sprites_at.1, sprites_at.2);
+            sprites.fit(sprites_at.0);
+        }
+        if let Some(memory) = &mut self.memory {
+            memory.update(views);
+            memory.fit(memory_height);
+        }
+    }
+
+    // draw every panel window, the sprite list over the game like in the
+    // game window and the others on their own
+    pub fn present(&mut self, frame: &[Color; 61440], times: Option<&FrameTimes>) {
+        let blank: &[Color; 61440] = self.blank[..].try_into().unwrap();
+        for window in &mut self.windows {
+            let panel = window.panel;
+            let overlays = Overlays {
+                picker: None,
+                menu: None,
+                help: None,
+                tiles: self.tiles.as_ref().filter(|_| panel == Panel::Tiles),
+                sprites: self.sprites.as_ref().filter(|_| panel == Panel::Sprites),
+                memory: self.memory.as_ref().filter(|_| panel == Panel::Memory),
+                frame_times: times.filter(|_| panel == Panel::FrameTimes),
+                error: None,
+                overlay: &mut window.overlay,
+            };
+            let frame = if panel == Panel::Sprites {
+                frame
+            } else {
+                blank
+            };
+            let result = window
+                .backend
+                .present(&window.window, frame, Scene::default(), overlays);
+            if let Err(e) = result {
+                warn!("could not present the {} window: {}", panel.name(), e);
+            }
+        }
+    }
+}
@@ -0,0 +1,99 @@
+use std::{collections::BTreeSet, sync::Mutex};
+
+use crate::buttons;
+
+#[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Reason {
+    FocusLost,
+    Menu,
+    Help,
+    // pause() until resume() or the pause hotkey
+    Script,
+    // the pause hotkey, left one frame at a time with frame_advance
+    Hotkey,
+}
+
+impl Reason {
+    fn describe(self) -> &'static str {
+        match self {
+            Reason::FocusLost => "window unfocused",
+            Reason::Menu => "menu open",
+            Reason::Help => "help open",
+            Reason::Script => "script",
+            Reason::Hotkey => "frame advance",
+        }
+    }
+}
+
+// Why emulation is paused, if it is, and how fast it runs otherwise.
+//
+// The emulator thread sets and clears reasons and stops stepping while any
+// is set; the window shows them in its title so a pause doesn't look like a
+// hang, and the speed so slow motion isn't forgotten. While paused for frame
+// advance it also shows the input the next frame will be stepped with.
+#[derive(Default)]
+pub struct Pause {
+    reasons: Mutex<BTreeSet<Reason>>,
+    // None at normal speed
+    speed: Mutex<Option<f64>>,
+    staged: Mutex<u8>,
+}
+
+impl Pause {
+    pub fn set(&self, reason: Reason, paused: bool) {
+        let mut reasons = self.reasons.lock().unwrap();
+        if paused {
+            reasons.insert(reason);
+        } else {
+            reasons.remove(&reason);
+        }
+    }
+
+    pub fn set_speed(&self, speed: f64) {
+        *self.speed.lock().unwrap() = (speed != 1.0).then_some(speed);
+    }
+
+    pub fn speed(&self) -> f64 {
+        self.speed.lock().unwrap().unwrap_or(1.0)
+    }
+
+    pub fn set_staged(&self, input: u8) {
+        *self.staged.lock().unwrap() = input;
+    }
+
+    pub fn is_set(&self, reason: Reason) -> bool {
+        self.reasons.lock().unwrap().contains(&reason)
+    }
+
+    pub fn is_paused(&self) -> bool {
+        !self.reasons.lock().unwrap().is_empty()
+    }
+
+    // e.g. "paused: window unfocused" or "0.25x", None while running at
+    // normal speed; "paused: frame advance, next: A Right" while stepping by
+    // hand
+    pub fn describe(&self) -> Option<String> {
+        let reasons = self.reasons.lock().unwrap();
+        let speed = *self.speed.lock().unwrap();
+        let speed = speed.map(|speed| format!("{}x", speed));
+        if reasons.is_empty() {
+            return speed;
+        }
+        let stepping = reasons.contains(&Reason::Hotkey);
+        let reasons: Vec<_> = reasons.iter().map(|r| r.describe()).collect();
+        let mut paused = format!("paused: {}", reasons.join(", "));
+        if stepping {
+            let staged = *self.staged.lock().unwrap();
+            let buttons: Vec<_> = buttons::pressed(staged).map(|b| b.name()).collect();
+            if buttons.is_empty() {
+                paused.push_str(", next: nothing");
+            } else {
+                paused.push_str(&format!(", next: {}", buttons.join(" ")));
+            }
+        }
+        Some(match speed {
+            Some(speed) => format!("{}, {}", paused, speed),
+            None => paused,
+        })
+    }
+}
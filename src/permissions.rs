@@ -0,0 +1,164 @@
+use std::collections::BTreeSet;
+
+use rlua::{prelude::LuaError, Context, MultiValue, Table};
+use tracing::debug;
+
+// Capability groups and the API functions (or libraries) they unlock.
+//
+// Scripts get everything else by default and declare the groups they need
+// in a header comment:
+//
+//   -- marlua: permissions = files, screenshots
+//
+// Functions of groups that were not declared are replaced with stubs that
+// name the missing permission; --trust-all grants every group. New API
+// functions that touch the outside world belong in one of these lists, in
+// addition to api::FEATURES.
+const GROUPS: &[(&str, &[&str])] = &[
+    // files at paths the script chooses
+    (
+        "files",
+        &[
+            "savestate.save_file",
+            "savestate.load_file",
+            "match_image",
+            "expect_frame",
+            "save_tiles",
+            "load_tiles",
+            "record_ram",
+        ],
+    ),
+    ("screenshots", &["screenshot"]),
+    // nothing uses the network yet
+    ("network", &[]),
+    // libraries loosened with --lua-allow
+    ("stdlib", &["io", "os", "debug"]),
+];
+
+const HEADER: &str = "marlua:";
+
+pub struct Permissions {
+    granted: BTreeSet<&'static str>,
+}
+
+impl Permissions {
+    pub fn none() -> Self {
+        Self {
+            granted: BTreeSet::new(),
+        }
+    }
+
+    pub fn grant(&mut self, other: Permissions) {
+        self.granted.extend(other.granted);
+    }
+
+    pub fn has(&self, group: &str) -> bool {
+        self.granted.contains(group)
+    }
+
+    pub fn granted(&self) -> Vec<&'static str> {
+        self.granted.iter().copied().collect()
+    }
+
+    pub fn all() -> Self {
+        Self {
+            granted: GROUPS.iter().map(|(name, _)| *name).collect(),
+        }
+    }
+
+    // the groups declared in the comments at the top of a script
+    pub fn parse(script: &str) -> Result<Self, String> {
+        let mut granted = BTreeSet::new();
+        let header = script
+            .lines()
+            .map(str::trim)
+            .take_while(|line| line.is_empty() || line.starts_with("--"));
+        for line in header {
+            let Some(rest) = line.trim_start_matches('-').trim().strip_prefix(HEADER) else {
+                continue;
+            };
+            let Some(names) = rest.trim().strip_prefix("permissions") else {
+                return Err(format!("unknown marlua header: {}", line));
+            };
+            let names = names
+                .trim_start()
+                .strip_prefix('=')
+                .ok_or_else(|| format!("expected `-- marlua: permissions = ...`, got {}", line))?;
+            for name in names.split(',').map(str::trim).filter(|n| !n.is_empty()) {
+                match GROUPS.iter().find(|(group, _)| *group == name) {
+                    Some((group, _)) => granted.insert(*group),
+                    None => {
+                        let valid: Vec<_> = GROUPS.iter().map(|(n, _)| *n).collect();
+                        return Err(format!(
+                            "unknown permission {}, expected one of {}",
+                            name,
+                            valid.join(", ")
+                        ));
+                    }
+                };
+            }
+        }
+        Ok(Self { granted })
+    }
+
+    // replace everything the script may not use; `loosened` are the names of
+    // the libraries allowed with --lua-allow, the others are not there anyway
+    pub fn apply(&self, ctx: Context, loosened: &[String]) -> Result<(), LuaError> {
+        let globals = ctx.globals();
+        for (group, names) in GROUPS {
+            if self.granted.contains(group) {
+                continue;
+            }
+            for &name in *names {
+                let message = format!(
+                    "{} needs the {} permission, add `-- {} permissions = {}` to the top of the script",
+                    name, group, HEADER, group
+                );
+                if *group == "stdlib" {
+                    if loosened.iter().any(|l| l == name) {
+                        deny_library(ctx, name, message)?;
+                    }
+                    continue;
+                }
+
+                let stub = ctx.create_function(move |_, _: MultiValue| {
+                    Err::<(), _>(LuaError::RuntimeError(message.clone()))
+                })?;
+                match name.split_once('.') {
+                    Some((table, field)) => globals.get::<_, Table>(table)?.set(field, stub)?,
+                    None => globals.set(name, stub)?,
+                }
+            }
+        }
+        debug!(
+            "script permissions: {}",
+            self.granted.iter().copied().collect::<Vec<_>>().join(", ")
+        );
+        Ok(())
+    }
+}
+
+// a table that raises `message` when anything is looked up in it; os keeps
+// the sandbox's clock and time
+fn deny_library(ctx: Context, name: &str, message: String) -> Result<(), LuaError> {
+    if name == "os" {
+        crate::sandbox::safe_os(ctx)?;
+        let os: Table = ctx.globals().get("os")?;
+        os.set_metatable(Some(deny(ctx, message)?));
+        return Ok(());
+    }
+    let table = ctx.create_table()?;
+    table.set_metatable(Some(deny(ctx, message)?));
+    ctx.globals().set(name, table)
+}
+
+fn deny(ctx: Context, message: String) -> Result<Table, LuaError> {
+    let meta = ctx.create_table()?;
+    meta.set(
+        "__index",
+        ctx.create_function(move |_, _: MultiValue| {
+            Err::<(), _>(LuaError::RuntimeError(message.clone()))
+        })?,
+    )?;
+    Ok(meta)
+}
@@ -0,0 +1,88 @@
+use std::path::{Path as FilePath, PathBuf};
+
+use femtovg::{renderer::OpenGl, Canvas, Color, FontId, Paint, Path};
+use winit::event::VirtualKeyCode;
+
+use crate::{cli::DEFAULT_SCRIPT, overlay};
+
+pub const TITLE: &str = "Open a ROM (Up/Down, Enter):";
+
+// The startup screen shown when marlua is run without a ROM: the recently
+// used ROM and script pairs plus an entry that opens a file dialog.
+pub struct Picker {
+    recent: Vec<(String, String)>,
+    selected: usize,
+}
+
+impl Picker {
+    pub fn new(recent: Vec<(String, String)>) -> Self {
+        Self {
+            recent,
+            selected: 0,
+        }
+    }
+
+    // the last entry is the file dialog
+    fn len(&self) -> usize {
+        self.recent.len() + 1
+    }
+
+    // the chosen ROM and script once Enter is pressed on an entry
+    pub fn key(&mut self, key: VirtualKeyCode) -> Option<(PathBuf, PathBuf)> {
+        match key {
+            VirtualKeyCode::Up => self.selected = (self.selected + self.len() - 1) % self.len(),
+            VirtualKeyCode::Down => self.selected = (self.selected + 1) % self.len(),
+            VirtualKeyCode::Return => {
+                return match self.recent.get(self.selected) {
+                    Some((rom, script)) => Some((rom.into(), script.into())),
+                    None => browse(),
+                }
+            }
+            _ => {}
+        }
+        None
+    }
+
+    // the lines to show and which one is selected, for any renderer
+    pub fn entries(&self) -> (Vec<String>, usize) {
+        let browse = "Browse...".to_owned();
+        let entries = self
+            .recent
+            .iter()
+            .map(|(rom, script)| format!("{}  +  {}", file_name(rom), file_name(script)))
+            .chain(std::iter::once(browse))
+            .collect();
+        (entries, self.selected)
+    }
+
+    pub fn draw(&self, canvas: &mut Canvas<OpenGl>, font: FontId) {
+        let mut background = Path::new();
+        background.rect(0.0, 0.0, canvas.width() as f32, canvas.height() as f32);
+        canvas.fill_path(&mut background, &Paint::color(Color::rgb(24, 24, 32)));
+
+        let (entries, selected) = self.entries();
+        let width = canvas.width() as f32 - 16.0;
+        overlay::list(canvas, font, (8.0, 8.0, width), TITLE, &entries, selected);
+    }
+}
+
+fn file_name(path: &str) -> String {
+    FilePath::new(path)
+        .file_name()
+        .map_or_else(|| path.to_owned(), |n| n.to_string_lossy().into_owned())
+}
+
+// asks for a ROM and then a script, which falls back to the default script
+// when that dialog is cancelled
+fn browse() -> Option<(PathBuf, PathBuf)> {
+    let rom = rfd::FileDialog::new()
+        .set_title("Open ROM")
+        .add_filter("NES ROM", &["nes"])
+        .pick_file()?;
+    let script = rfd::FileDialog::new()
+        .set_title("Open script")
+        .add_filter("Lua script", &["lua"])
+        .pick_file()
+        .unwrap_or_else(|| DEFAULT_SCRIPT.into());
+    Some((rom, script))
+}
@@ -0,0 +1,71 @@
+use std::{
+    fs::File,
+    io::{self, BufReader, BufWriter},
+    path::Path,
+};
+
+use png::{BitDepth, ColorType, Decoder, Encoder, Transformations};
+
+// An RGBA image loaded from a PNG file.
+pub struct Picture {
+    pub width: usize,
+    pub height: usize,
+    pub rgba: Vec<u8>,
+}
+
+fn invalid(e: impl std::error::Error + Send + Sync + 'static) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, e)
+}
+
+impl Picture {
+    pub fn load(path: impl AsRef<Path>) -> io::Result<Self> {
+        let mut decoder = Decoder::new(BufReader::new(File::open(path)?));
+        decoder.set_transformations(Transformations::normalize_to_color8());
+
+        let mut reader = decoder.read_info().map_err(invalid)?;
+        let mut buf = vec![0; reader.output_buffer_size()];
+        let info = reader.next_frame(&mut buf).map_err(invalid)?;
+        buf.truncate(info.buffer_size());
+
+        let rgba = match info.color_type {
+            ColorType::Rgba => buf,
+            ColorType::Rgb => buf
+                .chunks_exact(3)
+                .flat_map(|p| [p[0], p[1], p[2], 255])
+                .collect(),
+            ColorType::GrayscaleAlpha => buf
+                .chunks_exact(2)
+                .flat_map(|p| [p[0], p[0], p[0], p[1]])
+                .collect(),
+            ColorType::Grayscale => buf.iter().flat_map(|&v| [v, v, v, 255]).collect(),
+            ColorType::Indexed => unreachable!("palette is expanded by the decoder"),
+        };
+
+        Ok(Self {
+            width: info.width as usize,
+            height: info.height as usize,
+            rgba,
+        })
+    }
+}
+
+pub fn write_png(
+    path: impl AsRef<Path>,
+    width: usize,
+    height: usize,
+    rgba: &[u8],
+) -> io::Result<()> {
+    let path = path.as_ref();
+    if let Some(dir) = path.parent() {
+        std::fs::create_dir_all(dir)?;
+    }
+
+    let file = BufWriter::new(File::create(path)?);
+    let mut encoder = Encoder::new(file, width as u32, height as u32);
+    encoder.set_color(ColorType::Rgba);
+    encoder.set_depth(BitDepth::Eight);
+
+    let mut writer = encoder.write_header().map_err(invalid)?;
+    writer.write_image_data(rgba).map_err(invalid)?;
+    Ok(())
+}
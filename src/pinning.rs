@@ -0,0 +1,112 @@
+use std::sync::{Arc, Mutex};
+
+use sha2::{Digest, Sha256};
+
+const PREFIX: &str = "sha256:";
+
+// lowercase hex
+pub fn sha256(data: &[u8]) -> String {
+    Sha256::digest(data)
+        .iter()
+        .map(|byte| format!("{:02x}", byte))
+        .collect()
+}
+
+// --verify-script's argument, the hex digest in lowercase
+pub fn parse(pin: &str) -> Result<String, String> {
+    let hex = pin.strip_prefix(PREFIX).ok_or_else(|| {
+        format!(
+            "--verify-script {} must start with {}, e.g. as printed at the start of a run",
+            pin, PREFIX
+        )
+    })?;
+    if hex.len() != 64 || !hex.chars().all(|c| c.is_ascii_hexdigit()) {
+        return Err(format!(
+            "--verify-script {} must have 64 hexadecimal digits after {}",
+            pin, PREFIX
+        ));
+    }
+    Ok(hex.to_ascii_lowercase())
+}
+
+// The script hash of a run: the hash of its script's text, or with several
+// scripts the hash of theirs one per line. File names are left out, so a
+// bundle's renamed copies hash the same.
+pub fn script_hash<'a>(sources: impl IntoIterator<Item = &'a [u8]>) -> String {
+    let hashes: Vec<_> = sources.into_iter().map(sha256).collect();
+    match hashes.as_slice() {
+        [single] => single.clone(),
+        _ => sha256(format!("{}\n", hashes.join("\n")).as_bytes()),
+    }
+}
+
+// What a run's results can be attributed to: the hash of every script and
+// of every module they required, in the order they were loaded. Clones
+// share the modules, require adds them as it loads them.
+#[derive(Clone)]
+pub struct ScriptHashes {
+    script: String,
+    // path and hash
+    scripts: Vec<(String, String)>,
+    // name and hash
+    modules: Arc<Mutex<Vec<(String, String)>>>,
+}
+
+impl ScriptHashes {
+    pub fn new(scripts: &[(&str, String)]) -> Self {
+        Self {
+            script: script_hash(scripts.iter().map(|(_, source)| source.as_bytes())),
+            scripts: scripts
+                .iter()
+                .map(|(path, source)| (path.to_string(), sha256(source.as_bytes())))
+                .collect(),
+            modules: Arc::default(),
+        }
+    }
+
+    pub fn script(&self) -> &str {
+        &self.script
+    }
+
+    // --verify-script, refusing scripts that are not the pinned ones
+    pub fn verify(&self, pin: &str) -> Result<(), String> {
+        if self.script == pin {
+            return Ok(());
+        }
+        Err(format!(
+            "the script does not match --verify-script: expected {}{}, got {}{}",
+            PREFIX, pin, PREFIX, self.script
+        ))
+    }
+
+    // once per module, cached ones are not loaded again
+    pub fn module(&self, name: &str, source: &[u8]) {
+        let hash = sha256(source);
+        self.modules.lock().unwrap().push((name.to_owned(), hash));
+    }
+
+    // `sha256sum`'s format, the scripts then the modules
+    fn manifest(&self) -> String {
+        let modules = self.modules.lock().unwrap();
+        self.scripts
+            .iter()
+            .chain(modules.iter())
+            .map(|(name, hash)| format!("{}  {}\n", hash, name))
+            .collect()
+    }
+
+    // the lines of the run summary: the script hash, then with modules each
+    // of them and the hash of the whole manifest
+    pub fn summary(&self) -> Vec<String> {
+        let mut lines = vec![format!("script {}{}", PREFIX, self.script)];
+        let modules = self.modules.lock().unwrap().clone();
+        if !modules.is_empty() {
+            for (name, hash) in modules {
+                lines.push(format!("module {} {}{}", name, PREFIX, hash));
+            }
+            let manifest = sha256(self.manifest().as_bytes());
+            lines.push(format!("scripts and modules {}{}", PREFIX, manifest));
+        }
+        lines
+    }
+}
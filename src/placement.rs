@@ -0,0 +1,162 @@
+use std::{
+    fs,
+    path::{Path, PathBuf},
+};
+
+use tracing::{info, warn};
+use winit::{
+    dpi::PhysicalPosition,
+    monitor::MonitorHandle,
+    window::{Fullscreen, Window},
+};
+
+use crate::{
+    config::{Config, ConfigValue},
+    output,
+};
+
+fn file() -> PathBuf {
+    Path::new(output::BASE).join("window.toml")
+}
+
+#[derive(Clone, Debug)]
+pub enum Monitor {
+    // in the order the system lists them, from 0
+    Index(usize),
+    Name(String),
+}
+
+// Where the window opens: monitor, position and fullscreen from [window] in
+// the config, or where it was when marlua last exited with remember_window.
+// The window is not resizable, so its size is not part of it.
+#[derive(Clone, Default)]
+pub struct Placement {
+    pub monitor: Option<Monitor>,
+    // from the monitor's top left corner, in pixels
+    pub position: Option<(i32, i32)>,
+    pub fullscreen: bool,
+    // save the placement on exit, and start from the saved one
+    pub remember: bool,
+}
+
+impl Placement {
+    // `prefix` is "window." for marlua.toml, the saved file has no section
+    pub fn from_config(config: &Config, prefix: &str) -> Result<Self, String> {
+        let key = |name: &str| format!("{}{}", prefix, name);
+        let monitor = match config.get(&key("monitor")) {
+            None => None,
+            Some(ConfigValue::Integer(i)) if *i >= 0 => Some(Monitor::Index(*i as usize)),
+            Some(ConfigValue::String(name)) => Some(Monitor::Name(name.clone())),
+            Some(_) => {
+                return Err(format!(
+                    "{} must be an index from 0 or a monitor name",
+                    key("monitor")
+                ))
+            }
+        };
+        let position = match config.get(&key("position")) {
+            None => None,
+            Some(ConfigValue::Array(values)) => match values.as_slice() {
+                [ConfigValue::Integer(x), ConfigValue::Integer(y)] => Some((*x as i32, *y as i32)),
+                _ => return Err(format!("{} must be [x, y]", key("position"))),
+            },
+            Some(_) => return Err(format!("{} must be [x, y]", key("position"))),
+        };
+        Ok(Self {
+            monitor,
+            position,
+            fullscreen: config.boolean(&key("fullscreen"))?.unwrap_or(false),
+            remember: config.boolean(&key("remember_window"))?.unwrap_or(false),
+        })
+    }
+
+    // the saved placement instead of the configured one, if there is one
+    pub fn restore(self) -> Self {
+        if !self.remember || !file().exists() {
+            return self;
+        }
+        let saved = Config::load(file())
+            .map_err(|e| e.to_string())
+            .and_then(|config| Self::from_config(&config, ""));
+        match saved {
+            Ok(saved) => Self {
+                remember: true,
+                ..saved
+            },
+            Err(e) => {
+                warn!("ignoring {}: {}", file().display(), e);
+                self
+            }
+        }
+    }
+
+    // called once the window is open, it starts wherever the system put it
+    pub fn apply(&self, window: &Window) {
+        let monitors: Vec<MonitorHandle> = window.available_monitors().collect();
+        let found = match &self.monitor {
+            None => None,
+            Some(Monitor::Index(i)) => monitors.get(*i),
+            Some(Monitor::Name(name)) => monitors
+                .iter()
+                .find(|m| m.name().as_deref() == Some(name.as_str())),
+        };
+        if let (Some(monitor), None) = (&self.monitor, found) {
+            warn!("there is no monitor {:?}, using the current one", monitor);
+        }
+        let Some(monitor) = found.cloned().or_else(|| window.current_monitor()) else {
+            return;
+        };
+
+        if self.monitor.is_some() || self.position.is_some() {
+            // keep the whole window on the monitor, it may be smaller than
+            // the one the position was saved on
+            let (x, y) = self.position.unwrap_or((0, 0));
+            let size = monitor.size();
+            let outer = window.outer_size();
+            let x = x.clamp(0, size.width.saturating_sub(outer.width) as i32);
+            let y = y.clamp(0, size.height.saturating_sub(outer.height) as i32);
+            let origin = monitor.position();
+            window.set_outer_position(PhysicalPosition::new(origin.x + x, origin.y + y));
+        }
+        if self.fullscreen {
+            window.set_fullscreen(Some(Fullscreen::Borderless(Some(monitor))));
+        }
+    }
+
+    // on exit, with remember_window
+    pub fn save(window: &Window) {
+        let Some(monitor) = window.current_monitor() else {
+            return;
+        };
+        let mut lines = String::new();
+        match monitor.name() {
+            Some(name) => lines.push_str(&format!("monitor = {:?}\n", name)),
+            None => {
+                let index = window.available_monitors().position(|m| m == monitor);
+                if let Some(index) = index {
+                    lines.push_str(&format!("monitor = {}\n", index));
+                }
+            }
+        }
+        // a fullscreen window's position is the monitor's origin, which
+        // says nothing about where it goes when leaving fullscreen
+        let fullscreen = window.fullscreen().is_some();
+        if !fullscreen {
+            if let Ok(position) = window.outer_position() {
+                let origin = monitor.position();
+                lines.push_str(&format!(
+                    "position = [{}, {}]\n",
+                    position.x - origin.x,
+                    position.y - origin.y
+                ));
+            }
+        }
+        lines.push_str(&format!("fullscreen = {}\n", fullscreen));
+
+        let result = fs::create_dir_all(output::BASE).and_then(|_| fs::write(file(), lines));
+        match result {
+            Ok(()) => info!("saved the window placement to {}", file().display()),
+            Err(e) => warn!("could not save {}: {}", file().display(), e),
+        }
+    }
+}
@@ -0,0 +1,113 @@
+use std::{fs, path::PathBuf};
+
+use rlua::{prelude::LuaError, Function, Table};
+use tracing::warn;
+
+// what redo_key defaults to
+pub const REDO_KEY: &str = "Back";
+
+// practice.loop's argument, see there
+pub struct Options<'lua> {
+    pub state: String,
+    pub success: Function<'lua>,
+    pub fail: Function<'lua>,
+    pub on_attempt: Option<Function<'lua>>,
+    pub on_frame: Option<Function<'lua>>,
+    pub redo_key: String,
+    // stop after this many attempts, forever otherwise
+    pub attempts: Option<u64>,
+}
+
+impl<'lua> Options<'lua> {
+    pub fn from_lua(table: Table<'lua>) -> Result<Self, LuaError> {
+        let required = |name: &str| {
+            table.get::<_, Option<Function>>(name)?.ok_or_else(|| {
+                LuaError::RuntimeError(format!("practice.loop needs a {} function", name))
+            })
+        };
+        Ok(Self {
+            state: table.get::<_, Option<String>>("state")?.ok_or_else(|| {
+                LuaError::RuntimeError(
+                    "practice.loop needs the state to practice from, a checkpoint or a \
+                     savestate file"
+                        .to_owned(),
+                )
+            })?,
+            success: required("success")?,
+            fail: required("fail")?,
+            on_attempt: table.get("on_attempt")?,
+            on_frame: table.get("on_frame")?,
+            redo_key: table
+                .get::<_, Option<String>>("redo_key")?
+                .unwrap_or_else(|| REDO_KEY.to_owned()),
+            attempts: table.get("attempts")?,
+        })
+    }
+}
+
+// How often a state was practiced and how often that went well, kept in
+// output/<rom-name>/practice/ across sessions as `attempts = 12` and
+// `successes = 5` lines.
+pub struct Counter {
+    path: PathBuf,
+    pub attempts: u64,
+    pub successes: u64,
+}
+
+impl Counter {
+    // starts from zero when the file is missing or unreadable
+    pub fn load(path: PathBuf) -> Self {
+        let mut counter = Self {
+            path,
+            attempts: 0,
+            successes: 0,
+        };
+        let Ok(text) = fs::read_to_string(&counter.path) else {
+            return counter;
+        };
+        for line in text.lines() {
+            let Some((key, value)) = line.split_once('=') else {
+                continue;
+            };
+            let Ok(value) = value.trim().parse() else {
+                continue;
+            };
+            match key.trim() {
+                "attempts" => counter.attempts = value,
+                "successes" => counter.successes = value,
+                _ => {}
+            }
+        }
+        counter
+    }
+
+    pub fn record(&mut self, success: bool) {
+        self.attempts += 1;
+        if success {
+            self.successes += 1;
+        }
+        let text = format!(
+            "attempts = {}\nsuccesses = {}\n",
+            self.attempts, self.successes
+        );
+        let written = match self.path.parent() {
+            Some(dir) => fs::create_dir_all(dir).and_then(|()| fs::write(&self.path, text)),
+            None => fs::write(&self.path, text),
+        };
+        if let Err(e) = written {
+            warn!("could not save {}: {}", self.path.display(), e);
+        }
+    }
+
+    // e.g. "trick1: 5/12 (42%)"
+    pub fn summary(&self, name: &str) -> String {
+        let rate = match self.attempts {
+            0 => 0.0,
+            n => self.successes as f64 * 100.0 / n as f64,
+        };
+        format!(
+            "{}: {}/{} ({:.0}%)",
+            name, self.successes, self.attempts, rate
+        )
+    }
+}
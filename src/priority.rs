@@ -0,0 +1,113 @@
+use tracing::{info, warn};
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum Priority {
+    // above other programs, no privileges needed to ask on most systems
+    High,
+    // the scheduler runs it before anything else, usually needs root or
+    // CAP_SYS_NICE
+    Realtime,
+}
+
+impl Priority {
+    pub fn parse(name: &str) -> Result<Option<Self>, String> {
+        match name {
+            "normal" => Ok(None),
+            "high" => Ok(Some(Priority::High)),
+            "realtime" => Ok(Some(Priority::Realtime)),
+            other => Err(format!(
+                "unknown priority {}, expected normal, high or realtime",
+                other
+            )),
+        }
+    }
+
+    fn name(self) -> &'static str {
+        match self {
+            Priority::High => "high",
+            Priority::Realtime => "realtime",
+        }
+    }
+}
+
+// [emulator] priority and core, applied by the emulator thread to itself as
+// it starts so the window keeps its usual priority. What the system refuses
+// is logged and left as it was, marlua runs either way.
+pub fn apply(priority: Option<Priority>, core: Option<usize>) {
+    let priority = priority.filter(|&priority| match set_priority(priority) {
+        Ok(()) => true,
+        Err(e) => {
+            warn!(
+                "could not give the emulator {} priority: {}",
+                priority.name(),
+                e
+            );
+            false
+        }
+    });
+    let core = core.filter(|&core| match set_core(core) {
+        Ok(()) => true,
+        Err(e) => {
+            warn!("could not pin the emulator to core {}: {}", core, e);
+            false
+        }
+    });
+    info!(
+        "emulator thread: {} priority, {}",
+        priority.map_or("normal", Priority::name),
+        core.map_or_else(|| "any core".to_owned(), |core| format!("core {}", core))
+    );
+}
+
+#[cfg(unix)]
+fn set_priority(priority: Priority) -> Result<(), String> {
+    match priority {
+        Priority::High => {
+            // Linux gives every thread its own nice value, elsewhere this
+            // raises the whole process
+            #[cfg(target_os = "linux")]
+            let who = unsafe { libc::syscall(libc::SYS_gettid) } as libc::id_t;
+            #[cfg(not(target_os = "linux"))]
+            let who = 0;
+            if unsafe { libc::setpriority(libc::PRIO_PROCESS, who, -10) } != 0 {
+                return Err(std::io::Error::last_os_error().to_string());
+            }
+        }
+        Priority::Realtime => {
+            // some systems have private fields in sched_param
+            let mut param: libc::sched_param = unsafe { std::mem::zeroed() };
+            param.sched_priority = 10;
+            let result = unsafe {
+                libc::pthread_setschedparam(libc::pthread_self(), libc::SCHED_FIFO, &param)
+            };
+            if result != 0 {
+                return Err(std::io::Error::from_raw_os_error(result).to_string());
+            }
+        }
+    }
+    Ok(())
+}
+
+#[cfg(not(unix))]
+fn set_priority(_: Priority) -> Result<(), String> {
+    Err("not supported on this system".to_owned())
+}
+
+#[cfg(target_os = "linux")]
+fn set_core(core: usize) -> Result<(), String> {
+    if core >= libc::CPU_SETSIZE as usize {
+        return Err("no such core".to_owned());
+    }
+    let mut set: libc::cpu_set_t = unsafe { std::mem::zeroed() };
+    unsafe { libc::CPU_SET(core, &mut set) };
+    // 0 is the calling thread
+    if unsafe { libc::sched_setaffinity(0, std::mem::size_of::<libc::cpu_set_t>(), &set) } != 0 {
+        return Err(std::io::Error::last_os_error().to_string());
+    }
+    Ok(())
+}
+
+#[cfg(not(target_os = "linux"))]
+fn set_core(_: usize) -> Result<(), String> {
+    Err("not supported on this system".to_owned())
+}
@@ -0,0 +1,65 @@
+use std::{
+    collections::HashMap,
+    sync::{Arc, Mutex},
+};
+
+use rlua::{HookTriggers, Lua};
+
+// VM instructions between samples
+const INTERVAL: u32 = 1000;
+
+// A sampling profiler for scripts.
+//
+// While running it takes the current source line every INTERVAL
+// instructions. Lua 5.4 copies hooks into new coroutines, so samples taken
+// inside a coroutine are attributed to the coroutine's own function.
+#[derive(Default)]
+pub struct Profiler {
+    samples: Arc<Mutex<HashMap<(Vec<u8>, i32), u64>>>,
+}
+
+pub struct Entry {
+    pub location: String,
+    pub samples: u64,
+    pub percent: f64,
+}
+
+impl Profiler {
+    pub fn start(&self, lua: &Lua) {
+        self.samples.lock().unwrap().clear();
+
+        let samples = self.samples.clone();
+        let triggers = HookTriggers {
+            every_nth_instruction: Some(INTERVAL),
+            ..Default::default()
+        };
+        lua.set_hook(triggers, move |_, debug| {
+            let source = debug.source().short_src.unwrap_or(b"?").to_vec();
+            let line = debug.curr_line();
+            *samples.lock().unwrap().entry((source, line)).or_default() += 1;
+            Ok(())
+        });
+    }
+
+    pub fn stop(&self, lua: &Lua) {
+        lua.remove_hook();
+    }
+
+    // the `n` lines with the most samples
+    pub fn report(&self, n: usize) -> Vec<Entry> {
+        let samples = self.samples.lock().unwrap();
+        let total: u64 = samples.values().sum();
+
+        let mut entries: Vec<_> = samples
+            .iter()
+            .map(|((source, line), &count)| Entry {
+                location: format!("{}:{}", String::from_utf8_lossy(source), line),
+                samples: count,
+                percent: count as f64 * 100.0 / total.max(1) as f64,
+            })
+            .collect();
+        entries.sort_by(|a, b| b.samples.cmp(&a.samples));
+        entries.truncate(n);
+        entries
+    }
+}
@@ -0,0 +1,317 @@
+use std::{
+    fs::{self, File},
+    io::{self, BufWriter, Seek, SeekFrom, Write},
+    path::Path,
+    time::{Duration, Instant},
+};
+
+use crate::{machine::Machine, memory};
+
+const MAGIC: &[u8; 8] = b"MARLUARM";
+const VERSION: u16 = 1;
+// where the frame count is in the header, after MAGIC, VERSION and the ROM
+const FRAMES_AT: u64 = 14;
+const HEADER: usize = 20;
+
+// the most bytes sampled per frame, all of the CPU RAM twice over; every
+// frame costs this much file, so more is almost certainly a mistake
+const MAX_BYTES: usize = 4096;
+// an entry's value in bytes, little-endian
+const WIDTHS: [u8; 3] = [1, 2, 4];
+
+const FLUSH_INTERVAL: Duration = Duration::from_secs(1);
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct Entry {
+    pub addr: u16,
+    pub width: u8,
+}
+
+impl Entry {
+    // the CSV column, e.g. $0086:2
+    fn name(&self) -> String {
+        match self.width {
+            1 => format!("${:04X}", self.addr),
+            width => format!("${:04X}:{}", self.addr, width),
+        }
+    }
+}
+
+fn address(text: &str) -> Result<u16, String> {
+    let text = text.trim();
+    let parsed = match text.strip_prefix("0x").or_else(|| text.strip_prefix('$')) {
+        Some(hex) => u16::from_str_radix(hex, 16),
+        None => text.parse(),
+    };
+    parsed.map_err(|_| {
+        format!(
+            "invalid address {}, expected e.g. 0x075A, $075A or 1882",
+            text
+        )
+    })
+}
+
+// An address (0x075A, $075A or decimal), an address and the width of its
+// value in bytes (0x0086:2), or an inclusive range of single bytes
+// (0x0700-0x07FF).
+pub fn parse_entry(text: &str) -> Result<Vec<Entry>, String> {
+    if let Some((start, end)) = text.split_once('-') {
+        let (start, end) = (address(start)?, address(end)?);
+        if end < start {
+            return Err(format!("the range {} ends before it starts", text.trim()));
+        }
+        let len = end as usize - start as usize + 1;
+        if len > MAX_BYTES {
+            return Err(too_many(len));
+        }
+        return Ok((start..=end).map(|addr| Entry { addr, width: 1 }).collect());
+    }
+    let (addr, width) = match text.split_once(':') {
+        Some((addr, width)) => (addr, width.trim().parse().unwrap_or(0)),
+        None => (text, 1),
+    };
+    if !WIDTHS.contains(&width) {
+        return Err(format!(
+            "invalid width in {}, expected 1, 2 or 4 bytes",
+            text.trim()
+        ));
+    }
+    Ok(vec![Entry {
+        addr: address(addr)?,
+        width,
+    }])
+}
+
+fn too_many(bytes: usize) -> String {
+    format!(
+        "the address list samples {} bytes every frame, at most {} are allowed \
+         (all of the CPU RAM is {})",
+        bytes,
+        MAX_BYTES,
+        memory::RAM
+    )
+}
+
+// the list is not empty and within MAX_BYTES
+pub fn check(entries: &[Entry]) -> Result<(), String> {
+    match entries.is_empty() {
+        true => Err("the address list is empty".to_owned()),
+        false => check_size(entries),
+    }
+}
+
+// --record-ram's address list: an entry per line, see parse_entry, with #
+// comments
+pub fn load_list(path: &str) -> Result<Vec<Entry>, String> {
+    let source = fs::read_to_string(path).map_err(|e| format!("{}: {}", path, e))?;
+    let mut entries = Vec::new();
+    for (n, line) in source.lines().enumerate() {
+        let line = line.split('#').next().unwrap_or_default().trim();
+        if line.is_empty() {
+            continue;
+        }
+        let parsed = parse_entry(line).map_err(|e| format!("{}:{}: {}", path, n + 1, e))?;
+        entries.extend(parsed);
+        // long before the memory for a silly list runs out
+        check_size(&entries).map_err(|e| format!("{}: {}", path, e))?;
+    }
+    check(&entries).map_err(|e| format!("{}: {}", path, e))?;
+    Ok(entries)
+}
+
+pub fn check_size(entries: &[Entry]) -> Result<(), String> {
+    let bytes: usize = entries.iter().map(|e| e.width as usize).sum();
+    match bytes > MAX_BYTES {
+        true => Err(too_many(bytes)),
+        false => Ok(()),
+    }
+}
+
+// `--record-ram` and record_ram(): the listed addresses after every frame,
+// for looking at a run afterwards with `marlua ram-dump`.
+//
+// The header is MAGIC, VERSION, the ROM's CRC32, the number of frames
+// (written when the recording ends), the number of entries and each entry's
+// address and width. Then comes a row per frame: the frame number, as a
+// frame is sampled again after a state is restored, and every entry's
+// bytes, all little-endian.
+pub struct Recorder {
+    writer: BufWriter<File>,
+    entries: Vec<Entry>,
+    frames: u32,
+    flushed: Instant,
+}
+
+impl Recorder {
+    pub fn create(path: &Path, machine: &Machine, entries: Vec<Entry>) -> io::Result<Self> {
+        let mut writer = BufWriter::new(File::create(path)?);
+        writer.write_all(MAGIC)?;
+        writer.write_all(&VERSION.to_le_bytes())?;
+        writer.write_all(&machine.hash().to_le_bytes())?;
+        writer.write_all(&0u32.to_le_bytes())?;
+        writer.write_all(&(entries.len() as u16).to_le_bytes())?;
+        for entry in &entries {
+            writer.write_all(&entry.addr.to_le_bytes())?;
+            writer.write_all(&[entry.width])?;
+        }
+        Ok(Self {
+            writer,
+            entries,
+            frames: 0,
+            flushed: Instant::now(),
+        })
+    }
+
+    pub fn bytes(&self) -> usize {
+        self.entries.iter().map(|e| e.width as usize).sum()
+    }
+
+    // call after every frame
+    pub fn record(&mut self, machine: &Machine) -> io::Result<()> {
+        self.writer
+            .write_all(&(machine.frame() as u32).to_le_bytes())?;
+        for entry in &self.entries {
+            for i in 0..entry.width as u16 {
+                let byte = memory::read_u8(machine, entry.addr.wrapping_add(i));
+                self.writer.write_all(&[byte])?;
+            }
+        }
+        self.frames += 1;
+
+        if self.flushed.elapsed() >= FLUSH_INTERVAL {
+            self.writer.flush()?;
+            self.flushed = Instant::now();
+        }
+        Ok(())
+    }
+
+    fn finish(&mut self) -> io::Result<()> {
+        self.writer.flush()?;
+        let file = self.writer.get_mut();
+        file.seek(SeekFrom::Start(FRAMES_AT))?;
+        file.write_all(&self.frames.to_le_bytes())?;
+        file.seek(SeekFrom::End(0))?;
+        Ok(())
+    }
+}
+
+impl Drop for Recorder {
+    fn drop(&mut self) {
+        let _ = self.finish();
+    }
+}
+
+pub struct Dump {
+    pub rom_hash: u32,
+    pub entries: Vec<Entry>,
+    // the frame and every entry's value
+    pub rows: Vec<(u32, Vec<u32>)>,
+    // the frame count in the header differs from the rows: the recording
+    // did not end cleanly
+    pub cut_off: bool,
+}
+
+// a row cut off by a crash is ignored
+pub fn read(path: &Path) -> Result<Dump, String> {
+    let bytes = fs::read(path).map_err(|e| format!("{}: {}", path.display(), e))?;
+    let error = |msg: &str| format!("{}: {}", path.display(), msg);
+
+    if bytes.len() < HEADER || &bytes[..8] != MAGIC {
+        return Err(error("not a RAM recording"));
+    }
+    let version = u16::from_le_bytes([bytes[8], bytes[9]]);
+    if !(1..=VERSION).contains(&version) {
+        return Err(error(&format!("unknown RAM recording version {}", version)));
+    }
+    let u32_at = |i: usize| u32::from_le_bytes(bytes[i..i + 4].try_into().unwrap());
+    let rom_hash = u32_at(10);
+    let frames = u32_at(FRAMES_AT as usize);
+    let count = u16::from_le_bytes([bytes[18], bytes[19]]) as usize;
+
+    let body = HEADER + count * 3;
+    if bytes.len() < body {
+        return Err(error("the header is cut off"));
+    }
+    let entries: Vec<_> = bytes[HEADER..body]
+        .chunks_exact(3)
+        .map(|e| Entry {
+            addr: u16::from_le_bytes([e[0], e[1]]),
+            width: e[2],
+        })
+        .collect();
+    if entries.iter().any(|e| !WIDTHS.contains(&e.width)) {
+        return Err(error("corrupt header"));
+    }
+    check_size(&entries).map_err(|e| error(&e))?;
+
+    let row = 4 + entries.iter().map(|e| e.width as usize).sum::<usize>();
+    let rows: Vec<_> = bytes[body..]
+        .chunks_exact(row)
+        .map(|row| {
+            let frame = u32::from_le_bytes(row[..4].try_into().unwrap());
+            let mut rest = &row[4..];
+            let values = entries
+                .iter()
+                .map(|entry| {
+                    let (value, tail) = rest.split_at(entry.width as usize);
+                    rest = tail;
+                    value
+                        .iter()
+                        .rev()
+                        .fold(0, |acc, &byte| acc << 8 | byte as u32)
+                })
+                .collect();
+            (frame, values)
+        })
+        .collect();
+    Ok(Dump {
+        rom_hash,
+        cut_off: rows.len() != frames as usize,
+        entries,
+        rows,
+    })
+}
+
+// a header of frame and the entries, then a line per row
+pub fn csv(dump: &Dump) -> String {
+    let mut out = String::from("frame");
+    for entry in &dump.entries {
+        out.push(',');
+        out.push_str(&entry.name());
+    }
+    out.push('\n');
+    for (frame, values) in &dump.rows {
+        out.push_str(&frame.to_string());
+        for value in values {
+            out.push(',');
+            out.push_str(&value.to_string());
+        }
+        out.push('\n');
+    }
+    out
+}
+
+// `marlua ram-dump`: what a recording holds, or all of it with `csv`
+pub fn print(path: &str, csv: bool) -> Result<(), String> {
+    let dump = read(Path::new(path))?;
+    if csv {
+        print!("{}", self::csv(&dump));
+        return Ok(());
+    }
+    println!("ROM {:08X}", dump.rom_hash);
+    match (dump.rows.first(), dump.rows.last()) {
+        (Some((first, _)), Some((last, _))) => println!(
+            "{} frames sampled, frame {} to {}",
+            dump.rows.len(),
+            first,
+            last
+        ),
+        _ => println!("no frames sampled"),
+    }
+    if dump.cut_off {
+        println!("the recording did not end cleanly, the rows up to the crash are kept");
+    }
+    let names: Vec<_> = dump.entries.iter().map(Entry::name).collect();
+    println!("{} addresses: {}", names.len(), names.join(" "));
+    Ok(())
+}
@@ -0,0 +1,46 @@
+use std::{
+    fs,
+    path::{Path, PathBuf},
+};
+
+use tracing::warn;
+
+use crate::output;
+
+// how many ROM and script pairs to remember
+const KEEP: usize = 10;
+
+fn file() -> PathBuf {
+    Path::new(output::BASE).join("recent.txt")
+}
+
+// recently used ROM and script pairs, most recent first
+pub fn load() -> Vec<(String, String)> {
+    fs::read_to_string(file())
+        .unwrap_or_default()
+        .lines()
+        .filter_map(|line| line.split_once('\t'))
+        .map(|(rom, script)| (rom.to_owned(), script.to_owned()))
+        .collect()
+}
+
+pub fn add(rom: &str, script: &str) {
+    let absolute = |path: &str| {
+        fs::canonicalize(path).map_or_else(|_| path.to_owned(), |p| p.display().to_string())
+    };
+    let entry = (absolute(rom), absolute(script));
+
+    let mut recent = load();
+    recent.retain(|e| *e != entry);
+    recent.insert(0, entry);
+    recent.truncate(KEEP);
+
+    let lines: String = recent
+        .iter()
+        .map(|(rom, script)| format!("{}\t{}\n", rom, script))
+        .collect();
+    let result = fs::create_dir_all(output::BASE).and_then(|_| fs::write(file(), lines));
+    if let Err(e) = result {
+        warn!("could not update {}: {}", file().display(), e);
+    }
+}
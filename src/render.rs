@@ -0,0 +1,51 @@
+use fastnes::ppu::Color;
+use winit::window::Window;
+
+use crate::{
+    cli::Vsync, frametimes::FrameTimes, help::Help, hexview::HexViewer, letterbox::Scene,
+    menu::Menu, overlay::Overlay, picker::Picker, sprites::SpriteViewer, startup::ErrorScreen,
+    tiles::TileViewer,
+};
+
+// What the window shows on top of (or instead of) the game.
+pub struct Overlays<'a> {
+    pub picker: Option<&'a Picker>,
+    pub menu: Option<&'a Menu>,
+    // over the dimmed game
+    pub help: Option<&'a Help>,
+    // shown instead of the game
+    pub tiles: Option<&'a TileViewer>,
+    // drawn over the game
+    pub sprites: Option<&'a SpriteViewer>,
+    // shown instead of the game
+    pub memory: Option<&'a HexViewer>,
+    // in a corner, over everything but the menu
+    pub frame_times: Option<&'a FrameTimes>,
+    // shown instead of everything else
+    pub error: Option<&'a ErrorScreen>,
+    pub overlay: &'a mut Overlay,
+}
+
+// A way of drawing the window, picked with --renderer.
+//
+// The window hands every backend the same frame and overlays, so the event
+// loop does not care which one is active.
+pub trait Present {
+    fn present(
+        &mut self,
+        window: &Window,
+        frame: &[Color; 61440],
+        scene: Scene,
+        overlays: Overlays,
+    ) -> Result<(), String>;
+
+    // whether the overlays are drawn at all, notices go to the log otherwise
+    fn overlays(&self) -> bool {
+        true
+    }
+
+    // the --vsync mode in effect, None when it is up to the driver
+    fn vsync(&self) -> Option<Vsync> {
+        None
+    }
+}
@@ -0,0 +1,200 @@
+use std::path::Path;
+
+use crate::{
+    capture::{HEIGHT, WIDTH},
+    output::OutputDir,
+    picture::{write_png, Picture},
+    pinning::ScriptHashes,
+};
+
+// Collects the results of expect_frame and expect_memory, and the markers
+// the script set, for the summary at the end of the run.
+pub struct Report {
+    bless: bool,
+    checks: usize,
+    failures: Vec<String>,
+    // with several scripts: each one's name and the checks and failures
+    // before it started
+    scripts: Vec<(String, usize, usize)>,
+    // frame and name
+    markers: Vec<(usize, String)>,
+    // what the results are attributed to, for runs of scripts
+    hashes: Option<ScriptHashes>,
+}
+
+impl Report {
+    pub fn new(bless: bool) -> Self {
+        Self {
+            bless,
+            checks: 0,
+            failures: Vec::new(),
+            scripts: Vec::new(),
+            markers: Vec::new(),
+            hashes: None,
+        }
+    }
+
+    pub fn set_hashes(&mut self, hashes: ScriptHashes) {
+        self.hashes = Some(hashes);
+    }
+
+    // later results belong to this script, failures are prefixed with it
+    pub fn begin(&mut self, script: &str) {
+        self.scripts
+            .push((script.to_owned(), self.checks, self.failures.len()));
+    }
+
+    fn fail(&mut self, failure: String) {
+        match self.scripts.last() {
+            Some((script, _, _)) => self.failures.push(format!("{}: {}", script, failure)),
+            None => self.failures.push(failure),
+        }
+    }
+
+    pub fn passed(&self) -> bool {
+        self.failures.is_empty()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.checks == 0 && self.markers.is_empty()
+    }
+
+    pub fn marker(&mut self, frame: usize, name: String) {
+        self.markers.push((frame, name));
+    }
+
+    pub fn markers(&self) -> &[(usize, String)] {
+        &self.markers
+    }
+
+    pub fn expect_memory(&mut self, frame: usize, addr: u16, expected: u8, actual: u8) {
+        self.checks += 1;
+        if expected != actual {
+            self.fail(format!(
+                "frame {}: ${:04X} expected {:02X}, got {:02X}",
+                frame, addr, expected, actual
+            ));
+        }
+    }
+
+    // compare the full frame against a golden image, or overwrite the
+    // golden image with it when blessing
+    //
+    // golden images are part of the test suite and therefore read from and
+    // blessed to `path` itself, only failures go to the output directory
+    pub fn expect_frame(
+        &mut self,
+        output: &OutputDir,
+        frame: usize,
+        path: &str,
+        rgba: &[u8],
+        tolerance: u8,
+    ) {
+        self.checks += 1;
+
+        if self.bless {
+            if let Err(e) = write_png(path, WIDTH, HEIGHT, rgba) {
+                self.fail(format!("frame {}: could not bless {}: {}", frame, path, e));
+            }
+            return;
+        }
+
+        let golden = match Picture::load(path) {
+            Ok(golden) if golden.width == WIDTH && golden.height == HEIGHT => golden,
+            Ok(golden) => {
+                self.fail(format!(
+                    "frame {}: {} is {}x{}, expected {}x{}",
+                    frame, path, golden.width, golden.height, WIDTH, HEIGHT
+                ));
+                return;
+            }
+            Err(e) => {
+                self.fail(format!(
+                    "frame {}: could not load {}: {} (run with --bless to create it)",
+                    frame, path, e
+                ));
+                return;
+            }
+        };
+
+        let differs = |a: &[u8], b: &[u8]| (0..3).any(|c| a[c].abs_diff(b[c]) > tolerance);
+        let mismatches = rgba
+            .chunks_exact(4)
+            .zip(golden.rgba.chunks_exact(4))
+            .filter(|(a, b)| differs(a, b))
+            .count();
+        if mismatches == 0 {
+            return;
+        }
+
+        // golden | actual | differing pixels in red over the dimmed frame
+        let mut sheet = vec![0; WIDTH * 3 * HEIGHT * 4];
+        for y in 0..HEIGHT {
+            for x in 0..WIDTH {
+                let i = (y * WIDTH + x) * 4;
+                let expected = &golden.rgba[i..i + 4];
+                let actual = &rgba[i..i + 4];
+                let diff = if differs(expected, actual) {
+                    [255, 0, 0, 255]
+                } else {
+                    [actual[0] / 3, actual[1] / 3, actual[2] / 3, 255]
+                };
+
+                let row = y * WIDTH * 3;
+                for (panel, pixel) in [expected, actual, &diff[..]].into_iter().enumerate() {
+                    let j = (row + panel * WIDTH + x) * 4;
+                    sheet[j..j + 4].copy_from_slice(pixel);
+                }
+            }
+        }
+
+        let name = Path::new(path).file_name().unwrap_or_default();
+        let out = output.artifact(&format!("failures/{}", name.to_string_lossy()));
+        let saved = match out {
+            Ok(out) => match write_png(&out, WIDTH * 3, HEIGHT, &sheet) {
+                Ok(()) => format!("see {}", out.display()),
+                Err(e) => format!("could not write {}: {}", out.display(), e),
+            },
+            Err(e) => e.to_string(),
+        };
+        self.fail(format!(
+            "frame {}: {} pixels differ from {}, {}",
+            frame, mismatches, path, saved
+        ));
+    }
+
+    pub fn print(&self) {
+        for line in self.hashes.iter().flat_map(ScriptHashes::summary) {
+            eprintln!("{}", line);
+        }
+        for (frame, name) in &self.markers {
+            eprintln!("marker at frame {}: {}", frame, name);
+        }
+        if self.checks == 0 && !self.markers.is_empty() {
+            return;
+        }
+        for failure in &self.failures {
+            eprintln!("FAIL {}", failure);
+        }
+        if self.scripts.len() > 1 {
+            let ends = self.scripts.iter().skip(1).map(|(_, c, f)| (*c, *f));
+            let ends = ends.chain([(self.checks, self.failures.len())]);
+            for ((script, checks, failures), (checks_end, failures_end)) in
+                self.scripts.iter().zip(ends)
+            {
+                let (checks, failures) = (checks_end - checks, failures_end - failures);
+                eprintln!(
+                    "{}: {} of {} expectations passed",
+                    script,
+                    checks - failures,
+                    checks
+                );
+            }
+        }
+        eprintln!(
+            "{} of {} expectations passed",
+            self.checks - self.failures.len(),
+            self.checks
+        );
+    }
+}
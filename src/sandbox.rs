@@ -0,0 +1,127 @@
+use std::time::{Instant, SystemTime, UNIX_EPOCH};
+
+use rlua::{prelude::LuaError, Context, Function, Lua, MultiValue, StdLib, Value};
+use tracing::{debug, error, info, trace, warn};
+
+use crate::{debugger, logging::LUA};
+
+// libraries scripts never get by default; package stays disabled entirely
+// in favour of the sandboxed require
+const OPTIONAL: &[(&str, StdLib)] = &[
+    ("io", StdLib::IO),
+    ("os", StdLib::OS),
+    ("debug", StdLib::DEBUG),
+];
+
+pub fn stdlib(allow: &[String]) -> Result<StdLib, String> {
+    let mut libs =
+        StdLib::all().difference(StdLib::OS | StdLib::IO | StdLib::DEBUG | StdLib::PACKAGE);
+    for name in allow {
+        match OPTIONAL.iter().find(|(n, _)| *n == name.as_str()) {
+            Some((_, lib)) => libs |= *lib,
+            None => {
+                let valid: Vec<_> = OPTIONAL.iter().map(|(n, _)| *n).collect();
+                return Err(format!(
+                    "unknown Lua library {}, expected one of {}",
+                    name,
+                    valid.join(", ")
+                ));
+            }
+        }
+    }
+    Ok(libs)
+}
+
+pub fn new_lua(libs: StdLib) -> Lua {
+    let loosened: Vec<_> = OPTIONAL
+        .iter()
+        .filter(|(_, lib)| libs.contains(*lib))
+        .map(|(n, _)| *n)
+        .collect();
+    if !loosened.is_empty() {
+        warn!(
+            "Lua sandbox loosened, scripts have full access to: {}",
+            loosened.join(", ")
+        );
+    }
+
+    // the debugger always needs parts of the debug library, it is removed
+    // again below unless it was allowed
+    let lua = unsafe { Lua::unsafe_new_with(libs | StdLib::DEBUG) };
+    lua.context(|ctx| debugger::install(ctx, libs.contains(StdLib::DEBUG)))
+        .unwrap();
+    if !libs.contains(StdLib::OS) {
+        lua.context(safe_os).unwrap();
+    }
+    lua.context(logged_print).unwrap();
+    lua
+}
+
+// print and log(level, ...) go to marlua's log, marked as the script's and
+// with the frame they happened in, instead of straight to stdout
+fn logged_print(ctx: Context) -> Result<(), LuaError> {
+    ctx.globals().set(
+        "print",
+        ctx.create_function(|ctx, values: MultiValue| log_line("info", &line(ctx, values)?))?,
+    )?;
+    ctx.globals().set(
+        "log",
+        ctx.create_function(|ctx, (level, values): (String, MultiValue)| {
+            log_line(&level, &line(ctx, values)?)
+        })?,
+    )
+}
+
+// the values of print and log, tab separated like the standard print
+pub fn line(ctx: Context, values: MultiValue) -> Result<String, LuaError> {
+    let tostring: Function = ctx.globals().get("tostring")?;
+    let parts = values
+        .into_iter()
+        .map(|value| tostring.call::<_, String>(value))
+        .collect::<Result<Vec<_>, _>>()?;
+    Ok(parts.join("\t"))
+}
+
+pub fn log_line(level: &str, line: &str) -> Result<(), LuaError> {
+    match level {
+        "error" => error!(target: LUA, "{}", line),
+        "warn" => warn!(target: LUA, "{}", line),
+        "info" => info!(target: LUA, "{}", line),
+        "debug" => debug!(target: LUA, "{}", line),
+        "trace" => trace!(target: LUA, "{}", line),
+        _ => {
+            return Err(LuaError::RuntimeError(format!(
+                "unknown log level {}, expected error, warn, info, debug or trace",
+                level
+            )))
+        }
+    }
+    Ok(())
+}
+
+// os.clock and os.time without the rest of the os library
+//
+// os.clock returns seconds since the Lua state was created rather than
+// processor time; os.time only supports the form without arguments.
+pub fn safe_os(ctx: Context) -> Result<(), LuaError> {
+    let start = Instant::now();
+
+    let os = ctx.create_table()?;
+    os.set(
+        "clock",
+        ctx.create_function(move |_, ()| Ok(start.elapsed().as_secs_f64()))?,
+    )?;
+    os.set(
+        "time",
+        ctx.create_function(|_, table: Value| {
+            if !matches!(table, Value::Nil) {
+                return Err(LuaError::RuntimeError(
+                    "os.time only supports the current time in the sandbox".to_owned(),
+                ));
+            }
+            let now = SystemTime::now().duration_since(UNIX_EPOCH);
+            Ok(now.map_or(0, |d| d.as_secs() as i64))
+        })?,
+    )?;
+    ctx.globals().set("os", os)
+}
@@ -0,0 +1,256 @@
+use std::{
+    error::Error,
+    fmt::{self, Display},
+    fs,
+    io::{self, Read, Write},
+    path::Path,
+};
+
+use flate2::{read::DeflateDecoder, write::DeflateEncoder, Compression};
+
+use crate::{
+    machine::Machine,
+    memory::{self, RAM},
+};
+
+// File layout (all integers little-endian):
+//
+//   magic      8 bytes  "MARLUAST"
+//   version    u16
+//   rom hash   u32      CRC32 of the iNES file
+//   frames     u32      number of frames since power-on
+//   payload    DEFLATE  CPU RAM (since version 2, 2048 bytes), then one
+//                       controller byte per frame
+//
+// The inputs are enough to restore a state, the RAM is kept so states can
+// be compared without the ROM.
+const MAGIC: &[u8; 8] = b"MARLUAST";
+const VERSION: u16 = 2;
+const HEADER: usize = 18;
+
+#[derive(Debug)]
+pub enum StateError {
+    Io(io::Error),
+    NotAState,
+    UnknownVersion(u16),
+    RomMismatch { expected: u32, found: u32 },
+    Corrupt,
+    NoCheckpoint,
+}
+
+impl Display for StateError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            StateError::Io(e) => write!(f, "{}", e),
+            StateError::NotAState => write!(f, "not a marlua savestate"),
+            StateError::UnknownVersion(v) => {
+                write!(
+                    f,
+                    "unknown savestate version {} (expected 1 to {})",
+                    v, VERSION
+                )
+            }
+            StateError::RomMismatch { expected, found } => write!(
+                f,
+                "savestate was made with ROM {:08x}, but ROM {:08x} is loaded",
+                found, expected
+            ),
+            StateError::Corrupt => write!(f, "savestate is corrupt"),
+            StateError::NoCheckpoint => write!(f, "no checkpoint to resume from"),
+        }
+    }
+}
+
+impl Error for StateError {}
+
+impl From<io::Error> for StateError {
+    fn from(e: io::Error) -> Self {
+        StateError::Io(e)
+    }
+}
+
+#[derive(Clone)]
+pub struct Savestate {
+    pub rom_hash: u32,
+    pub inputs: Vec<u8>,
+    // None for version 1 states, which did not store it
+    pub ram: Option<Vec<u8>>,
+}
+
+impl Savestate {
+    pub fn capture(machine: &Machine) -> Self {
+        Self {
+            rom_hash: machine.hash(),
+            inputs: machine.inputs().to_vec(),
+            ram: Some(memory::read_ram(machine)),
+        }
+    }
+
+    pub fn frame(&self) -> usize {
+        self.inputs.len()
+    }
+
+    pub fn apply(self, machine: &mut Machine) -> Result<(), StateError> {
+        if self.rom_hash != machine.hash() {
+            return Err(StateError::RomMismatch {
+                expected: machine.hash(),
+                found: self.rom_hash,
+            });
+        }
+        machine.restore(self.inputs);
+        Ok(())
+    }
+
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut bytes = Vec::with_capacity(HEADER);
+        bytes.extend_from_slice(MAGIC);
+        // states read from version 1 files are written back without RAM
+        let version = if self.ram.is_some() { VERSION } else { 1 };
+        bytes.extend_from_slice(&version.to_le_bytes());
+        bytes.extend_from_slice(&self.rom_hash.to_le_bytes());
+        bytes.extend_from_slice(&(self.inputs.len() as u32).to_le_bytes());
+
+        let mut encoder = DeflateEncoder::new(bytes, Compression::best());
+        if let Some(ram) = &self.ram {
+            encoder.write_all(ram).unwrap();
+        }
+        encoder.write_all(&self.inputs).unwrap();
+        encoder.finish().unwrap()
+    }
+
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self, StateError> {
+        if bytes.len() < HEADER || &bytes[0..8] != MAGIC {
+            return Err(StateError::NotAState);
+        }
+
+        let version = u16::from_le_bytes([bytes[8], bytes[9]]);
+        if version == 0 || version > VERSION {
+            return Err(StateError::UnknownVersion(version));
+        }
+
+        let rom_hash = u32::from_le_bytes(bytes[10..14].try_into().unwrap());
+        let frames = u32::from_le_bytes(bytes[14..18].try_into().unwrap()) as usize;
+
+        // the header is not trusted with an allocation, and one byte more
+        // than expected is enough to tell the payload is too long
+        let expected = if version == 1 { 0 } else { RAM } + frames;
+        let mut payload = Vec::new();
+        DeflateDecoder::new(&bytes[HEADER..])
+            .take(expected as u64 + 1)
+            .read_to_end(&mut payload)
+            .map_err(|_| StateError::Corrupt)?;
+        if payload.len() != expected {
+            return Err(StateError::Corrupt);
+        }
+        let ram = match version {
+            1 => None,
+            _ => Some(payload.drain(..RAM).collect()),
+        };
+
+        Ok(Self {
+            rom_hash,
+            inputs: payload,
+            ram,
+        })
+    }
+
+    pub fn write(&self, path: impl AsRef<Path>) -> Result<(), StateError> {
+        let path = path.as_ref();
+        if let Some(dir) = path.parent() {
+            fs::create_dir_all(dir)?;
+        }
+        fs::write(path, self.to_bytes())?;
+        Ok(())
+    }
+
+    pub fn read(path: impl AsRef<Path>) -> Result<Self, StateError> {
+        Self::from_bytes(&fs::read(path)?)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn state(frames: usize) -> Savestate {
+        Savestate {
+            rom_hash: 0x1234_5678,
+            inputs: (0..frames).map(|i| i as u8).collect(),
+            ram: Some(vec![0xAA; RAM]),
+        }
+    }
+
+    #[test]
+    fn round_trip() {
+        let read = Savestate::from_bytes(&state(300).to_bytes()).unwrap();
+        assert_eq!(read.rom_hash, 0x1234_5678);
+        assert_eq!(read.inputs, state(300).inputs);
+        assert_eq!(read.ram, state(300).ram);
+    }
+
+    #[test]
+    fn truncated() {
+        let bytes = state(300).to_bytes();
+        for len in [0, 8, HEADER - 1] {
+            assert!(matches!(
+                Savestate::from_bytes(&bytes[..len]),
+                Err(StateError::NotAState)
+            ));
+        }
+        assert!(matches!(
+            Savestate::from_bytes(&bytes[..bytes.len() / 2]),
+            Err(StateError::Corrupt)
+        ));
+    }
+
+    #[test]
+    fn frame_count_mismatch() {
+        for frames in [0, 299, 301, u32::MAX] {
+            let mut bytes = state(300).to_bytes();
+            bytes[14..18].copy_from_slice(&frames.to_le_bytes());
+            assert!(matches!(
+                Savestate::from_bytes(&bytes),
+                Err(StateError::Corrupt)
+            ));
+        }
+    }
+
+    #[test]
+    fn garbage_header() {
+        let mut bytes = state(10).to_bytes();
+        bytes[0] = b'X';
+        assert!(matches!(
+            Savestate::from_bytes(&bytes),
+            Err(StateError::NotAState)
+        ));
+
+        let mut bytes = state(10).to_bytes();
+        bytes[8..10].copy_from_slice(&9u16.to_le_bytes());
+        assert!(matches!(
+            Savestate::from_bytes(&bytes),
+            Err(StateError::UnknownVersion(9))
+        ));
+
+        let mut bytes = state(10).to_bytes();
+        bytes.truncate(HEADER);
+        bytes.extend_from_slice(&[0xFF; 64]);
+        assert!(matches!(
+            Savestate::from_bytes(&bytes),
+            Err(StateError::Corrupt)
+        ));
+    }
+
+    #[test]
+    fn deflate_bomb() {
+        // a megabyte of zeroes behind a header claiming a single frame
+        let mut bytes = state(1).to_bytes();
+        bytes.truncate(HEADER);
+        let mut encoder = DeflateEncoder::new(bytes, Compression::best());
+        encoder.write_all(&vec![0; 1 << 20]).unwrap();
+        let bytes = encoder.finish().unwrap();
+        assert!(matches!(
+            Savestate::from_bytes(&bytes),
+            Err(StateError::Corrupt)
+        ));
+    }
+}
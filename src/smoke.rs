@@ -0,0 +1,178 @@
+use std::{
+    fs,
+    panic::{self, AssertUnwindSafe},
+    path::{Path, PathBuf},
+    sync::{atomic::AtomicU8, Arc, Mutex},
+    thread,
+};
+
+use crate::{
+    capture::{Capture, Region, HEIGHT, WIDTH},
+    failure::panic_message,
+    machine::Machine,
+    startup,
+};
+
+// how often a ROM's frame is looked at for anything but one color
+const CHECK_EVERY: usize = 10;
+
+pub struct Outcome {
+    rom: String,
+    // why it could not be loaded or where it panicked
+    error: Option<String>,
+    loaded: bool,
+    // whether any frame checked had more than one color
+    drew: bool,
+    frames: usize,
+    // FNV-1a of the last frame like hash_region, once it ran all frames
+    hash: Option<u64>,
+}
+
+// `marlua smoke DIR`: every .nes file in DIR, headless and without input for
+// `frames` frames, on `threads` threads. A panic only ends its own ROM, so
+// one unsupported mapper does not stop the rest.
+pub fn run(dir: &str, frames: usize, threads: usize) -> Result<Vec<Outcome>, String> {
+    let mut roms: Vec<PathBuf> = fs::read_dir(dir)
+        .map_err(|e| format!("{}: {}", dir, e))?
+        .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+        .filter(|path| {
+            let extension = path.extension().and_then(|e| e.to_str());
+            extension.map_or(false, |e| e.eq_ignore_ascii_case("nes"))
+        })
+        .collect();
+    if roms.is_empty() {
+        return Err(format!("{} has no .nes files", dir));
+    }
+    roms.sort();
+
+    // the messages are in the results, not on stderr in between
+    let hook = panic::take_hook();
+    panic::set_hook(Box::new(|_| {}));
+    let queue = Mutex::new(roms.into_iter().enumerate());
+    let results = Mutex::new(Vec::new());
+    thread::scope(|scope| {
+        for _ in 0..threads.max(1) {
+            scope.spawn(|| loop {
+                let Some((i, rom)) = queue.lock().unwrap().next() else {
+                    break;
+                };
+                let result = smoke(&rom, frames);
+                results.lock().unwrap().push((i, result));
+            });
+        }
+    });
+    panic::set_hook(hook);
+
+    let mut results = results.into_inner().unwrap();
+    results.sort_by_key(|(i, _)| *i);
+    Ok(results.into_iter().map(|(_, result)| result).collect())
+}
+
+fn smoke(path: &Path, frames: usize) -> Outcome {
+    let mut result = Outcome {
+        rom: path.to_string_lossy().into_owned(),
+        error: None,
+        loaded: false,
+        drew: false,
+        frames: 0,
+        hash: None,
+    };
+    if let Err(e) = startup::check(&result.rom, &[]) {
+        result.error = Some(e);
+        return result;
+    }
+    let rom = match fs::read(path) {
+        Ok(rom) => rom,
+        Err(e) => {
+            result.error = Some(e.to_string());
+            return result;
+        }
+    };
+
+    let caught = panic::catch_unwind(AssertUnwindSafe(|| {
+        let mut machine = Machine::new(rom, Arc::new(AtomicU8::new(0)));
+        result.loaded = true;
+        let mut capture = Capture::new();
+        for frame in 1..=frames {
+            machine.next_frame();
+            result.frames = frame;
+            if !result.drew && (frame % CHECK_EVERY == 0 || frame == frames) {
+                let pixels = capture.frame(&mut machine);
+                result.drew = pixels.chunks_exact(4).any(|p| p != &pixels[..4]);
+            }
+        }
+        let region = Region::new(0, 0, WIDTH, HEIGHT).unwrap();
+        result.hash = Some(capture.hash(&mut machine, region));
+    }));
+    if let Err(payload) = caught {
+        let message = panic_message(payload.as_ref());
+        result.error = Some(format!(
+            "panicked at frame {}: {}",
+            result.frames + 1,
+            message
+        ));
+    }
+    result
+}
+
+pub fn passed(results: &[Outcome]) -> bool {
+    results.iter().all(|result| result.error.is_none())
+}
+
+pub fn print(results: &[Outcome]) {
+    let width = results.iter().map(|r| r.rom.len()).max().unwrap_or(0);
+    println!(
+        "{:width$}  loads  draws  frames  hash              error",
+        "rom"
+    );
+    for result in results {
+        let yes = |b: bool| if b { "yes" } else { "no" };
+        let hash = result.hash.map_or(String::new(), |h| format!("{:016X}", h));
+        println!(
+            "{:width$}  {:5}  {:5}  {:6}  {:16}  {}",
+            result.rom,
+            yes(result.loaded),
+            yes(result.drew),
+            result.frames,
+            hash,
+            result.error.as_deref().unwrap_or_default()
+        );
+    }
+    let failed = results.iter().filter(|r| r.error.is_some()).count();
+    println!(
+        "{} of {} ROMs ran without errors",
+        results.len() - failed,
+        results.len()
+    );
+}
+
+pub fn json(results: &[Outcome]) -> String {
+    let string = |s: &str| {
+        let mut quoted = String::from("\"");
+        for c in s.chars() {
+            match c {
+                '"' => quoted.push_str("\\\""),
+                '\\' => quoted.push_str("\\\\"),
+                c if (c as u32) < 0x20 => quoted.push_str(&format!("\\u{:04x}", c as u32)),
+                c => quoted.push(c),
+            }
+        }
+        quoted.push('"');
+        quoted
+    };
+    let rows: Vec<_> = results
+        .iter()
+        .map(|result| {
+            format!(
+                "  {{\"rom\": {}, \"loaded\": {}, \"drew\": {}, \"frames\": {}, \"hash\": {}, \"error\": {}}}",
+                string(&result.rom),
+                result.loaded,
+                result.drew,
+                result.frames,
+                result.hash.map_or("null".to_owned(), |h| string(&format!("{:016X}", h))),
+                result.error.as_deref().map_or("null".to_owned(), string)
+            )
+        })
+        .collect();
+    format!("[\n{}\n]\n", rows.join(",\n"))
+}
@@ -0,0 +1,81 @@
+use std::num::NonZeroU32;
+
+use fastnes::ppu::Color;
+use softbuffer::{Context, Surface};
+use winit::window::Window;
+
+use crate::{
+    letterbox::Scene,
+    render::{Overlays, Present},
+};
+
+// Draws the frame on the CPU for when OpenGL is not available, such as over
+// X forwarding or on old VMs.
+//
+// The frame is scaled up by the largest whole factor that fits the window
+// and drawn in its top left corner, or where --capture-layout puts it.
+// There is no femtovg canvas, so nothing else is drawn.
+pub struct Software {
+    // the surface must not outlive its context
+    surface: Surface,
+    _context: Context,
+}
+
+impl Software {
+    pub fn new(window: &Window) -> Result<Self, String> {
+        let context = unsafe { Context::new(window) }.map_err(|e| e.to_string())?;
+        let surface = unsafe { Surface::new(&context, window) }.map_err(|e| e.to_string())?;
+        Ok(Self {
+            surface,
+            _context: context,
+        })
+    }
+}
+
+impl Present for Software {
+    fn present(
+        &mut self,
+        window: &Window,
+        frame: &[Color; 61440],
+        scene: Scene,
+        _overlays: Overlays,
+    ) -> Result<(), String> {
+        let size = window.inner_size();
+        let (Some(width), Some(height)) =
+            (NonZeroU32::new(size.width), NonZeroU32::new(size.height))
+        else {
+            // minimized
+            return Ok(());
+        };
+        self.surface
+            .resize(width, height)
+            .map_err(|e| e.to_string())?;
+
+        let (width, height) = (size.width as usize, size.height as usize);
+        let rgb = |r: u8, g: u8, b: u8| (r as u32) << 16 | (g as u32) << 8 | b as u32;
+        let (left, top, scale) = match scene == Scene::default() {
+            true => (0, 0, (width / 256).min(height / 240).max(1)),
+            false => {
+                let (x, y, w, _) = scene.image;
+                (x as usize, y as usize, (w as usize / 256).max(1))
+            }
+        };
+        let mut buffer = self.surface.buffer_mut().map_err(|e| e.to_string())?;
+        let [r, g, b] = scene.background;
+        buffer.fill(rgb(r, g, b));
+        for y in 0..(240 * scale).min(height.saturating_sub(top)) {
+            let src = &frame[y / scale * 256..][..256];
+            let row = &mut buffer[(top + y) * width..][..width];
+            let dst = &mut row[left.min(width)..(left + 256 * scale).min(width)];
+            for (x, pixel) in dst.iter_mut().enumerate() {
+                let color = src[x / scale];
+                *pixel = rgb(color.r, color.g, color.b);
+            }
+        }
+        buffer.present().map_err(|e| e.to_string())
+    }
+
+    fn overlays(&self) -> bool {
+        false
+    }
+}
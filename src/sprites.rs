@@ -0,0 +1,198 @@
+use femtovg::{renderer::OpenGl, Canvas, Color, FontId, Paint, Path};
+use winit::event::VirtualKeyCode;
+
+use crate::{views::Views, IMAGE_RECT};
+
+pub const TITLE: &str = "Sprites (PageUp/PageDown)";
+pub const HEADER: &str = " #   x   y  tile attr";
+
+// where the list starts, right of the game image
+pub const PANEL_X: f32 = 264.0;
+pub const ROW_HEIGHT: f32 = 18.0;
+// the first row's baseline
+const FIRST_ROW: f32 = 48.0;
+
+// rows at or below this are hidden
+const HIDDEN_Y: u8 = 0xEF;
+
+// One of the 64 OAM entries.
+#[derive(Clone, Copy)]
+pub struct Sprite {
+    pub x: u8,
+    // the top of the sprite minus one, as stored in OAM
+    pub y: u8,
+    pub tile: u8,
+    pub attributes: u8,
+}
+
+impl Sprite {
+    pub fn visible(&self) -> bool {
+        self.y < HIDDEN_Y
+    }
+
+    // in NES pixels; sprites are assumed to be 8x8 since the size is set
+    // in PPUCTRL, which cannot be read back
+    pub fn rect(&self) -> (u32, u32, u32, u32) {
+        (self.x as u32, self.y as u32 + 1, 8, 8)
+    }
+
+    fn contains(&self, (x, y): (u32, u32)) -> bool {
+        let (left, top, w, h) = self.rect();
+        self.visible() && (left..left + w).contains(&x) && (top..top + h).contains(&y)
+    }
+}
+
+// The OAM viewer, a list of all sprites next to the game with boxes around
+// the visible ones. The sprite under the mouse cursor is highlighted.
+pub struct SpriteViewer {
+    sprites: Vec<Sprite>,
+    scroll: usize,
+    cursor: Option<(u32, u32)>,
+    rows: usize,
+    // where the game is in the window, see letterbox.rs
+    image: (f32, f32, f32, f32),
+}
+
+impl SpriteViewer {
+    pub fn new() -> Self {
+        Self {
+            sprites: Vec::new(),
+            scroll: 0,
+            cursor: None,
+            rows: 1,
+            image: IMAGE_RECT,
+        }
+    }
+
+    // pick up what the emulator published; `cursor` is in NES pixels and
+    // `image` the game's rect in the window
+    pub fn update(
+        &mut self,
+        views: &Views,
+        cursor: Option<(u32, u32)>,
+        image: (f32, f32, f32, f32),
+    ) {
+        if let Some(oam) = views.take_sprites() {
+            self.sprites = oam
+                .chunks_exact(4)
+                .map(|e| Sprite {
+                    y: e[0],
+                    tile: e[1],
+                    attributes: e[2],
+                    x: e[3],
+                })
+                .collect();
+        }
+        self.cursor = cursor;
+        self.image = image;
+    }
+
+    pub fn key(&mut self, key: VirtualKeyCode) {
+        let last = self.sprites.len().saturating_sub(self.rows);
+        match key {
+            VirtualKeyCode::PageUp => self.scroll = self.scroll.saturating_sub(self.rows),
+            VirtualKeyCode::PageDown => self.scroll = (self.scroll + self.rows).min(last),
+            _ => {}
+        }
+    }
+
+    // how many rows fit in a window of this height, call before drawing
+    pub fn fit(&mut self, height: f32) {
+        self.rows = (((height - FIRST_ROW) / ROW_HEIGHT) as usize).max(1);
+        let last = self.sprites.len().saturating_sub(self.rows);
+        self.scroll = self.scroll.min(last);
+    }
+
+    fn hovered(&self) -> Option<usize> {
+        let cursor = self.cursor?;
+        self.sprites.iter().position(|s| s.contains(cursor))
+    }
+
+    // the rows to list with their baselines and whether they are
+    // highlighted, for any renderer
+    pub fn rows(&self) -> Vec<(f32, String, bool)> {
+        let hovered = self.hovered();
+        self.sprites
+            .iter()
+            .enumerate()
+            .skip(self.scroll)
+            .take(self.rows)
+            .enumerate()
+            .map(|(row, (i, s))| {
+                let text = format!(
+                    "{:2} {:3} {:3}   {:02X}   {:02X}{}",
+                    i,
+                    s.x,
+                    s.y,
+                    s.tile,
+                    s.attributes,
+                    if s.visible() { "" } else { "  hidden" }
+                );
+                (
+                    FIRST_ROW + row as f32 * ROW_HEIGHT,
+                    text,
+                    hovered == Some(i),
+                )
+            })
+            .collect()
+    }
+
+    // outlines of the visible sprites in window pixels
+    pub fn boxes(&self) -> Vec<((f32, f32, f32, f32), bool)> {
+        let hovered = self.hovered();
+        let (ix, iy, iw, ih) = self.image;
+        let (sx, sy) = (iw / 256.0, ih / 240.0);
+        self.sprites
+            .iter()
+            .enumerate()
+            .filter(|(_, s)| s.visible())
+            .map(|(i, s)| {
+                let (x, y, w, h) = s.rect();
+                let rect = (
+                    ix + x as f32 * sx,
+                    iy + y as f32 * sy,
+                    w as f32 * sx,
+                    h as f32 * sy,
+                );
+                (rect, hovered == Some(i))
+            })
+            .collect()
+    }
+
+    pub fn draw(&self, canvas: &mut Canvas<OpenGl>, font: FontId) {
+        let (width, height) = (canvas.width() as f32, canvas.height() as f32);
+
+        for ((x, y, w, h), hovered) in self.boxes() {
+            let mut path = Path::new();
+            path.rect(x + 0.5, y + 0.5, w - 1.0, h - 1.0);
+            let mut paint = Paint::color(outline(hovered));
+            paint.set_line_width(1.0);
+            canvas.stroke_path(&mut path, &paint);
+        }
+
+        let mut panel = Path::new();
+        panel.rect(PANEL_X, 0.0, width - PANEL_X, height);
+        canvas.fill_path(&mut panel, &Paint::color(Color::rgba(24, 24, 32, 220)));
+
+        let mut paint = Paint::color(Color::white());
+        paint.set_font(&[font]);
+        paint.set_font_size(14.0);
+        let _ = canvas.fill_text(PANEL_X + 8.0, 20.0, TITLE, &paint);
+        let _ = canvas.fill_text(PANEL_X + 8.0, 36.0, HEADER, &paint);
+        for (y, text, hovered) in self.rows() {
+            if hovered {
+                let mut highlight = Path::new();
+                highlight.rect(PANEL_X, y - 14.0, width - PANEL_X, ROW_HEIGHT);
+                canvas.fill_path(&mut highlight, &Paint::color(Color::rgb(96, 96, 32)));
+            }
+            let _ = canvas.fill_text(PANEL_X + 8.0, y, &text, &paint);
+        }
+    }
+}
+
+pub fn outline(hovered: bool) -> Color {
+    match hovered {
+        true => Color::rgb(255, 255, 0),
+        false => Color::rgb(255, 0, 255),
+    }
+}
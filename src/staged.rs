@@ -0,0 +1,176 @@
+use std::sync::atomic::{AtomicU8, Ordering};
+
+use crate::{buttons, machine::Machine};
+
+// The controller input scripts and hotkeys change between frames, which the
+// driver commits to the controller right before it steps the next one. It
+// also remembers the buttons that went down since the last commit, so a
+// press that is released again before any frame saw it can be reported.
+#[derive(Default)]
+pub struct Staged {
+    input: AtomicU8,
+    pressed: AtomicU8,
+}
+
+impl Staged {
+    pub fn load(&self) -> u8 {
+        self.input.load(Ordering::Relaxed)
+    }
+
+    // from scripts
+    pub fn store(&self, input: u8) {
+        let before = self.input.swap(input, Ordering::Relaxed);
+        self.pressed.fetch_or(input & !before, Ordering::Relaxed);
+    }
+
+    // from recordings and hotkeys, which mean exactly this input
+    pub fn reset(&self, input: u8) {
+        self.input.store(input, Ordering::Relaxed);
+        self.pressed.store(0, Ordering::Relaxed);
+    }
+
+    // the input for the next frame, and the buttons pressed since the last
+    // commit that it does not hold anymore
+    pub fn commit(&self) -> (u8, u8) {
+        let input = self.load();
+        let pressed = self.pressed.swap(0, Ordering::Relaxed);
+        (input, pressed & !input)
+    }
+
+    // commits and steps the machine a frame with the input, the only way the
+    // controller changes; returns what commit does
+    pub fn step(&self, machine: &mut Machine) -> (u8, u8) {
+        let (input, dropped) = self.commit();
+        machine.set_input(input);
+        machine.next_frame();
+        (input, dropped)
+    }
+}
+
+// Presses released before any frame saw them, see Staged::commit. A press
+// and release between the same two frames never reach the game, the script
+// most likely misses a wait between them; with lua.strict_inputs the first
+// one is an error for wait to raise.
+pub struct Dropped {
+    count: usize,
+    strict: bool,
+    error: Option<String>,
+}
+
+impl Dropped {
+    pub fn new(strict: bool) -> Self {
+        Self {
+            count: 0,
+            strict,
+            error: None,
+        }
+    }
+
+    // the buttons commit returned as dropped before stepping `frame`; returns
+    // the warning for them
+    pub fn add(&mut self, frame: usize, bits: u8) -> String {
+        let names: Vec<_> = buttons::pressed(bits).map(|b| b.name()).collect();
+        let message = format!(
+            "frame {}: {} pressed and released before any frame saw it",
+            frame,
+            names.join(", ")
+        );
+        self.count += names.len();
+        if self.strict && self.error.is_none() {
+            self.error = Some(message.clone());
+        }
+        message
+    }
+
+    // the number of buttons dropped so far
+    pub fn count(&self) -> usize {
+        self.count
+    }
+
+    pub fn take_error(&mut self) -> Option<String> {
+        self.error.take()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{buttons::Button, testing};
+
+    // a press staged between frames N and N + 1 is the input of frame
+    // N + 1, inputs()[N], and of no frame before it
+    #[test]
+    fn timing() {
+        let staged = Staged::default();
+        let mut machine = testing::machine();
+        for _ in 0..10 {
+            staged.step(&mut machine);
+        }
+
+        staged.store(Button::A.bit());
+        // whatever the machine had been told so far is overridden
+        machine.set_input(Button::B.bit());
+        assert_eq!(machine.frame(), 10);
+        assert_eq!(staged.step(&mut machine), (Button::A.bit(), 0));
+        assert_eq!(machine.inputs()[..10], [0; 10]);
+        assert_eq!(machine.inputs()[10], Button::A.bit());
+
+        // held until released
+        staged.step(&mut machine);
+        staged.store(0);
+        staged.step(&mut machine);
+        assert_eq!(
+            machine.inputs()[10..],
+            [Button::A.bit(), Button::A.bit(), 0]
+        );
+    }
+
+    #[test]
+    fn commit() {
+        let a = Button::A.bit();
+        let b = Button::B.bit();
+        let staged = Staged::default();
+
+        // pressed and released before the commit
+        staged.store(a);
+        staged.store(0);
+        assert_eq!(staged.commit(), (0, a));
+        assert_eq!(staged.commit(), (0, 0));
+
+        // recordings and hotkeys forget what scripts pressed
+        staged.store(a);
+        staged.reset(0);
+        assert_eq!(staged.commit(), (0, 0));
+
+        // a press that is held again, or still, is no drop
+        staged.store(a);
+        staged.store(0);
+        staged.store(a | b);
+        assert_eq!(staged.commit(), (a | b, 0));
+        staged.store(a);
+        assert_eq!(staged.commit(), (a, 0));
+    }
+
+    #[test]
+    fn dropped() {
+        let a = Button::A.bit();
+        let b = Button::B.bit();
+
+        let mut lenient = Dropped::new(false);
+        lenient.add(3, a);
+        assert_eq!(lenient.count(), 1);
+        assert_eq!(lenient.take_error(), None);
+
+        let mut strict = Dropped::new(true);
+        let message = strict.add(5, a | b);
+        assert_eq!(
+            message,
+            "frame 5: A, B pressed and released before any frame saw it"
+        );
+        strict.add(6, b);
+        assert_eq!(strict.count(), 3);
+        // the first one is raised
+        assert_eq!(strict.take_error(), Some(message));
+        assert_eq!(strict.take_error(), None);
+    }
+}
@@ -0,0 +1,111 @@
+use std::{fs, path::PathBuf, time::Duration};
+
+use femtovg::{renderer::OpenGl, Canvas, Color, FontId, Paint, Path};
+use winit::event::VirtualKeyCode;
+
+use crate::overlay;
+
+pub const TITLE: &str = "marlua cannot start, retrying every second:";
+
+// how often the emulator thread looks at the files again
+pub const RETRY: Duration = Duration::from_secs(1);
+
+// iNES files start with "NES" and an MS-DOS end of file
+const MAGIC: &[u8] = b"NES\x1a";
+const HEADER_LEN: usize = 16;
+
+// Checks the ROM and the scripts before anything is emulated, so a wrong
+// path is an error message rather than a failure halfway through setting
+// up the run.
+pub fn check(rom: &str, scripts: &[&str]) -> Result<(), String> {
+    let data = fs::read(rom).map_err(|e| format!("cannot read the ROM {}: {}", rom, e))?;
+    if data.len() < HEADER_LEN || !data.starts_with(MAGIC) {
+        return Err(format!("{} is not an iNES ROM (.nes file)", rom));
+    }
+    for script in scripts {
+        match fs::metadata(script) {
+            Ok(metadata) if metadata.is_file() => {}
+            Ok(_) => return Err(format!("the script {} is not a file", script)),
+            Err(e) => return Err(format!("cannot read the script {}: {}", script, e)),
+        }
+    }
+    Ok(())
+}
+
+pub enum Choice {
+    Open(PathBuf),
+    Quit,
+}
+
+const ENTRIES: [&str; 3] = ["Open another ROM...", "Open another script...", "Quit"];
+
+// The window's screen for a failed check, until the files are fixed, picked
+// here or dropped on the window.
+pub struct ErrorScreen {
+    message: String,
+    selected: usize,
+}
+
+impl ErrorScreen {
+    pub fn new(message: String) -> Self {
+        Self {
+            message,
+            selected: 0,
+        }
+    }
+
+    pub fn message(&self) -> &str {
+        &self.message
+    }
+
+    pub fn key(&mut self, key: VirtualKeyCode) -> Option<Choice> {
+        let len = ENTRIES.len();
+        match key {
+            VirtualKeyCode::Up => self.selected = (self.selected + len - 1) % len,
+            VirtualKeyCode::Down => self.selected = (self.selected + 1) % len,
+            VirtualKeyCode::Return => {
+                let (title, name, extension) = match self.selected {
+                    0 => ("Open ROM", "NES ROM", "nes"),
+                    1 => ("Open script", "Lua script", "lua"),
+                    _ => return Some(Choice::Quit),
+                };
+                return rfd::FileDialog::new()
+                    .set_title(title)
+                    .add_filter(name, &[extension])
+                    .pick_file()
+                    .map(Choice::Open);
+            }
+            _ => {}
+        }
+        None
+    }
+
+    // the message's lines, then the entries and which one is selected
+    pub fn lines(&self) -> (Vec<&str>, Vec<String>, usize) {
+        let entries = ENTRIES.iter().map(|&e| e.to_owned()).collect();
+        (self.message.lines().collect(), entries, self.selected)
+    }
+
+    // where the entries start below `lines` lines of message
+    pub fn entries_y(lines: usize) -> f32 {
+        40.0 + lines as f32 * 18.0
+    }
+
+    pub fn draw(&self, canvas: &mut Canvas<OpenGl>, font: FontId) {
+        let (width, height) = (canvas.width() as f32, canvas.height() as f32);
+        let mut background = Path::new();
+        background.rect(0.0, 0.0, width, height);
+        canvas.fill_path(&mut background, &Paint::color(Color::rgb(64, 16, 16)));
+
+        let mut paint = Paint::color(Color::white());
+        paint.set_font(&[font]);
+        paint.set_font_size(14.0);
+        let (lines, entries, selected) = self.lines();
+        for (i, line) in lines.iter().enumerate() {
+            let _ = canvas.fill_text(16.0, 48.0 + i as f32 * 18.0, line, &paint);
+        }
+        let y = Self::entries_y(lines.len());
+        overlay::list(canvas, font, (8.0, y, width - 16.0), "", &entries, selected);
+        let _ = canvas.fill_text(16.0, 28.0, TITLE, &paint);
+    }
+}
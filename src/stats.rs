@@ -0,0 +1,251 @@
+use std::{
+    collections::VecDeque,
+    sync::{
+        atomic::{AtomicU8, AtomicUsize, Ordering},
+        Mutex,
+    },
+    time::{Duration, Instant},
+};
+
+use crate::{
+    cli::Vsync,
+    frametimes::{self, FrameTimes},
+};
+
+// What the window can show about the run, see Stat for the names. The
+// emulator updates it as it goes; the window samples it once a second.
+#[derive(Default)]
+pub struct Stats {
+    frame: AtomicUsize,
+    // frames the emulator thread finished too late to keep 60 per second
+    missed: AtomicUsize,
+    // button presses no frame saw, see Driver::drop_input
+    dropped: AtomicUsize,
+    // file names of the ROM and the script
+    names: Mutex<(String, String)>,
+    // set by the window once it is open
+    vsync: Mutex<Option<Vsync>>,
+    times: Mutex<FrameTimes>,
+    // the savestate slot last saved or loaded, 0 before that
+    slot: AtomicU8,
+    // from the emulator publishing a frame to the window presenting it,
+    // for the last frametimes::LEN frames the window presented
+    latency: Mutex<VecDeque<Duration>>,
+}
+
+impl Stats {
+    pub fn set_frame(&self, frame: usize) {
+        self.frame.store(frame, Ordering::Relaxed);
+    }
+
+    pub fn set_missed(&self, missed: usize) {
+        self.missed.store(missed, Ordering::Relaxed);
+    }
+
+    pub fn set_dropped(&self, dropped: usize) {
+        self.dropped.store(dropped, Ordering::Relaxed);
+    }
+
+    pub fn record_times(&self, emulation: Duration, lua: Duration, slack: Duration) {
+        self.times.lock().unwrap().record(emulation, lua, slack);
+    }
+
+    pub fn frame_times(&self) -> FrameTimes {
+        let mut times = *self.times.lock().unwrap();
+        times.latency = self.latency();
+        times
+    }
+
+    pub fn record_latency(&self, latency: Duration) {
+        let mut recent = self.latency.lock().unwrap();
+        if recent.len() == frametimes::LEN {
+            recent.pop_front();
+        }
+        recent.push_back(latency);
+    }
+
+    // the median of the recent frames, None before the first
+    pub fn latency(&self) -> Option<Duration> {
+        let mut recent: Vec<_> = self.latency.lock().unwrap().iter().copied().collect();
+        recent.sort_unstable();
+        recent.get(recent.len() / 2).copied()
+    }
+
+    pub fn set_slot(&self, slot: u8) {
+        self.slot.store(slot, Ordering::Relaxed);
+    }
+
+    pub fn slot(&self) -> Option<u8> {
+        Some(self.slot.load(Ordering::Relaxed)).filter(|&slot| slot != 0)
+    }
+
+    pub fn set_vsync(&self, vsync: Option<Vsync>) {
+        *self.vsync.lock().unwrap() = vsync;
+    }
+
+    pub fn set_names(&self, rom: &str, script: &str) {
+        let name = |path: &str| {
+            std::path::Path::new(path)
+                .file_name()
+                .map_or_else(|| path.to_owned(), |n| n.to_string_lossy().into_owned())
+        };
+        *self.names.lock().unwrap() = (name(rom), name(script));
+    }
+
+    pub fn names(&self) -> (String, String) {
+        self.names.lock().unwrap().clone()
+    }
+}
+
+// Every placeholder of window.title_format. Adding a stat here and to
+// Sample::value is all it takes to make a new one available.
+#[derive(Clone, Copy)]
+enum Stat {
+    Rom,
+    Script,
+    Frame,
+    FpsEmu,
+    Speed,
+    Status,
+    Vsync,
+    Missed,
+    Dropped,
+    Latency,
+}
+
+const STATS: &[(&str, Stat)] = &[
+    ("rom", Stat::Rom),
+    ("script", Stat::Script),
+    ("frame", Stat::Frame),
+    ("fps_emu", Stat::FpsEmu),
+    ("speed", Stat::Speed),
+    ("status", Stat::Status),
+    ("vsync", Stat::Vsync),
+    ("missed", Stat::Missed),
+    ("dropped", Stat::Dropped),
+    ("latency", Stat::Latency),
+];
+
+// The stats at one point in time, with the emulation rate since the last
+// sample.
+pub struct Sample {
+    rom: String,
+    script: String,
+    frame: usize,
+    fps_emu: f64,
+    missed: usize,
+    dropped: usize,
+    vsync: Option<Vsync>,
+    latency: Option<Duration>,
+    time: Instant,
+    // set by the window, from Pause
+    pub speed: f64,
+    pub status: Option<String>,
+}
+
+impl Sample {
+    pub fn take(stats: &Stats, last: Option<&Sample>) -> Self {
+        let frame = stats.frame.load(Ordering::Relaxed);
+        let time = Instant::now();
+        let fps_emu = match last {
+            Some(last) => {
+                let elapsed = time.duration_since(last.time).as_secs_f64();
+                let frames = frame.saturating_sub(last.frame) as f64;
+                if elapsed > 0.0 {
+                    frames / elapsed
+                } else {
+                    last.fps_emu
+                }
+            }
+            None => 0.0,
+        };
+        let (rom, script) = stats.names.lock().unwrap().clone();
+        Self {
+            rom,
+            script,
+            frame,
+            fps_emu,
+            missed: stats.missed.load(Ordering::Relaxed),
+            dropped: stats.dropped.load(Ordering::Relaxed),
+            vsync: *stats.vsync.lock().unwrap(),
+            latency: stats.latency(),
+            time,
+            speed: 1.0,
+            status: None,
+        }
+    }
+
+    pub fn age(&self) -> Duration {
+        self.time.elapsed()
+    }
+
+    fn value(&self, stat: Stat) -> String {
+        match stat {
+            Stat::Rom => self.rom.clone(),
+            Stat::Script => self.script.clone(),
+            Stat::Frame => self.frame.to_string(),
+            Stat::FpsEmu => format!("{:.0}", self.fps_emu),
+            Stat::Speed => format!("{}x", self.speed),
+            Stat::Status => self.status.clone().unwrap_or_default(),
+            Stat::Missed => self.missed.to_string(),
+            Stat::Dropped => self.dropped.to_string(),
+            Stat::Vsync => self.vsync.map_or("default", Vsync::name).to_owned(),
+            Stat::Latency => match self.latency {
+                Some(latency) => format!("{:.1}ms", latency.as_secs_f64() * 1000.0),
+                None => "-".to_owned(),
+            },
+        }
+    }
+}
+
+enum Part {
+    Text(String),
+    Stat(Stat),
+}
+
+// window.title_format, e.g. "{rom} - frame {frame} at {fps_emu} fps"
+pub struct Title {
+    parts: Vec<Part>,
+}
+
+impl Title {
+    // unknown placeholders are kept as text and returned for a warning
+    pub fn parse(format: &str) -> (Self, Vec<String>) {
+        let mut parts = Vec::new();
+        let mut unknown = Vec::new();
+        let mut rest = format;
+        while let Some(start) = rest.find('{') {
+            let Some(len) = rest[start..].find('}') else {
+                break;
+            };
+            let name = &rest[start + 1..start + len];
+            parts.push(Part::Text(rest[..start].to_owned()));
+            match STATS.iter().find(|(n, _)| *n == name) {
+                Some((_, stat)) => parts.push(Part::Stat(*stat)),
+                None => {
+                    unknown.push(name.to_owned());
+                    parts.push(Part::Text(rest[start..=start + len].to_owned()));
+                }
+            }
+            rest = &rest[start + len + 1..];
+        }
+        parts.push(Part::Text(rest.to_owned()));
+        (Self { parts }, unknown)
+    }
+
+    pub fn render(&self, sample: &Sample) -> String {
+        let mut title = String::new();
+        for part in &self.parts {
+            match part {
+                Part::Text(text) => title.push_str(text),
+                Part::Stat(stat) => title.push_str(&sample.value(*stat)),
+            }
+        }
+        title
+    }
+}
+
+// for the warning about unknown placeholders
+pub fn names() -> Vec<&'static str> {
+    STATS.iter().map(|(name, _)| *name).collect()
+}
@@ -0,0 +1,155 @@
+use std::{fs, path::Path};
+
+use crate::FPS;
+
+// how long a marker stays up, unless the next one comes first
+const MARKER_FRAMES: usize = 3 * FPS as usize;
+
+struct Cue {
+    // the frames it is shown on, from `start` up to and not including `end`
+    start: usize,
+    end: usize,
+    text: String,
+}
+
+// Text shown at the bottom of the window while `marlua play` replays a
+// journal: the journal's markers, and a sidecar file for commentary written
+// afterwards,
+//
+//   1
+//   300 --> 480
+//   entered 4-2
+//
+//   660 --> 700
+//   a second cue, over
+//   two lines
+//
+// like .srt with frames (counted like frame()) instead of times. The
+// numbers before the frames are optional. Cues that overlap are shown
+// together, the earlier one on top.
+#[derive(Default)]
+pub struct Subtitles {
+    cues: Vec<Cue>,
+}
+
+impl Subtitles {
+    pub fn from_markers(markers: &[(usize, String)]) -> Self {
+        let cues = markers.iter().enumerate().map(|(i, (frame, name))| {
+            let next = markers.get(i + 1).map_or(usize::MAX, |(next, _)| *next);
+            Cue {
+                start: *frame,
+                end: (frame + MARKER_FRAMES).min(next.max(frame + 1)),
+                text: name.clone(),
+            }
+        });
+        Self {
+            cues: cues.collect(),
+        }
+    }
+
+    pub fn read(path: &Path) -> Result<Self, String> {
+        let text = fs::read_to_string(path).map_err(|e| format!("{}: {}", path.display(), e))?;
+        let mut cues = Vec::new();
+        let mut lines = text.lines().enumerate().peekable();
+        while let Some((i, line)) = lines.next() {
+            let error = |msg: &str| format!("{}:{}: {}", path.display(), i + 1, msg);
+            let mut line = line.trim();
+            if line.is_empty() {
+                continue;
+            }
+            if !line.contains("-->") {
+                // the optional number
+                match lines.next() {
+                    Some((_, next)) if line.parse::<usize>().is_ok() => line = next.trim(),
+                    _ => return Err(error("expected START --> END frames")),
+                }
+            }
+            let Some((start, end)) = line.split_once("-->") else {
+                return Err(error("expected START --> END frames"));
+            };
+            let (Ok(start), Ok(end)) = (start.trim().parse(), end.trim().parse()) else {
+                return Err(error("frames have to be numbers"));
+            };
+            if end <= start {
+                return Err(error("a cue has to end after it starts"));
+            }
+            let mut text = Vec::new();
+            while let Some((_, line)) = lines.next_if(|(_, line)| !line.trim().is_empty()) {
+                text.push(line.trim());
+            }
+            cues.push(Cue {
+                start,
+                end,
+                text: text.join("\n"),
+            });
+        }
+        cues.sort_by_key(|cue| cue.start);
+        Ok(Self { cues })
+    }
+
+    pub fn extend(&mut self, other: Subtitles) {
+        self.cues.extend(other.cues);
+        self.cues.sort_by_key(|cue| cue.start);
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.cues.is_empty()
+    }
+
+    // what is shown on `frame`, if anything
+    pub fn at(&self, frame: usize) -> Option<String> {
+        let shown: Vec<_> = self
+            .cues
+            .iter()
+            .take_while(|cue| cue.start <= frame)
+            .filter(|cue| frame < cue.end)
+            .map(|cue| cue.text.as_str())
+            .collect();
+        (!shown.is_empty()).then(|| shown.join("\n"))
+    }
+}
+
+// the sidecar file of a journal, inputs.srt for inputs.journal
+pub fn sidecar(journal: &str) -> std::path::PathBuf {
+    Path::new(journal).with_extension("srt")
+}
+
+// splits `text` into lines of at most `columns` characters, at spaces where
+// it can
+pub fn wrap(text: &str, columns: usize) -> Vec<String> {
+    let columns = columns.max(1);
+    let mut lines = Vec::new();
+    for paragraph in text.lines() {
+        let mut line = String::new();
+        for word in paragraph.split_whitespace() {
+            let mut word = word;
+            loop {
+                let len = line.chars().count();
+                let needed = word.chars().count() + (len > 0) as usize;
+                if len + needed <= columns {
+                    if len > 0 {
+                        line.push(' ');
+                    }
+                    line.push_str(word);
+                    break;
+                }
+                if len > 0 {
+                    lines.push(std::mem::take(&mut line));
+                    continue;
+                }
+                // a word longer than a whole line
+                let cut = word
+                    .char_indices()
+                    .nth(columns)
+                    .map_or(word.len(), |(i, _)| i);
+                lines.push(word[..cut].to_owned());
+                word = &word[cut..];
+                if word.is_empty() {
+                    break;
+                }
+            }
+        }
+        lines.push(line);
+    }
+    lines
+}
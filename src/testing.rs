@@ -0,0 +1,56 @@
+// Helpers for the unit tests.
+
+use std::{
+    env, fs,
+    path::PathBuf,
+    sync::{atomic::AtomicU8, Arc, Mutex, MutexGuard},
+};
+
+use crate::machine::Machine;
+
+const PRG: usize = 0x4000;
+const CHR: usize = 0x2000;
+
+// An NROM image whose program counts up $0000 forever:
+//
+//   $8000  INC $00
+//   $8002  JMP $8000
+//
+// with every vector pointing at $8000.
+pub fn rom() -> Vec<u8> {
+    let mut rom = b"NES\x1A\x01\x01".to_vec();
+    rom.resize(16, 0);
+    let mut prg = vec![0; PRG];
+    prg[..5].copy_from_slice(&[0xE6, 0x00, 0x4C, 0x00, 0x80]);
+    for vector in prg[PRG - 6..].chunks_exact_mut(2) {
+        vector.copy_from_slice(&[0x00, 0x80]);
+    }
+    rom.extend_from_slice(&prg);
+    rom.resize(rom.len() + CHR, 0);
+    rom
+}
+
+pub fn machine() -> Machine {
+    Machine::new(rom(), Arc::new(AtomicU8::new(0)))
+}
+
+// steps a frame with `input` on the controller
+pub fn step(machine: &mut Machine, input: u8) {
+    machine.set_input(input);
+    machine.next_frame();
+}
+
+// an empty directory of its own for a test
+pub fn temp_dir(name: &str) -> PathBuf {
+    let dir = env::temp_dir().join(format!("marlua-{}-{}", name, std::process::id()));
+    let _ = fs::remove_dir_all(&dir);
+    fs::create_dir_all(&dir).unwrap();
+    dir
+}
+
+// held by tests that run sessions, which all see the one interrupt flag, so
+// that interrupting one does not stop another
+pub fn session() -> MutexGuard<'static, ()> {
+    static SESSION: Mutex<()> = Mutex::new(());
+    SESSION.lock().unwrap_or_else(|e| e.into_inner())
+}
@@ -0,0 +1,105 @@
+use std::{fs, path::Path};
+
+use rlua::{prelude::LuaError, Context, Table};
+
+use crate::capture::{HEIGHT, WIDTH};
+
+pub const COLUMNS: usize = WIDTH / 8;
+pub const ROWS: usize = HEIGHT / 8;
+
+const HEADER: &str = "# marlua tile hashes";
+
+// A hash of every 8x8 tile of the frame, row by row, that only depends on
+// which pixels of the tile share a color and not on the colors themselves:
+// each pixel is replaced by the order in which its color first appears in
+// the tile. A palette change or fade that keeps colors apart therefore
+// leaves the grid as it is.
+pub fn grid(rgba: &[u8]) -> Vec<u64> {
+    let mut grid = Vec::with_capacity(COLUMNS * ROWS);
+    // a tile has at most 64 colors, the NES draws it with at most a few
+    let mut seen: Vec<&[u8]> = Vec::with_capacity(64);
+    for row in 0..ROWS {
+        for column in 0..COLUMNS {
+            seen.clear();
+            let mut hash: u64 = 0xcbf29ce484222325;
+            for y in row * 8..row * 8 + 8 {
+                let start = (y * WIDTH + column * 8) * 4;
+                for pixel in rgba[start..start + 32].chunks_exact(4) {
+                    let color = &pixel[..3];
+                    let index = match seen.iter().position(|&c| c == color) {
+                        Some(index) => index,
+                        None => {
+                            seen.push(color);
+                            seen.len() - 1
+                        }
+                    };
+                    hash ^= index as u64;
+                    hash = hash.wrapping_mul(0x100000001b3);
+                }
+            }
+            grid.push(hash);
+        }
+    }
+    grid
+}
+
+// ROWS tables of COLUMNS hashes for scripts
+pub fn to_lua<'lua>(ctx: Context<'lua>, grid: &[u64]) -> Result<Table<'lua>, LuaError> {
+    ctx.create_sequence_from(
+        grid.chunks(COLUMNS)
+            .map(|row| row.iter().map(|&hash| hash as i64).collect::<Vec<_>>()),
+    )
+}
+
+pub fn from_lua(rows: Vec<Vec<i64>>) -> Result<Vec<u64>, LuaError> {
+    if rows.len() != ROWS || rows.iter().any(|row| row.len() != COLUMNS) {
+        return Err(LuaError::RuntimeError(format!(
+            "expected {} rows of {} tile hashes, like tile_hashes() returns",
+            ROWS, COLUMNS
+        )));
+    }
+    Ok(rows.into_iter().flatten().map(|hash| hash as u64).collect())
+}
+
+// the fraction of tiles that are the same in both grids
+pub fn matching(a: &[u64], b: &[u64]) -> f64 {
+    let same = a.iter().zip(b).filter(|(a, b)| a == b).count();
+    same as f64 / (COLUMNS * ROWS) as f64
+}
+
+// a header line and ROWS lines of COLUMNS hexadecimal hashes
+pub fn write(path: &Path, grid: &[u64]) -> Result<(), String> {
+    let mut text = format!("{} {}x{}\n", HEADER, COLUMNS, ROWS);
+    for row in grid.chunks(COLUMNS) {
+        let row: Vec<_> = row.iter().map(|hash| format!("{:016x}", hash)).collect();
+        text.push_str(&row.join(" "));
+        text.push('\n');
+    }
+    if let Some(dir) = path.parent() {
+        fs::create_dir_all(dir).map_err(|e| format!("{}: {}", dir.display(), e))?;
+    }
+    fs::write(path, text).map_err(|e| format!("{}: {}", path.display(), e))
+}
+
+pub fn read(path: &Path) -> Result<Vec<u64>, String> {
+    let error = |msg: &str| format!("{}: {}", path.display(), msg);
+    let text = fs::read_to_string(path).map_err(|e| error(&e.to_string()))?;
+    let mut lines = text.lines();
+    if !lines.next().map_or(false, |line| line.starts_with(HEADER)) {
+        return Err(error("not a tile hash file"));
+    }
+    let grid = lines
+        .flat_map(str::split_whitespace)
+        .map(|hash| u64::from_str_radix(hash, 16))
+        .collect::<Result<Vec<_>, _>>()
+        .map_err(|_| error("corrupt tile hash file"))?;
+    if grid.len() != COLUMNS * ROWS {
+        return Err(error(&format!(
+            "expected {}x{} tile hashes, found {}",
+            COLUMNS,
+            ROWS,
+            grid.len()
+        )));
+    }
+    Ok(grid)
+}
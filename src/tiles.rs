@@ -0,0 +1,133 @@
+use femtovg::{
+    imgref::Img, renderer::OpenGl, rgb::FromSlice, Canvas, Color, FontId, ImageFlags, Paint, Path,
+};
+use winit::event::VirtualKeyCode;
+
+use crate::views::Views;
+
+// both pattern tables side by side, 16x16 tiles of 8x8 pixels each
+pub const WIDTH: usize = 256;
+pub const HEIGHT: usize = 128;
+const SCALE: f32 = 2.0;
+
+// fastnes does not expose the palette RAM, so tiles are shown with one of
+// these instead
+const PALETTES: &[(&str, [[u8; 3]; 4])] = &[
+    (
+        "grey",
+        [[0, 0, 0], [85, 85, 85], [170, 170, 170], [255, 255, 255]],
+    ),
+    (
+        "overworld",
+        [[92, 148, 252], [128, 208, 16], [0, 168, 0], [0, 0, 0]],
+    ),
+    (
+        "mario",
+        [[92, 148, 252], [181, 49, 32], [234, 158, 34], [107, 109, 0]],
+    ),
+    (
+        "underground",
+        [[0, 0, 0], [24, 60, 92], [0, 128, 136], [252, 252, 252]],
+    ),
+];
+
+pub const TITLE: &str = "Pattern tables (Tab: palette)";
+
+// nametables live in PPU memory, which fastnes does not expose
+pub const NAMETABLES: &str = "nametables are not available: fastnes does not expose PPU memory";
+
+// The pattern table viewer, shown instead of the game.
+pub struct TileViewer {
+    chr: Vec<u8>,
+    palette: usize,
+    rgba: Vec<u8>,
+}
+
+impl TileViewer {
+    pub fn new() -> Self {
+        Self {
+            chr: Vec::new(),
+            palette: 0,
+            rgba: vec![0; WIDTH * HEIGHT * 4],
+        }
+    }
+
+    // pick up what the emulator published
+    pub fn update(&mut self, views: &Views) {
+        if let Some(chr) = views.take_tiles() {
+            self.chr = chr;
+            self.render();
+        }
+    }
+
+    pub fn key(&mut self, key: VirtualKeyCode) {
+        if key == VirtualKeyCode::Tab {
+            self.palette = (self.palette + 1) % PALETTES.len();
+            self.render();
+        }
+    }
+
+    // the first 8KB of CHR ROM as RGBA, see WIDTH and HEIGHT
+    fn render(&mut self) {
+        let colors = PALETTES[self.palette].1;
+        self.rgba.fill(0);
+        for (tile, bytes) in self.chr.chunks_exact(16).take(512).enumerate() {
+            let (bank, index) = (tile / 256, tile % 256);
+            let left = bank * 128 + index % 16 * 8;
+            let top = index / 16 * 8;
+            for y in 0..8 {
+                for x in 0..8 {
+                    let low = bytes[y] >> (7 - x) & 1;
+                    let high = bytes[y + 8] >> (7 - x) & 1;
+                    let [r, g, b] = colors[(high << 1 | low) as usize];
+                    let i = ((top + y) * WIDTH + left + x) * 4;
+                    self.rgba[i..i + 4].copy_from_slice(&[r, g, b, 255]);
+                }
+            }
+        }
+    }
+
+    // what to show below the title, for any renderer
+    pub fn lines(&self) -> Vec<String> {
+        let tiles = match self.chr.is_empty() {
+            true => "no CHR ROM, this game uses CHR RAM".to_owned(),
+            false => format!("palette: {}", PALETTES[self.palette].0),
+        };
+        vec![tiles, NAMETABLES.to_owned()]
+    }
+
+    pub fn rgba(&self) -> &[u8] {
+        &self.rgba
+    }
+
+    // where the tables go and their size on screen
+    pub fn rect() -> (f32, f32, f32, f32) {
+        (8.0, 32.0, WIDTH as f32 * SCALE, HEIGHT as f32 * SCALE)
+    }
+
+    pub fn draw(&self, canvas: &mut Canvas<OpenGl>, font: FontId) {
+        let mut background = Path::new();
+        background.rect(0.0, 0.0, canvas.width() as f32, canvas.height() as f32);
+        canvas.fill_path(&mut background, &Paint::color(Color::rgb(24, 24, 32)));
+
+        let mut paint = Paint::color(Color::white());
+        paint.set_font(&[font]);
+        paint.set_font_size(14.0);
+        let _ = canvas.fill_text(8.0, 20.0, TITLE, &paint);
+
+        let (x, y, w, h) = Self::rect();
+        let img = Img::new(self.rgba.as_rgba(), WIDTH, HEIGHT);
+        if let Ok(image) = canvas.create_image(img, ImageFlags::NEAREST) {
+            let mut path = Path::new();
+            path.rect(x, y, w, h);
+            canvas.fill_path(&mut path, &Paint::image(image, x, y, w, h, 0.0, 1.0));
+            // the image has to be drawn before it can be deleted
+            canvas.flush();
+            canvas.delete_image(image);
+        }
+
+        for (i, line) in self.lines().iter().enumerate() {
+            let _ = canvas.fill_text(8.0, y + h + 22.0 + i as f32 * 18.0, line, &paint);
+        }
+    }
+}
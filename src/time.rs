@@ -0,0 +1,174 @@
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+// NTSC NES frame rate
+pub const FRAME_RATE: f64 = 60.0988;
+
+const WEEKDAYS: [&str; 7] = [
+    "Sunday",
+    "Monday",
+    "Tuesday",
+    "Wednesday",
+    "Thursday",
+    "Friday",
+    "Saturday",
+];
+const MONTHS: [&str; 12] = [
+    "January",
+    "February",
+    "March",
+    "April",
+    "May",
+    "June",
+    "July",
+    "August",
+    "September",
+    "October",
+    "November",
+    "December",
+];
+
+// How much of one core the process uses from `start` on, to compare the
+// trailing loop at full speed with it idling. None where the platform does
+// not tell the CPU time.
+pub struct CpuUsage {
+    wall: Instant,
+    cpu: Duration,
+}
+
+impl CpuUsage {
+    pub fn start() -> Option<Self> {
+        Some(Self {
+            wall: Instant::now(),
+            cpu: cpu_time()?,
+        })
+    }
+
+    // in percent of one core
+    pub fn percent(&self) -> Option<f64> {
+        let wall = self.wall.elapsed().as_secs_f64();
+        let cpu = cpu_time()?.saturating_sub(self.cpu).as_secs_f64();
+        (wall > 0.0).then(|| cpu / wall * 100.0)
+    }
+}
+
+// user and system time of every thread so far
+fn cpu_time() -> Option<Duration> {
+    #[cfg(unix)]
+    {
+        let mut usage: libc::rusage = unsafe { std::mem::zeroed() };
+        if unsafe { libc::getrusage(libc::RUSAGE_SELF, &mut usage) } == 0 {
+            let time = |t: libc::timeval| {
+                Duration::from_secs(t.tv_sec as u64) + Duration::from_micros(t.tv_usec as u64)
+            };
+            return Some(time(usage.ru_utime) + time(usage.ru_stime));
+        }
+    }
+    None
+}
+
+// A broken-down calendar time.
+pub struct DateTime {
+    pub year: i64,
+    pub month: u32,
+    pub day: u32,
+    pub hour: u32,
+    pub minute: u32,
+    pub second: u32,
+    // 0 is Sunday
+    pub weekday: u32,
+}
+
+impl DateTime {
+    pub fn utc(time: SystemTime) -> Self {
+        let secs = time.duration_since(UNIX_EPOCH).map_or(0, |d| d.as_secs());
+        Self::from_unix(secs as i64)
+    }
+
+    // falls back to UTC where the local time zone is unknown
+    pub fn local(time: SystemTime) -> Self {
+        #[cfg(unix)]
+        {
+            let secs = time.duration_since(UNIX_EPOCH).map_or(0, |d| d.as_secs());
+            let t = secs as libc::time_t;
+            let mut tm: libc::tm = unsafe { std::mem::zeroed() };
+            if !unsafe { libc::localtime_r(&t, &mut tm) }.is_null() {
+                return Self {
+                    year: tm.tm_year as i64 + 1900,
+                    month: tm.tm_mon as u32 + 1,
+                    day: tm.tm_mday as u32,
+                    hour: tm.tm_hour as u32,
+                    minute: tm.tm_min as u32,
+                    second: tm.tm_sec as u32,
+                    weekday: tm.tm_wday as u32,
+                };
+            }
+        }
+        Self::utc(time)
+    }
+
+    fn from_unix(secs: i64) -> Self {
+        let (days, secs) = (secs.div_euclid(86400), secs.rem_euclid(86400));
+
+        // civil-from-days, see http://howardhinnant.github.io/date_algorithms.html
+        let z = days + 719468;
+        let era = z.div_euclid(146097);
+        let doe = z.rem_euclid(146097);
+        let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146096) / 365;
+        let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+        let mp = (5 * doy + 2) / 153;
+        let day = doy - (153 * mp + 2) / 5 + 1;
+        let month = if mp < 10 { mp + 3 } else { mp - 9 };
+        let year = yoe + era * 400 + (month <= 2) as i64;
+
+        Self {
+            year,
+            month: month as u32,
+            day: day as u32,
+            hour: (secs / 3600) as u32,
+            minute: (secs / 60 % 60) as u32,
+            second: (secs % 60) as u32,
+            // 1970-01-01 was a Thursday
+            weekday: (days + 4).rem_euclid(7) as u32,
+        }
+    }
+
+    // strftime-style formatting supporting %Y %y %m %d %H %I %M %S %p
+    // %A %a %B %b %F %T and %%
+    pub fn format(&self, fmt: &str) -> Result<String, String> {
+        let mut out = String::with_capacity(fmt.len() * 2);
+        let mut chars = fmt.chars();
+        while let Some(c) = chars.next() {
+            if c != '%' {
+                out.push(c);
+                continue;
+            }
+            let hour12 = match self.hour % 12 {
+                0 => 12,
+                h => h,
+            };
+            let weekday = WEEKDAYS[self.weekday as usize];
+            let month = MONTHS[self.month as usize - 1];
+            match chars.next() {
+                Some('Y') => out += &format!("{:04}", self.year),
+                Some('y') => out += &format!("{:02}", self.year.rem_euclid(100)),
+                Some('m') => out += &format!("{:02}", self.month),
+                Some('d') => out += &format!("{:02}", self.day),
+                Some('H') => out += &format!("{:02}", self.hour),
+                Some('I') => out += &format!("{:02}", hour12),
+                Some('M') => out += &format!("{:02}", self.minute),
+                Some('S') => out += &format!("{:02}", self.second),
+                Some('p') => out += if self.hour < 12 { "AM" } else { "PM" },
+                Some('A') => out += weekday,
+                Some('a') => out += &weekday[..3],
+                Some('B') => out += month,
+                Some('b') => out += &month[..3],
+                Some('F') => out += &self.format("%Y-%m-%d")?,
+                Some('T') => out += &self.format("%H:%M:%S")?,
+                Some('%') => out.push('%'),
+                Some(c) => return Err(format!("unsupported date format %{}", c)),
+                None => return Err("date format ends with %".to_owned()),
+            }
+        }
+        Ok(out)
+    }
+}
@@ -0,0 +1,114 @@
+use std::{fs, path::Path};
+
+use crate::{
+    buttons,
+    capture::{self, Capture},
+    diff, hashes, journal,
+    machine::Machine,
+    memory,
+    output::OutputDir,
+    picture,
+};
+
+// inputs listed on either side of the first mismatch
+const CONTEXT: usize = 5;
+
+// `marlua verify ROM JOURNAL`: replays the journal without a window and
+// compares every frame with the hash track recorded alongside it. At the
+// first mismatch the last matching and the first mismatching frame are
+// written to a run directory as PNGs, and the RAM that changed between them
+// and the inputs around them are printed. Ok(false) when the replay
+// diverged.
+pub fn run(rom: &str, journal: &str, track: &Path, output: Option<&str>) -> Result<bool, String> {
+    let file = fs::read(rom).map_err(|e| format!("cannot read {}: {}", rom, e))?;
+    let mut machine = Machine::replay(&file, &[]);
+    let recording = journal::read(Path::new(journal), machine.hash())?;
+    let expected = hashes::read(track, machine.hash())?;
+
+    let mut checked = 0;
+    for &input in &recording.inputs {
+        machine.set_input(input);
+        machine.next_frame();
+        let frame = machine.frame();
+        let Some(&Some(hash)) = expected.get(frame - 1) else {
+            continue;
+        };
+        let actual = hashes::state(&machine);
+        if actual == hash {
+            checked += 1;
+            continue;
+        }
+
+        println!(
+            "frame {} does not match: expected state {:016X}, replayed {:016X}",
+            frame, hash, actual
+        );
+        let before = Machine::replay(&file, &recording.inputs[..frame - 1]);
+        report(rom, output, before, machine, &recording.inputs)?;
+        return Ok(false);
+    }
+
+    let unchecked = recording.inputs.len() - checked;
+    println!(
+        "{} frames match their recorded hashes, {} had none",
+        checked, unchecked
+    );
+    if expected.len() > recording.inputs.len() {
+        println!(
+            "the hash track goes on for {} more frames than the journal",
+            expected.len() - recording.inputs.len()
+        );
+    }
+    Ok(true)
+}
+
+fn report(
+    rom: &str,
+    output: Option<&str>,
+    mut before: Machine,
+    mut after: Machine,
+    inputs: &[u8],
+) -> Result<(), String> {
+    let frame = after.frame();
+    let output = OutputDir::create(rom, output).map_err(|e| e.to_string())?;
+    for (name, machine) in [("last-match", &mut before), ("first-mismatch", &mut after)] {
+        // a capture per machine, their revisions are unrelated
+        let mut capture = Capture::new();
+        let path = output
+            .artifact(&format!("{}-{:06}.png", name, machine.frame()))
+            .map_err(|e| e.to_string())?;
+        let pixels = capture.frame(machine);
+        picture::write_png(&path, capture::WIDTH, capture::HEIGHT, pixels)
+            .map_err(|e| format!("{}: {}", path.display(), e))?;
+        println!("wrote {}", path.display());
+    }
+
+    let changes = diff::ram(&memory::read_ram(&before), &memory::read_ram(&after));
+    println!(
+        "{} RAM bytes changed from frame {} to frame {}",
+        changes.len(),
+        frame - 1,
+        frame
+    );
+    for change in changes {
+        let note = if change.zero_page() {
+            "  zero page"
+        } else {
+            ""
+        };
+        println!(
+            "${:04X}  {:02X} -> {:02X}{}",
+            change.addr, change.old, change.new, note
+        );
+    }
+
+    println!("inputs around frame {}:", frame);
+    let first = frame.saturating_sub(CONTEXT).max(1);
+    let last = (frame + CONTEXT).min(inputs.len());
+    for n in first..=last {
+        let names: Vec<_> = buttons::pressed(inputs[n - 1]).map(|b| b.name()).collect();
+        let mark = if n == frame { ">" } else { " " };
+        println!("{} {:6}  {}", mark, n, names.join("+"));
+    }
+    Ok(())
+}
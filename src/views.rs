@@ -0,0 +1,116 @@
+use std::sync::Mutex;
+
+use crate::{machine::Machine, memory};
+
+// The debug views the window can show, toggled by hotkeys.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum View {
+    Tiles,
+    Sprites,
+    Memory,
+}
+
+// frames between updates of an open view
+pub const UPDATE_EVERY: usize = 10;
+
+impl View {
+    // the memory view highlights what changed every frame
+    pub fn every(self) -> usize {
+        match self {
+            View::Memory => 1,
+            _ => UPDATE_EVERY,
+        }
+    }
+}
+
+// A sample of CPU RAM and which bytes changed since the one before.
+#[derive(Default)]
+pub struct Ram {
+    pub bytes: Vec<u8>,
+    pub changed: Vec<bool>,
+}
+
+// What the emulator publishes for the open view next to the frame.
+//
+// A view is updated when it is opened and every few frames after that
+// (see View::every), so a paused emulator still shows its current state and frame
+// advance updates it right away.
+#[derive(Default)]
+pub struct Views {
+    tiles: Mutex<Option<Vec<u8>>>,
+    sprites: Mutex<Option<[u8; 256]>>,
+    ram: Mutex<Option<Ram>>,
+    // the previous sample, to find changes
+    last_ram: Mutex<Vec<u8>>,
+}
+
+impl Views {
+    pub fn publish(&self, view: View, machine: &Machine) {
+        match view {
+            View::Tiles => *self.tiles.lock().unwrap() = Some(chr(machine.rom()).to_vec()),
+            View::Sprites => *self.sprites.lock().unwrap() = Some(oam(machine)),
+            View::Memory => self.publish_ram(machine),
+        }
+    }
+
+    fn publish_ram(&self, machine: &Machine) {
+        let bytes = memory::read_ram(machine);
+        let mut last = self.last_ram.lock().unwrap();
+        let changed = match last.len() {
+            0 => vec![false; bytes.len()],
+            _ => bytes.iter().zip(last.iter()).map(|(a, b)| a != b).collect(),
+        };
+        *last = bytes.clone();
+
+        // keep changes the window has not picked up yet
+        let mut ram = self.ram.lock().unwrap();
+        let changed = match ram.take() {
+            Some(old) => changed
+                .iter()
+                .zip(old.changed)
+                .map(|(a, b)| *a || b)
+                .collect(),
+            None => changed,
+        };
+        *ram = Some(Ram { bytes, changed });
+    }
+
+    // the pattern table data published since the last call
+    pub fn take_tiles(&self) -> Option<Vec<u8>> {
+        self.tiles.lock().unwrap_or_else(|e| e.into_inner()).take()
+    }
+
+    // the OAM entries published since the last call
+    pub fn take_sprites(&self) -> Option<[u8; 256]> {
+        self.sprites
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .take()
+    }
+
+    // the RAM sample published since the last call
+    pub fn take_ram(&self) -> Option<Ram> {
+        self.ram.lock().unwrap_or_else(|e| e.into_inner()).take()
+    }
+}
+
+// the CHR ROM of an iNES file, empty for games with CHR RAM
+fn chr(rom: &[u8]) -> &[u8] {
+    if rom.len() < 16 {
+        return &[];
+    }
+    let trainer = if rom[6] & 0b100 != 0 { 512 } else { 0 };
+    let start = 16 + trainer + rom[4] as usize * 0x4000;
+    let end = start + rom[5] as usize * 0x2000;
+    rom.get(start..end.min(rom.len())).unwrap_or_default()
+}
+
+// fastnes does not expose the PPU's OAM, so this reads the page nearly every
+// game copies to it with OAM DMA each frame
+fn oam(machine: &Machine) -> [u8; 256] {
+    let mut oam = [0; 256];
+    for (i, byte) in oam.iter_mut().enumerate() {
+        *byte = machine.nes().read_internal(0x200 + i as u16);
+    }
+    oam
+}
@@ -0,0 +1,228 @@
+use std::{
+    fs::{self, File},
+    io,
+    path::{Path, PathBuf},
+    time::SystemTime,
+};
+
+use tracing::{debug, info, warn};
+
+use crate::{
+    machine::Machine,
+    memory,
+    savestate::{Savestate, StateError},
+};
+
+// what the entries may take up together, each is a few KB
+pub const LIMIT: u64 = 1 << 20;
+
+// The machine after the warm-up, kept across runs with --warm-cache.
+//
+// Entries are savestates in output/cache/warmup named after the CRC32s of the
+// ROM and of the warm-up, its inputs together with the RAM it checks, so
+// changing either looks for another entry. An entry is only used when it
+// holds exactly the warm-up's inputs and restoring it gives the RAM it
+// stored; anything else is removed and the warm-up runs as before. Once the
+// entries outgrow LIMIT, the least recently used ones go.
+//
+// Restoring a state replays its inputs from power-on (see Machine), and those
+// are the warm-up's, so a hit emulates as much as the warm-up does until
+// fastnes can load a state directly.
+pub struct WarmCache {
+    dir: PathBuf,
+    path: PathBuf,
+    limit: u64,
+}
+
+impl WarmCache {
+    pub fn new(dir: PathBuf, rom_hash: u32, warmup: &[u8], check: &[(u16, u8)]) -> Self {
+        let mut hasher = crc32fast::Hasher::new();
+        hasher.update(warmup);
+        for &(addr, value) in check {
+            hasher.update(&addr.to_le_bytes());
+            hasher.update(&[value]);
+        }
+        let name = format!("{:08x}-{:08x}.state", rom_hash, hasher.finalize());
+        Self {
+            path: dir.join(name),
+            dir,
+            limit: LIMIT,
+        }
+    }
+
+    // restores the entry onto a machine at power-on and tells whether it
+    // did; otherwise the machine is left at power-on for the warm-up
+    pub fn restore(&self, machine: &mut Machine, warmup: &[u8]) -> bool {
+        let state = match Savestate::read(&self.path) {
+            Ok(state) => state,
+            Err(StateError::Io(e)) if e.kind() == io::ErrorKind::NotFound => {
+                debug!("no warm-up cache {}", self.path.display());
+                return false;
+            }
+            Err(e) => return self.discard(machine, &e.to_string()),
+        };
+        if state.inputs != warmup {
+            return self.discard(machine, "it holds other inputs than the warm-up");
+        }
+        let ram = state.ram.clone();
+        if let Err(e) = state.apply(machine) {
+            return self.discard(machine, &e.to_string());
+        }
+        if ram.as_deref() != Some(&memory::read_ram(machine)[..]) {
+            return self.discard(machine, "its RAM differs from the restored one");
+        }
+
+        // recently used entries are the last to be evicted
+        if let Err(e) = File::options()
+            .append(true)
+            .open(&self.path)
+            .and_then(|file| file.set_modified(SystemTime::now()))
+        {
+            debug!("could not touch {}: {}", self.path.display(), e);
+        }
+        info!("restored the warm-up from {}", self.path.display());
+        true
+    }
+
+    // keeps the machine as it is after the warm-up
+    pub fn store(&self, machine: &Machine) {
+        if let Err(e) = Savestate::capture(machine).write(&self.path) {
+            warn!(
+                "could not cache the warm-up in {}: {}",
+                self.path.display(),
+                e
+            );
+            return;
+        }
+        info!("cached the warm-up in {}", self.path.display());
+        evict(&self.dir, self.limit);
+    }
+
+    fn discard(&self, machine: &mut Machine, reason: &str) -> bool {
+        warn!(
+            "ignoring the warm-up cache {}: {}",
+            self.path.display(),
+            reason
+        );
+        let _ = fs::remove_file(&self.path);
+        if machine.frame() > 0 {
+            machine.restore(Vec::new());
+        }
+        false
+    }
+}
+
+// removes the least recently used entries until the rest fit in `limit`
+fn evict(dir: &Path, limit: u64) {
+    let mut entries: Vec<(SystemTime, u64, PathBuf)> = fs::read_dir(dir)
+        .into_iter()
+        .flatten()
+        .flatten()
+        .filter(|entry| entry.path().extension().map_or(false, |e| e == "state"))
+        .filter_map(|entry| {
+            let metadata = entry.metadata().ok()?;
+            Some((metadata.modified().ok()?, metadata.len(), entry.path()))
+        })
+        .collect();
+    entries.sort();
+
+    let mut total: u64 = entries.iter().map(|(_, len, _)| len).sum();
+    for (_, len, path) in entries {
+        if total <= limit {
+            break;
+        }
+        debug!("evicting {} from the warm-up cache", path.display());
+        if fs::remove_file(&path).is_ok() {
+            total -= len;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::*;
+    use crate::testing;
+
+    const WARMUP: &[u8] = &[0, 0, 8, 0];
+
+    fn warmed_up() -> Machine {
+        let mut machine = testing::machine();
+        for &input in WARMUP {
+            testing::step(&mut machine, input);
+        }
+        machine
+    }
+
+    #[test]
+    fn restore() {
+        let dir = testing::temp_dir("warmcache");
+        let machine = warmed_up();
+        let cache = WarmCache::new(dir.clone(), machine.hash(), WARMUP, &[(0, 1)]);
+
+        let mut cold = testing::machine();
+        assert!(!cache.restore(&mut cold, WARMUP));
+        cache.store(&machine);
+        assert!(cache.restore(&mut cold, WARMUP));
+        assert_eq!(cold.inputs(), WARMUP);
+
+        // another warm-up or ROM is another entry
+        let other = WarmCache::new(dir.clone(), machine.hash(), &[0, 8], &[(0, 1)]);
+        assert_ne!(other.path, cache.path);
+        let other = WarmCache::new(dir.clone(), machine.hash(), WARMUP, &[(0, 2)]);
+        assert_ne!(other.path, cache.path);
+        let other = WarmCache::new(dir.clone(), machine.hash() ^ 1, WARMUP, &[(0, 1)]);
+        assert_ne!(other.path, cache.path);
+
+        // stale and broken entries are removed, leaving the machine at
+        // power-on
+        let mut stale = Savestate::capture(&machine);
+        stale.inputs.push(0);
+        stale.write(&cache.path).unwrap();
+        let mut cold = testing::machine();
+        assert!(!cache.restore(&mut cold, WARMUP));
+        assert_eq!(cold.frame(), 0);
+        assert!(!cache.path.exists());
+
+        let mut wrong = Savestate::capture(&machine);
+        wrong.ram.as_mut().unwrap()[0] ^= 0xFF;
+        wrong.write(&cache.path).unwrap();
+        assert!(!cache.restore(&mut cold, WARMUP));
+        assert_eq!(cold.frame(), 0);
+        assert!(!cache.path.exists());
+
+        fs::write(&cache.path, b"not a state").unwrap();
+        assert!(!cache.restore(&mut cold, WARMUP));
+        assert!(!cache.path.exists());
+    }
+
+    #[test]
+    fn evict() {
+        let dir = testing::temp_dir("warmcache-evict");
+        let now = SystemTime::now();
+        for (i, name) in ["a.state", "b.state", "c.state", "d.txt"]
+            .iter()
+            .enumerate()
+        {
+            let path = dir.join(name);
+            fs::write(&path, [0; 100]).unwrap();
+            let used = now - Duration::from_secs(100 - i as u64);
+            File::options()
+                .append(true)
+                .open(&path)
+                .unwrap()
+                .set_modified(used)
+                .unwrap();
+        }
+
+        super::evict(&dir, 300);
+        assert!(dir.join("a.state").exists());
+        super::evict(&dir, 250);
+        assert!(!dir.join("a.state").exists());
+        assert!(dir.join("b.state").exists() && dir.join("c.state").exists());
+        super::evict(&dir, 0);
+        assert!(!dir.join("b.state").exists() && !dir.join("c.state").exists());
+        assert!(dir.join("d.txt").exists());
+    }
+}
@@ -0,0 +1,94 @@
+use tracing::warn;
+
+// what watchdog.black_level defaults to
+pub const BLACK_LEVEL: u8 = 16;
+
+// Rolling facts about the frames for is_black_screen, is_frozen and the
+// frozen game warning, kept up to date as the driver steps so the calls
+// are O(1).
+//
+// Drawing and hashing every frame is not free, so this only starts once a
+// script asks or watchdog.frozen_seconds is set; is_frozen counts from
+// then on.
+pub struct Watchdog {
+    tracking: bool,
+    hash: Option<u64>,
+    // the frames since the picture last changed, not counting the first
+    same: usize,
+    // the brightest channel of any pixel
+    brightest: u8,
+    black_level: u8,
+    // watchdog.frozen_seconds in frames, and whether it was warned about
+    warn_after: Option<usize>,
+    warned: bool,
+}
+
+impl Watchdog {
+    pub fn new(black_level: u8, frozen_frames: Option<usize>) -> Self {
+        Self {
+            tracking: frozen_frames.is_some(),
+            hash: None,
+            same: 0,
+            brightest: 0,
+            black_level,
+            warn_after: frozen_frames,
+            warned: false,
+        }
+    }
+
+    pub fn is_tracking(&self) -> bool {
+        self.tracking
+    }
+
+    // from now on every frame is looked at, starting with `rgba`
+    pub fn track(&mut self, rgba: &[u8]) {
+        if !self.tracking {
+            self.tracking = true;
+            self.update(rgba, false);
+        }
+    }
+
+    // after every frame while tracking; `idle` when no script is driving
+    // the frames, the only time a frozen game is warned about
+    pub fn update(&mut self, rgba: &[u8], idle: bool) {
+        let mut hash: u64 = 0xcbf29ce484222325;
+        let mut brightest = 0;
+        for pixel in rgba.chunks_exact(4) {
+            for &byte in &pixel[..3] {
+                hash ^= byte as u64;
+                hash = hash.wrapping_mul(0x100000001b3);
+                brightest = brightest.max(byte);
+            }
+        }
+        self.brightest = brightest;
+        if self.hash == Some(hash) {
+            self.same += 1;
+        } else {
+            self.hash = Some(hash);
+            self.same = 0;
+            self.warned = false;
+        }
+
+        let Some(after) = self.warn_after else {
+            return;
+        };
+        if idle && !self.warned && self.same >= after {
+            warn!(
+                "the picture has not changed for {} frames, the game may be hung",
+                self.same
+            );
+            self.warned = true;
+        }
+    }
+
+    // every pixel at or below `level` in each channel, by default
+    // watchdog.black_level
+    pub fn is_black(&self, level: Option<u8>) -> bool {
+        self.brightest <= level.unwrap_or(self.black_level)
+    }
+
+    // the last `frames` frames were identical
+    pub fn is_frozen(&self, frames: usize) -> bool {
+        self.hash.is_some() && self.same + 1 >= frames
+    }
+}